@@ -3,6 +3,8 @@ use std::{
     io::{Read, Seek, SeekFrom, Write},
 };
 
+use semblance::inst::{WasmHostState, WasmSnapshotError};
+
 thread_local! {
     static IO_TABLE: RefCell<IoTable> = RefCell::new(IoTable::new());
 }
@@ -59,6 +61,47 @@ fn with_io_table_mut<T, F: FnOnce(&mut IoTable) -> T>(f: F) -> T {
     IO_TABLE.with_borrow_mut(f)
 }
 
+/// Saves the position of every open reader, so restoring a snapshot seeks
+/// the guest's files back to where they were.
+pub struct IoSnapshotState;
+
+impl WasmHostState for IoSnapshotState {
+    fn key(&self) -> &str {
+        "semblance-mars/io"
+    }
+
+    fn save(&self) -> Vec<u8> {
+        with_io_table_mut(|io| {
+            let mut out = Vec::with_capacity(io.0.len() * 8);
+            for entry in &mut io.0 {
+                let pos = match entry.as_reader() {
+                    Some(reader) => reader.stream_position().map(|i| i as i64).unwrap_or(-1),
+                    None => -1,
+                };
+                out.extend_from_slice(&pos.to_le_bytes());
+            }
+            out
+        })
+    }
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), WasmSnapshotError> {
+        with_io_table_mut(|io| {
+            if bytes.len() != io.0.len() * 8 {
+                return Err(WasmSnapshotError::InvalidHostState(self.key().to_string()));
+            }
+            for (entry, pos) in io.0.iter_mut().zip(bytes.chunks_exact(8)) {
+                let pos = i64::from_le_bytes(pos.try_into().unwrap());
+                if let Some(reader) = entry.as_reader()
+                    && pos >= 0
+                {
+                    reader.seek(SeekFrom::Start(pos as u64))?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
 pub fn fopen(path: &str, mode: &str) -> i32 {
     if mode.contains("w") {
         match path {
@@ -1,7 +1,17 @@
-use std::{path::PathBuf, rc::Rc};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use sdl2::event::Event;
-use semblance::{inst::WasmInvokeOptions, link::WasmLinker, module::WasmModule};
+use sdl2::{event::Event, keyboard::Keycode};
+use semblance::{
+    inst::{WasmHostState, WasmInvokeOptions, WasmSnapshot, WasmStore},
+    instrument::{WasmProfileOptions, WasmProfiler},
+    link::WasmLinker,
+    module::{WasmModule, default_cache_dir},
+};
 
 use crate::guest_input::QueuedKeyEvent;
 
@@ -11,11 +21,46 @@ mod guest_io;
 mod syscalls;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let module_path = std::env::args().nth(1).expect("missing module path");
-    let module_path = PathBuf::from(module_path);
+    let mut module_path = None;
+    let mut profile_path = None;
+    let mut profile_opts = WasmProfileOptions::default();
+    let mut no_cache = false;
+    // Keys vanilla DOOM leaves unbound; other guests may want different ones.
+    let mut save_key = Keycode::Insert;
+    let mut load_key = Keycode::Home;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--profile" => {
+                profile_path = Some(PathBuf::from(
+                    args.next().expect("--profile missing <FILE>"),
+                ))
+            }
+            "--profile-interval" => {
+                profile_opts.sample_interval = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .expect("--profile-interval expects a number")
+            }
+            "--no-cache" => no_cache = true,
+            "--save-key" => save_key = parse_key(args.next(), "--save-key"),
+            "--load-key" => load_key = parse_key(args.next(), "--load-key"),
+            flag if flag.starts_with("-") => return Err(format!("unknown flag: {}", flag).into()),
+            _ => module_path = Some(PathBuf::from(arg)),
+        }
+    }
+    let module_path = module_path.expect("missing module path");
+    let snapshot_path = module_path.with_extension("snapshot");
+    let mut profiler = profile_path
+        .is_some()
+        .then(|| WasmProfiler::new(profile_opts));
     let mut linker = WasmLinker::new();
     syscalls::add_to_linker(&mut linker);
-    let wmod = WasmModule::read(&module_path).expect("unable to load module");
+    let wmod = match default_cache_dir() {
+        Some(cache_dir) if !no_cache => WasmModule::read_cached(&module_path, &cache_dir),
+        _ => WasmModule::read(&module_path),
+    }
+    .expect("unable to load module");
     let (mut store, externvals) = linker.link(&wmod).expect("unable to resolve imports");
     let winst_id = store
         .instantiate(Rc::new(wmod), &externvals)
@@ -28,7 +73,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .resolve_export_fn_by_name("_tick")
         .expect("no _tick func exported");
     store
-        .invoke(initfunc, Box::new([]), WasmInvokeOptions::default())
+        .invoke(initfunc, Box::new([]), invoke_options(&mut profiler))
         .expect("guest trapped during init");
 
     let mut event_pump =
@@ -39,6 +84,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Event::Quit { .. } => {
                     break 'running;
                 }
+                // The snapshot keys are kept from the guest, presses and
+                // releases alike.
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat,
+                    ..
+                } if keycode == save_key || keycode == load_key => {
+                    if repeat {
+                        continue;
+                    }
+                    if keycode == save_key {
+                        save_state(&store, &snapshot_path);
+                    } else {
+                        load_state(&mut store, &snapshot_path);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } if keycode == save_key || keycode == load_key => {}
                 Event::KeyDown {
                     keycode: Some(keycode),
                     ..
@@ -61,9 +126,44 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         store
-            .invoke(tickfunc, Box::new([]), WasmInvokeOptions::default())
+            .invoke(tickfunc, Box::new([]), invoke_options(&mut profiler))
             .expect("guest trapped during _tick");
     }
 
+    if let (Some(profiler), Some(profile_path)) = (profiler, profile_path) {
+        let out = BufWriter::new(File::create(profile_path)?);
+        profiler.write_collapsed(&store, out)?;
+    }
+
     Ok(())
 }
+
+/// Parses an SDL key name, like `F5` or `Insert`.
+fn parse_key(name: Option<String>, flag: &str) -> Keycode {
+    let name = name.unwrap_or_else(|| panic!("{} missing <KEY>", flag));
+    Keycode::from_name(&name).unwrap_or_else(|| panic!("{}: unknown key {:?}", flag, name))
+}
+
+fn save_state(store: &WasmStore, path: &Path) {
+    let snapshot = store.snapshot(&[&guest_io::IoSnapshotState as &dyn WasmHostState]);
+    match snapshot.save(path) {
+        Ok(()) => eprintln!("saved state to {}", path.display()),
+        Err(e) => eprintln!("failed to save state: {:?}", e),
+    }
+}
+
+fn load_state(store: &mut WasmStore, path: &Path) {
+    let res = WasmSnapshot::load(path)
+        .and_then(|snapshot| store.restore(&snapshot, &mut [&mut guest_io::IoSnapshotState]));
+    match res {
+        Ok(()) => eprintln!("loaded state from {}", path.display()),
+        Err(e) => eprintln!("failed to load state: {:?}", e),
+    }
+}
+
+fn invoke_options(profiler: &mut Option<WasmProfiler>) -> WasmInvokeOptions<'_> {
+    match profiler {
+        Some(profiler) => WasmInvokeOptions::default().with_hooks(profiler),
+        None => WasmInvokeOptions::default(),
+    }
+}
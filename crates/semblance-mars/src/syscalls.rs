@@ -365,7 +365,7 @@ fn syscall_read_key(
 
 pub fn add_to_linker(linker: &mut WasmLinker) {
     linker.add_host_module(
-        "semblance".to_string(),
+        "semblance",
         &[
             ("exit", &SYSCALL_EXIT_TYPE, &syscall_exit),
             (
@@ -0,0 +1,541 @@
+use std::io::{BufRead, Write};
+
+use crate::{
+    debug::{WasmCodeLocation, WasmPauseReason, WasmPauseState, WasmResumeMode},
+    inst::{
+        WasmBacktrace, WasmExecHooks, WasmFuncImpl, WasmMemAccess, WasmStack, WasmStore, WasmTrap,
+        WasmWatchHit, WasmWatchWrite,
+    },
+    instrument::{names::func_label, trace::func_operand_types},
+    module::{MaybeUntyped, WasmGlobalIdx, WasmInstruction, WasmLocalIdx, WasmMemIdx},
+};
+
+const HELP_TEXT: &str = "\
+Commands:
+    c, continue            Resume execution
+    s, step                Execute one instruction
+    n, next                Step over calls
+    finish                 Run until the current function returns
+    b, break <FN|OFFSET>   Break at a function or a code section offset
+    d, delete <ID>         Delete a breakpoint
+    breakpoints            List breakpoints
+    watchpoints            List watchpoints on the current instance's memory
+    where                  Show the current instruction
+    bt, backtrace          Show the call stack
+    stack                  Show the value stack
+    locals                 Show the locals of the current frame
+    globals                Show the globals of the current instance
+    x <ADDR> [LEN]         Hex dump linear memory
+    q, quit                Abort execution
+";
+
+const DEFAULT_DUMP_LEN: usize = 64;
+
+/// A command line debugger that pauses execution before the first
+/// instruction and reads commands from `input` whenever it is paused.
+/// Reaching the end of `input` detaches the debugger.
+pub struct WasmDebugger<R: BufRead, W: Write> {
+    input: R,
+    out: W,
+    pause: WasmPauseState,
+    detached: bool,
+}
+
+enum Command {
+    Resume(WasmResumeMode),
+    Quit,
+}
+
+/// Formats bytes as hex, eliding all but the first few of long runs.
+fn format_bytes(bytes: &[u8]) -> String {
+    const MAX: usize = 16;
+    let mut s = bytes
+        .iter()
+        .take(MAX)
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if bytes.len() > MAX {
+        s.push_str(" ...");
+    }
+    s
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+impl<R: BufRead, W: Write> WasmDebugger<R, W> {
+    pub fn new(input: R, out: W) -> Self {
+        WasmDebugger {
+            input,
+            out,
+            pause: WasmPauseState::new(),
+            detached: false,
+        }
+    }
+
+    pub fn pause_state_mut(&mut self) -> &mut WasmPauseState {
+        &mut self.pause
+    }
+
+    fn prompt(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> std::io::Result<Command> {
+        loop {
+            write!(self.out, "(sdb) ")?;
+            self.out.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                writeln!(self.out)?;
+                self.detached = true;
+                return Ok(Command::Resume(WasmResumeMode::Continue));
+            }
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                [] => {}
+                ["c" | "continue"] => return Ok(Command::Resume(WasmResumeMode::Continue)),
+                ["s" | "step"] => return Ok(Command::Resume(WasmResumeMode::Step)),
+                ["n" | "next"] => return Ok(Command::Resume(WasmResumeMode::StepOver)),
+                ["finish"] => return Ok(Command::Resume(WasmResumeMode::StepOut)),
+                ["q" | "quit"] => return Ok(Command::Quit),
+                ["b" | "break", loc] => self.cmd_break(store, stack, loc)?,
+                ["d" | "delete", id] => match id.parse() {
+                    Ok(id) if self.pause.remove_breakpoint(id) => {}
+                    _ => writeln!(self.out, "no breakpoint {}", id)?,
+                },
+                ["watchpoints"] => self.cmd_watchpoints(store, stack)?,
+                ["breakpoints"] => self.cmd_breakpoints(store)?,
+                ["where"] => self.cmd_where(store, stack, instr)?,
+                ["bt" | "backtrace"] => {
+                    write!(self.out, "{}", WasmBacktrace::capture(store, stack, instr))?
+                }
+                ["stack"] => self.cmd_stack(store, stack, instr)?,
+                ["locals"] => self.cmd_locals(store, stack)?,
+                ["globals"] => self.cmd_globals(store, stack)?,
+                ["x", addr] => self.cmd_dump(store, stack, addr, DEFAULT_DUMP_LEN)?,
+                ["x", addr, len] => match len.parse() {
+                    Ok(len) => self.cmd_dump(store, stack, addr, len)?,
+                    Err(_) => writeln!(self.out, "invalid length: {}", len)?,
+                },
+                ["h" | "help"] => write!(self.out, "{}", HELP_TEXT)?,
+                _ => writeln!(self.out, "unknown command: {}", line.trim())?,
+            }
+        }
+    }
+
+    fn cmd_break(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        loc: &str,
+    ) -> std::io::Result<()> {
+        let winst_id = stack.current_frame().winst_id;
+        let resolved = match parse_number(loc) {
+            Some(offset) => WasmCodeLocation::of_code_offset(store, winst_id, offset),
+            None => WasmCodeLocation::of_func(store, winst_id, loc),
+        };
+        match resolved {
+            Some(resolved) => {
+                let id = self.pause.add_breakpoint(resolved);
+                writeln!(
+                    self.out,
+                    "breakpoint {} at {}",
+                    id,
+                    self.describe(store, resolved)
+                )
+            }
+            None => writeln!(self.out, "no function or instruction at {}", loc),
+        }
+    }
+
+    fn cmd_breakpoints(&mut self, store: &WasmStore) -> std::io::Result<()> {
+        let breakpoints = self.pause.breakpoints().collect::<Vec<_>>();
+        if breakpoints.is_empty() {
+            writeln!(self.out, "no breakpoints")?;
+        }
+        for (id, loc) in breakpoints {
+            writeln!(self.out, "{:>4}: {}", id, self.describe(store, loc))?;
+        }
+        Ok(())
+    }
+
+    fn describe(&self, store: &WasmStore, loc: WasmCodeLocation) -> String {
+        let label = func_label(store, loc.funcaddr).unwrap_or_default();
+        let mut desc = format!("{}+{}", label, loc.instr_idx.0);
+        if let Some(offset) = loc.code_offset(store) {
+            desc.push_str(&format!(" [{:#x}]", offset));
+        }
+        if let Some(source) = store.resolve_source_location(loc.funcaddr, loc.instr_idx) {
+            desc.push_str(&format!(" at {}", source));
+        }
+        desc
+    }
+
+    fn cmd_where(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> std::io::Result<()> {
+        match WasmCodeLocation::current(store, stack, instr) {
+            Some(loc) => writeln!(self.out, "{}: {:?}", self.describe(store, loc), instr),
+            None => writeln!(self.out, "{:?}", instr),
+        }
+    }
+
+    fn pause(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> Result<(), WasmTrap> {
+        let _ = self.cmd_where(store, stack, instr);
+        match self.prompt(store, stack, instr) {
+            Ok(Command::Resume(mode)) => {
+                self.pause.resume(mode, stack);
+                Ok(())
+            }
+            Ok(Command::Quit) => Err(WasmTrap("aborted by debugger")),
+            Err(_) => {
+                self.detached = true;
+                Ok(())
+            }
+        }
+    }
+
+    fn report_watch(&mut self, hit: &WasmWatchHit) -> std::io::Result<()> {
+        let access = match hit.access {
+            WasmMemAccess::Read => "read",
+            WasmMemAccess::Write => "write",
+        };
+        writeln!(
+            self.out,
+            "watchpoint {}, {} of {} bytes at {:#x}",
+            hit.watchpoint.id,
+            access,
+            hit.old.len(),
+            hit.addr
+        )?;
+        writeln!(self.out, "    old: {}", format_bytes(hit.old))?;
+        match hit.new {
+            Some(WasmWatchWrite::Bytes(new)) => {
+                writeln!(self.out, "    new: {}", format_bytes(new))?
+            }
+            Some(WasmWatchWrite::Fill(byte)) => writeln!(
+                self.out,
+                "    new: {:02x} (filling {} bytes)",
+                byte,
+                hit.old.len()
+            )?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn cmd_watchpoints(&mut self, store: &WasmStore, stack: &WasmStack) -> std::io::Result<()> {
+        let winst = store.instances.resolve(stack.current_frame().winst_id);
+        let Some(memaddr) = winst.memaddrs.first() else {
+            return writeln!(self.out, "instance has no memory");
+        };
+        let watchpoints = store.mems.resolve(*memaddr).watchpoints();
+        if watchpoints.is_empty() {
+            writeln!(self.out, "no watchpoints")?;
+        }
+        for wp in watchpoints {
+            writeln!(
+                self.out,
+                "{:>4}: {:#x}..{:#x} ({:?})",
+                wp.id, wp.range.start, wp.range.end, wp.kind
+            )?;
+        }
+        Ok(())
+    }
+
+    fn cmd_stack(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> std::io::Result<()> {
+        let values = stack.values();
+        if values.is_empty() {
+            writeln!(self.out, "value stack is empty")?;
+        }
+        let types = value_types(store, stack, instr);
+        for (i, (val, ty)) in values.iter().zip(types).enumerate().rev() {
+            match ty {
+                MaybeUntyped::KnownType(ty) => {
+                    writeln!(self.out, "{:>4}: {} = {}", i, ty, val.format(&ty))?
+                }
+                MaybeUntyped::UnknownType => {
+                    let bits = unsafe { val.num.i64 };
+                    writeln!(
+                        self.out,
+                        "{:>4}: {:#010x} ({})",
+                        i, bits as u32, bits as i32
+                    )?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn cmd_locals(&mut self, store: &WasmStore, stack: &WasmStack) -> std::io::Result<()> {
+        let frame = stack.current_frame();
+        let func = store.funcs.resolve(frame.funcaddr);
+        let WasmFuncImpl::Wasm {
+            winst_id,
+            func: body,
+        } = func.impl_
+        else {
+            return Ok(());
+        };
+        let func_idx = store.resolve_func_idx(frame.funcaddr).unwrap();
+        let names = &store.instances.resolve(winst_id).wmod.names;
+        let types = func.type_.input_type.0.iter().chain(body.locals.iter());
+        for (i, (ty, val)) in types.zip(frame.locals.iter()).enumerate() {
+            let name = names.local_name(func_idx, WasmLocalIdx(i as u32));
+            writeln!(
+                self.out,
+                "{:>4} {}: {} = {}",
+                i,
+                name.unwrap_or(""),
+                ty,
+                val.format(ty)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn cmd_globals(&mut self, store: &WasmStore, stack: &WasmStack) -> std::io::Result<()> {
+        let winst = store.instances.resolve(stack.current_frame().winst_id);
+        if winst.globaladdrs.is_empty() {
+            writeln!(self.out, "instance has no globals")?;
+        }
+        for (i, globaladdr) in winst.globaladdrs.iter().enumerate() {
+            let global = store.globals.resolve(*globaladdr);
+            let ty = &global.type_.val_type;
+            let name = winst.wmod.names.global_name(WasmGlobalIdx(i as u32));
+            writeln!(
+                self.out,
+                "{:>4} {}: {} = {}",
+                i,
+                name.unwrap_or(""),
+                ty,
+                global.val.format(ty)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn cmd_dump(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        addr: &str,
+        len: usize,
+    ) -> std::io::Result<()> {
+        let Some(addr) = parse_number(addr) else {
+            return writeln!(self.out, "invalid address: {}", addr);
+        };
+        let winst = store.instances.resolve(stack.current_frame().winst_id);
+        if winst.memaddrs.is_empty() {
+            return writeln!(self.out, "instance has no memory");
+        }
+        let mem = store.mems.resolve(winst.addr_of(WasmMemIdx::ZERO));
+        let start = (addr as usize).min(mem.data.len());
+        let end = start.saturating_add(len).min(mem.data.len());
+        if start == end {
+            return writeln!(self.out, "address {:#x} is out of bounds", addr);
+        }
+        for (i, chunk) in mem.data[start..end].chunks(16).enumerate() {
+            write!(self.out, "{:08x}:", start + i * 16)?;
+            for byte in chunk {
+                write!(self.out, " {:02x}", byte)?;
+            }
+            let pad = (16 - chunk.len()) * 3;
+            let ascii = chunk
+                .iter()
+                .map(|b| {
+                    if b.is_ascii_graphic() || *b == b' ' {
+                        *b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            writeln!(self.out, "{:pad$}  |{}|", "", ascii, pad = pad)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead, W: Write> WasmExecHooks for WasmDebugger<R, W> {
+    fn on_instr(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> Result<(), WasmTrap> {
+        if self.detached {
+            return Ok(());
+        }
+        let Some(reason) = self.pause.check(store, stack, instr) else {
+            return Ok(());
+        };
+        if let WasmPauseReason::Breakpoint(id) = reason {
+            let _ = write!(self.out, "breakpoint {}, ", id);
+        }
+        self.pause(store, stack, instr)
+    }
+
+    fn on_watchpoint(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        hit: &WasmWatchHit,
+    ) -> Result<(), WasmTrap> {
+        if self.detached {
+            return Ok(());
+        }
+        let _ = self.report_watch(hit);
+        self.pause(store, stack, hit.instr)
+    }
+
+    fn on_trap(&mut self, store: &WasmStore, _stack: &WasmStack, trap: &WasmTrap) {
+        let _ = writeln!(self.out, "trap: {}", trap.0);
+        if let Some(backtrace) = store.trap_backtrace() {
+            let _ = write!(self.out, "{}", backtrace);
+        }
+    }
+}
+
+/// The types of the values on the stack, bottom first. Each frame types
+/// the operands it pushed, as validation finds them before the instruction
+/// it is at; values below a host frame stay untyped.
+fn value_types(store: &WasmStore, stack: &WasmStack, instr: &WasmInstruction) -> Vec<MaybeUntyped> {
+    let mut types = vec![];
+    let mut remaining = stack.values().len();
+    // The arguments of the callee, which its caller's operands still include.
+    let mut args = 0;
+    for (frame, ip) in stack.call_stack(instr) {
+        if frame.funcaddr.is_null() {
+            break;
+        }
+        let func = store.funcs.resolve(frame.funcaddr);
+        let Some(instr_idx) = func.instr_idx(ip) else {
+            break;
+        };
+        let Some(func_types) = func_operand_types(store, frame.funcaddr, remaining + args) else {
+            break;
+        };
+        let before = &func_types[instr_idx.0 as usize];
+        let own = &before[..before.len().saturating_sub(args)];
+        types.extend(own.iter().rev().copied());
+        remaining -= own.len();
+        args = func.type_.input_type.0.len();
+    }
+    types.resize(stack.values().len(), MaybeUntyped::UnknownType);
+    types.reverse();
+    types
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        inst::WasmInvokeOptions,
+        module::{WasmModuleRaw, validate},
+    };
+
+    #[test]
+    fn test_break_and_step() {
+        // No names, so `double` can only be found by its export.
+        let text = r#"(module
+            (func (export "main") (result i32) i32.const 21 call 1)
+            (func (export "double") (param i32) (result i32)
+                local.get 0
+                local.get 0
+                i32.add))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let main = store
+            .instances
+            .resolve(winst_id)
+            .resolve_export_fn_by_name("main")
+            .unwrap();
+        let input = "break double\nc\ns\nstack\nc\n".as_bytes();
+        let mut debugger = WasmDebugger::new(input, Vec::new());
+        let options = WasmInvokeOptions::default().with_hooks(&mut debugger);
+        let res = store.invoke(main, Box::new([]), options).unwrap();
+        assert_eq!(unsafe { res.res.0[0].num.i32 }, 42);
+        let out = String::from_utf8(debugger.out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "func[0]+0: I32Const { val: 21 }",
+                "(sdb) breakpoint 1 at func[1]+0",
+                "(sdb) breakpoint 1, func[1]+0: LocalGet { local_idx: WasmLocalIdx(0) }",
+                "(sdb) func[1]+1: LocalGet { local_idx: WasmLocalIdx(0) }",
+                "(sdb)    0: i32 = 21",
+                "(sdb) ",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_types() {
+        let text = r#"(module
+            (func (export "main") (result i64)
+                i64.const 5000000000
+                f64.const 0.5
+                i32.const 7
+                call $f
+                drop
+                drop)
+            (func $f (param i32) (result f64)
+                local.get 0
+                f32.const 1.5
+                drop
+                drop
+                f64.const 2.5))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let main = store
+            .instances
+            .resolve(winst_id)
+            .resolve_export_fn_by_name("main")
+            .unwrap();
+        let input = "break f\nc\ns\ns\nstack\nc\n".as_bytes();
+        let mut debugger = WasmDebugger::new(input, Vec::new());
+        let options = WasmInvokeOptions::default().with_hooks(&mut debugger);
+        store.invoke(main, Box::new([]), options).unwrap();
+        let out = String::from_utf8(debugger.out).unwrap();
+        // The caller's operands are typed too, without the argument `f` took.
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[5..],
+            [
+                "(sdb)    3: f32 = 1.5",
+                "   2: i32 = 7",
+                "   1: f64 = 0.5",
+                "   0: i64 = 5000000000",
+                "(sdb) ",
+            ],
+            "{}",
+            out
+        );
+    }
+}
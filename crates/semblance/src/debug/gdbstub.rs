@@ -0,0 +1,733 @@
+use std::io::{Read, Write};
+
+use crate::{
+    debug::{WasmCodeLocation, WasmPauseReason, WasmPauseState, WasmResumeMode},
+    inst::{
+        WasmExecHooks, WasmFrame, WasmFuncAddr, WasmFuncImpl, WasmInstanceAddr, WasmStack,
+        WasmStore, WasmTrap, WasmValue, WasmWatchHit, WasmWatchKind,
+    },
+    module::{
+        WasmGlobalIdx, WasmInstruction, WasmInstructionIdx, WasmMemIdx, WasmNumType, WasmValueType,
+    },
+};
+
+/// Addresses in the wasm flavor of the remote protocol carry their address
+/// space in the top two bits and the module id in the upper 32 bits.
+const ADDR_SPACE_MEMORY: u64 = 0;
+const ADDR_SPACE_CODE: u64 = 1;
+
+const TRIPLE: &str = "wasm32-unknown-unknown-wasm";
+const SIGTRAP: u8 = 5;
+
+/// A module whose code is exposed to the client. The module id used in
+/// addresses is its position in the stub's module list.
+struct GdbModule {
+    winst_id: WasmInstanceAddr,
+    name: String,
+    image: Box<[u8]>,
+    code_start: u32,
+}
+
+enum Command {
+    Resume(WasmResumeMode),
+    Kill,
+    Detach,
+}
+
+/// A GDB remote serial protocol server speaking the wasm extensions LLDB
+/// uses (`qWasmCallStack`, `qWasmLocal`, `qWasmGlobal`, `qWasmStackValue`
+/// and `qWasmMem`). Like [`super::WasmDebugger`], execution starts paused
+/// at the first instruction and the stub serves packets from `conn`
+/// whenever it is paused.
+pub struct WasmGdbStub<S: Read + Write> {
+    conn: S,
+    no_ack: bool,
+    pause: WasmPauseState,
+    modules: Vec<GdbModule>,
+    resumed: bool,
+    detached: bool,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+/// Finds the file offset of the code section contents, which is what code
+/// section relative offsets are relative to.
+fn code_section_start(image: &[u8]) -> Option<u32> {
+    fn leb128(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+        let mut result = 0u32;
+        let mut shift = 0;
+        loop {
+            let byte = *bytes.get(*pos)?;
+            *pos += 1;
+            result |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+            if byte & 0x80 == 0 {
+                return Some(result);
+            }
+            shift += 7;
+        }
+    }
+
+    let mut pos = 8;
+    while pos < image.len() {
+        let id = image[pos];
+        pos += 1;
+        let len = leb128(image, &mut pos)? as usize;
+        if id == 10 {
+            return Some(pos as u32);
+        }
+        pos += len;
+    }
+    None
+}
+
+fn value_bytes(ty: Option<&WasmValueType>, val: &WasmValue) -> Vec<u8> {
+    unsafe {
+        match ty {
+            Some(WasmValueType::Num(WasmNumType::I32 | WasmNumType::F32)) => {
+                val.num.i32.to_le_bytes().to_vec()
+            }
+            Some(WasmValueType::Vec(_)) => val.vec.to_le_bytes().to_vec(),
+            _ => val.num.i64.to_le_bytes().to_vec(),
+        }
+    }
+}
+
+impl<S: Read + Write> WasmGdbStub<S> {
+    pub fn new(conn: S) -> Self {
+        WasmGdbStub {
+            conn,
+            no_ack: false,
+            pause: WasmPauseState::new(),
+            modules: Vec::new(),
+            resumed: false,
+            detached: false,
+        }
+    }
+
+    /// Exposes an instance's module to the client. `image` must be the
+    /// binary the module was decoded from, since the client reads it
+    /// through the code address space to find symbols and debug info. See
+    /// [`crate::module::WasmModule::read_image`] for modules in the text
+    /// format.
+    pub fn add_module(&mut self, winst_id: WasmInstanceAddr, name: &str, image: Vec<u8>) {
+        let code_start = code_section_start(&image).unwrap_or(0);
+        self.modules.push(GdbModule {
+            winst_id,
+            name: name.to_string(),
+            image: image.into_boxed_slice(),
+            code_start,
+        });
+    }
+
+    /// Tells the client that execution has ended, unless it has detached
+    /// or killed the program already.
+    pub fn finish(&mut self, res: Result<(), &WasmTrap>) -> std::io::Result<()> {
+        if self.detached {
+            return Ok(());
+        }
+        self.detached = true;
+        match res {
+            Ok(()) => self.send("W00"),
+            Err(_) => self.send("W01"),
+        }
+    }
+
+    fn read_byte(&mut self) -> std::io::Result<u8> {
+        let mut buf = [0u8];
+        self.conn.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn recv(&mut self) -> std::io::Result<String> {
+        loop {
+            // Acks and interrupt requests are meaningless while paused.
+            while self.read_byte()? != b'$' {}
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    b'}' => data.push(self.read_byte()? ^ 0x20),
+                    byte => data.push(byte),
+                }
+            }
+            let cs = [self.read_byte()?, self.read_byte()?];
+            let cs = std::str::from_utf8(&cs).ok().and_then(parse_hex);
+            if self.no_ack {
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+            if cs == Some(checksum(&data) as u64) {
+                self.conn.write_all(b"+")?;
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+            self.conn.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> std::io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for byte in data.bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.push(b'}');
+                packet.push(byte ^ 0x20);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let cs = checksum(&packet[1..]);
+        packet.extend_from_slice(format!("#{:02x}", cs).as_bytes());
+        loop {
+            self.conn.write_all(&packet)?;
+            self.conn.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                b'-' => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn module_of(&self, winst_id: WasmInstanceAddr) -> Option<(u64, &GdbModule)> {
+        self.modules
+            .iter()
+            .enumerate()
+            .find(|(_, module)| module.winst_id == winst_id)
+            .map(|(id, module)| (id as u64, module))
+    }
+
+    fn pc(&self, store: &WasmStore, loc: WasmCodeLocation) -> u64 {
+        let WasmFuncImpl::Wasm { winst_id, func: _ } = store.funcs.resolve(loc.funcaddr).impl_
+        else {
+            return 0;
+        };
+        match (self.module_of(winst_id), loc.code_offset(store)) {
+            (Some((id, module)), Some(offset)) => {
+                ADDR_SPACE_CODE << 62 | id << 32 | (module.code_start + offset) as u64
+            }
+            _ => 0,
+        }
+    }
+
+    /// Resolves a code address to an instruction of a registered module.
+    fn code_location(&self, store: &WasmStore, addr: u64) -> Option<WasmCodeLocation> {
+        if addr >> 62 != ADDR_SPACE_CODE {
+            return None;
+        }
+        let module = self.modules.get((addr >> 32 & 0x3fff_ffff) as usize)?;
+        let offset = (addr as u32).checked_sub(module.code_start)?;
+        WasmCodeLocation::of_code_offset(store, module.winst_id, offset)
+    }
+
+    fn read_memory(&self, store: &WasmStore, addr: u64, len: usize) -> Option<Vec<u8>> {
+        let module = self.modules.get((addr >> 32 & 0x3fff_ffff) as usize)?;
+        let offset = addr as u32 as usize;
+        let bytes: &[u8] = match addr >> 62 {
+            ADDR_SPACE_CODE => &module.image,
+            ADDR_SPACE_MEMORY => {
+                let winst = store.instances.resolve(module.winst_id);
+                if winst.memaddrs.is_empty() {
+                    return None;
+                }
+                &store.mems.resolve(winst.addr_of(WasmMemIdx::ZERO)).data
+            }
+            _ => return None,
+        };
+        if offset >= bytes.len() {
+            return None;
+        }
+        Some(bytes[offset..offset.saturating_add(len).min(bytes.len())].to_vec())
+    }
+
+    fn stop_reply(&self, store: &WasmStore, frames: &[(&WasmFrame, WasmCodeLocation)]) -> String {
+        let pc = frames
+            .first()
+            .map(|(_, loc)| self.pc(store, *loc))
+            .unwrap_or(0);
+        format!("T{:02x}thread:1;00:{};", SIGTRAP, hex(&pc.to_le_bytes()))
+    }
+
+    fn library_list(&self) -> String {
+        let mut xml = String::from("<library-list>");
+        for (id, module) in self.modules.iter().enumerate() {
+            xml.push_str(&format!(
+                "<library name=\"{}\"><section address=\"{:#018x}\"/></library>",
+                module.name,
+                ADDR_SPACE_CODE << 62 | (id as u64) << 32
+            ));
+        }
+        xml.push_str("</library-list>");
+        xml
+    }
+
+    fn wasm_local(&self, store: &WasmStore, frame: &WasmFrame, idx: usize) -> Option<String> {
+        let func = store.funcs.resolve(frame.funcaddr);
+        let WasmFuncImpl::Wasm {
+            winst_id: _,
+            func: body,
+        } = func.impl_
+        else {
+            return None;
+        };
+        let ty = func
+            .type_
+            .input_type
+            .0
+            .iter()
+            .chain(body.locals.iter())
+            .nth(idx);
+        let val = frame.locals.get(idx)?;
+        Some(hex(&value_bytes(ty, val)))
+    }
+
+    fn wasm_global(&self, store: &WasmStore, frame: &WasmFrame, idx: u32) -> Option<String> {
+        let winst = store.instances.resolve(frame.winst_id);
+        if idx as usize >= winst.globaladdrs.len() {
+            return None;
+        }
+        let global = store.globals.resolve(winst.addr_of(WasmGlobalIdx(idx)));
+        Some(hex(&value_bytes(Some(&global.type_.val_type), &global.val)))
+    }
+
+    /// Handles one packet while paused, returning a command if it resumes
+    /// execution.
+    fn handle(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        frames: &[(&WasmFrame, WasmCodeLocation)],
+        packet: &str,
+        stop: &str,
+    ) -> std::io::Result<Option<Command>> {
+        let frame_arg = |s: &str| s.parse::<usize>().ok().and_then(|i| frames.get(i));
+        let reply = match packet {
+            "?" => stop.to_string(),
+            "QStartNoAckMode" => {
+                self.send("OK")?;
+                self.no_ack = true;
+                return Ok(None);
+            }
+            "qHostInfo" => format!(
+                "vendor:semblance;ostype:unknown;arch:wasm32;triple:{};endian:little;ptrsize:4;",
+                hex(TRIPLE.as_bytes())
+            ),
+            "qProcessInfo" => format!(
+                "pid:1;ppid:0;triple:{};endian:little;ptrsize:4;",
+                hex(TRIPLE.as_bytes())
+            ),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qAttached" => "1".to_string(),
+            "qRegisterInfo0" => "name:pc;alt-name:pc;bitsize:64;offset:0;encoding:uint;\
+                format:hex;set:General Purpose Registers;generic:pc;"
+                .to_string(),
+            "g" => {
+                let pc = frames
+                    .first()
+                    .map(|(_, loc)| self.pc(store, *loc))
+                    .unwrap_or(0);
+                hex(&pc.to_le_bytes())
+            }
+            "vCont?" => "vCont;c;s".to_string(),
+            "c" => return Ok(Some(Command::Resume(WasmResumeMode::Continue))),
+            "s" => return Ok(Some(Command::Resume(WasmResumeMode::Step))),
+            "k" => {
+                self.send("X09")?;
+                return Ok(Some(Command::Kill));
+            }
+            "D" => {
+                self.send("OK")?;
+                return Ok(Some(Command::Detach));
+            }
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=4000;QStartNoAckMode+;qXfer:libraries:read+".to_string()
+            }
+            _ if packet.starts_with("qThreadStopInfo") => stop.to_string(),
+            _ if packet.starts_with('H') => "OK".to_string(),
+            _ if packet.starts_with("qRegisterInfo") => "E45".to_string(),
+            _ if packet.starts_with("vCont;c") => {
+                return Ok(Some(Command::Resume(WasmResumeMode::Continue)));
+            }
+            _ if packet.starts_with("vCont;s") => {
+                return Ok(Some(Command::Resume(WasmResumeMode::Step)));
+            }
+            _ if packet.starts_with('p') => {
+                match packet[1..].split(';').next().and_then(parse_hex) {
+                    Some(0) => {
+                        let pc = frames
+                            .first()
+                            .map(|(_, loc)| self.pc(store, *loc))
+                            .unwrap_or(0);
+                        hex(&pc.to_le_bytes())
+                    }
+                    _ => "E45".to_string(),
+                }
+            }
+            _ if packet.starts_with('m') => {
+                let read = packet[1..].split_once(',').and_then(|(addr, len)| {
+                    self.read_memory(store, parse_hex(addr)?, parse_hex(len)? as usize)
+                });
+                match read {
+                    Some(bytes) => hex(&bytes),
+                    None => "E03".to_string(),
+                }
+            }
+            _ if packet.starts_with("Z0,") || packet.starts_with("z0,") => {
+                let loc = packet[3..]
+                    .split(',')
+                    .next()
+                    .and_then(parse_hex)
+                    .and_then(|addr| self.code_location(store, addr));
+                match loc {
+                    Some(loc) if packet.starts_with('Z') => {
+                        self.pause.add_breakpoint(loc);
+                        "OK".to_string()
+                    }
+                    Some(loc) => {
+                        self.pause.remove_breakpoint_at(loc);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with("qXfer:libraries:read::") => {
+                let xml = self.library_list();
+                let range = packet["qXfer:libraries:read::".len()..]
+                    .split_once(',')
+                    .and_then(|(off, len)| Some((parse_hex(off)?, parse_hex(len)?)));
+                match range {
+                    Some((off, len)) => {
+                        let start = (off as usize).min(xml.len());
+                        let end = start.saturating_add(len as usize).min(xml.len());
+                        let more = if end < xml.len() { "m" } else { "l" };
+                        format!("{}{}", more, &xml[start..end])
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with("qWasmCallStack") => frames
+                .iter()
+                .map(|(_, loc)| hex(&self.pc(store, *loc).to_le_bytes()))
+                .collect(),
+            _ if packet.starts_with("qWasmLocal:") => {
+                let value =
+                    packet["qWasmLocal:".len()..]
+                        .split_once(';')
+                        .and_then(|(frame, idx)| {
+                            self.wasm_local(store, frame_arg(frame)?.0, idx.parse().ok()?)
+                        });
+                value.unwrap_or_else(|| "E03".to_string())
+            }
+            _ if packet.starts_with("qWasmGlobal:") => {
+                let value =
+                    packet["qWasmGlobal:".len()..]
+                        .split_once(';')
+                        .and_then(|(frame, idx)| {
+                            self.wasm_global(store, frame_arg(frame)?.0, idx.parse().ok()?)
+                        });
+                value.unwrap_or_else(|| "E03".to_string())
+            }
+            // The value stack is shared by all frames, so stack values are
+            // indexed from its bottom regardless of the frame.
+            _ if packet.starts_with("qWasmStackValue:") => {
+                let value =
+                    packet["qWasmStackValue:".len()..]
+                        .split_once(';')
+                        .and_then(|(frame, idx)| {
+                            frame_arg(frame)?;
+                            let val = stack.values().get(idx.parse::<usize>().ok()?)?;
+                            Some(hex(&value_bytes(None, val)))
+                        });
+                value.unwrap_or_else(|| "E03".to_string())
+            }
+            _ if packet.starts_with("qWasmMem:") => {
+                let mut args = packet["qWasmMem:".len()..].split(';');
+                let read = (|| {
+                    let (frame, _) = frame_arg(args.next()?)?;
+                    let (id, _) = self.module_of(frame.winst_id)?;
+                    let addr = parse_hex(args.next()?)? as u32 as u64;
+                    let len = parse_hex(args.next()?)? as usize;
+                    self.read_memory(store, ADDR_SPACE_MEMORY << 62 | id << 32 | addr, len)
+                })();
+                match read {
+                    Some(bytes) => hex(&bytes),
+                    None => "E03".to_string(),
+                }
+            }
+            _ => String::new(),
+        };
+        self.send(&reply)?;
+        Ok(None)
+    }
+
+    fn serve(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        frames: &[(&WasmFrame, WasmCodeLocation)],
+        stop: &str,
+    ) -> std::io::Result<Command> {
+        loop {
+            let packet = self.recv()?;
+            if let Some(command) = self.handle(store, stack, frames, &packet, stop)? {
+                return Ok(command);
+            }
+        }
+    }
+
+    fn pause(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        frames: &[(&WasmFrame, WasmCodeLocation)],
+        stop: &str,
+        announce: bool,
+    ) -> Result<(), WasmTrap> {
+        let served = if announce {
+            self.send(stop)
+                .and_then(|()| self.serve(store, stack, frames, stop))
+        } else {
+            self.serve(store, stack, frames, stop)
+        };
+        match served {
+            Ok(Command::Resume(mode)) => {
+                self.pause.resume(mode, stack);
+                self.resumed = true;
+                Ok(())
+            }
+            Ok(Command::Kill) => {
+                self.detached = true;
+                Err(WasmTrap("killed by debugger"))
+            }
+            Ok(Command::Detach) | Err(_) => {
+                self.detached = true;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn frame_locations<'s>(
+    store: &WasmStore,
+    stack: &'s WasmStack,
+    instr: &WasmInstruction,
+) -> Vec<(&'s WasmFrame, WasmCodeLocation)> {
+    stack
+        .call_stack(instr)
+        .into_iter()
+        .filter(|(frame, _)| !frame.funcaddr.is_null())
+        .map(|(frame, ip)| {
+            let instr_idx = store
+                .funcs
+                .resolve(frame.funcaddr)
+                .instr_idx(ip)
+                .unwrap_or(WasmInstructionIdx(0));
+            (frame, location(frame.funcaddr, instr_idx))
+        })
+        .collect()
+}
+
+fn location(funcaddr: WasmFuncAddr, instr_idx: WasmInstructionIdx) -> WasmCodeLocation {
+    WasmCodeLocation {
+        funcaddr,
+        instr_idx,
+    }
+}
+
+impl<S: Read + Write> WasmExecHooks for WasmGdbStub<S> {
+    fn on_instr(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> Result<(), WasmTrap> {
+        if self.detached {
+            return Ok(());
+        }
+        let Some(reason) = self.pause.check(store, stack, instr) else {
+            return Ok(());
+        };
+        let frames = frame_locations(store, stack, instr);
+        let mut stop = self.stop_reply(store, &frames);
+        match reason {
+            WasmPauseReason::Breakpoint(_) => stop.push_str("reason:breakpoint;"),
+            WasmPauseReason::Step => stop.push_str("reason:trace;"),
+        }
+        // The client asks for the initial stop itself once it connects.
+        self.pause(store, stack, &frames, &stop, self.resumed)
+    }
+
+    fn on_watchpoint(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        hit: &WasmWatchHit,
+    ) -> Result<(), WasmTrap> {
+        if self.detached {
+            return Ok(());
+        }
+        let Some(id) = (self.modules.iter().enumerate())
+            .find(|(_, module)| {
+                store
+                    .instances
+                    .resolve(module.winst_id)
+                    .memaddrs
+                    .first()
+                    .is_some_and(|memaddr| *memaddr == hit.memaddr)
+            })
+            .map(|(id, _)| id as u64)
+        else {
+            return Ok(());
+        };
+        let frames = frame_locations(store, stack, hit.instr);
+        let key = match hit.watchpoint.kind {
+            WasmWatchKind::Read => "rwatch",
+            WasmWatchKind::Write => "watch",
+            WasmWatchKind::ReadWrite => "awatch",
+        };
+        let addr = ADDR_SPACE_MEMORY << 62 | id << 32 | hit.addr as u64;
+        let mut stop = self.stop_reply(store, &frames);
+        stop.push_str(&format!("{}:{:x};", key, addr));
+        self.pause(store, stack, &frames, &stop, true)
+    }
+
+    fn on_trap(&mut self, store: &WasmStore, stack: &WasmStack, trap: &WasmTrap) {
+        if self.detached {
+            return;
+        }
+        let Some(backtrace) = store.trap_backtrace() else {
+            return;
+        };
+        let frames = stack
+            .frames()
+            .rev()
+            .filter(|frame| !frame.funcaddr.is_null())
+            .zip(backtrace.frames())
+            .map(|(frame, bt)| {
+                let instr_idx = bt.instr_idx.unwrap_or(WasmInstructionIdx(0));
+                (frame, location(frame.funcaddr, instr_idx))
+            })
+            .collect::<Vec<_>>();
+        let mut stop = self.stop_reply(store, &frames);
+        stop.push_str(&format!(
+            "reason:exception;description:{};",
+            hex(trap.0.as_bytes())
+        ));
+        let _ = self.pause(store, stack, &frames, &stop, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, rc::Rc};
+
+    use super::*;
+    use crate::{inst::WasmInvokeOptions, module::WasmModule};
+
+    /// `(func (result i32) i32.const 7)`, with its code section contents
+    /// starting at file offset 0x15.
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00, 0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x07, 0x0b,
+    ];
+
+    struct ScriptedClient {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for ScriptedClient {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for ScriptedClient {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum(data.as_bytes()))
+    }
+
+    /// Steps once through the function of `MODULE`, or of a module with
+    /// the same code, and returns what the stub sent.
+    fn scripted_session(wmod: WasmModule, image: Vec<u8>) -> String {
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let funcaddr = store.instances.resolve(winst_id).funcaddrs[0];
+
+        // The OK reply to QStartNoAckMode is the last one the client acks.
+        let script = [
+            packet("QStartNoAckMode") + "+",
+            packet("?"),
+            packet("m4000000000000000,4"),
+            packet("s"),
+            packet("qWasmStackValue:0;0"),
+            packet("c"),
+        ]
+        .concat();
+        let mut client = ScriptedClient {
+            input: Cursor::new(script.into_bytes()),
+            output: Vec::new(),
+        };
+        let mut stub = WasmGdbStub::new(&mut client);
+        stub.add_module(winst_id, "test.wasm", image);
+        let res = store.invoke(
+            funcaddr,
+            Box::new([]),
+            WasmInvokeOptions::default().with_hooks(&mut stub),
+        );
+        stub.finish(res.as_ref().map(|_| ())).unwrap();
+        assert_eq!(unsafe { res.unwrap().res.0[0].num.i32 }, 7);
+        String::from_utf8(client.output).unwrap()
+    }
+
+    fn expected_session() -> String {
+        [
+            "+".to_string() + &packet("OK"),
+            packet("T05thread:1;00:1800000000000040;reason:trace;"),
+            packet("0061736d"),
+            packet("T05thread:1;00:1a00000000000040;reason:trace;"),
+            packet("0700000000000000"),
+            packet("W00"),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_scripted_session() {
+        let wmod = WasmModule::from_bytes(MODULE).unwrap();
+        assert_eq!(scripted_session(wmod, MODULE.to_vec()), expected_session());
+    }
+
+    #[test]
+    fn test_scripted_session_wat() {
+        let path = std::env::temp_dir().join(format!("semblance-gdb-{}.wat", std::process::id()));
+        std::fs::write(&path, "(module (func (result i32) i32.const 7))").unwrap();
+        let res = WasmModule::read_image(&path);
+        std::fs::remove_file(&path).unwrap();
+        let (wmod, image) = res.unwrap();
+        // Code addresses point into the encoded binary, not the text.
+        assert_eq!(image, MODULE);
+        assert_eq!(scripted_session(wmod, image), expected_session());
+    }
+}
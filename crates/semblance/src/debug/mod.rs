@@ -0,0 +1,7 @@
+mod debugger;
+mod gdbstub;
+mod pause;
+
+pub use debugger::WasmDebugger;
+pub use gdbstub::WasmGdbStub;
+pub use pause::{WasmCodeLocation, WasmPauseReason, WasmPauseState, WasmResumeMode};
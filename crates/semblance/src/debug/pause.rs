@@ -0,0 +1,203 @@
+use crate::{
+    inst::{WasmFuncAddr, WasmFuncImpl, WasmInstanceAddr, WasmStack, WasmStore},
+    instrument::names::func_label,
+    module::{WasmInstruction, WasmInstructionIdx},
+};
+
+/// An instruction within a function instance.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct WasmCodeLocation {
+    pub funcaddr: WasmFuncAddr,
+    pub instr_idx: WasmInstructionIdx,
+}
+
+impl WasmCodeLocation {
+    pub fn current(store: &WasmStore, stack: &WasmStack, instr: &WasmInstruction) -> Option<Self> {
+        let funcaddr = stack.current_frame().funcaddr;
+        if funcaddr.is_null() {
+            return None;
+        }
+        let instr_idx = store.funcs.resolve(funcaddr).instr_idx(instr)?;
+        Some(WasmCodeLocation {
+            funcaddr,
+            instr_idx,
+        })
+    }
+
+    /// Finds the first instruction of the function with the given name,
+    /// which may also be written as `func[N]` or be the name it is exported
+    /// under.
+    pub fn of_func(store: &WasmStore, winst_id: WasmInstanceAddr, name: &str) -> Option<Self> {
+        let winst = store.instances.resolve(winst_id);
+        let funcaddr = winst
+            .funcaddrs
+            .iter()
+            .copied()
+            .find(|funcaddr| func_label(store, *funcaddr).is_some_and(|label| label == name))
+            .or_else(|| winst.resolve_export_fn_by_name(name))
+            // Host functions have no instructions to break at.
+            .filter(|funcaddr| store.resolve_func_idx(*funcaddr).is_some())?;
+        Some(WasmCodeLocation {
+            funcaddr,
+            instr_idx: WasmInstructionIdx(0),
+        })
+    }
+
+    /// Finds the instruction covering a byte offset into the code section.
+    pub fn of_code_offset(
+        store: &WasmStore,
+        winst_id: WasmInstanceAddr,
+        offset: u32,
+    ) -> Option<Self> {
+        for funcaddr in store.instances.resolve(winst_id).funcaddrs.iter() {
+            let WasmFuncImpl::Wasm {
+                winst_id: owner,
+                func: _,
+            } = store.funcs.resolve(*funcaddr).impl_
+            else {
+                continue;
+            };
+            if owner != winst_id {
+                continue;
+            }
+            let Some(func) = store.resolve_func_body(*funcaddr) else {
+                continue;
+            };
+            let (Some(first), Some(last)) = (func.offsets.first(), func.offsets.last()) else {
+                continue;
+            };
+            if (*first..=*last).contains(&offset) {
+                let i = func.offsets.partition_point(|o| *o <= offset) - 1;
+                return Some(WasmCodeLocation {
+                    funcaddr: *funcaddr,
+                    instr_idx: WasmInstructionIdx(i as u32),
+                });
+            }
+        }
+        None
+    }
+
+    pub fn code_offset(&self, store: &WasmStore) -> Option<u32> {
+        let func = store.resolve_func_body(self.funcaddr)?;
+        func.offsets.get(self.instr_idx.0 as usize).copied()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WasmResumeMode {
+    Continue,
+    Step,
+    StepOver,
+    StepOut,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WasmPauseReason {
+    Step,
+    Breakpoint(usize),
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Resume {
+    Continue,
+    Step,
+    StepOver { depth: usize },
+    StepOut { depth: usize },
+}
+
+/// Breakpoints and stepping state shared by the debugger front ends.
+/// Execution starts paused at the first instruction.
+#[derive(Debug)]
+pub struct WasmPauseState {
+    breakpoints: Vec<Option<WasmCodeLocation>>,
+    resume: Resume,
+}
+
+impl Default for WasmPauseState {
+    fn default() -> Self {
+        WasmPauseState {
+            breakpoints: Vec::new(),
+            resume: Resume::Step,
+        }
+    }
+}
+
+fn call_depth(stack: &WasmStack) -> usize {
+    stack
+        .frames()
+        .filter(|frame| !frame.funcaddr.is_null())
+        .count()
+}
+
+impl WasmPauseState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a breakpoint and returns its id.
+    pub fn add_breakpoint(&mut self, loc: WasmCodeLocation) -> usize {
+        self.breakpoints.push(Some(loc));
+        self.breakpoints.len()
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        match id.checked_sub(1).and_then(|i| self.breakpoints.get_mut(i)) {
+            Some(bp) => bp.take().is_some(),
+            None => false,
+        }
+    }
+
+    pub fn remove_breakpoint_at(&mut self, loc: WasmCodeLocation) -> bool {
+        let mut removed = false;
+        for bp in self.breakpoints.iter_mut() {
+            if *bp == Some(loc) {
+                *bp = None;
+                removed = true;
+            }
+        }
+        removed
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, WasmCodeLocation)> + '_ {
+        self.breakpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bp)| bp.map(|bp| (i + 1, bp)))
+    }
+
+    pub fn resume(&mut self, mode: WasmResumeMode, stack: &WasmStack) {
+        self.resume = match mode {
+            WasmResumeMode::Continue => Resume::Continue,
+            WasmResumeMode::Step => Resume::Step,
+            WasmResumeMode::StepOver => Resume::StepOver {
+                depth: call_depth(stack),
+            },
+            WasmResumeMode::StepOut => Resume::StepOut {
+                depth: call_depth(stack),
+            },
+        };
+    }
+
+    /// Decides whether execution should pause before `instr` runs.
+    pub fn check(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> Option<WasmPauseReason> {
+        if matches!(self.resume, Resume::Continue) && self.breakpoints.is_empty() {
+            return None;
+        }
+        let loc = WasmCodeLocation::current(store, stack, instr)?;
+        if let Some((id, _)) = self.breakpoints().find(|(_, bp)| *bp == loc) {
+            return Some(WasmPauseReason::Breakpoint(id));
+        }
+        let step = match self.resume {
+            Resume::Continue => false,
+            Resume::Step => true,
+            Resume::StepOver { depth } => call_depth(stack) <= depth,
+            Resume::StepOut { depth } => call_depth(stack) < depth,
+        };
+        step.then_some(WasmPauseReason::Step)
+    }
+}
@@ -1,13 +1,47 @@
 use crate::{
     inst::{
-        ControlStackEntry, WasmFrame, WasmFuncImpl, WasmHostCallContext, WasmLabel, WasmMemInst,
-        WasmRefValue, WasmStack, WasmStore, WasmTrap, WasmValue,
+        ControlStackEntry, WasmBacktrace, WasmExecHooks, WasmFrame, WasmFuncImpl,
+        WasmHostCallContext, WasmLabel, WasmMemAccess, WasmMemInst, WasmNoHooks, WasmRefValue,
+        WasmStack, WasmStore, WasmTrap, WasmValue, WasmWatchHit, WasmWatchWrite,
     },
     module::{WasmExpr, WasmInstruction, WasmInstructionRepr, WasmLabelIdx, WasmMemIdx},
 };
 
+macro_rules! check_watchpoints {
+    ($store:ident, $stack:ident, $hooks:ident, $ip:ident, $memaddr:expr, $addr:expr, $len:expr, $new:expr) => {
+        let memaddr = $memaddr;
+        if $hooks.watches_memory() && !$store.mems.resolve(memaddr).watchpoints.is_empty() {
+            let mem = $store.mems.resolve(memaddr);
+            // Matching keeps temporaries in `$new` alive for the whole check.
+            match ($addr, $len, $new) {
+                (addr, len, new) => {
+                    let access = if new.is_some() {
+                        WasmMemAccess::Write
+                    } else {
+                        WasmMemAccess::Read
+                    };
+                    for watchpoint in &mem.watchpoints {
+                        if watchpoint.kind.matches(access) && watchpoint.overlaps(addr, len) {
+                            let hit = WasmWatchHit {
+                                memaddr,
+                                watchpoint,
+                                access,
+                                addr,
+                                old: &mem.data[addr..(addr + len)],
+                                new,
+                                instr: unsafe { &*$ip },
+                            };
+                            $hooks.on_watchpoint($store, $stack, &hit)?;
+                        }
+                    }
+                }
+            }
+        }
+    };
+}
+
 macro_rules! mem_load {
-    ($t:ty => $t2:ty, $stack:ident, $store:ident, $memarg:ident) => {
+    ($t:ty => $t2:ty, $stack:ident, $store:ident, $hooks:ident, $ip:ident, $memarg:ident) => {
         let frame = $stack.current_frame();
         let winst = $store.instances.resolve(frame.winst_id);
         let memaddr = winst.memaddrs[0];
@@ -18,34 +52,51 @@ macro_rules! mem_load {
         if ea + N > mem.data.len() {
             return Err(WasmTrap("out of bounds memory access"));
         }
+        check_watchpoints!($store, $stack, $hooks, $ip, memaddr, ea, N, None);
         let bytes = mem.data[ea..].first_chunk::<N>().unwrap();
+        $hooks.on_mem_load($store, memaddr, ea, bytes);
         let val = <$t>::from_le_bytes(*bytes);
         $stack.push_value(val as $t2);
     };
 }
 
 macro_rules! mem_store {
-    ($t:ident => $t2:ty, $stack:ident, $store:ident, $memarg:ident) => {
+    ($t:ident => $t2:ty, $stack:ident, $store:ident, $hooks:ident, $ip:ident, $memarg:ident) => {
         let frame = $stack.current_frame();
         let winst = $store.instances.resolve(frame.winst_id);
         let memaddr = winst.memaddrs[0];
-        let mem = $store.mems.resolve_mut(memaddr);
         let val = unsafe { $stack.pop_value().num.$t };
         let i = unsafe { $stack.pop_value().num.i32 } as u32 as usize;
         let ea = i + ($memarg.offset as usize);
         const N: usize = std::mem::size_of::<$t2>();
-        if ea + N > mem.data.len() {
+        if ea + N > $store.mems.resolve(memaddr).data.len() {
             return Err(WasmTrap("out of bounds memory access"));
         }
-        (&mut mem.data[ea..(ea + N)]).copy_from_slice(&(val as $t2).to_le_bytes());
+        let bytes = (val as $t2).to_le_bytes();
+        check_watchpoints!(
+            $store,
+            $stack,
+            $hooks,
+            $ip,
+            memaddr,
+            ea,
+            N,
+            Some(WasmWatchWrite::Bytes(&bytes))
+        );
+        $hooks.on_mem_store($store, memaddr, ea, &bytes);
+        let mem = $store.mems.resolve_mut(memaddr);
+        (&mut mem.data[ea..(ea + N)]).copy_from_slice(&bytes);
     };
 }
 
 macro_rules! invoke {
-    ($f:ident, $stack:ident, $store:ident, $winst_id:ident, $ip:ident) => {
-        let args = $stack.pop_values($f.type_.input_type.0.len());
-        match $f.impl_ {
+    ($funcaddr:ident, $stack:ident, $store:ident, $hooks:ident, $winst_id:ident, $ip:ident) => {
+        $store.validate_lazily($funcaddr)?;
+        let func = $store.funcs.resolve($funcaddr);
+        let args = $stack.pop_values(func.type_.input_type.0.len());
+        match func.impl_ {
             WasmFuncImpl::Host { hostfunc } => {
+                $hooks.on_func_enter($store, $stack, $funcaddr);
                 hostfunc.call(
                     &args,
                     &mut WasmHostCallContext {
@@ -54,6 +105,7 @@ macro_rules! invoke {
                         inst: $winst_id,
                     },
                 );
+                $hooks.on_func_exit($store, $stack, $funcaddr);
             }
             WasmFuncImpl::Wasm {
                 winst_id,
@@ -69,10 +121,12 @@ macro_rules! invoke {
                 $stack.push_frame(WasmFrame {
                     locals: locals.into_boxed_slice(),
                     winst_id,
+                    funcaddr: $funcaddr,
                 })?;
                 $stack.push_label(WasmLabel {
                     instr: funcimpl.body.last().unwrap(),
                 })?;
+                $hooks.on_func_enter($store, $stack, $funcaddr);
                 goto!($ip, &funcimpl.body[0]);
             }
         }
@@ -115,10 +169,59 @@ macro_rules! trunc_float_checked {
     };
 }
 
-pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Result<(), WasmTrap> {
+pub fn exec(
+    stack: &mut WasmStack,
+    store: &mut WasmStore,
+    expr: &WasmExpr,
+    hooks: Option<&mut dyn WasmExecHooks>,
+) -> Result<(), WasmTrap> {
+    match hooks {
+        Some(hooks) => exec_with_hooks(stack, store, expr, hooks),
+        None => exec_with_hooks(stack, store, expr, &mut WasmNoHooks),
+    }
+}
+
+fn exec_with_hooks<H: WasmExecHooks + ?Sized>(
+    stack: &mut WasmStack,
+    store: &mut WasmStore,
+    expr: &WasmExpr,
+    hooks: &mut H,
+) -> Result<(), WasmTrap> {
+    let mut ip = std::ptr::null();
+    let res = exec_loop(stack, store, expr, hooks, &mut ip);
+    if let Err(trap) = &res {
+        store.trap_backtrace = Some(WasmBacktrace::capture(store, stack, ip));
+        hooks.on_trap(store, stack, trap);
+    }
+    res
+}
+
+/// Reports leaving a frame, unless it is the frame of a constant expression,
+/// which belongs to no function.
+fn exit_frame<H: WasmExecHooks + ?Sized>(
+    hooks: &mut H,
+    store: &WasmStore,
+    stack: &WasmStack,
+    frame: &WasmFrame,
+) {
+    if !frame.funcaddr.is_null() {
+        hooks.on_func_exit(store, stack, frame.funcaddr);
+    }
+}
+
+fn exec_loop<H: WasmExecHooks + ?Sized>(
+    stack: &mut WasmStack,
+    store: &mut WasmStore,
+    expr: &WasmExpr,
+    hooks: &mut H,
+    trap_ip: &mut *const WasmInstruction,
+) -> Result<(), WasmTrap> {
     let mut ip: *const WasmInstruction = &expr[0];
     loop {
         use WasmInstructionRepr::*;
+        // Kept up to date so that a trap can be traced back to its instruction.
+        *trap_ip = ip;
+        hooks.on_instr(store, stack, unsafe { &*ip })?;
         match unsafe { &*ip } {
             I32Const { val } => stack.push_value(*val),
             I64Const { val } => stack.push_value(*val),
@@ -935,12 +1038,15 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
             GlobalGet { global_idx } => {
                 let frame = stack.current_frame();
                 let globaladdr = store.instances.resolve(frame.winst_id).addr_of(*global_idx);
-                stack.push_value(store.globals.resolve(globaladdr).val);
+                let val = store.globals.resolve(globaladdr).val;
+                hooks.on_global_get(store, globaladdr, val);
+                stack.push_value(val);
             }
             GlobalSet { global_idx } => {
                 let val = stack.pop_value();
                 let frame = stack.current_frame();
                 let globaladdr = store.instances.resolve(frame.winst_id).addr_of(*global_idx);
+                hooks.on_global_set(store, globaladdr, val);
                 store.globals.resolve_mut(globaladdr).val = val;
             }
             LocalGet { local_idx } => {
@@ -994,7 +1100,8 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
                 goto!(ip, label.instr);
             }
             ExprEnd => match stack.pop_control() {
-                Some(ControlStackEntry::Frame(_frame)) => {
+                Some(ControlStackEntry::Frame(frame)) => {
+                    exit_frame(hooks, store, stack, &frame);
                     if let Some(ControlStackEntry::Label(label)) = stack.pop_control() {
                         goto!(ip, label.instr);
                     } else {
@@ -1007,7 +1114,9 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
                         _ => false,
                     };
                     if end_of_func {
-                        let _frame = stack.pop_control();
+                        if let Some(ControlStackEntry::Frame(frame)) = stack.pop_control() {
+                            exit_frame(hooks, store, stack, &frame);
+                        }
                         if let Some(ControlStackEntry::Label(label)) = stack.pop_control() {
                             goto!(ip, label.instr);
                         } else {
@@ -1039,8 +1148,9 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
                 goto!(ip, label.instr);
             }
             Return { imm } => {
-                stack.pop_frame();
+                let frame = stack.pop_frame();
                 stack.truncate_values_within(imm.arity as usize, imm.drop as usize);
+                exit_frame(hooks, store, stack, &frame);
                 if let Some(ControlStackEntry::Label(label)) = stack.pop_control() {
                     goto!(ip, label.instr);
                 } else {
@@ -1050,8 +1160,7 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
             Call { func_idx } => {
                 let winst_id = stack.current_frame().winst_id;
                 let funcaddr = store.instances.resolve(winst_id).addr_of(*func_idx);
-                let func = store.funcs.resolve(funcaddr);
-                invoke!(func, stack, store, winst_id, ip);
+                invoke!(funcaddr, stack, store, hooks, winst_id, ip);
             }
             CallIndirect {
                 table_idx,
@@ -1069,12 +1178,13 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
                 if unsafe { r.func }.is_null() {
                     return Err(WasmTrap("uninitialized element"));
                 }
-                let func = store.funcs.resolve(unsafe { r.func });
+                let funcaddr = unsafe { r.func };
+                let func = store.funcs.resolve(funcaddr);
                 let ft_actual = &*func.type_;
                 if ft_actual != ft_expect {
                     return Err(WasmTrap("call_indirect type mismatch"));
                 }
-                invoke!(func, stack, store, winst_id, ip);
+                invoke!(funcaddr, stack, store, hooks, winst_id, ip);
             }
             RefNull { ref_type: _ } => {
                 stack.push_value(WasmRefValue::NULL);
@@ -1102,73 +1212,73 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
                 }
             }
             I32Load { memarg } => {
-                mem_load!(i32 => i32, stack, store, memarg);
+                mem_load!(i32 => i32, stack, store, hooks, ip, memarg);
             }
             I32Load8U { memarg } => {
-                mem_load!(u8 => i32, stack, store, memarg);
+                mem_load!(u8 => i32, stack, store, hooks, ip, memarg);
             }
             I32Load8S { memarg } => {
-                mem_load!(i8 => i32, stack, store, memarg);
+                mem_load!(i8 => i32, stack, store, hooks, ip, memarg);
             }
             I32Load16U { memarg } => {
-                mem_load!(u16 => i32, stack, store, memarg);
+                mem_load!(u16 => i32, stack, store, hooks, ip, memarg);
             }
             I32Load16S { memarg } => {
-                mem_load!(i16 => i32, stack, store, memarg);
+                mem_load!(i16 => i32, stack, store, hooks, ip, memarg);
             }
             I64Load { memarg } => {
-                mem_load!(i64 => i64, stack, store, memarg);
+                mem_load!(i64 => i64, stack, store, hooks, ip, memarg);
             }
             I64Load8U { memarg } => {
-                mem_load!(u8 => i64, stack, store, memarg);
+                mem_load!(u8 => i64, stack, store, hooks, ip, memarg);
             }
             I64Load8S { memarg } => {
-                mem_load!(i8 => i64, stack, store, memarg);
+                mem_load!(i8 => i64, stack, store, hooks, ip, memarg);
             }
             I64Load16U { memarg } => {
-                mem_load!(u16 => i64, stack, store, memarg);
+                mem_load!(u16 => i64, stack, store, hooks, ip, memarg);
             }
             I64Load16S { memarg } => {
-                mem_load!(i16 => i64, stack, store, memarg);
+                mem_load!(i16 => i64, stack, store, hooks, ip, memarg);
             }
             I64Load32U { memarg } => {
-                mem_load!(u32 => i64, stack, store, memarg);
+                mem_load!(u32 => i64, stack, store, hooks, ip, memarg);
             }
             I64Load32S { memarg } => {
-                mem_load!(i32 => i64, stack, store, memarg);
+                mem_load!(i32 => i64, stack, store, hooks, ip, memarg);
             }
             F32Load { memarg } => {
-                mem_load!(f32 => f32, stack, store, memarg);
+                mem_load!(f32 => f32, stack, store, hooks, ip, memarg);
             }
             F64Load { memarg } => {
-                mem_load!(f64 => f64, stack, store, memarg);
+                mem_load!(f64 => f64, stack, store, hooks, ip, memarg);
             }
             I32Store { memarg } => {
-                mem_store!(i32 => i32, stack, store, memarg);
+                mem_store!(i32 => i32, stack, store, hooks, ip, memarg);
             }
             I32Store8 { memarg } => {
-                mem_store!(i32 => i8, stack, store, memarg);
+                mem_store!(i32 => i8, stack, store, hooks, ip, memarg);
             }
             I32Store16 { memarg } => {
-                mem_store!(i32 => i16, stack, store, memarg);
+                mem_store!(i32 => i16, stack, store, hooks, ip, memarg);
             }
             I64Store { memarg } => {
-                mem_store!(i64 => i64, stack, store, memarg);
+                mem_store!(i64 => i64, stack, store, hooks, ip, memarg);
             }
             I64Store8 { memarg } => {
-                mem_store!(i64 => i8, stack, store, memarg);
+                mem_store!(i64 => i8, stack, store, hooks, ip, memarg);
             }
             I64Store16 { memarg } => {
-                mem_store!(i64 => i16, stack, store, memarg);
+                mem_store!(i64 => i16, stack, store, hooks, ip, memarg);
             }
             I64Store32 { memarg } => {
-                mem_store!(i64 => i32, stack, store, memarg);
+                mem_store!(i64 => i32, stack, store, hooks, ip, memarg);
             }
             F32Store { memarg } => {
-                mem_store!(f32 => f32, stack, store, memarg);
+                mem_store!(f32 => f32, stack, store, hooks, ip, memarg);
             }
             F64Store { memarg } => {
-                mem_store!(f64 => f64, stack, store, memarg);
+                mem_store!(f64 => f64, stack, store, hooks, ip, memarg);
             }
             MemoryInit { data_idx } => {
                 let n = unsafe { stack.pop_value().num.i32 } as u32 as usize;
@@ -1176,17 +1286,22 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
                 let d = unsafe { stack.pop_value().num.i32 } as u32 as usize;
                 let frame = stack.current_frame();
                 let winst = store.instances.resolve(frame.winst_id);
-                let mem = store.mems.resolve_mut(winst.addr_of(WasmMemIdx::ZERO));
-                let data = store.datas.resolve(winst.addr_of(*data_idx));
-                let data_len = data.data.map(|d| d.len()).unwrap_or(0);
-                if d + n > mem.data.len() {
+                let memaddr = winst.addr_of(WasmMemIdx::ZERO);
+                let data = store.datas.resolve(winst.addr_of(*data_idx)).data;
+                let data_len = data.map(|d| d.len()).unwrap_or(0);
+                if d + n > store.mems.resolve(memaddr).data.len() {
                     return Err(WasmTrap("out of bounds memory access"));
                 }
                 if s + n > data_len {
                     return Err(WasmTrap("out of bounds data access"));
                 }
                 if n > 0 {
-                    (&mut mem.data[d..(d + n)]).copy_from_slice(&data.data.unwrap()[s..(s + n)]);
+                    let src = &data.unwrap()[s..(s + n)];
+                    let new = Some(WasmWatchWrite::Bytes(src));
+                    check_watchpoints!(store, stack, hooks, ip, memaddr, d, n, new);
+                    hooks.on_mem_store(store, memaddr, d, src);
+                    let mem = store.mems.resolve_mut(memaddr);
+                    (&mut mem.data[d..(d + n)]).copy_from_slice(src);
                 }
             }
             MemorySize => {
@@ -1217,13 +1332,19 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
             MemoryFill => {
                 let frame = stack.current_frame();
                 let winst = store.instances.resolve(frame.winst_id);
-                let mem = store.mems.resolve_mut(winst.addr_of(WasmMemIdx::ZERO));
+                let memaddr = winst.addr_of(WasmMemIdx::ZERO);
                 let n = unsafe { stack.pop_value().num.i32 } as u32 as usize;
                 let val = unsafe { stack.pop_value().num.i32 };
                 let d = unsafe { stack.pop_value().num.i32 } as u32 as usize;
-                if d + n > mem.data.len() {
+                if d + n > store.mems.resolve(memaddr).data.len() {
                     return Err(WasmTrap("out of bounds memory access"));
                 }
+                let new = Some(WasmWatchWrite::Fill(val as u8));
+                check_watchpoints!(store, stack, hooks, ip, memaddr, d, n, new);
+                if n > 0 {
+                    hooks.on_mem_store(store, memaddr, d, &vec![val as u8; n]);
+                }
+                let mem = store.mems.resolve_mut(memaddr);
                 for byte in &mut mem.data[d..(d + n)] {
                     *byte = val as u8
                 }
@@ -1231,13 +1352,23 @@ pub fn exec(stack: &mut WasmStack, store: &mut WasmStore, expr: &WasmExpr) -> Re
             MemoryCopy => {
                 let frame = stack.current_frame();
                 let winst = store.instances.resolve(frame.winst_id);
-                let mem = store.mems.resolve_mut(winst.addr_of(WasmMemIdx::ZERO));
+                let memaddr = winst.addr_of(WasmMemIdx::ZERO);
                 let n = unsafe { stack.pop_value().num.i32 } as u32 as usize;
                 let s = unsafe { stack.pop_value().num.i32 } as u32 as usize;
                 let d = unsafe { stack.pop_value().num.i32 } as u32 as usize;
-                if s.max(d) + n > mem.data.len() {
+                if s.max(d) + n > store.mems.resolve(memaddr).data.len() {
                     return Err(WasmTrap("out of bounds memory access"));
                 }
+                check_watchpoints!(store, stack, hooks, ip, memaddr, s, n, None);
+                let src = &store.mems.resolve(memaddr).data[s..(s + n)];
+                let new = Some(WasmWatchWrite::Bytes(src));
+                check_watchpoints!(store, stack, hooks, ip, memaddr, d, n, new);
+                if n > 0 {
+                    let src = &store.mems.resolve(memaddr).data[s..(s + n)];
+                    hooks.on_mem_load(store, memaddr, s, src);
+                    hooks.on_mem_store(store, memaddr, d, src);
+                }
+                let mem = store.mems.resolve_mut(memaddr);
                 unsafe {
                     std::ptr::copy(mem.data.as_ptr().add(s), mem.data.as_mut_ptr().add(d), n);
                 }
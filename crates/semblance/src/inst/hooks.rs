@@ -0,0 +1,224 @@
+use crate::{
+    inst::{
+        WasmFuncAddr, WasmGlobalAddr, WasmMemAddr, WasmStack, WasmStore, WasmTrap, WasmValue,
+        WasmWatchHit,
+    },
+    module::WasmInstruction,
+};
+
+/// Callbacks invoked by the interpreter as it executes guest code.
+///
+/// Every callback has an empty default implementation, so implementors only
+/// override the events they care about. Memory and global callbacks fire
+/// before the access takes effect, so the store still holds the old value.
+#[allow(unused_variables)]
+pub trait WasmExecHooks {
+    fn on_func_enter(&mut self, store: &WasmStore, stack: &WasmStack, funcaddr: WasmFuncAddr) {}
+
+    fn on_func_exit(&mut self, store: &WasmStore, stack: &WasmStack, funcaddr: WasmFuncAddr) {}
+
+    /// Called before each instruction executes. Returning a trap aborts
+    /// execution as if the instruction had trapped.
+    fn on_instr(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> Result<(), WasmTrap> {
+        Ok(())
+    }
+
+    /// Called with the bytes a load reads at `addr`, including the source
+    /// range of `memory.copy`.
+    fn on_mem_load(&mut self, store: &WasmStore, memaddr: WasmMemAddr, addr: usize, bytes: &[u8]) {}
+
+    /// Called with the bytes a store is about to write at `addr`, including
+    /// the destination range of `memory.init`, `memory.copy` and
+    /// `memory.fill`.
+    fn on_mem_store(&mut self, store: &WasmStore, memaddr: WasmMemAddr, addr: usize, bytes: &[u8]) {
+    }
+
+    /// Whether memory accesses are checked against the watchpoints of the
+    /// accessed memory. [`WasmNoHooks`] opts out, so watchpoints only fire
+    /// while hooks are installed.
+    fn watches_memory(&self) -> bool {
+        true
+    }
+
+    /// Called when an access overlaps a watchpoint of the accessed memory,
+    /// once per matching watchpoint. Returning a trap aborts execution.
+    fn on_watchpoint(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        hit: &WasmWatchHit,
+    ) -> Result<(), WasmTrap> {
+        Ok(())
+    }
+
+    fn on_global_get(&mut self, store: &WasmStore, globaladdr: WasmGlobalAddr, val: WasmValue) {}
+
+    fn on_global_set(&mut self, store: &WasmStore, globaladdr: WasmGlobalAddr, val: WasmValue) {}
+
+    fn on_trap(&mut self, store: &WasmStore, stack: &WasmStack, trap: &WasmTrap) {}
+}
+
+/// The hooks used when none are installed. Every callback is a no-op, so
+/// the interpreter loop monomorphized over this type carries no overhead.
+pub struct WasmNoHooks;
+
+impl WasmExecHooks for WasmNoHooks {
+    fn watches_memory(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        inst::WasmInvokeOptions,
+        module::{WasmModuleRaw, validate},
+    };
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+        instrs: usize,
+    }
+
+    impl WasmExecHooks for Recorder {
+        fn on_func_enter(&mut self, store: &WasmStore, _: &WasmStack, funcaddr: WasmFuncAddr) {
+            let name = store.resolve_func_name(funcaddr).unwrap();
+            self.events.push(format!("enter {}", name));
+        }
+
+        fn on_func_exit(&mut self, store: &WasmStore, _: &WasmStack, funcaddr: WasmFuncAddr) {
+            let name = store.resolve_func_name(funcaddr).unwrap();
+            self.events.push(format!("exit {}", name));
+        }
+
+        fn on_instr(
+            &mut self,
+            _: &WasmStore,
+            _: &WasmStack,
+            _: &WasmInstruction,
+        ) -> Result<(), WasmTrap> {
+            self.instrs += 1;
+            Ok(())
+        }
+
+        fn on_mem_load(&mut self, _: &WasmStore, _: WasmMemAddr, addr: usize, bytes: &[u8]) {
+            self.events.push(format!("load {} {:?}", addr, bytes));
+        }
+
+        fn on_mem_store(&mut self, _: &WasmStore, _: WasmMemAddr, addr: usize, bytes: &[u8]) {
+            self.events.push(format!("store {} {:?}", addr, bytes));
+        }
+
+        fn on_trap(&mut self, _: &WasmStore, _: &WasmStack, trap: &WasmTrap) {
+            self.events.push(format!("trap {}", trap.0));
+        }
+    }
+
+    #[test]
+    fn test_hook_events() {
+        let text = r#"(module
+            (func $main (export "main") (param i32) (result i32)
+                local.get 0
+                call $double)
+            (func $double (param i32) (result i32)
+                local.get 0
+                i32.const 2
+                call $mul
+                return)
+            (func $mul (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.mul)
+            (func $fail (export "fail")
+                call $crash)
+            (func $crash unreachable))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let winst = store.instances.resolve(winst_id);
+        let main = winst.resolve_export_fn_by_name("main").unwrap();
+        let fail = winst.resolve_export_fn_by_name("fail").unwrap();
+
+        let mut recorder = Recorder::default();
+        let options = WasmInvokeOptions::default().with_hooks(&mut recorder);
+        let res = store.invoke(main, Box::new([21.into()]), options).unwrap();
+        assert_eq!(unsafe { res.res.0[0].num.i32 }, 42);
+        assert_eq!(
+            recorder.events,
+            [
+                "enter main",
+                "enter double",
+                "enter mul",
+                "exit mul",
+                "exit double",
+                "exit main"
+            ]
+        );
+        // `main` and `mul` run their `end`, `double` leaves through `return`.
+        assert_eq!(recorder.instrs, 3 + 4 + 4);
+
+        let mut recorder = Recorder::default();
+        let options = WasmInvokeOptions::default().with_hooks(&mut recorder);
+        let trap = store.invoke(fail, Box::new([]), options).err().unwrap();
+        assert_eq!(trap.0, "unreachable");
+        assert_eq!(
+            recorder.events,
+            ["enter fail", "enter crash", "trap unreachable"]
+        );
+    }
+
+    #[test]
+    fn test_bulk_mem_hooks() {
+        let text = r#"(module
+            (memory 1)
+            (data $init "\01\02")
+            (func $main (export "main")
+                i32.const 4
+                i32.const 0
+                i32.const 2
+                memory.init $init
+                i32.const 8
+                i32.const 7
+                i32.const 3
+                memory.fill
+                i32.const 5
+                i32.const 4
+                i32.const 2
+                memory.copy
+                i32.const 0
+                i32.const 0
+                i32.const 0
+                memory.fill))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let main = store
+            .instances
+            .resolve(winst_id)
+            .resolve_export_fn_by_name("main")
+            .unwrap();
+
+        let mut recorder = Recorder::default();
+        let options = WasmInvokeOptions::default().with_hooks(&mut recorder);
+        store.invoke(main, Box::new([]), options).unwrap();
+        // Empty ranges touch no memory, so they are not reported.
+        assert_eq!(
+            recorder.events[1..recorder.events.len() - 1],
+            [
+                "store 4 [1, 2]",
+                "store 8 [7, 7, 7]",
+                "load 4 [1, 2]",
+                "store 5 [1, 2]"
+            ]
+        );
+    }
+}
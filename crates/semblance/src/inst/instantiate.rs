@@ -71,8 +71,9 @@ fn exec_with_auxiliary_frame(
     stack.push_frame(WasmFrame {
         locals: Box::new([]),
         winst_id,
+        funcaddr: WasmFuncAddr::NULL,
     })?;
-    exec(stack, store, expr)
+    store.with_hooks(None, |store, hooks| exec(stack, store, expr, hooks))
 }
 
 impl WasmStore {
@@ -291,6 +292,8 @@ impl WasmStore {
         self.mems.add(WasmMemInst {
             type_: ModuleRef(mem),
             data: vec![0; mem.limits.min as usize * WasmMemInst::PAGE_SIZE],
+            watchpoints: Vec::new(),
+            next_watchpoint_id: 1,
         })
     }
 
@@ -432,7 +435,11 @@ fn match_tabletype(
     }
 }
 
-fn match_limits(externlimits: &WasmLimits, limits: &WasmLimits, actual_size: usize) -> bool {
+pub(crate) fn match_limits(
+    externlimits: &WasmLimits,
+    limits: &WasmLimits,
+    actual_size: usize,
+) -> bool {
     let externmin = externlimits.min.max(actual_size as u32);
     if externmin >= limits.min {
         match limits.max {
@@ -1,23 +1,30 @@
 mod externval;
+mod hooks;
 mod hostfunc;
 mod idx;
 mod instance;
 mod instantiate;
 mod result;
+mod snapshot;
 mod stack;
 mod store;
 mod table;
 mod trap;
 mod val;
+mod watch;
 
 pub use externval::{WasmExternVal, WasmExternValKind};
+pub use hooks::{WasmExecHooks, WasmNoHooks};
 pub use hostfunc::*;
 pub use idx::WasmIdx;
 pub use instance::WasmModuleInst;
+pub(crate) use instantiate::match_limits;
 pub use instantiate::{WasmInstantiationError, WasmInstantiationResult};
 pub use result::{DynamicWasmResult, WasmResult};
+pub use snapshot::{WasmHostState, WasmSnapshot, WasmSnapshotError};
 pub use stack::{ControlStackEntry, WasmFrame, WasmLabel, WasmStack};
 pub use store::*;
 pub use table::*;
 pub use trap::*;
 pub use val::*;
+pub use watch::{WasmMemAccess, WasmWatchHit, WasmWatchKind, WasmWatchWrite, WasmWatchpoint};
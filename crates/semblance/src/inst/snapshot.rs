@@ -0,0 +1,661 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    inst::{
+        WasmExternAddr, WasmFuncAddr, WasmMemInst, WasmModuleInst, WasmRefValue, WasmStore,
+        WasmValue, store::ModuleRef, table::ToIdx,
+    },
+    module::{WasmLimits, WasmNumType, WasmRefType, WasmValueType},
+};
+
+const MAGIC: &[u8; 8] = b"SMBLSNAP";
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum WasmSnapshotError {
+    Io(io::Error),
+    MagicBytes,
+    UnsupportedVersion(u32),
+    UnexpectedEof,
+    /// The store does not have the instances, functions, memories, tables,
+    /// globals, element or data segments the snapshot was taken from.
+    StoreMismatch(&'static str),
+    MissingHostState(String),
+    InvalidHostState(String),
+    /// A function reference to an address the snapshot has no function at.
+    InvalidFuncRef(u32),
+}
+
+impl From<io::Error> for WasmSnapshotError {
+    fn from(value: io::Error) -> Self {
+        WasmSnapshotError::Io(value)
+    }
+}
+
+/// Host state that is saved and restored along with a store. Host functions
+/// that keep state outside of the store, like open files or a clock, should
+/// implement this so restoring a snapshot doesn't leave them out of sync
+/// with the guest.
+pub trait WasmHostState {
+    /// Identifies this state in a snapshot. It must be unique among the
+    /// states snapshotted together.
+    fn key(&self) -> &str;
+
+    fn save(&self) -> Vec<u8>;
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), WasmSnapshotError>;
+}
+
+/// The mutable runtime state of a store: memory contents, global values,
+/// table and element contents, and which data segments were dropped.
+///
+/// A snapshot holds no code, so it can only be restored into a store built
+/// the same way as the one it was taken from, i.e. with the same modules
+/// instantiated in the same order.
+pub struct WasmSnapshot {
+    instances: Vec<[u32; 6]>,
+    funcs: u32,
+    mems: Vec<Vec<u8>>,
+    globals: Vec<(WasmValueType, WasmValue)>,
+    tables: Vec<(WasmRefType, Vec<WasmRefValue>)>,
+    elems: Vec<(WasmRefType, Vec<WasmRefValue>)>,
+    datas_dropped: Vec<bool>,
+    host: Vec<(String, Vec<u8>)>,
+}
+
+impl std::fmt::Debug for WasmSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmSnapshot")
+            .field("instances", &self.instances.len())
+            .field("mems", &self.mems.len())
+            .field("globals", &self.globals.len())
+            .field("tables", &self.tables.len())
+            .field("elems", &self.elems.len())
+            .field("datas", &self.datas_dropped.len())
+            .field(
+                "host",
+                &self.host.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+fn instance_shape(winst: &WasmModuleInst) -> [u32; 6] {
+    [
+        winst.funcaddrs.len() as u32,
+        winst.tableaddrs.len() as u32,
+        winst.memaddrs.len() as u32,
+        winst.globaladdrs.len() as u32,
+        winst.elemaddrs.len() as u32,
+        winst.dataaddrs.len() as u32,
+    ]
+}
+
+impl WasmStore {
+    pub fn snapshot(&self, host: &[&dyn WasmHostState]) -> WasmSnapshot {
+        WasmSnapshot {
+            instances: self.instances.iter().map(instance_shape).collect(),
+            funcs: self.funcs.len() as u32,
+            mems: self.mems.iter().map(|mem| mem.data.clone()).collect(),
+            globals: self
+                .globals
+                .iter()
+                .map(|global| (global.type_.val_type, global.val))
+                .collect(),
+            tables: self
+                .tables
+                .iter()
+                .map(|table| (table.type_.ref_type, table.elems.clone()))
+                .collect(),
+            elems: self
+                .elems
+                .iter()
+                .map(|elem| (elem.type_, elem.elem.to_vec()))
+                .collect(),
+            datas_dropped: self.datas.iter().map(|data| data.data.is_none()).collect(),
+            host: host
+                .iter()
+                .map(|state| (state.key().to_string(), state.save()))
+                .collect(),
+        }
+    }
+
+    /// Restores a snapshot taken from a store with the same instances, along
+    /// with the given host states. Nothing is changed if the snapshot doesn't
+    /// fit the store or lacks a host state. Host states are restored before
+    /// the store, and if one fails, the ones already restored are put back
+    /// the way they were and the store is left untouched.
+    pub fn restore(
+        &mut self,
+        snapshot: &WasmSnapshot,
+        host: &mut [&mut dyn WasmHostState],
+    ) -> Result<(), WasmSnapshotError> {
+        use WasmSnapshotError::StoreMismatch;
+
+        if !self
+            .instances
+            .iter()
+            .map(instance_shape)
+            .eq(snapshot.instances.iter().copied())
+        {
+            return Err(StoreMismatch("instances"));
+        }
+        if self.funcs.len() != snapshot.funcs as usize {
+            return Err(StoreMismatch("funcs"));
+        }
+        // Memories and tables may have grown since, but only within the
+        // limits of their types.
+        let fits = |len: usize, limits: &WasmLimits, max: usize| {
+            len >= limits.min as usize && len <= limits.max.map_or(max, |max| max as usize)
+        };
+        if self.mems.len() != snapshot.mems.len()
+            || !self.mems.iter().zip(&snapshot.mems).all(|(mem, data)| {
+                data.len().is_multiple_of(WasmMemInst::PAGE_SIZE)
+                    && fits(
+                        data.len() / WasmMemInst::PAGE_SIZE,
+                        &mem.type_.limits,
+                        1 << 16,
+                    )
+            })
+        {
+            return Err(StoreMismatch("mems"));
+        }
+        if !self
+            .globals
+            .iter()
+            .map(|global| global.type_.val_type)
+            .eq(snapshot.globals.iter().map(|(ty, _)| *ty))
+        {
+            return Err(StoreMismatch("globals"));
+        }
+        if !self
+            .tables
+            .iter()
+            .map(|table| table.type_.ref_type)
+            .eq(snapshot.tables.iter().map(|(ty, _)| *ty))
+            || !self
+                .tables
+                .iter()
+                .zip(&snapshot.tables)
+                .all(|(table, (_, elems))| {
+                    fits(elems.len(), &table.type_.limits, u32::MAX as usize)
+                })
+        {
+            return Err(StoreMismatch("tables"));
+        }
+        if !self
+            .elems
+            .iter()
+            .map(|elem| elem.type_)
+            .eq(snapshot.elems.iter().map(|(ty, _)| *ty))
+        {
+            return Err(StoreMismatch("elems"));
+        }
+        if self.datas.len() != snapshot.datas_dropped.len() {
+            return Err(StoreMismatch("datas"));
+        }
+        let host_bytes = host
+            .iter()
+            .map(|state| {
+                snapshot
+                    .host
+                    .iter()
+                    .find(|(key, _)| key == state.key())
+                    .map(|(_, bytes)| bytes)
+                    .ok_or_else(|| WasmSnapshotError::MissingHostState(state.key().to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let saved = host.iter().map(|state| state.save()).collect::<Vec<_>>();
+        for i in 0..host.len() {
+            if let Err(e) = host[i].restore(host_bytes[i]) {
+                for (state, bytes) in host[..i].iter_mut().zip(&saved) {
+                    // The bytes come from the state's own `save`, which it
+                    // should always be able to restore. If it can't, the
+                    // error that started the rollback is still the one to
+                    // report.
+                    let _ = state.restore(bytes);
+                }
+                return Err(e);
+            }
+        }
+
+        for (mem, data) in self.mems.iter_mut().zip(&snapshot.mems) {
+            mem.data.clone_from(data);
+        }
+        for (global, (_, val)) in self.globals.iter_mut().zip(&snapshot.globals) {
+            global.val = *val;
+        }
+        for (table, (_, elems)) in self.tables.iter_mut().zip(&snapshot.tables) {
+            table.elems.clone_from(elems);
+        }
+        for (elem, (_, refs)) in self.elems.iter_mut().zip(&snapshot.elems) {
+            elem.elem = refs.clone().into_boxed_slice();
+        }
+        // Dropped segments lose their bytes, so undropping one takes them
+        // from the module that defines it.
+        for winst in self.instances.iter() {
+            for (data, dataaddr) in winst.wmod.datas.iter().zip(&winst.dataaddrs) {
+                self.datas.resolve_mut(*dataaddr).data =
+                    if snapshot.datas_dropped[dataaddr.to_idx()] {
+                        None
+                    } else {
+                        Some(ModuleRef(data.bytes.as_ref()))
+                    };
+            }
+        }
+        Ok(())
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+fn value_type_tag(ty: WasmValueType) -> u8 {
+    match ty {
+        WasmValueType::Num(WasmNumType::I32) => 0x7f,
+        WasmValueType::Num(WasmNumType::I64) => 0x7e,
+        WasmValueType::Num(WasmNumType::F32) => 0x7d,
+        WasmValueType::Num(WasmNumType::F64) => 0x7c,
+        WasmValueType::Vec(_) => 0x7b,
+        WasmValueType::Ref(WasmRefType::FuncRef) => 0x70,
+        WasmValueType::Ref(WasmRefType::ExternRef) => 0x6f,
+    }
+}
+
+fn ref_type_tag(ty: WasmRefType) -> u8 {
+    value_type_tag(WasmValueType::Ref(ty))
+}
+
+fn put_ref(out: &mut Vec<u8>, ty: WasmRefType, val: &WasmRefValue) {
+    let raw = unsafe {
+        match ty {
+            WasmRefType::FuncRef => val.func.to_raw(),
+            WasmRefType::ExternRef => val.extern_.0,
+        }
+    };
+    put_u32(out, raw);
+}
+
+fn put_value(out: &mut Vec<u8>, ty: WasmValueType, val: &WasmValue) {
+    unsafe {
+        match ty {
+            WasmValueType::Num(WasmNumType::I32 | WasmNumType::F32) => {
+                out.extend_from_slice(&val.num.i32.to_le_bytes())
+            }
+            WasmValueType::Num(WasmNumType::I64 | WasmNumType::F64) => {
+                out.extend_from_slice(&val.num.i64.to_le_bytes())
+            }
+            WasmValueType::Vec(_) => out.extend_from_slice(&val.vec.to_le_bytes()),
+            WasmValueType::Ref(ty) => put_ref(out, ty, &val.ref_),
+        }
+    }
+}
+
+struct SnapshotReader<'b> {
+    bytes: &'b [u8],
+    /// The number of functions in the store the snapshot was taken from,
+    /// which function references must point into.
+    funcs: u32,
+}
+
+impl<'b> SnapshotReader<'b> {
+    fn take(&mut self, n: usize) -> Result<&'b [u8], WasmSnapshotError> {
+        if self.bytes.len() < n {
+            return Err(WasmSnapshotError::UnexpectedEof);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], WasmSnapshotError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, WasmSnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, WasmSnapshotError> {
+        Ok(u32::from_le_bytes(self.take_array()?))
+    }
+
+    fn bytes(&mut self) -> Result<&'b [u8], WasmSnapshotError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads a count of items that each take at least `min_size` bytes,
+    /// so a corrupt count can't make us reserve absurd amounts of memory.
+    fn count(&mut self, min_size: usize) -> Result<usize, WasmSnapshotError> {
+        let n = self.u32()? as usize;
+        if n.saturating_mul(min_size) > self.bytes.len() {
+            return Err(WasmSnapshotError::UnexpectedEof);
+        }
+        Ok(n)
+    }
+
+    fn value_type(&mut self) -> Result<WasmValueType, WasmSnapshotError> {
+        use crate::module::WasmVecType;
+        Ok(match self.u8()? {
+            0x7f => WasmValueType::Num(WasmNumType::I32),
+            0x7e => WasmValueType::Num(WasmNumType::I64),
+            0x7d => WasmValueType::Num(WasmNumType::F32),
+            0x7c => WasmValueType::Num(WasmNumType::F64),
+            0x7b => WasmValueType::Vec(WasmVecType::V128),
+            0x70 => WasmValueType::Ref(WasmRefType::FuncRef),
+            0x6f => WasmValueType::Ref(WasmRefType::ExternRef),
+            _ => return Err(WasmSnapshotError::StoreMismatch("value type")),
+        })
+    }
+
+    fn ref_type(&mut self) -> Result<WasmRefType, WasmSnapshotError> {
+        match self.value_type()? {
+            WasmValueType::Ref(ty) => Ok(ty),
+            _ => Err(WasmSnapshotError::StoreMismatch("ref type")),
+        }
+    }
+
+    fn ref_value(&mut self, ty: WasmRefType) -> Result<WasmRefValue, WasmSnapshotError> {
+        let raw = self.u32()?;
+        Ok(match ty {
+            WasmRefType::FuncRef if raw > self.funcs => {
+                return Err(WasmSnapshotError::InvalidFuncRef(raw));
+            }
+            WasmRefType::FuncRef => WasmRefValue {
+                func: WasmFuncAddr::from_raw(raw),
+            },
+            WasmRefType::ExternRef => WasmRefValue {
+                extern_: WasmExternAddr(raw),
+            },
+        })
+    }
+
+    fn value(&mut self, ty: WasmValueType) -> Result<WasmValue, WasmSnapshotError> {
+        Ok(match ty {
+            WasmValueType::Num(WasmNumType::I32 | WasmNumType::F32) => {
+                i32::from_le_bytes(self.take_array()?).into()
+            }
+            WasmValueType::Num(WasmNumType::I64 | WasmNumType::F64) => {
+                i64::from_le_bytes(self.take_array()?).into()
+            }
+            WasmValueType::Vec(_) => WasmValue {
+                vec: i128::from_le_bytes(self.take_array()?),
+            },
+            WasmValueType::Ref(ty) => self.ref_value(ty)?.into(),
+        })
+    }
+
+    fn refs(&mut self) -> Result<(WasmRefType, Vec<WasmRefValue>), WasmSnapshotError> {
+        let ty = self.ref_type()?;
+        let n = self.count(4)?;
+        let refs = (0..n)
+            .map(|_| self.ref_value(ty))
+            .collect::<Result<_, _>>()?;
+        Ok((ty, refs))
+    }
+}
+
+impl WasmSnapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        put_u32(&mut out, VERSION);
+        put_u32(&mut out, self.instances.len() as u32);
+        for shape in &self.instances {
+            for n in shape {
+                put_u32(&mut out, *n);
+            }
+        }
+        put_u32(&mut out, self.funcs);
+        put_u32(&mut out, self.mems.len() as u32);
+        for mem in &self.mems {
+            put_bytes(&mut out, mem);
+        }
+        put_u32(&mut out, self.globals.len() as u32);
+        for (ty, val) in &self.globals {
+            out.push(value_type_tag(*ty));
+            put_value(&mut out, *ty, val);
+        }
+        for refs in [&self.tables, &self.elems] {
+            put_u32(&mut out, refs.len() as u32);
+            for (ty, refs) in refs {
+                out.push(ref_type_tag(*ty));
+                put_u32(&mut out, refs.len() as u32);
+                for val in refs {
+                    put_ref(&mut out, *ty, val);
+                }
+            }
+        }
+        put_u32(&mut out, self.datas_dropped.len() as u32);
+        for dropped in &self.datas_dropped {
+            out.push(*dropped as u8);
+        }
+        put_u32(&mut out, self.host.len() as u32);
+        for (key, bytes) in &self.host {
+            put_bytes(&mut out, key.as_bytes());
+            put_bytes(&mut out, bytes);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WasmSnapshotError> {
+        let mut r = SnapshotReader { bytes, funcs: 0 };
+        if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(WasmSnapshotError::MagicBytes);
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(WasmSnapshotError::UnsupportedVersion(version));
+        }
+        let n = r.count(24)?;
+        let mut instances = Vec::with_capacity(n);
+        for _ in 0..n {
+            let mut shape = [0; 6];
+            for n in shape.iter_mut() {
+                *n = r.u32()?;
+            }
+            instances.push(shape);
+        }
+        let funcs = r.u32()?;
+        r.funcs = funcs;
+        let n = r.count(4)?;
+        let mems = (0..n)
+            .map(|_| r.bytes().map(<[u8]>::to_vec))
+            .collect::<Result<_, _>>()?;
+        let n = r.count(5)?;
+        let globals = (0..n)
+            .map(|_| {
+                let ty = r.value_type()?;
+                Ok((ty, r.value(ty)?))
+            })
+            .collect::<Result<_, WasmSnapshotError>>()?;
+        let n = r.count(5)?;
+        let tables = (0..n).map(|_| r.refs()).collect::<Result<_, _>>()?;
+        let n = r.count(5)?;
+        let elems = (0..n).map(|_| r.refs()).collect::<Result<_, _>>()?;
+        let n = r.count(1)?;
+        let datas_dropped = (0..n)
+            .map(|_| r.u8().map(|b| b != 0))
+            .collect::<Result<_, _>>()?;
+        let n = r.count(8)?;
+        let host = (0..n)
+            .map(|_| {
+                let key = String::from_utf8_lossy(r.bytes()?).into_owned();
+                Ok((key, r.bytes()?.to_vec()))
+            })
+            .collect::<Result<_, WasmSnapshotError>>()?;
+        Ok(WasmSnapshot {
+            instances,
+            funcs,
+            mems,
+            globals,
+            tables,
+            elems,
+            datas_dropped,
+            host,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), WasmSnapshotError> {
+        let mut f = File::create(path)?;
+        f.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, WasmSnapshotError> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        WasmSnapshot::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        inst::WasmInvokeOptions,
+        module::{WasmGlobalIdx, WasmMemIdx, WasmModule, WasmModuleRaw, validate},
+    };
+
+    /// A module with one page of memory, a mutable i32 global initialized
+    /// to 5, and a function that stores 42 at address 0 and increments the
+    /// global.
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00, 0x03,
+        0x02, 0x01, 0x00, 0x05, 0x03, 0x01, 0x00, 0x01, 0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x05,
+        0x0b, 0x0a, 0x12, 0x01, 0x10, 0x00, 0x41, 0x00, 0x41, 0x2a, 0x36, 0x02, 0x00, 0x23, 0x00,
+        0x41, 0x01, 0x6a, 0x24, 0x00, 0x0b,
+    ];
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let wmod = Rc::new(WasmModule::from_bytes(MODULE).unwrap());
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(wmod, &[]).unwrap();
+        let winst = store.instances.resolve(winst_id);
+        let funcaddr = winst.funcaddrs[0];
+        let memaddr = winst.addr_of(WasmMemIdx::ZERO);
+        let globaladdr = winst.addr_of(WasmGlobalIdx(0));
+
+        let bytes = store.snapshot(&[]).to_bytes();
+        store
+            .invoke(funcaddr, Box::new([]), WasmInvokeOptions::default())
+            .unwrap();
+        assert_eq!(store.mems.resolve(memaddr).data[0], 42);
+        assert_eq!(unsafe { store.globals.resolve(globaladdr).val.num.i32 }, 6);
+
+        let snapshot = WasmSnapshot::from_bytes(&bytes).unwrap();
+        // The memory has no maximum, but needs whole pages and at least one.
+        for len in [3, 0, WasmMemInst::PAGE_SIZE + 1] {
+            let mut bad = WasmSnapshot::from_bytes(&bytes).unwrap();
+            bad.mems[0].resize(len, 0);
+            let res = store.restore(&bad, &mut []);
+            assert!(matches!(res, Err(WasmSnapshotError::StoreMismatch("mems"))));
+        }
+        store.restore(&snapshot, &mut []).unwrap();
+        assert_eq!(store.mems.resolve(memaddr).data[0], 0);
+        assert_eq!(unsafe { store.globals.resolve(globaladdr).val.num.i32 }, 5);
+    }
+
+    struct Counter {
+        key: &'static str,
+        val: u8,
+    }
+
+    impl WasmHostState for Counter {
+        fn key(&self) -> &str {
+            self.key
+        }
+
+        fn save(&self) -> Vec<u8> {
+            vec![self.val]
+        }
+
+        fn restore(&mut self, bytes: &[u8]) -> Result<(), WasmSnapshotError> {
+            let [val] = bytes else {
+                return Err(WasmSnapshotError::InvalidHostState(self.key.to_string()));
+            };
+            self.val = *val;
+            Ok(())
+        }
+    }
+
+    fn table_store() -> WasmStore {
+        let text = "(module (table 1 funcref) (func $f) (elem (i32.const 0) $f))";
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        store.instantiate(Rc::new(wmod), &[]).unwrap();
+        store
+    }
+
+    #[test]
+    fn test_snapshot_rejects_bad_bytes() {
+        let store = table_store();
+        let bytes = store.snapshot(&[]).to_bytes();
+        assert!(WasmSnapshot::from_bytes(&bytes).is_ok());
+        assert!(matches!(
+            WasmSnapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(WasmSnapshotError::UnexpectedEof)
+        ));
+
+        let mut snapshot = store.snapshot(&[]);
+        snapshot.tables[0].1[0] = WasmRefValue {
+            func: WasmFuncAddr::from_raw(2),
+        };
+        assert!(matches!(
+            WasmSnapshot::from_bytes(&snapshot.to_bytes()),
+            Err(WasmSnapshotError::InvalidFuncRef(2))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_restore_is_all_or_nothing() {
+        let mut store = table_store();
+        let mut a = Counter { key: "a", val: 1 };
+        let mut b = Counter { key: "b", val: 1 };
+        let mut snapshot = store.snapshot(&[&a, &b]);
+        a.val = 2;
+        b.val = 2;
+        store.tables.iter_mut().next().unwrap().elems[0] = WasmRefValue {
+            func: WasmFuncAddr::from_raw(0),
+        };
+
+        // `b` fails after `a` was restored, so `a` is put back.
+        snapshot.host[1].1.clear();
+        let res = store.restore(&snapshot, &mut [&mut a, &mut b]);
+        assert!(matches!(res, Err(WasmSnapshotError::InvalidHostState(_))));
+        assert_eq!((a.val, b.val), (2, 2));
+        let table = store.tables.iter().next().unwrap();
+        assert!(unsafe { table.elems[0].func }.is_null());
+
+        snapshot.host[1].1.push(1);
+
+        // A table outside its limits is caught before any host state changes.
+        let elems = std::mem::take(&mut snapshot.tables[0].1);
+        let res = store.restore(&snapshot, &mut [&mut a, &mut b]);
+        assert!(matches!(
+            res,
+            Err(WasmSnapshotError::StoreMismatch("tables"))
+        ));
+        assert_eq!((a.val, b.val), (2, 2));
+        snapshot.tables[0].1 = elems;
+
+        store.restore(&snapshot, &mut [&mut a, &mut b]).unwrap();
+        assert_eq!((a.val, b.val), (1, 1));
+        let table = store.tables.iter().next().unwrap();
+        assert!(!unsafe { table.elems[0].func }.is_null());
+    }
+}
@@ -1,5 +1,8 @@
 use crate::{
-    inst::{WasmTrap, WasmValue, table::WasmInstanceAddr},
+    inst::{
+        WasmTrap, WasmValue,
+        table::{WasmFuncAddr, WasmInstanceAddr},
+    },
     module::{WasmInstruction, WasmLabelIdx},
 };
 
@@ -21,6 +24,7 @@ pub struct WasmLabel {
 pub struct WasmFrame {
     pub locals: Box<[WasmValue]>,
     pub winst_id: WasmInstanceAddr,
+    pub funcaddr: WasmFuncAddr,
 }
 
 pub struct WasmValueStack(Vec<WasmValue>);
@@ -52,6 +56,10 @@ impl WasmStack {
         &mut self.value_stack
     }
 
+    pub fn values(&self) -> &[WasmValue] {
+        &self.value_stack.0
+    }
+
     pub fn push_value<V: Into<WasmValue>>(&mut self, val: V) {
         self.value_stack.push(val);
     }
@@ -128,6 +136,44 @@ impl WasmStack {
         panic!("no call frame");
     }
 
+    pub fn frames(&self) -> impl DoubleEndedIterator<Item = &WasmFrame> {
+        self.control_stack.iter().filter_map(|entry| match entry {
+            ControlStackEntry::Frame(frame) => Some(frame),
+            ControlStackEntry::Label(_) => None,
+        })
+    }
+
+    /// Pairs each frame, innermost first, with the instruction it is
+    /// executing. `ip` is the instruction the innermost frame is at; callers
+    /// are at the call recorded in the return label below their callee.
+    pub fn call_stack(
+        &self,
+        mut ip: *const WasmInstruction,
+    ) -> Vec<(&WasmFrame, *const WasmInstruction)> {
+        let mut call_stack = Vec::new();
+        let mut entries = self.control_stack.iter().rev().peekable();
+        while let Some(entry) = entries.next() {
+            if let ControlStackEntry::Frame(frame) = entry {
+                call_stack.push((frame, ip));
+                if let Some(ControlStackEntry::Label(label)) = entries.peek() {
+                    ip = label.instr.wrapping_sub(1);
+                }
+            }
+        }
+        call_stack
+    }
+
+    /// The call the innermost frame returns to, when it was called from
+    /// wasm.
+    pub fn return_instr(&self) -> Option<*const WasmInstruction> {
+        let mut entries = self.control_stack.iter().rev();
+        entries.find(|entry| matches!(entry, ControlStackEntry::Frame(_)))?;
+        match entries.next()? {
+            ControlStackEntry::Label(label) => Some(label.instr.wrapping_sub(1)),
+            ControlStackEntry::Frame(_) => None,
+        }
+    }
+
     pub fn current_frame(&self) -> &WasmFrame {
         for entry in self.control_stack.iter().rev() {
             if let ControlStackEntry::Frame(frame) = entry {
@@ -1,21 +1,28 @@
-use std::ops::Deref;
+use std::{
+    ops::{Deref, Range},
+    rc::{Rc, Weak},
+};
 
 use crate::{
     exec::exec,
     inst::{
-        DynamicWasmResult, WasmFrame, WasmFuncAddr, WasmGlobalAddr, WasmInstanceAddr, WasmLabel,
-        WasmMemAddr, WasmModuleInst, WasmRefValue, WasmResult, WasmStack, WasmTableAddr, WasmTrap,
-        WasmValue, hostfunc::WasmHostFunc,
+        DynamicWasmResult, WasmBacktrace, WasmExecHooks, WasmFrame, WasmFuncAddr, WasmGlobalAddr,
+        WasmInstanceAddr, WasmLabel, WasmMemAddr, WasmModuleInst, WasmRefValue, WasmResult,
+        WasmStack, WasmTableAddr, WasmTrap, WasmValue, WasmWatchKind, WasmWatchpoint,
+        hostfunc::WasmHostFunc,
     },
     module::{
-        WasmFunc, WasmFuncType, WasmGlobalType, WasmInstruction, WasmMemType, WasmRefType,
-        WasmTableType,
+        WasmFunc, WasmFuncIdx, WasmFuncType, WasmGlobalType, WasmImportDesc, WasmInstruction,
+        WasmInstructionIdx, WasmMemType, WasmRefType, WasmSourceLocation, WasmTableType,
     },
 };
 
 use super::table::StoreTable;
 
 pub struct WasmStore {
+    /// Outlives every handle given out for the store, so a dead handle
+    /// means the store was dropped.
+    token: Rc<()>,
     pub instances: StoreTable<WasmModuleInst>,
     pub funcs: StoreTable<WasmFuncInst>,
     pub tables: StoreTable<WasmTableInst>,
@@ -23,11 +30,14 @@ pub struct WasmStore {
     pub globals: StoreTable<WasmGlobalInst>,
     pub elems: StoreTable<WasmElemInst>,
     pub datas: StoreTable<WasmDataInst>,
+    hooks: Option<Box<dyn WasmExecHooks>>,
+    pub(crate) trap_backtrace: Option<WasmBacktrace>,
 }
 
 impl WasmStore {
     pub fn new() -> Self {
         WasmStore {
+            token: Rc::new(()),
             instances: StoreTable::new(),
             funcs: StoreTable::new(),
             tables: StoreTable::new(),
@@ -35,15 +45,51 @@ impl WasmStore {
             globals: StoreTable::new(),
             elems: StoreTable::new(),
             datas: StoreTable::new(),
+            hooks: None,
+            trap_backtrace: None,
         }
     }
 
+    /// A handle identifying this store, which stops upgrading once the
+    /// store is dropped.
+    pub(crate) fn handle(&self) -> Weak<()> {
+        Rc::downgrade(&self.token)
+    }
+
+    pub fn set_hooks(&mut self, hooks: Box<dyn WasmExecHooks>) {
+        self.hooks = Some(hooks);
+    }
+
+    pub fn take_hooks(&mut self) -> Option<Box<dyn WasmExecHooks>> {
+        self.hooks.take()
+    }
+
+    pub(crate) fn with_hooks<R>(
+        &mut self,
+        hooks: Option<&mut dyn WasmExecHooks>,
+        f: impl FnOnce(&mut WasmStore, Option<&mut dyn WasmExecHooks>) -> R,
+    ) -> R {
+        if hooks.is_some() || self.hooks.is_none() {
+            return f(self, hooks);
+        }
+        let mut installed = self.hooks.take().unwrap();
+        let res = f(self, Some(installed.as_mut()));
+        self.hooks = Some(installed);
+        res
+    }
+
+    /// The call stack at the point of the most recent trap.
+    pub fn trap_backtrace(&self) -> Option<&WasmBacktrace> {
+        self.trap_backtrace.as_ref()
+    }
+
     pub fn invoke(
         &mut self,
         funcaddr: WasmFuncAddr,
         args: Box<[WasmValue]>,
         opts: WasmInvokeOptions,
     ) -> Result<DynamicWasmResult, WasmTrap> {
+        self.validate_lazily(funcaddr)?;
         let func = self.funcs.resolve(funcaddr);
         let ty = func.type_;
         match func.impl_ {
@@ -57,11 +103,17 @@ impl WasmStore {
                 stack.push_frame(WasmFrame {
                     locals: locals.into_boxed_slice(),
                     winst_id,
+                    funcaddr,
                 })?;
                 stack.push_label(WasmLabel {
                     instr: func.body.last().expect("func body has no end instr"),
                 })?;
-                exec(&mut stack, self, &func.body)?;
+                self.with_hooks(opts.hooks, |store, mut hooks| {
+                    if let Some(hooks) = hooks.as_deref_mut() {
+                        hooks.on_func_enter(store, &stack, funcaddr);
+                    }
+                    exec(&mut stack, store, &func.body, hooks)
+                })?;
                 let mut out = Vec::with_capacity(ty.output_type.0.len());
                 for _ in 0..ty.output_type.0.len() {
                     out.push(stack.pop_value());
@@ -76,6 +128,78 @@ impl WasmStore {
         }
     }
 
+    /// Validates the body of a function from a lazily validated module
+    /// before it first runs.
+    pub(crate) fn validate_lazily(&mut self, funcaddr: WasmFuncAddr) -> Result<(), WasmTrap> {
+        let WasmFuncImpl::Wasm { winst_id, func } = self.funcs.resolve(funcaddr).impl_ else {
+            return Ok(());
+        };
+        let wmod = &self.instances.resolve(winst_id).wmod;
+        let Some(i) = wmod.deferred_func_index(func.0) else {
+            return Ok(());
+        };
+        let validated = wmod.validated_func(i).ok_or(WasmTrap("invalid function"))?;
+        let func = ModuleRef(validated as *const WasmFunc);
+        self.funcs.resolve_mut(funcaddr).impl_ = WasmFuncImpl::Wasm { winst_id, func };
+        Ok(())
+    }
+
+    /// The body of a wasm function. A body whose validation was deferred is
+    /// validated now, and is `None` if invalid.
+    pub fn resolve_func_body(&self, funcaddr: WasmFuncAddr) -> Option<&WasmFunc> {
+        let WasmFuncImpl::Wasm { winst_id, func } = self.funcs.try_resolve(funcaddr)?.impl_ else {
+            return None;
+        };
+        let wmod = &self.instances.resolve(winst_id).wmod;
+        match wmod.deferred_func_index(func.0) {
+            Some(i) => wmod.validated_func(i),
+            None => Some(unsafe { &*func.0 }),
+        }
+    }
+
+    pub fn resolve_func_idx(&self, funcaddr: WasmFuncAddr) -> Option<WasmFuncIdx> {
+        if funcaddr.is_null() {
+            return None;
+        }
+        match self.funcs.try_resolve(funcaddr)?.impl_ {
+            WasmFuncImpl::Wasm { winst_id, func } => {
+                let wmod = &self.instances.resolve(winst_id).wmod;
+                let imported = wmod
+                    .imports
+                    .iter()
+                    .filter(|import| matches!(import.desc, WasmImportDesc::Func(_)))
+                    .count();
+                let i = wmod.defined_func_index(func.0);
+                Some(WasmFuncIdx((imported + i) as u32))
+            }
+            WasmFuncImpl::Host { hostfunc: _ } => None,
+        }
+    }
+
+    pub fn resolve_func_name(&self, funcaddr: WasmFuncAddr) -> Option<&str> {
+        let func_idx = self.resolve_func_idx(funcaddr)?;
+        match self.funcs.resolve(funcaddr).impl_ {
+            WasmFuncImpl::Wasm { winst_id, func: _ } => {
+                self.instances.resolve(winst_id).wmod.func_name(func_idx)
+            }
+            WasmFuncImpl::Host { hostfunc: _ } => None,
+        }
+    }
+
+    pub fn resolve_source_location(
+        &self,
+        funcaddr: WasmFuncAddr,
+        instr_idx: WasmInstructionIdx,
+    ) -> Option<WasmSourceLocation> {
+        let WasmFuncImpl::Wasm { winst_id, func: _ } = self.funcs.try_resolve(funcaddr)?.impl_
+        else {
+            return None;
+        };
+        let func = self.resolve_func_body(funcaddr)?;
+        let offset = *func.offsets.get(instr_idx.0 as usize)?;
+        self.instances.resolve(winst_id).wmod.lines.lookup(offset)
+    }
+
     pub fn alloc_hostfunc(
         &mut self,
         type_: &'static WasmFuncType,
@@ -113,6 +237,8 @@ impl WasmStore {
         self.mems.add(WasmMemInst {
             type_: ModuleRef(ty),
             data: bytes,
+            watchpoints: Vec::new(),
+            next_watchpoint_id: 1,
         })
     }
 }
@@ -135,19 +261,36 @@ impl<T: ?Sized> Clone for ModuleRef<T> {
 
 impl<T: ?Sized> Copy for ModuleRef<T> {}
 
-#[derive(Debug)]
-pub struct WasmInvokeOptions {
+pub struct WasmInvokeOptions<'h> {
     max_control_stack_depth: usize,
+    hooks: Option<&'h mut dyn WasmExecHooks>,
 }
 
-impl Default for WasmInvokeOptions {
+impl<'h> WasmInvokeOptions<'h> {
+    pub fn with_hooks(mut self, hooks: &'h mut dyn WasmExecHooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+}
+
+impl Default for WasmInvokeOptions<'_> {
     fn default() -> Self {
         Self {
             max_control_stack_depth: 1024,
+            hooks: None,
         }
     }
 }
 
+impl std::fmt::Debug for WasmInvokeOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmInvokeOptions")
+            .field("max_control_stack_depth", &self.max_control_stack_depth)
+            .field("hooks", &self.hooks.is_some())
+            .finish()
+    }
+}
+
 pub struct WasmDataInst {
     pub data: Option<ModuleRef<[u8]>>,
 }
@@ -165,10 +308,35 @@ pub struct WasmGlobalInst {
 pub struct WasmMemInst {
     pub type_: ModuleRef<WasmMemType>,
     pub data: Vec<u8>,
+    pub(crate) watchpoints: Vec<WasmWatchpoint>,
+    /// Ids are never reused, so that a removed watchpoint's id cannot
+    /// refer to a later one.
+    pub(crate) next_watchpoint_id: usize,
 }
 
 impl WasmMemInst {
     pub const PAGE_SIZE: usize = 65536;
+
+    /// Watches a byte range and returns the watchpoint's id. Accesses
+    /// matching `kind` are reported to `WasmExecHooks::on_watchpoint`.
+    /// Watchpoints are only checked while hooks are installed; code run
+    /// without hooks ignores them.
+    pub fn add_watchpoint(&mut self, range: Range<usize>, kind: WasmWatchKind) -> usize {
+        let id = self.next_watchpoint_id;
+        self.next_watchpoint_id += 1;
+        self.watchpoints.push(WasmWatchpoint { id, range, kind });
+        id
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|wp| wp.id != id);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[WasmWatchpoint] {
+        &self.watchpoints
+    }
 }
 
 pub struct WasmTableInst {
@@ -181,6 +349,23 @@ pub struct WasmFuncInst {
     pub impl_: WasmFuncImpl,
 }
 
+impl WasmFuncInst {
+    pub fn instr_idx(&self, ptr: *const WasmInstruction) -> Option<WasmInstructionIdx> {
+        match self.impl_ {
+            WasmFuncImpl::Wasm { winst_id: _, func } => {
+                let body = func.body.as_ptr_range();
+                if body.contains(&ptr) {
+                    let i = (ptr.addr() - body.start.addr()) / size_of::<WasmInstruction>();
+                    Some(WasmInstructionIdx(i as u32))
+                } else {
+                    None
+                }
+            }
+            WasmFuncImpl::Host { hostfunc: _ } => None,
+        }
+    }
+}
+
 pub enum WasmFuncImpl {
     Wasm {
         winst_id: WasmInstanceAddr,
@@ -3,19 +3,19 @@ use super::{
     WasmTableInst,
 };
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct WasmInstanceAddr(u32);
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct WasmFuncAddr(u32);
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct WasmTableAddr(u32);
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct WasmMemAddr(u32);
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct WasmGlobalAddr(u32);
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct WasmElemAddr(u32);
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct WasmDataAddr(u32);
 
 impl std::fmt::Display for WasmFuncAddr {
@@ -32,6 +32,14 @@ impl WasmFuncAddr {
     pub fn is_null(self) -> bool {
         self.0 == 0
     }
+
+    pub(crate) fn to_raw(self) -> u32 {
+        self.0
+    }
+
+    pub(crate) fn from_raw(raw: u32) -> Self {
+        WasmFuncAddr(raw)
+    }
 }
 
 pub trait ToIdx {
@@ -193,6 +201,22 @@ impl<T: Addressable> StoreTable<T> {
         T::Addr::from_idx(idx)
     }
 
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.items.iter_mut()
+    }
+
     pub fn resolve(&self, addr: T::Addr) -> &T {
         &self.items[addr.to_idx()]
     }
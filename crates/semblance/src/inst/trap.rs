@@ -1,2 +1,72 @@
+use crate::{
+    inst::{WasmFuncAddr, WasmStack, WasmStore},
+    module::{WasmFuncIdx, WasmInstruction, WasmInstructionIdx, WasmSourceLocation},
+};
+
 #[derive(Debug)]
 pub struct WasmTrap(pub &'static str);
+
+/// The wasm call stack captured when a trap was raised, innermost frame first.
+#[derive(Debug, Clone)]
+pub struct WasmBacktrace(pub Box<[WasmBacktraceFrame]>);
+
+#[derive(Debug, Clone)]
+pub struct WasmBacktraceFrame {
+    pub funcaddr: WasmFuncAddr,
+    pub func_idx: Option<WasmFuncIdx>,
+    pub func_name: Option<String>,
+    pub instr_idx: Option<WasmInstructionIdx>,
+    pub location: Option<WasmSourceLocation>,
+}
+
+impl WasmBacktrace {
+    pub fn capture(store: &WasmStore, stack: &WasmStack, ip: *const WasmInstruction) -> Self {
+        let frames = stack
+            .call_stack(ip)
+            .into_iter()
+            .filter(|(frame, _)| !frame.funcaddr.is_null())
+            .map(|(frame, ip)| {
+                let instr_idx = store.funcs.resolve(frame.funcaddr).instr_idx(ip);
+                WasmBacktraceFrame {
+                    funcaddr: frame.funcaddr,
+                    func_idx: store.resolve_func_idx(frame.funcaddr),
+                    func_name: store.resolve_func_name(frame.funcaddr).map(str::to_string),
+                    instr_idx,
+                    location: instr_idx
+                        .and_then(|idx| store.resolve_source_location(frame.funcaddr, idx)),
+                }
+            })
+            .collect();
+        WasmBacktrace(frames)
+    }
+
+    pub fn frames(&self) -> &[WasmBacktraceFrame] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for WasmBacktraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.func_name, self.func_idx) {
+            (Some(name), _) => write!(f, "{}", name)?,
+            (None, Some(func_idx)) => write!(f, "func[{}]", func_idx.0)?,
+            (None, None) => write!(f, "<unknown>")?,
+        }
+        if let Some(instr_idx) = self.instr_idx {
+            write!(f, "+{}", instr_idx.0)?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for WasmBacktrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, frame) in self.0.iter().enumerate() {
+            writeln!(f, "{:>4}: {}", i, frame)?;
+        }
+        Ok(())
+    }
+}
@@ -11,6 +11,21 @@ pub union WasmValue {
 }
 
 impl WasmValue {
+    /// Formats the value as a value of type `ty`.
+    pub fn format(&self, ty: &WasmValueType) -> String {
+        unsafe {
+            match ty {
+                WasmValueType::Num(WasmNumType::I32) => self.num.i32.to_string(),
+                WasmValueType::Num(WasmNumType::I64) => self.num.i64.to_string(),
+                WasmValueType::Num(WasmNumType::F32) => self.num.f32.to_string(),
+                WasmValueType::Num(WasmNumType::F64) => self.num.f64.to_string(),
+                WasmValueType::Vec(_) => format!("{:#034x}", self.vec),
+                WasmValueType::Ref(WasmRefType::FuncRef) => self.ref_.func.to_string(),
+                WasmValueType::Ref(WasmRefType::ExternRef) => self.ref_.extern_.0.to_string(),
+            }
+        }
+    }
+
     pub fn default_of_type(value_type: &WasmValueType) -> Self {
         use WasmValueType::*;
         match value_type {
@@ -0,0 +1,179 @@
+use std::ops::Range;
+
+use crate::{inst::WasmMemAddr, module::WasmInstruction};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WasmMemAccess {
+    Read,
+    Write,
+}
+
+/// Which accesses to a watched range fire the watchpoint.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WasmWatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WasmWatchKind {
+    pub fn matches(self, access: WasmMemAccess) -> bool {
+        match self {
+            WasmWatchKind::Read => access == WasmMemAccess::Read,
+            WasmWatchKind::Write => access == WasmMemAccess::Write,
+            WasmWatchKind::ReadWrite => true,
+        }
+    }
+}
+
+/// A byte range of a memory instance that reports accesses to the
+/// execution hooks. Nothing checks it while code runs without hooks, so a
+/// watchpoint never fires on a store invoked without any.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WasmWatchpoint {
+    pub id: usize,
+    pub range: Range<usize>,
+    pub kind: WasmWatchKind,
+}
+
+impl WasmWatchpoint {
+    pub fn overlaps(&self, addr: usize, len: usize) -> bool {
+        addr < self.range.end && self.range.start < addr.saturating_add(len)
+    }
+}
+
+/// What a write to a watched range stores.
+#[derive(Debug, Copy, Clone)]
+pub enum WasmWatchWrite<'a> {
+    Bytes(&'a [u8]),
+    /// Every byte of the range is set to the value, as by `memory.fill`.
+    Fill(u8),
+}
+
+/// An access that overlapped a watchpoint. It is reported before it takes
+/// effect, so `old` is what the accessed bytes hold at that point.
+#[derive(Debug)]
+pub struct WasmWatchHit<'a> {
+    pub memaddr: WasmMemAddr,
+    pub watchpoint: &'a WasmWatchpoint,
+    pub access: WasmMemAccess,
+    pub addr: usize,
+    pub old: &'a [u8],
+    /// What is being written to the `old.len()` bytes at `addr`, `None`
+    /// for reads.
+    pub new: Option<WasmWatchWrite<'a>>,
+    pub instr: &'a WasmInstruction,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        inst::{WasmExecHooks, WasmInvokeOptions, WasmStack, WasmStore, WasmTrap},
+        module::{WasmModuleRaw, validate},
+    };
+
+    #[derive(Default)]
+    struct Recorder {
+        hits: Vec<String>,
+        trap: bool,
+    }
+
+    impl WasmExecHooks for Recorder {
+        fn on_watchpoint(
+            &mut self,
+            _: &WasmStore,
+            _: &WasmStack,
+            hit: &WasmWatchHit,
+        ) -> Result<(), WasmTrap> {
+            let new = match hit.new {
+                Some(WasmWatchWrite::Bytes(bytes)) => format!(" {:?}", bytes),
+                Some(WasmWatchWrite::Fill(byte)) => format!(" fill {:#x}", byte),
+                None => String::new(),
+            };
+            self.hits.push(format!(
+                "{} {:?} {}+{}{}",
+                hit.watchpoint.id,
+                hit.access,
+                hit.addr,
+                hit.old.len(),
+                new
+            ));
+            match self.trap {
+                true => Err(WasmTrap("watchpoint")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_watchpoint_hits() {
+        let text = r#"(module
+            (memory 1)
+            (func (export "run")
+                (drop (i32.load (i32.const 8)))
+                (i32.store16 (i32.const 10) (i32.const 7))
+                (memory.fill (i32.const 0) (i32.const 0xaa) (i32.const 16))
+                (memory.copy (i32.const 32) (i32.const 8) (i32.const 4))))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let winst = store.instances.resolve(winst_id);
+        let memaddr = winst.memaddrs[0];
+        let run = winst.resolve_export_fn_by_name("run").unwrap();
+        let mem = store.mems.resolve_mut(memaddr);
+        assert_eq!(mem.add_watchpoint(8..12, WasmWatchKind::ReadWrite), 1);
+        assert_eq!(mem.add_watchpoint(0..1, WasmWatchKind::Read), 2);
+        assert!(mem.remove_watchpoint(2));
+        // A removed watchpoint's id is not handed out again.
+        assert_eq!(mem.add_watchpoint(32..34, WasmWatchKind::Write), 3);
+
+        let mut recorder = Recorder::default();
+        let options = WasmInvokeOptions::default().with_hooks(&mut recorder);
+        store.invoke(run, Box::new([]), options).unwrap();
+        assert_eq!(
+            recorder.hits,
+            [
+                "1 Read 8+4",
+                "1 Write 10+2 [7, 0]",
+                "1 Write 0+16 fill 0xaa",
+                "1 Read 8+4",
+                "3 Write 32+4 [170, 170, 170, 170]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watchpoints_need_hooks() {
+        let text = r#"(module
+            (memory 1)
+            (func (export "run") (i32.store (i32.const 0) (i32.const 1))))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let winst = store.instances.resolve(winst_id);
+        let memaddr = winst.memaddrs[0];
+        let run = winst.resolve_export_fn_by_name("run").unwrap();
+        store
+            .mems
+            .resolve_mut(memaddr)
+            .add_watchpoint(0..4, WasmWatchKind::Write);
+
+        // Without hooks the interpreter skips the checks, so the store goes
+        // ahead even though the watchpoint would trap.
+        store
+            .invoke(run, Box::new([]), WasmInvokeOptions::default())
+            .unwrap();
+        assert_eq!(store.mems.resolve(memaddr).data[0], 1);
+
+        let mut recorder = Recorder {
+            trap: true,
+            ..Default::default()
+        };
+        let options = WasmInvokeOptions::default().with_hooks(&mut recorder);
+        assert!(store.invoke(run, Box::new([]), options).is_err());
+        assert_eq!(recorder.hits, ["1 Write 0+4 [1, 0, 0, 0]"]);
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    sync::Arc,
+};
+
+use crate::{
+    inst::{
+        WasmExecHooks, WasmFuncAddr, WasmFuncImpl, WasmInstanceAddr, WasmStack, WasmStore, WasmTrap,
+    },
+    instrument::names::func_label,
+    module::{WasmFuncIdx, WasmInstruction},
+};
+
+/// Records which instructions of each wasm function were executed.
+#[derive(Default)]
+pub struct WasmCoverage {
+    hits: HashMap<WasmFuncAddr, Box<[bool]>>,
+}
+
+#[derive(Debug)]
+pub struct WasmFuncCoverage {
+    pub funcaddr: WasmFuncAddr,
+    pub func_idx: WasmFuncIdx,
+    pub name: Option<String>,
+    pub instrs_covered: usize,
+    pub instrs_total: usize,
+    pub blocks_covered: usize,
+    pub blocks_total: usize,
+}
+
+impl WasmCoverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_covered(&self, funcaddr: WasmFuncAddr, instr_idx: usize) -> bool {
+        self.hits
+            .get(&funcaddr)
+            .is_some_and(|hits| hits.get(instr_idx).copied().unwrap_or(false))
+    }
+
+    /// Summarizes coverage for every function defined by the given instance,
+    /// including the ones that never ran.
+    pub fn report(&self, store: &WasmStore, winst_id: WasmInstanceAddr) -> Vec<WasmFuncCoverage> {
+        let mut report = Vec::new();
+        for funcaddr in store.instances.resolve(winst_id).funcaddrs.iter() {
+            let func = match store.funcs.resolve(*funcaddr).impl_ {
+                WasmFuncImpl::Wasm {
+                    winst_id: owner,
+                    func: _,
+                } if owner == winst_id => store.resolve_func_body(*funcaddr),
+                _ => continue,
+            };
+            // An invalid body whose validation was deferred has nothing to cover.
+            let Some(func) = func else {
+                continue;
+            };
+            let func_idx = store.resolve_func_idx(*funcaddr).unwrap();
+            let name = store.resolve_func_name(*funcaddr).map(str::to_string);
+            let hits = self.hits.get(funcaddr);
+            let hit = |i: usize| hits.is_some_and(|hits| hits[i]);
+            let leaders = block_leaders(&func.body);
+            report.push(WasmFuncCoverage {
+                funcaddr: *funcaddr,
+                func_idx,
+                name,
+                instrs_covered: (0..func.body.len()).filter(|i| hit(*i)).count(),
+                instrs_total: func.body.len(),
+                blocks_covered: leaders.iter().filter(|i| hit(**i)).count(),
+                blocks_total: leaders.len(),
+            });
+        }
+        report
+    }
+
+    pub fn write_report<W: Write>(
+        &self,
+        store: &WasmStore,
+        winst_id: WasmInstanceAddr,
+        mut out: W,
+    ) -> std::io::Result<()> {
+        let report = self.report(store, winst_id);
+        let (mut instrs_covered, mut instrs_total) = (0, 0);
+        let (mut blocks_covered, mut blocks_total) = (0, 0);
+        for func in &report {
+            let label = match &func.name {
+                Some(name) => format!("{}[{}]", name, func.func_idx.0),
+                None => format!("func[{}]", func.func_idx.0),
+            };
+            writeln!(
+                out,
+                "{}: {}/{} instructions ({:.1}%), {}/{} blocks ({:.1}%)",
+                label,
+                func.instrs_covered,
+                func.instrs_total,
+                percent(func.instrs_covered, func.instrs_total),
+                func.blocks_covered,
+                func.blocks_total,
+                percent(func.blocks_covered, func.blocks_total),
+            )?;
+            instrs_covered += func.instrs_covered;
+            instrs_total += func.instrs_total;
+            blocks_covered += func.blocks_covered;
+            blocks_total += func.blocks_total;
+        }
+        writeln!(
+            out,
+            "total: {}/{} instructions ({:.1}%), {}/{} blocks ({:.1}%)",
+            instrs_covered,
+            instrs_total,
+            percent(instrs_covered, instrs_total),
+            blocks_covered,
+            blocks_total,
+            percent(blocks_covered, blocks_total),
+        )?;
+        out.flush()
+    }
+}
+
+#[derive(Default)]
+struct LcovFile {
+    funcs: Vec<(u32, String, bool)>,
+    lines: BTreeMap<u32, bool>,
+}
+
+impl WasmCoverage {
+    /// Writes an lcov tracefile for the functions defined by the given
+    /// instance, using the module's DWARF line table to map instructions to
+    /// source lines. Instructions without line info are left out.
+    pub fn write_lcov<W: Write>(
+        &self,
+        store: &WasmStore,
+        winst_id: WasmInstanceAddr,
+        mut out: W,
+    ) -> std::io::Result<()> {
+        let lines = &store.instances.resolve(winst_id).wmod.lines;
+        let mut files: BTreeMap<Arc<str>, LcovFile> = BTreeMap::new();
+        for funcaddr in store.instances.resolve(winst_id).funcaddrs.iter() {
+            let func = match store.funcs.resolve(*funcaddr).impl_ {
+                WasmFuncImpl::Wasm {
+                    winst_id: owner,
+                    func: _,
+                } if owner == winst_id => store.resolve_func_body(*funcaddr),
+                _ => continue,
+            };
+            // An invalid body whose validation was deferred has nothing to cover.
+            let Some(func) = func else {
+                continue;
+            };
+            let mut func_entry = None;
+            for (i, offset) in func.offsets.iter().enumerate() {
+                let Some(location) = lines.lookup(*offset) else {
+                    continue;
+                };
+                let file = files.entry(location.file.clone()).or_default();
+                let hit = file.lines.entry(location.line).or_insert(false);
+                *hit |= self.is_covered(*funcaddr, i);
+                if func_entry.is_none() {
+                    func_entry = Some((location.file, location.line));
+                }
+            }
+            if let Some((file, line)) = func_entry {
+                let name = func_label(store, *funcaddr).unwrap();
+                let hit = self.is_covered(*funcaddr, 0);
+                files.get_mut(&file).unwrap().funcs.push((line, name, hit));
+            }
+        }
+        writeln!(out, "TN:")?;
+        for (path, file) in &files {
+            writeln!(out, "SF:{}", path)?;
+            for (line, name, _) in &file.funcs {
+                writeln!(out, "FN:{},{}", line, name)?;
+            }
+            for (_, name, hit) in &file.funcs {
+                writeln!(out, "FNDA:{},{}", *hit as u32, name)?;
+            }
+            writeln!(out, "FNF:{}", file.funcs.len())?;
+            writeln!(out, "FNH:{}", file.funcs.iter().filter(|f| f.2).count())?;
+            for (line, hit) in &file.lines {
+                writeln!(out, "DA:{},{}", line, *hit as u32)?;
+            }
+            writeln!(out, "LF:{}", file.lines.len())?;
+            writeln!(
+                out,
+                "LH:{}",
+                file.lines.values().filter(|hit| **hit).count()
+            )?;
+            writeln!(out, "end_of_record")?;
+        }
+        out.flush()
+    }
+}
+
+fn percent(covered: usize, total: usize) -> f64 {
+    if total == 0 {
+        100.0
+    } else {
+        covered as f64 * 100.0 / total as f64
+    }
+}
+
+/// Returns the index of the first instruction of each basic block. Every
+/// branch target is either the start of a loop body or the instruction
+/// following an `else` or `end`, so a block begins after any control
+/// instruction.
+fn block_leaders(body: &[WasmInstruction]) -> Vec<usize> {
+    let mut leaders = vec![0];
+    for (i, instr) in body.iter().enumerate() {
+        let ends_block = matches!(
+            instr,
+            WasmInstruction::Unreachable
+                | WasmInstruction::Block { .. }
+                | WasmInstruction::Loop { .. }
+                | WasmInstruction::If { .. }
+                | WasmInstruction::Else
+                | WasmInstruction::Break { .. }
+                | WasmInstruction::BreakIf { .. }
+                | WasmInstruction::BreakTable { .. }
+                | WasmInstruction::Return { .. }
+                | WasmInstruction::ExprEnd
+        );
+        if ends_block && i + 1 < body.len() {
+            leaders.push(i + 1);
+        }
+    }
+    leaders
+}
+
+impl WasmExecHooks for WasmCoverage {
+    fn on_instr(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> Result<(), WasmTrap> {
+        let funcaddr = stack.current_frame().funcaddr;
+        if funcaddr.is_null() {
+            return Ok(());
+        }
+        let func = store.funcs.resolve(funcaddr);
+        let Some(instr_idx) = func.instr_idx(instr) else {
+            return Ok(());
+        };
+        let hits = self
+            .hits
+            .entry(funcaddr)
+            .or_insert_with(|| match func.impl_ {
+                WasmFuncImpl::Wasm { winst_id: _, func } => vec![false; func.body.len()].into(),
+                WasmFuncImpl::Host { hostfunc: _ } => unreachable!("host funcs have no body"),
+            });
+        hits[instr_idx.0 as usize] = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        inst::WasmInvokeOptions,
+        module::{WasmModuleRaw, validate},
+    };
+
+    #[test]
+    fn test_coverage_totals() {
+        let text = r#"(module
+            (func $main (export "main") (param i32) (result i32)
+                local.get 0
+                if (result i32)
+                    i32.const 1
+                else
+                    i32.const 2
+                end)
+            (func $unused (result i32)
+                i32.const 0))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let main = store
+            .instances
+            .resolve(winst_id)
+            .resolve_export_fn_by_name("main")
+            .unwrap();
+        let mut coverage = WasmCoverage::new();
+        let options = WasmInvokeOptions::default().with_hooks(&mut coverage);
+        store.invoke(main, Box::new([1.into()]), options).unwrap();
+
+        let mut out = Vec::new();
+        coverage.write_report(&store, winst_id, &mut out).unwrap();
+        // The else branch and the end of the if it skips are not covered.
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main[0]: 5/7 instructions (71.4%), 3/4 blocks (75.0%)\n\
+             unused[1]: 0/2 instructions (0.0%), 0/1 blocks (0.0%)\n\
+             total: 5/9 instructions (55.6%), 3/5 blocks (60.0%)\n"
+        );
+    }
+}
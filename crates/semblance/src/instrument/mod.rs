@@ -0,0 +1,8 @@
+mod coverage;
+pub(crate) mod names;
+mod profile;
+pub(crate) mod trace;
+
+pub use coverage::{WasmCoverage, WasmFuncCoverage};
+pub use profile::{WasmProfileOptions, WasmProfiler};
+pub use trace::{WasmTraceOptions, WasmTracer};
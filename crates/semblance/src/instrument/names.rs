@@ -0,0 +1,19 @@
+use crate::inst::{WasmFuncAddr, WasmStore};
+
+/// Names a wasm function for reports, falling back to its index when the
+/// module has no name for it. Returns `None` for host functions.
+pub(crate) fn func_label(store: &WasmStore, funcaddr: WasmFuncAddr) -> Option<String> {
+    let func_idx = store.resolve_func_idx(funcaddr)?;
+    Some(match store.resolve_func_name(funcaddr) {
+        Some(name) => name.to_string(),
+        None => format!("func[{}]", func_idx.0),
+    })
+}
+
+/// Like `func_label`, but always includes the function index, as in
+/// `name[idx]`, so that duplicate or imported names stay distinguishable.
+pub(crate) fn func_label_indexed(store: &WasmStore, funcaddr: WasmFuncAddr) -> Option<String> {
+    let func_idx = store.resolve_func_idx(funcaddr)?;
+    let name = store.resolve_func_name(funcaddr).unwrap_or("func");
+    Some(format!("{}[{}]", name, func_idx.0))
+}
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    inst::{WasmExecHooks, WasmFuncAddr, WasmFuncImpl, WasmStack, WasmStore, WasmTrap},
+    instrument::names::func_label,
+    module::{WasmInstruction, WasmInstructionIdx},
+};
+
+#[derive(Debug)]
+pub struct WasmProfileOptions {
+    /// Number of executed instructions between samples. An interval of 1
+    /// attributes every instruction, giving exact instruction counts.
+    pub sample_interval: u64,
+    /// Attribute samples to source lines from the module's DWARF line
+    /// table instead of just to functions.
+    pub source_lines: bool,
+}
+
+impl Default for WasmProfileOptions {
+    fn default() -> Self {
+        WasmProfileOptions {
+            sample_interval: 1,
+            source_lines: false,
+        }
+    }
+}
+
+/// Index of a call stack interned by the profiler.
+type StackId = usize;
+
+/// A call stack, as the innermost function, the instruction it is at and
+/// the stack of its caller.
+type StackKey = (Option<StackId>, WasmFuncAddr, WasmInstructionIdx);
+
+/// A wasm frame being profiled. `stack` is the frame's stack with the
+/// instruction left out, which is all a sample needs without source lines.
+struct ProfiledFrame {
+    funcaddr: WasmFuncAddr,
+    caller: Option<StackId>,
+    stack: StackId,
+}
+
+/// Attributes executed instructions to the wasm call stack they ran under.
+pub struct WasmProfiler {
+    sample_interval: u64,
+    countdown: u64,
+    source_lines: bool,
+    frames: Vec<ProfiledFrame>,
+    stacks: Vec<StackKey>,
+    stack_ids: HashMap<StackKey, StackId>,
+    samples: Vec<u64>,
+}
+
+impl WasmProfiler {
+    pub fn new(opts: WasmProfileOptions) -> Self {
+        let sample_interval = opts.sample_interval.max(1);
+        WasmProfiler {
+            sample_interval,
+            countdown: sample_interval,
+            source_lines: opts.source_lines,
+            frames: Vec::new(),
+            stacks: Vec::new(),
+            stack_ids: HashMap::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    fn intern(&mut self, key: StackKey) -> StackId {
+        *self.stack_ids.entry(key).or_insert_with(|| {
+            self.stacks.push(key);
+            self.samples.push(0);
+            self.stacks.len() - 1
+        })
+    }
+
+    /// Writes the collected samples in the collapsed-stack format used by
+    /// flamegraph tools, one `outer;inner count` line per distinct stack.
+    pub fn write_collapsed<W: Write>(&self, store: &WasmStore, mut out: W) -> std::io::Result<()> {
+        let mut lines = HashMap::new();
+        for (stack, &count) in self.samples.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let mut labels = vec![];
+            let mut next = Some(stack);
+            while let Some(stack) = next {
+                let (caller, funcaddr, instr_idx) = self.stacks[stack];
+                let mut label =
+                    func_label(store, funcaddr).unwrap_or_else(|| "<unknown>".to_string());
+                if self.source_lines
+                    && let Some(location) = store.resolve_source_location(funcaddr, instr_idx)
+                {
+                    label = format!("{} ({}:{})", label, location.file, location.line);
+                }
+                labels.push(label.replace(';', "_"));
+                next = caller;
+            }
+            labels.reverse();
+            *lines.entry(labels.join(";")).or_insert(0) += count;
+        }
+        let mut lines = lines.into_iter().collect::<Vec<_>>();
+        lines.sort();
+        for (line, count) in lines {
+            writeln!(out, "{} {}", line, count)?;
+        }
+        out.flush()
+    }
+}
+
+impl WasmExecHooks for WasmProfiler {
+    fn on_func_enter(&mut self, store: &WasmStore, stack: &WasmStack, funcaddr: WasmFuncAddr) {
+        // Host functions run no instructions and get no frame.
+        if matches!(
+            store.funcs.resolve(funcaddr).impl_,
+            WasmFuncImpl::Host { .. }
+        ) {
+            return;
+        }
+        let caller = match self.frames.last() {
+            Some(frame) if self.source_lines => {
+                let instr_idx = stack
+                    .return_instr()
+                    .and_then(|ip| store.funcs.resolve(frame.funcaddr).instr_idx(ip))
+                    .unwrap_or(WasmInstructionIdx(0));
+                Some(self.intern((frame.caller, frame.funcaddr, instr_idx)))
+            }
+            Some(frame) => Some(frame.stack),
+            None => None,
+        };
+        let stack = self.intern((caller, funcaddr, WasmInstructionIdx(0)));
+        self.frames.push(ProfiledFrame {
+            funcaddr,
+            caller,
+            stack,
+        });
+    }
+
+    fn on_func_exit(&mut self, store: &WasmStore, _: &WasmStack, funcaddr: WasmFuncAddr) {
+        if !matches!(
+            store.funcs.resolve(funcaddr).impl_,
+            WasmFuncImpl::Host { .. }
+        ) {
+            self.frames.pop();
+        }
+    }
+
+    fn on_instr(
+        &mut self,
+        store: &WasmStore,
+        _: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> Result<(), WasmTrap> {
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return Ok(());
+        }
+        self.countdown = self.sample_interval;
+        let Some(frame) = self.frames.last() else {
+            return Ok(());
+        };
+        let stack = match self.source_lines {
+            true => {
+                let instr_idx = store
+                    .funcs
+                    .resolve(frame.funcaddr)
+                    .instr_idx(instr)
+                    .unwrap_or(WasmInstructionIdx(0));
+                self.intern((frame.caller, frame.funcaddr, instr_idx))
+            }
+            false => frame.stack,
+        };
+        self.samples[stack] += self.sample_interval;
+        Ok(())
+    }
+
+    fn on_trap(&mut self, _: &WasmStore, _: &WasmStack, _: &WasmTrap) {
+        // The trapped invocation's frames are gone without exits.
+        self.frames.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        inst::WasmInvokeOptions,
+        module::{WasmModuleRaw, validate},
+    };
+
+    fn profile(sample_interval: u64) -> String {
+        let text = r#"(module
+            (func $main (export "main") (result i32)
+                call $one
+                call $one
+                i32.add)
+            (func $one (result i32)
+                i32.const 1))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let main = store
+            .instances
+            .resolve(winst_id)
+            .resolve_export_fn_by_name("main")
+            .unwrap();
+        let mut profiler = WasmProfiler::new(WasmProfileOptions {
+            sample_interval,
+            source_lines: false,
+        });
+        for _ in 0..2 {
+            let options = WasmInvokeOptions::default().with_hooks(&mut profiler);
+            store.invoke(main, Box::new([]), options).unwrap();
+        }
+        let mut out = Vec::new();
+        profiler.write_collapsed(&store, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_profile_counts() {
+        assert_eq!(profile(1), "main 8\nmain;one 8\n");
+        assert_eq!(profile(2), "main 8\nmain;one 8\n");
+        assert_eq!(profile(3), "main 9\nmain;one 6\n");
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    rc::Rc,
+};
+
+use crate::{
+    inst::{WasmExecHooks, WasmFuncAddr, WasmFuncImpl, WasmStack, WasmStore, WasmTrap},
+    instrument::names::{func_label, func_label_indexed},
+    module::{MaybeUntyped, WasmInstruction},
+};
+
+const TRACE_STACK_DEPTH: usize = 3;
+
+#[derive(Debug, Default)]
+pub struct WasmTraceOptions {
+    pub funcs: Vec<String>,
+    pub max_lines: Option<usize>,
+}
+
+type OperandTypes = Rc<[Box<[MaybeUntyped]>]>;
+
+/// The types of at most `depth` operands before each instruction of the
+/// function at `funcaddr`, or `None` for host functions.
+pub(crate) fn func_operand_types(
+    store: &WasmStore,
+    funcaddr: WasmFuncAddr,
+    depth: usize,
+) -> Option<Box<[Box<[MaybeUntyped]>]>> {
+    let WasmFuncImpl::Wasm { winst_id, func } = store.funcs.resolve(funcaddr).impl_ else {
+        return None;
+    };
+    let wmod = &store.instances.resolve(winst_id).wmod;
+    wmod.operand_types(&func, depth)
+}
+
+/// What tracing the instructions of a frame needs, worked out when the
+/// frame is entered.
+struct TracedFrame {
+    label: String,
+    types: OperandTypes,
+}
+
+pub struct WasmTracer<W: Write> {
+    out: W,
+    funcs: HashSet<String>,
+    max_lines: Option<usize>,
+    lines: usize,
+    /// The call stack, with `None` for frames that aren't traced.
+    frames: Vec<Option<TracedFrame>>,
+    operand_types: HashMap<WasmFuncAddr, OperandTypes>,
+}
+
+impl<W: Write> WasmTracer<W> {
+    pub fn new(out: W, opts: WasmTraceOptions) -> Self {
+        WasmTracer {
+            out,
+            funcs: opts.funcs.into_iter().collect(),
+            max_lines: opts.max_lines,
+            lines: 0,
+            frames: Vec::new(),
+            operand_types: HashMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.max_lines.is_some_and(|max| self.lines >= max)
+    }
+
+    fn traced_frame(&mut self, store: &WasmStore, funcaddr: WasmFuncAddr) -> Option<TracedFrame> {
+        let label = func_label_indexed(store, funcaddr)?;
+        // Functions may be selected by name or by their indexed label.
+        if !self.funcs.is_empty()
+            && !self.funcs.contains(&label)
+            && !func_label(store, funcaddr).is_some_and(|name| self.funcs.contains(&name))
+        {
+            return None;
+        }
+        let types = match self.operand_types.get(&funcaddr) {
+            Some(types) => types.clone(),
+            None => {
+                let types: OperandTypes =
+                    func_operand_types(store, funcaddr, TRACE_STACK_DEPTH)?.into();
+                self.operand_types.insert(funcaddr, types.clone());
+                types
+            }
+        };
+        Some(TracedFrame { label, types })
+    }
+
+    fn write_line(&mut self, line: std::fmt::Arguments) {
+        if self.is_exhausted() {
+            return;
+        }
+        let _ = self.out.write_fmt(line);
+        let _ = self.out.write_all(b"\n");
+        self.lines += 1;
+        if let Some(max) = self.max_lines
+            && self.lines == max
+        {
+            let _ = writeln!(self.out, "trace limit of {} lines reached", max);
+        }
+    }
+}
+
+impl<W: Write> WasmExecHooks for WasmTracer<W> {
+    fn on_func_enter(&mut self, store: &WasmStore, _stack: &WasmStack, funcaddr: WasmFuncAddr) {
+        let frame = self.traced_frame(store, funcaddr);
+        self.frames.push(frame);
+    }
+
+    fn on_func_exit(&mut self, _store: &WasmStore, _stack: &WasmStack, _funcaddr: WasmFuncAddr) {
+        self.frames.pop();
+    }
+
+    fn on_instr(
+        &mut self,
+        store: &WasmStore,
+        stack: &WasmStack,
+        instr: &WasmInstruction,
+    ) -> Result<(), WasmTrap> {
+        if self.is_exhausted() {
+            return Ok(());
+        }
+        let Some(Some(frame)) = self.frames.last() else {
+            return Ok(());
+        };
+        let funcaddr = stack.current_frame().funcaddr;
+        let func = store.funcs.resolve(funcaddr);
+        let Some(instr_idx) = func.instr_idx(instr) else {
+            return Ok(());
+        };
+        let values = stack.values();
+        let types = &frame.types[instr_idx.0 as usize];
+        let top = &values[values.len().saturating_sub(types.len())..];
+        let mut rendered = String::new();
+        if values.len() > top.len() {
+            rendered.push_str("..");
+        }
+        for (val, ty) in top.iter().zip(types) {
+            if !rendered.is_empty() {
+                rendered.push_str(", ");
+            }
+            match ty {
+                MaybeUntyped::KnownType(ty) => rendered.push_str(&val.format(ty)),
+                MaybeUntyped::UnknownType => rendered.push('?'),
+            }
+        }
+        let mut position = format!("{}+{}", frame.label, instr_idx.0);
+        if let WasmFuncImpl::Wasm { func, .. } = func.impl_
+            && let Some(offset) = func.offsets.get(instr_idx.0 as usize)
+        {
+            position.push_str(&format!(" @{:#x}", offset));
+        }
+        if let Some(location) = store.resolve_source_location(funcaddr, instr_idx) {
+            position.push_str(&format!(" ({})", location));
+        }
+        self.write_line(format_args!("{}: {:?} [{}]", position, instr, rendered));
+        Ok(())
+    }
+
+    fn on_trap(&mut self, _store: &WasmStore, _stack: &WasmStack, trap: &WasmTrap) {
+        self.frames.clear();
+        self.write_line(format_args!("trap: {}", trap.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        inst::WasmInvokeOptions,
+        module::{WasmModule, WasmModuleRaw, validate},
+    };
+
+    fn trace(opts: WasmTraceOptions) -> String {
+        let text = r#"(module
+            (func $main (export "main") (param i64) (result f64)
+                local.get 0
+                f32.const 1.5
+                call $half)
+            (func $half (param i64 f32) (result f64)
+                local.get 0
+                f64.convert_i64_s
+                f64.const 0.5
+                f64.mul))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let bytes = wmod.to_bytes().unwrap();
+        let wmod = WasmModule::from_bytes(&bytes).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let main = store
+            .instances
+            .resolve(winst_id)
+            .resolve_export_fn_by_name("main")
+            .unwrap();
+        let mut tracer = WasmTracer::new(Vec::new(), opts);
+        let options = WasmInvokeOptions::default().with_hooks(&mut tracer);
+        store
+            .invoke(main, Box::new([5_000_000_000i64.into()]), options)
+            .unwrap();
+        String::from_utf8(tracer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_trace_values() {
+        let out = trace(WasmTraceOptions {
+            funcs: vec!["half".to_string()],
+            max_lines: None,
+        });
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5, "{}", out);
+        assert_eq!(
+            lines[0],
+            "half[1]+0 @0xf: LocalGet { local_idx: WasmLocalIdx(0) } []"
+        );
+        // The i64 operand is converted, so the f64 must not print as its bits.
+        assert_eq!(
+            lines[2],
+            "half[1]+2 @0x12: F64Const { val: 0.5 } [5000000000]"
+        );
+        assert_eq!(lines[3], "half[1]+3 @0x1b: F64Mul [5000000000, 0.5]");
+        assert_eq!(lines[4], "half[1]+4 @0x1c: ExprEnd [2500000000]");
+
+        // Functions can also be selected by their indexed label.
+        let indexed = trace(WasmTraceOptions {
+            funcs: vec!["half[1]".to_string()],
+            max_lines: None,
+        });
+        assert_eq!(indexed, out);
+    }
+
+    #[test]
+    fn test_trace_limit() {
+        let out = trace(WasmTraceOptions {
+            funcs: vec![],
+            max_lines: Some(2),
+        });
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("main[0]+0"));
+        assert_eq!(lines[2], "trace limit of 2 lines reached");
+    }
+}
@@ -1,4 +1,6 @@
+pub mod debug;
 mod exec;
 pub mod inst;
+pub mod instrument;
 pub mod link;
 pub mod module;
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::module::WasmModule;
 
@@ -26,22 +26,33 @@ impl<'s> WasmDependencyGraph<'s> {
         self.add_deps(modname, deps.into_boxed_slice());
     }
 
-    pub fn toposort(mut self, start: &'s str) -> Vec<&'s str> {
-        let mut stack = vec![start];
+    /// Orders `start` and the modules it depends on so that every module
+    /// comes after its dependencies. Fails with the path of the first cycle
+    /// found, which starts and ends with the same module.
+    pub fn toposort(&self, start: &'s str) -> Result<Vec<&'s str>, Vec<&'s str>> {
+        let mut done = HashSet::new();
+        let mut path = vec![(start, 0)];
         let mut out = vec![];
-        while let Some(modname) = stack.pop() {
-            if let Some(deps) = self.deps.remove(&modname) {
-                stack.push(modname);
-                for dep in deps {
-                    if self.deps.contains_key(&dep) {
-                        stack.push(dep);
-                    }
-                }
-            } else {
+        while let Some((modname, next)) = path.last_mut() {
+            let modname = *modname;
+            let Some(&dep) = self.deps.get(modname).and_then(|deps| deps.get(*next)) else {
+                done.insert(modname);
                 out.push(modname);
+                path.pop();
+                continue;
+            };
+            *next += 1;
+            if done.contains(dep) || !self.deps.contains_key(dep) {
+                continue;
             }
+            if let Some(i) = path.iter().position(|&(m, _)| m == dep) {
+                let mut cycle: Vec<_> = path[i..].iter().map(|&(m, _)| m).collect();
+                cycle.push(dep);
+                return Err(cycle);
+            }
+            path.push((dep, 0));
         }
-        out
+        Ok(out)
     }
 }
 
@@ -56,6 +67,15 @@ mod tests {
         depgraph.add_deps("b", Box::new(["c"]));
         depgraph.add_deps("c", Box::new([]));
         let order = depgraph.toposort("a");
-        assert_eq!(order, vec!["c", "b", "a"]);
+        assert_eq!(order, Ok(vec!["c", "b", "a"]));
+    }
+
+    #[test]
+    fn test_topo_sort_cycle() {
+        let mut depgraph = WasmDependencyGraph::new();
+        depgraph.add_deps("a", Box::new(["b"]));
+        depgraph.add_deps("b", Box::new(["c", "env"]));
+        depgraph.add_deps("c", Box::new(["b"]));
+        assert_eq!(depgraph.toposort("a"), Err(vec!["b", "c", "b"]));
     }
 }
@@ -1,8 +1,19 @@
-use std::{collections::HashMap, path::Path, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    path::Path,
+    rc::{Rc, Weak},
+};
 
 use crate::{
-    inst::{WasmExternVal, WasmHostFunc, WasmInstantiationError, WasmStore},
-    module::{WasmFuncType, WasmModule},
+    inst::{
+        WasmExternVal, WasmHostFunc, WasmInstanceAddr, WasmInstantiationError, WasmMemInst,
+        WasmRefValue, WasmStore, WasmValue, match_limits,
+    },
+    module::{
+        WasmExternType, WasmFuncType, WasmGlobalType, WasmMemType, WasmModule, WasmTableType,
+    },
 };
 
 use super::depgraph::WasmDependencyGraph;
@@ -10,13 +21,117 @@ use super::depgraph::WasmDependencyGraph;
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum WasmLinkError {
-    UnknownModule(String),
-    UnknownSymbol(String, String),
     UnableToInferModuleNameFromPath(String),
+    /// Modules that import from each other, starting and ending with the
+    /// same module.
+    DependencyCycle(Vec<String>),
+    Unlinkable(WasmLinkReport),
     DependencyInstantiation {
         modname: String,
         err: WasmInstantiationError,
     },
+    /// Host memory bytes that are not a whole number of pages within the
+    /// limits of its type.
+    InvalidHostMem {
+        modname: String,
+        name: String,
+        len: usize,
+        ty: &'static WasmMemType,
+    },
+}
+
+impl fmt::Display for WasmLinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasmLinkError::UnableToInferModuleNameFromPath(path) => {
+                write!(f, "unable to infer a module name from {}", path)
+            }
+            WasmLinkError::DependencyCycle(cycle) => {
+                write!(f, "dependency cycle: {}", cycle.join(" -> "))
+            }
+            WasmLinkError::Unlinkable(report) => write!(f, "{}", report),
+            WasmLinkError::DependencyInstantiation { modname, err } => {
+                write!(f, "failed to instantiate {}: {:?}", modname, err)
+            }
+            WasmLinkError::InvalidHostMem {
+                modname,
+                name,
+                len,
+                ty,
+            } => write!(
+                f,
+                "host memory {}.{} of {} bytes does not fit {}",
+                modname,
+                name,
+                len,
+                WasmExternType::Mem(ty)
+            ),
+        }
+    }
+}
+
+/// Everything that stopped a module from linking. `importer` is `None` for
+/// the module being linked and the name of the dependency otherwise.
+#[derive(Debug, Default)]
+pub struct WasmLinkReport {
+    pub unresolved: Vec<WasmUnresolvedImport>,
+    pub mismatches: Vec<WasmImportMismatch>,
+    /// Linked modules that nothing imports from.
+    pub unused: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct WasmUnresolvedImport {
+    pub importer: Option<String>,
+    pub module: String,
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct WasmImportMismatch {
+    pub importer: Option<String>,
+    pub module: String,
+    pub name: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl WasmLinkReport {
+    pub fn is_linkable(&self) -> bool {
+        self.unresolved.is_empty() && self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for WasmLinkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let importer = |importer: &Option<String>| match importer {
+            Some(modname) => format!(" (imported by {})", modname),
+            None => String::new(),
+        };
+        let mut lines = vec![];
+        for import in &self.unresolved {
+            lines.push(format!(
+                "unresolved import {}.{}{}",
+                import.module,
+                import.name,
+                importer(&import.importer)
+            ));
+        }
+        for import in &self.mismatches {
+            lines.push(format!(
+                "type mismatch for import {}.{}{}: expected {}, found {}",
+                import.module,
+                import.name,
+                importer(&import.importer),
+                import.expected,
+                import.actual
+            ));
+        }
+        for modname in &self.unused {
+            lines.push(format!("unused module {}", modname));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
 }
 
 pub type WasmLinkResult<T> = Result<T, WasmLinkError>;
@@ -29,88 +144,190 @@ pub fn infer_module_name_from_path(path: &Path) -> WasmLinkResult<String> {
         })
 }
 
+#[derive(Default)]
 struct HostModule {
     funcs: HashMap<&'static str, (&'static WasmFuncType, WasmHostFunc)>,
+    globals: HashMap<&'static str, (&'static WasmGlobalType, WasmValue)>,
+    tables: HashMap<&'static str, (&'static WasmTableType, Vec<WasmRefValue>)>,
+    mems: HashMap<&'static str, (&'static WasmMemType, Vec<u8>)>,
 }
 
 enum LinkerEntry {
     Wasm(Rc<WasmModule>),
     Host(HostModule),
+    Instance(WasmInstanceAddr),
+}
+
+/// The items a host module was instantiated as in one store.
+type HostInstance = HashMap<&'static str, WasmExternVal>;
+
+/// A host module instantiated by `link_into`, along with the store holding
+/// it.
+struct CachedHostInstance {
+    store: Weak<()>,
+    modname: String,
+    hostinst: Rc<HostInstance>,
 }
 
 pub struct WasmLinker {
     modules: HashMap<String, LinkerEntry>,
+    /// Host modules instantiated by `link_into`. Entries for dropped stores
+    /// are evicted on the next call.
+    host_instances: RefCell<Vec<CachedHostInstance>>,
 }
 
 impl WasmLinker {
     pub fn new() -> Self {
         WasmLinker {
             modules: HashMap::new(),
+            host_instances: RefCell::new(Vec::new()),
         }
     }
 
     pub fn add_host_module(
         &mut self,
-        modname: String,
+        modname: impl Into<String>,
         hostfuncs: &[(&'static str, &'static WasmFuncType, WasmHostFunc)],
     ) {
-        let mut funcs = HashMap::with_capacity(hostfuncs.len());
+        let hostmod = self.host_module(modname.into());
         for (name, functype, func) in hostfuncs {
-            funcs.insert(*name, (*functype, *func));
+            hostmod.funcs.insert(*name, (*functype, *func));
+        }
+    }
+
+    /// Adds a global to the host module `modname`. Every store the module
+    /// is linked into gets its own copy, starting at `val`.
+    pub fn add_host_global(
+        &mut self,
+        modname: impl Into<String>,
+        name: &'static str,
+        ty: &'static WasmGlobalType,
+        val: WasmValue,
+    ) {
+        self.host_module(modname.into())
+            .globals
+            .insert(name, (ty, val));
+    }
+
+    pub fn add_host_table(
+        &mut self,
+        modname: impl Into<String>,
+        name: &'static str,
+        ty: &'static WasmTableType,
+        elems: Vec<WasmRefValue>,
+    ) {
+        self.host_module(modname.into())
+            .tables
+            .insert(name, (ty, elems));
+    }
+
+    /// Adds a memory to the host module `modname`, holding `bytes`, which
+    /// must be a whole number of pages within the limits of `ty`.
+    pub fn add_host_mem(
+        &mut self,
+        modname: impl Into<String>,
+        name: &'static str,
+        ty: &'static WasmMemType,
+        bytes: Vec<u8>,
+    ) -> WasmLinkResult<()> {
+        let modname = modname.into();
+        let pages = bytes.len() / WasmMemInst::PAGE_SIZE;
+        if !bytes.len().is_multiple_of(WasmMemInst::PAGE_SIZE)
+            || pages < ty.limits.min as usize
+            || ty.limits.max.is_some_and(|max| pages > max as usize)
+        {
+            return Err(WasmLinkError::InvalidHostMem {
+                modname,
+                name: name.to_string(),
+                len: bytes.len(),
+                ty,
+            });
         }
+        self.host_module(modname).mems.insert(name, (ty, bytes));
+        Ok(())
+    }
+
+    /// The host module named `modname`, replacing any wasm module of that
+    /// name. Stores it was instantiated into get a new instance with the
+    /// change.
+    fn host_module(&mut self, modname: String) -> &mut HostModule {
+        self.host_instances
+            .get_mut()
+            .retain(|cached| cached.modname != modname);
+        let entry = self
+            .modules
+            .entry(modname)
+            .and_modify(|entry| {
+                if !matches!(entry, LinkerEntry::Host(_)) {
+                    *entry = LinkerEntry::Host(HostModule::default());
+                }
+            })
+            .or_insert_with(|| LinkerEntry::Host(HostModule::default()));
+        match entry {
+            LinkerEntry::Host(hostmod) => hostmod,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn add_module(&mut self, modname: impl Into<String>, module: Rc<WasmModule>) {
         self.modules
-            .insert(modname, LinkerEntry::Host(HostModule { funcs }));
+            .insert(modname.into(), LinkerEntry::Wasm(module));
     }
 
-    pub fn add_module(&mut self, modname: String, module: Rc<WasmModule>) {
-        self.modules.insert(modname, LinkerEntry::Wasm(module));
+    /// Registers an instance under `modname`, so that modules linked into
+    /// the store holding it with `link_into` can import its exports.
+    pub fn add_instance(&mut self, modname: impl Into<String>, winst_id: WasmInstanceAddr) {
+        self.modules
+            .insert(modname.into(), LinkerEntry::Instance(winst_id));
     }
 
     pub fn link(&self, wmod: &WasmModule) -> WasmLinkResult<(WasmStore, Vec<WasmExternVal>)> {
         let mut store = WasmStore::new();
-        let order = {
-            let mut depgraph = WasmDependencyGraph::new();
-            for (modname, entry) in &self.modules {
-                match entry {
-                    LinkerEntry::Wasm(module) => {
-                        depgraph.add_module_deps(modname, module);
-                    }
-                    LinkerEntry::Host(_) => {
-                        depgraph.add_deps(modname, Box::new([]));
-                    }
-                }
-            }
-            depgraph.add_module_deps("", wmod);
-            let mut order = depgraph.toposort("");
-            order.pop();
-            order
-        };
+        let externvals = self.link_into(&mut store, wmod)?;
+        Ok((store, externvals))
+    }
+
+    /// Resolves the imports of `wmod` in an existing store. The wasm modules
+    /// it depends on are instantiated into the store on every call, while
+    /// host modules are instantiated once per store and shared, as are
+    /// registered instances.
+    pub fn link_into(
+        &self,
+        store: &mut WasmStore,
+        wmod: &WasmModule,
+    ) -> WasmLinkResult<Vec<WasmExternVal>> {
+        let order = self.link_order(wmod)?;
+        let report = self.check(store, &order, wmod);
+        if !report.is_linkable() {
+            return Err(WasmLinkError::Unlinkable(report));
+        }
+        self.host_instances
+            .borrow_mut()
+            .retain(|cached| cached.store.strong_count() > 0);
         let mut env = HashMap::<(&str, &str), WasmExternVal>::new();
         for modname in order {
-            let entry = self
-                .modules
-                .get(modname)
-                .ok_or_else(|| WasmLinkError::UnknownModule(modname.to_string()))?;
-            match entry {
+            match &self.modules[modname] {
                 LinkerEntry::Host(hostmod) => {
-                    for (name, (functype, func)) in &hostmod.funcs {
-                        let funcaddr = store.alloc_hostfunc(functype, *func);
-                        env.insert((&modname, name), WasmExternVal::Func(funcaddr));
+                    let hostinst = match self.host_instance(store, modname) {
+                        Some(hostinst) => hostinst,
+                        None => {
+                            let hostinst = Rc::new(instantiate_host_module(store, hostmod));
+                            self.host_instances.borrow_mut().push(CachedHostInstance {
+                                store: store.handle(),
+                                modname: modname.to_string(),
+                                hostinst: hostinst.clone(),
+                            });
+                            hostinst
+                        }
+                    };
+                    for (name, externval) in hostinst.iter() {
+                        env.insert((modname, name), *externval);
                     }
                 }
+                // Exports of registered instances are looked up as needed.
+                LinkerEntry::Instance(_) => {}
                 LinkerEntry::Wasm(wmod) => {
-                    let mut externvals = Vec::with_capacity(wmod.imports.len());
-                    for import in &wmod.imports {
-                        externvals.push(
-                            *env.get(&(&import.module_name.0, &import.item_name.0))
-                                .ok_or_else(|| {
-                                    WasmLinkError::UnknownSymbol(
-                                        import.module_name.0.to_string(),
-                                        import.item_name.0.to_string(),
-                                    )
-                                })?,
-                        );
-                    }
+                    let externvals = self.resolve_imports(store, &env, wmod)?;
                     let winst_id = store.instantiate(wmod.clone(), &externvals).map_err(|e| {
                         WasmLinkError::DependencyInstantiation {
                             modname: modname.to_string(),
@@ -124,18 +341,457 @@ impl WasmLinker {
                 }
             }
         }
+        self.resolve_imports(store, &env, wmod)
+    }
+
+    /// Checks `wmod` as `link_into` would, without instantiating anything.
+    /// The report lists unused modules even when `wmod` links, so that they
+    /// can be warned about.
+    pub fn report(&self, store: &WasmStore, wmod: &WasmModule) -> WasmLinkResult<WasmLinkReport> {
+        let order = self.link_order(wmod)?;
+        Ok(self.check(store, &order, wmod))
+    }
+
+    /// The modules `wmod` depends on, each after its own dependencies.
+    fn link_order<'a>(&'a self, wmod: &'a WasmModule) -> WasmLinkResult<Vec<&'a str>> {
+        let mut depgraph = WasmDependencyGraph::new();
+        for (modname, entry) in &self.modules {
+            match entry {
+                LinkerEntry::Wasm(module) => {
+                    depgraph.add_module_deps(modname, module);
+                }
+                LinkerEntry::Host(_) | LinkerEntry::Instance(_) => {
+                    depgraph.add_deps(modname, Box::new([]));
+                }
+            }
+        }
+        depgraph.add_module_deps("", wmod);
+        let mut order = depgraph.toposort("").map_err(|cycle| {
+            WasmLinkError::DependencyCycle(cycle.iter().map(|m| m.to_string()).collect())
+        })?;
+        order.pop();
+        Ok(order)
+    }
+
+    fn resolve_imports(
+        &self,
+        store: &WasmStore,
+        env: &HashMap<(&str, &str), WasmExternVal>,
+        wmod: &WasmModule,
+    ) -> WasmLinkResult<Vec<WasmExternVal>> {
         let mut externvals = Vec::with_capacity(wmod.imports.len());
         for import in &wmod.imports {
-            externvals.push(
-                *env.get(&(&import.module_name.0, &import.item_name.0))
-                    .ok_or_else(|| {
-                        WasmLinkError::UnknownSymbol(
-                            import.module_name.0.to_string(),
-                            import.item_name.0.to_string(),
-                        )
-                    })?,
-            );
+            let (modname, name) = (import.module_name.0.as_ref(), import.item_name.0.as_ref());
+            let externval = match self.modules.get(modname) {
+                Some(LinkerEntry::Instance(winst_id)) => store
+                    .instances
+                    .try_resolve(*winst_id)
+                    .and_then(|winst| winst.resolve_export_by_name(name)),
+                _ => env.get(&(modname, name)).copied(),
+            };
+            externvals.push(externval.ok_or_else(|| {
+                WasmLinkError::Unlinkable(WasmLinkReport {
+                    unresolved: vec![WasmUnresolvedImport {
+                        importer: None,
+                        module: modname.to_string(),
+                        name: name.to_string(),
+                    }],
+                    ..Default::default()
+                })
+            })?);
         }
-        Ok((store, externvals))
+        Ok(externvals)
+    }
+
+    /// Checks the imports of `wmod` and of the modules in `order`, which it
+    /// depends on, against the types of the items they import, before
+    /// anything is instantiated.
+    fn check(&self, store: &WasmStore, order: &[&str], wmod: &WasmModule) -> WasmLinkReport {
+        let mut report = WasmLinkReport::default();
+        let importers = order
+            .iter()
+            .filter_map(|&modname| match &self.modules[modname] {
+                LinkerEntry::Wasm(module) => Some((Some(modname), &**module)),
+                _ => None,
+            })
+            .chain([(None, wmod)]);
+        for (importer, module) in importers {
+            for import in module.import_types() {
+                let importer = importer.map(|modname| modname.to_string());
+                let Some((actual, actual_size)) =
+                    self.export_type(store, import.module, import.name)
+                else {
+                    report.unresolved.push(WasmUnresolvedImport {
+                        importer,
+                        module: import.module.to_string(),
+                        name: import.name.to_string(),
+                    });
+                    continue;
+                };
+                if !extern_type_matches(actual, actual_size, import.ty) {
+                    report.mismatches.push(WasmImportMismatch {
+                        importer,
+                        module: import.module.to_string(),
+                        name: import.name.to_string(),
+                        expected: import.ty.to_string(),
+                        actual: actual.to_string(),
+                    });
+                }
+            }
+        }
+        report.unused = self
+            .modules
+            .keys()
+            .filter(|modname| !order.contains(&modname.as_str()))
+            .cloned()
+            .collect();
+        report.unused.sort();
+        report
+    }
+    /// The instance of host module `modname` in `store`, if it has been
+    /// linked into it.
+    fn host_instance(&self, store: &WasmStore, modname: &str) -> Option<Rc<HostInstance>> {
+        let handle = store.handle();
+        self.host_instances
+            .borrow()
+            .iter()
+            .find(|cached| cached.modname == modname && cached.store.ptr_eq(&handle))
+            .map(|cached| cached.hostinst.clone())
+    }
+
+    /// The type of the item `modname` exports as `name`, along with the
+    /// current size of a table or memory that already exists.
+    fn export_type<'a>(
+        &'a self,
+        store: &'a WasmStore,
+        modname: &str,
+        name: &str,
+    ) -> Option<(WasmExternType<'a>, usize)> {
+        match self.modules.get(modname)? {
+            LinkerEntry::Wasm(module) => Some((module.export_type(name)?.ty, 0)),
+            LinkerEntry::Host(hostmod) => {
+                // A table or memory already in the store may have grown.
+                let externval = self
+                    .host_instance(store, modname)
+                    .and_then(|hostinst| hostinst.get(name).copied());
+                if let Some((ty, _)) = hostmod.funcs.get(name) {
+                    Some((WasmExternType::Func(ty), 0))
+                } else if let Some((ty, _)) = hostmod.globals.get(name) {
+                    Some((WasmExternType::Global(ty), 0))
+                } else if let Some((ty, elems)) = hostmod.tables.get(name) {
+                    let size = match externval {
+                        Some(WasmExternVal::Table(tableaddr)) => {
+                            store.tables.resolve(tableaddr).elems.len()
+                        }
+                        _ => elems.len(),
+                    };
+                    Some((WasmExternType::Table(ty), size))
+                } else {
+                    let (ty, bytes) = hostmod.mems.get(name)?;
+                    let size = match externval {
+                        Some(WasmExternVal::Mem(memaddr)) => store.mems.resolve(memaddr).data.len(),
+                        _ => bytes.len(),
+                    };
+                    Some((WasmExternType::Mem(ty), size / WasmMemInst::PAGE_SIZE))
+                }
+            }
+            LinkerEntry::Instance(winst_id) => {
+                let winst = store.instances.try_resolve(*winst_id)?;
+                let size = match winst.resolve_export_by_name(name)? {
+                    WasmExternVal::Table(tableaddr) => store.tables.resolve(tableaddr).elems.len(),
+                    WasmExternVal::Mem(memaddr) => {
+                        store.mems.resolve(memaddr).data.len() / WasmMemInst::PAGE_SIZE
+                    }
+                    _ => 0,
+                };
+                Some((winst.wmod.export_type(name)?.ty, size))
+            }
+        }
+    }
+}
+
+fn instantiate_host_module(store: &mut WasmStore, hostmod: &HostModule) -> HostInstance {
+    let mut hostinst = HostInstance::new();
+    for (name, (functype, func)) in &hostmod.funcs {
+        let funcaddr = store.alloc_hostfunc(functype, *func);
+        hostinst.insert(name, WasmExternVal::Func(funcaddr));
+    }
+    for (name, (ty, val)) in &hostmod.globals {
+        let globaladdr = store.alloc_host_global(ty, *val);
+        hostinst.insert(name, WasmExternVal::Global(globaladdr));
+    }
+    for (name, (ty, elems)) in &hostmod.tables {
+        let tableaddr = store.alloc_host_table(ty, elems.clone());
+        hostinst.insert(name, WasmExternVal::Table(tableaddr));
+    }
+    for (name, (ty, bytes)) in &hostmod.mems {
+        let memaddr = store.alloc_host_mem(ty, bytes.clone());
+        hostinst.insert(name, WasmExternVal::Mem(memaddr));
+    }
+    hostinst
+}
+
+/// Import matching as instantiation does it, so that a module that passes
+/// the check links.
+fn extern_type_matches(
+    actual: WasmExternType,
+    actual_size: usize,
+    expected: WasmExternType,
+) -> bool {
+    match (actual, expected) {
+        (WasmExternType::Func(actual), WasmExternType::Func(expected)) => actual == expected,
+        (WasmExternType::Global(actual), WasmExternType::Global(expected)) => actual == expected,
+        (WasmExternType::Table(actual), WasmExternType::Table(expected)) => {
+            actual.ref_type == expected.ref_type
+                && match_limits(&actual.limits, &expected.limits, actual_size)
+        }
+        (WasmExternType::Mem(actual), WasmExternType::Mem(expected)) => {
+            match_limits(&actual.limits, &expected.limits, actual_size)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inst::{WasmInvokeOptions, WasmMemInst},
+        module::{
+            WasmGlobalMutability, WasmLimits, WasmModuleRaw, WasmNumType, WasmRefType,
+            WasmValueType, validate,
+        },
+    };
+
+    #[test]
+    fn test_host_items() {
+        let text = r#"(module
+            (import "env" "memory" (memory 1))
+            (import "env" "__stack_pointer" (global $sp (mut i32)))
+            (import "env" "table" (table 2 funcref))
+            (func (export "load") (result i32)
+                global.get $sp
+                i32.load))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let limits = WasmLimits { min: 1, max: None };
+        let mut linker = WasmLinker::new();
+        linker
+            .add_host_mem(
+                "env",
+                "memory",
+                Box::leak(Box::new(WasmMemType { limits })),
+                [0, 0, 0, 0, 42, 0, 0, 0].repeat(WasmMemInst::PAGE_SIZE / 8),
+            )
+            .unwrap();
+        let small = Box::leak(Box::new(WasmMemType {
+            limits: WasmLimits {
+                min: 1,
+                max: Some(1),
+            },
+        }));
+        for len in [3, 0, 2 * WasmMemInst::PAGE_SIZE] {
+            let res = linker.add_host_mem("env", "small", small, vec![0; len]);
+            assert!(matches!(res, Err(WasmLinkError::InvalidHostMem { .. })));
+        }
+        linker.add_host_global(
+            "env",
+            "__stack_pointer",
+            Box::leak(Box::new(WasmGlobalType {
+                mutability: WasmGlobalMutability::Mutable,
+                val_type: WasmValueType::Num(WasmNumType::I32),
+            })),
+            4.into(),
+        );
+        linker.add_host_table(
+            "env",
+            "table",
+            Box::leak(Box::new(WasmTableType {
+                limits: WasmLimits { min: 2, max: None },
+                ref_type: WasmRefType::FuncRef,
+            })),
+            vec![WasmRefValue::NULL; 2],
+        );
+
+        let (mut store, externvals) = linker.link(&wmod).unwrap();
+        let winst_id = store.instantiate(Rc::new(wmod), &externvals).unwrap();
+        let funcaddr = store
+            .instances
+            .resolve(winst_id)
+            .resolve_export_fn_by_name("load")
+            .unwrap();
+        let res = store
+            .invoke(funcaddr, Box::new([]), WasmInvokeOptions::default())
+            .unwrap();
+        assert_eq!(unsafe { res.res.0[0].num.i32 }, 42);
+    }
+
+    #[test]
+    fn test_link_into_shared_instance() {
+        let runtime = r#"(module
+            (global $count (mut i32) (i32.const 0))
+            (func (export "bump") (result i32)
+                global.get $count
+                i32.const 1
+                i32.add
+                global.set $count
+                global.get $count))"#;
+        let guest = r#"(module
+            (import "rt" "bump" (func $bump (result i32)))
+            (func (export "run") (result i32) call $bump))"#;
+        let runtime = validate(WasmModuleRaw::from_wat(runtime).unwrap()).unwrap();
+        let guest = Rc::new(validate(WasmModuleRaw::from_wat(guest).unwrap()).unwrap());
+
+        let mut store = WasmStore::new();
+        let rt_id = store.instantiate(Rc::new(runtime), &[]).unwrap();
+        let mut linker = WasmLinker::new();
+        linker.add_instance("rt", rt_id);
+        let mut counts = vec![];
+        for _ in 0..2 {
+            let externvals = linker.link_into(&mut store, &guest).unwrap();
+            let winst_id = store.instantiate(guest.clone(), &externvals).unwrap();
+            let run = store
+                .instances
+                .resolve(winst_id)
+                .resolve_export_fn_by_name("run")
+                .unwrap();
+            let res = store
+                .invoke(run, Box::new([]), WasmInvokeOptions::default())
+                .unwrap();
+            counts.push(unsafe { res.res.0[0].num.i32 });
+        }
+        assert_eq!(counts, [1, 2]);
+    }
+
+    #[test]
+    fn test_link_into_shared_host_mem() {
+        let writer = r#"(module
+            (import "env" "memory" (memory 1))
+            (func (export "run") (result i32)
+                i32.const 0
+                i32.const 42
+                i32.store
+                i32.const 0))"#;
+        let reader = r#"(module
+            (import "env" "memory" (memory 1))
+            (func (export "run") (result i32)
+                i32.const 0
+                i32.load))"#;
+        let mut linker = WasmLinker::new();
+        linker
+            .add_host_mem(
+                "env",
+                "memory",
+                Box::leak(Box::new(WasmMemType {
+                    limits: WasmLimits { min: 1, max: None },
+                })),
+                vec![0; WasmMemInst::PAGE_SIZE],
+            )
+            .unwrap();
+        let mut store = WasmStore::new();
+        let mut results = vec![];
+        for text in [writer, reader] {
+            let guest = Rc::new(validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap());
+            let externvals = linker.link_into(&mut store, &guest).unwrap();
+            let winst_id = store.instantiate(guest, &externvals).unwrap();
+            let run = store
+                .instances
+                .resolve(winst_id)
+                .resolve_export_fn_by_name("run")
+                .unwrap();
+            let res = store
+                .invoke(run, Box::new([]), WasmInvokeOptions::default())
+                .unwrap();
+            results.push(unsafe { res.res.0[0].num.i32 });
+        }
+        assert_eq!(results, [0, 42]);
+        assert_eq!(store.mems.len(), 1);
+    }
+
+    #[test]
+    fn test_link_into_two_stores() {
+        let writer = r#"(module
+            (import "env" "memory" (memory 1))
+            (func (export "run") (result i32)
+                i32.const 0
+                i32.const 42
+                i32.store
+                i32.const 0))"#;
+        let reader = r#"(module
+            (import "env" "memory" (memory 1))
+            (func (export "run") (result i32)
+                i32.const 0
+                i32.load))"#;
+        let mut linker = WasmLinker::new();
+        linker
+            .add_host_mem(
+                "env",
+                "memory",
+                Box::leak(Box::new(WasmMemType {
+                    limits: WasmLimits { min: 1, max: None },
+                })),
+                vec![0; WasmMemInst::PAGE_SIZE],
+            )
+            .unwrap();
+        let run = |store: &mut WasmStore, text: &str| {
+            let guest = Rc::new(validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap());
+            let externvals = linker.link_into(store, &guest).unwrap();
+            let winst_id = store.instantiate(guest, &externvals).unwrap();
+            let run = store
+                .instances
+                .resolve(winst_id)
+                .resolve_export_fn_by_name("run")
+                .unwrap();
+            let res = store
+                .invoke(run, Box::new([]), WasmInvokeOptions::default())
+                .unwrap();
+            unsafe { res.res.0[0].num.i32 }
+        };
+
+        // Each store gets its own memory, however the stores are allocated.
+        let mut first = WasmStore::new();
+        let mut second = WasmStore::new();
+        assert_eq!(run(&mut first, writer), 0);
+        assert_eq!(run(&mut second, reader), 0);
+        assert_eq!(run(&mut first, reader), 42);
+        assert_eq!((first.mems.len(), second.mems.len()), (1, 1));
+        assert_eq!(linker.host_instances.borrow().len(), 2);
+
+        // Dropping a store evicts its instance on the next link.
+        drop(first);
+        assert_eq!(run(&mut second, writer), 0);
+        assert_eq!(run(&mut second, reader), 42);
+        assert_eq!(linker.host_instances.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_link_report() {
+        let lib = r#"(module
+            (import "env" "missing" (global i32))
+            (func (export "f") (param i32)))"#;
+        let text = r#"(module
+            (import "lib" "f" (func (param i64)))
+            (import "lib" "g" (func)))"#;
+        let lib = validate(WasmModuleRaw::from_wat(lib).unwrap()).unwrap();
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let mut linker = WasmLinker::new();
+        linker.add_module("lib", Rc::new(lib));
+        linker.add_host_module("env", &[]);
+        linker.add_module("unused", Rc::new(WasmModule::empty()));
+        let Err(WasmLinkError::Unlinkable(report)) = linker.link(&wmod) else {
+            panic!("module linked");
+        };
+        assert_eq!(
+            report.to_string(),
+            "unresolved import env.missing (imported by lib)\n\
+             unresolved import lib.g\n\
+             type mismatch for import lib.f: expected (func (param i64)), found (func (param i32))\n\
+             unused module unused"
+        );
+
+        let wmod = validate(WasmModuleRaw::from_wat("(module)").unwrap()).unwrap();
+        let report = linker.report(&WasmStore::new(), &wmod).unwrap();
+        assert!(report.is_linkable());
+        assert_eq!(report.unused, ["env", "lib", "unused"]);
+        assert!(linker.link(&wmod).is_ok());
     }
 }
@@ -1,4 +1,7 @@
 mod depgraph;
 mod linker;
 
-pub use linker::{WasmLinkError, WasmLinkResult, WasmLinker, infer_module_name_from_path};
+pub use linker::{
+    WasmImportMismatch, WasmLinkError, WasmLinkReport, WasmLinkResult, WasmLinker,
+    WasmUnresolvedImport, infer_module_name_from_path,
+};
@@ -1,31 +1,70 @@
 use std::{
     f32,
     ffi::{CStr, c_char},
+    fs::File,
+    io::{BufWriter, Write},
+    net::TcpListener,
     num::{ParseFloatError, ParseIntError},
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::Rc,
     sync::LazyLock,
 };
 
 use semblance::{
+    debug::{WasmDebugger, WasmGdbStub},
     inst::{
         DynamicWasmResult, WasmInstanceAddr, WasmInstantiationError, WasmInvokeOptions,
-        WasmNumValue, WasmStore, WasmTrap, WasmValue,
+        WasmNumValue, WasmStore, WasmTrap, WasmValue, WasmWatchKind,
     },
+    instrument::{WasmCoverage, WasmProfileOptions, WasmProfiler, WasmTraceOptions, WasmTracer},
     link::{WasmLinkError, WasmLinker, infer_module_name_from_path},
     module::{
-        WasmFuncType, WasmMemIdx, WasmModule, WasmNumType, WasmReadError, WasmResultType,
-        WasmValueType,
+        WasmExportDesc, WasmExternType, WasmFuncType, WasmGlobalMutability, WasmLimits, WasmMemIdx,
+        WasmModule, WasmNumType, WasmParseError, WasmPrintOptions, WasmReadError, WasmResultType,
+        WasmValidationOptions, WasmValueType, default_cache_dir,
     },
 };
 
 const HELP_TEXT: &'static str = "
 semblance <MODULE> [OPTIONS]
+semblance debug <MODULE> [OPTIONS]
+semblance dis <MODULE> [OPTIONS]
+semblance inspect <MODULE> [OPTIONS]
+
+<MODULE> is a binary .wasm file, or a .wat file in the text format.
 
 Options:
     -h, --help                      Print this help text
     -I, --invoke <FN> [ARGS...]     Invoke an exported function
     -L, --link <MODULE>[ as ALIAS]  Load an additional module to be processed by the linker
+    --trace                         Print every executed instruction
+    --trace-func <FN>               Only trace instructions in the function named FN or FN[idx] (repeatable)
+    --trace-limit <N>               Stop tracing after N lines
+    --trace-out <FILE>              Write the trace to FILE instead of stderr
+    --profile <FILE>                Write a collapsed-stack profile to FILE for flamegraph tools
+    --profile-interval <N>          Sample the call stack every N instructions (default 1)
+    --profile-lines                 Attribute profile samples to source lines (needs DWARF)
+    --coverage <FILE>               Write a per-function coverage report to FILE
+    --lcov <FILE>                   Write an lcov coverage tracefile to FILE (needs DWARF)
+    --gdb <ADDR>                    With debug, wait for a GDB/LLDB remote connection on ADDR
+    --watch <ADDR>[+LEN][:r|w|rw]   With debug, pause on accesses to linear memory (default w)
+    --no-cache                      Always decode modules instead of using the cache
+    --validate-threads <N>          Validate function bodies on N threads (default 1)
+    --lazy-validation               Validate each function body the first time it is called
+    --fold                          With dis, print instructions in folded form
+    --json                          With inspect, print JSON instead of text
+
+The debug subcommand runs the invoked function (default _start) under an
+interactive debugger. Type `help` at the (sdb) prompt for its commands.
+
+The dis subcommand prints the module in the WebAssembly text format.
+
+The inspect subcommand prints the module's imports and exports with their
+types.
+
+Decoded modules are cached in $SEMBLANCE_CACHE_DIR, or in semblance/ under
+$XDG_CACHE_HOME or ~/.cache. Function bodies loaded from the cache are
+validated the first time they are called.
 ";
 
 #[derive(Debug)]
@@ -33,6 +72,18 @@ struct CliArgs {
     pub module_path: PathBuf,
     pub link: Vec<LinkArgs>,
     pub invoke: Option<InvokeArgs>,
+    pub trace: Option<TraceArgs>,
+    pub profile: Option<ProfileArgs>,
+    pub coverage: Option<CoverageArgs>,
+    pub debug: bool,
+    pub gdb: Option<String>,
+    pub watch: Vec<WatchArgs>,
+    pub no_cache: bool,
+    pub validation: WasmValidationOptions,
+    pub dis: bool,
+    pub fold: bool,
+    pub inspect: bool,
+    pub json: bool,
 }
 
 #[derive(Debug)]
@@ -41,6 +92,57 @@ struct InvokeArgs {
     pub argv: Vec<String>,
 }
 
+#[derive(Debug, Default)]
+struct TraceArgs {
+    funcs: Vec<String>,
+    limit: Option<usize>,
+    out: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+struct ProfileArgs {
+    out: PathBuf,
+    interval: u64,
+    source_lines: bool,
+}
+
+#[derive(Debug, Default)]
+struct CoverageArgs {
+    report: Option<PathBuf>,
+    lcov: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+struct WatchArgs {
+    addr: usize,
+    len: usize,
+    kind: WasmWatchKind,
+}
+
+impl WatchArgs {
+    fn parse(s: &str) -> Option<Self> {
+        fn number(s: &str) -> Option<usize> {
+            match s.strip_prefix("0x") {
+                Some(hex) => usize::from_str_radix(hex, 16).ok(),
+                None => s.parse().ok(),
+            }
+        }
+
+        let (range, kind) = match s.split_once(':') {
+            Some((range, "r")) => (range, WasmWatchKind::Read),
+            Some((range, "w")) => (range, WasmWatchKind::Write),
+            Some((range, "rw")) => (range, WasmWatchKind::ReadWrite),
+            Some(_) => return None,
+            None => (s, WasmWatchKind::Write),
+        };
+        let (addr, len) = match range.split_once('+') {
+            Some((addr, len)) => (number(addr)?, number(len)?),
+            None => (number(range)?, 1),
+        };
+        Some(WatchArgs { addr, len, kind })
+    }
+}
+
 #[derive(Debug)]
 struct LinkArgs {
     name: Option<String>,
@@ -51,6 +153,22 @@ enum CliFlag<'s> {
     Module(PathBuf),
     Invoke(Option<InvokeArgs>),
     Link(Option<LinkArgs>),
+    Trace,
+    TraceFunc(Option<&'s str>),
+    TraceLimit(Option<&'s str>),
+    TraceOut(Option<&'s str>),
+    Profile(Option<&'s str>),
+    ProfileInterval(Option<&'s str>),
+    ProfileLines,
+    Coverage(Option<&'s str>),
+    Lcov(Option<&'s str>),
+    Gdb(Option<&'s str>),
+    Watch(Option<&'s str>),
+    NoCache,
+    ValidateThreads(Option<&'s str>),
+    LazyValidation,
+    Fold,
+    Json,
     Help,
     Noop,
     Unknown(&'s str),
@@ -70,6 +188,32 @@ fn parse_flag<'a>(argv: &'a [&'a str]) -> (CliFlag<'a>, &'a [&'a str]) {
             let (link_args, rest) = parse_link_args(rest);
             (CliFlag::Link(link_args), rest)
         }
+        ["--trace", rest @ ..] => (CliFlag::Trace, rest),
+        ["--trace-func", name, rest @ ..] => (CliFlag::TraceFunc(Some(name)), rest),
+        ["--trace-func"] => (CliFlag::TraceFunc(None), &[]),
+        ["--trace-limit", n, rest @ ..] => (CliFlag::TraceLimit(Some(n)), rest),
+        ["--trace-limit"] => (CliFlag::TraceLimit(None), &[]),
+        ["--trace-out", path, rest @ ..] => (CliFlag::TraceOut(Some(path)), rest),
+        ["--trace-out"] => (CliFlag::TraceOut(None), &[]),
+        ["--profile", path, rest @ ..] => (CliFlag::Profile(Some(path)), rest),
+        ["--profile"] => (CliFlag::Profile(None), &[]),
+        ["--profile-interval", n, rest @ ..] => (CliFlag::ProfileInterval(Some(n)), rest),
+        ["--profile-interval"] => (CliFlag::ProfileInterval(None), &[]),
+        ["--coverage", path, rest @ ..] => (CliFlag::Coverage(Some(path)), rest),
+        ["--coverage"] => (CliFlag::Coverage(None), &[]),
+        ["--profile-lines", rest @ ..] => (CliFlag::ProfileLines, rest),
+        ["--lcov", path, rest @ ..] => (CliFlag::Lcov(Some(path)), rest),
+        ["--lcov"] => (CliFlag::Lcov(None), &[]),
+        ["--gdb", addr, rest @ ..] => (CliFlag::Gdb(Some(addr)), rest),
+        ["--gdb"] => (CliFlag::Gdb(None), &[]),
+        ["--watch", spec, rest @ ..] => (CliFlag::Watch(Some(spec)), rest),
+        ["--watch"] => (CliFlag::Watch(None), &[]),
+        ["--no-cache", rest @ ..] => (CliFlag::NoCache, rest),
+        ["--validate-threads", n, rest @ ..] => (CliFlag::ValidateThreads(Some(n)), rest),
+        ["--validate-threads"] => (CliFlag::ValidateThreads(None), &[]),
+        ["--lazy-validation", rest @ ..] => (CliFlag::LazyValidation, rest),
+        ["--fold", rest @ ..] => (CliFlag::Fold, rest),
+        ["--json", rest @ ..] => (CliFlag::Json, rest),
         [s, rest @ ..] if s.starts_with("-") => (CliFlag::Unknown(s), rest),
         [s, rest @ ..] => (CliFlag::Module(PathBuf::from(s)), rest),
         _ => unreachable!("argv is not empty"),
@@ -135,10 +279,39 @@ impl CliArgs {
         let mut module_path = None;
         let mut link = vec![];
         let mut invoke = None;
+        let mut trace: Option<TraceArgs> = None;
+        let mut profile_out = None;
+        let mut profile_interval = 1;
+        let mut profile_lines = false;
+        let mut coverage: Option<CoverageArgs> = None;
+        let mut debug = false;
+        let mut gdb = None;
+        let mut watch = vec![];
+        let mut no_cache = false;
+        let mut validation = WasmValidationOptions::default();
+        let mut dis = false;
+        let mut fold = false;
+        let mut inspect = false;
+        let mut json = false;
 
         let argv = std::env::args().collect::<Vec<_>>();
         let strs = argv.iter().map(|s| s.as_str()).collect::<Vec<_>>();
         let mut rem = &strs[1..];
+        match rem {
+            ["debug", rest @ ..] => {
+                debug = true;
+                rem = rest;
+            }
+            ["dis", rest @ ..] => {
+                dis = true;
+                rem = rest;
+            }
+            ["inspect", rest @ ..] => {
+                inspect = true;
+                rem = rest;
+            }
+            _ => {}
+        }
 
         while rem.len() > 0 {
             let (flag, rest) = parse_flag(rem);
@@ -161,6 +334,96 @@ impl CliArgs {
                         exit();
                     }
                 }
+                CliFlag::Trace => {
+                    trace.get_or_insert_default();
+                }
+                CliFlag::TraceFunc(Some(name)) => {
+                    trace.get_or_insert_default().funcs.push(name.to_string());
+                }
+                CliFlag::TraceLimit(Some(n)) => match n.parse() {
+                    Ok(n) => trace.get_or_insert_default().limit = Some(n),
+                    Err(_) => {
+                        eprintln!("--trace-limit expects a number, got {}", n);
+                        exit();
+                    }
+                },
+                CliFlag::TraceOut(Some(path)) => {
+                    trace.get_or_insert_default().out = Some(PathBuf::from(path));
+                }
+                CliFlag::TraceFunc(None) => {
+                    eprintln!("--trace-func missing <FN>");
+                    exit();
+                }
+                CliFlag::TraceLimit(None) => {
+                    eprintln!("--trace-limit missing <N>");
+                    exit();
+                }
+                CliFlag::TraceOut(None) => {
+                    eprintln!("--trace-out missing <FILE>");
+                    exit();
+                }
+                CliFlag::Profile(Some(path)) => profile_out = Some(PathBuf::from(path)),
+                CliFlag::ProfileInterval(Some(n)) => match n.parse() {
+                    Ok(n) if n > 0 => profile_interval = n,
+                    _ => {
+                        eprintln!("--profile-interval expects a positive number, got {}", n);
+                        exit();
+                    }
+                },
+                CliFlag::Profile(None) => {
+                    eprintln!("--profile missing <FILE>");
+                    exit();
+                }
+                CliFlag::ProfileInterval(None) => {
+                    eprintln!("--profile-interval missing <N>");
+                    exit();
+                }
+                CliFlag::ProfileLines => profile_lines = true,
+                CliFlag::Coverage(Some(path)) => {
+                    coverage.get_or_insert_default().report = Some(PathBuf::from(path));
+                }
+                CliFlag::Lcov(Some(path)) => {
+                    coverage.get_or_insert_default().lcov = Some(PathBuf::from(path));
+                }
+                CliFlag::Lcov(None) => {
+                    eprintln!("--lcov missing <FILE>");
+                    exit();
+                }
+                CliFlag::Coverage(None) => {
+                    eprintln!("--coverage missing <FILE>");
+                    exit();
+                }
+                CliFlag::Gdb(Some(addr)) => gdb = Some(addr.to_string()),
+                CliFlag::Gdb(None) => {
+                    eprintln!("--gdb missing <ADDR>");
+                    exit();
+                }
+                CliFlag::Watch(Some(spec)) => match WatchArgs::parse(spec) {
+                    Some(w) => watch.push(w),
+                    None => {
+                        eprintln!("--watch expects <ADDR>[+LEN][:r|w|rw], got {}", spec);
+                        exit();
+                    }
+                },
+                CliFlag::Watch(None) => {
+                    eprintln!("--watch missing <ADDR>");
+                    exit();
+                }
+                CliFlag::NoCache => no_cache = true,
+                CliFlag::ValidateThreads(Some(n)) => match n.parse() {
+                    Ok(n) if n > 0 => validation.threads = n,
+                    _ => {
+                        eprintln!("--validate-threads expects a positive number, got {}", n);
+                        exit();
+                    }
+                },
+                CliFlag::ValidateThreads(None) => {
+                    eprintln!("--validate-threads missing <N>");
+                    exit();
+                }
+                CliFlag::LazyValidation => validation.lazy = true,
+                CliFlag::Fold => fold = true,
+                CliFlag::Json => json = true,
                 CliFlag::Module(m) => module_path = Some(m),
                 CliFlag::Unknown(f) => {
                     eprintln!("unknown flag: {}", f);
@@ -175,11 +438,69 @@ impl CliArgs {
             exit();
         }
 
+        let instruments = [trace.is_some(), profile_out.is_some(), coverage.is_some()];
+        if instruments.iter().filter(|enabled| **enabled).count() > 1 {
+            eprintln!("only one of --trace, --profile and --coverage may be given");
+            exit();
+        }
+        if debug && instruments.contains(&true) {
+            eprintln!("--trace, --profile and --coverage cannot be used with debug");
+            exit();
+        }
+        if gdb.is_some() && !debug {
+            eprintln!("--gdb can only be used with debug");
+            exit();
+        }
+        if !watch.is_empty() && !debug {
+            eprintln!("--watch can only be used with debug");
+            exit();
+        }
+        if fold && !dis {
+            eprintln!("--fold can only be used with dis");
+            exit();
+        }
+        if dis && (invoke.is_some() || !link.is_empty() || instruments.contains(&true)) {
+            eprintln!("dis cannot be combined with --invoke, --link or instrumentation");
+            exit();
+        }
+        if json && !inspect {
+            eprintln!("--json can only be used with inspect");
+            exit();
+        }
+        if inspect && (invoke.is_some() || !link.is_empty() || instruments.contains(&true)) {
+            eprintln!("inspect cannot be combined with --invoke, --link or instrumentation");
+            exit();
+        }
+        if debug && invoke.is_none() {
+            invoke = Some(InvokeArgs {
+                fn_name: "_start".to_string(),
+                argv: vec![],
+            });
+        }
+
+        let profile = profile_out.map(|out| ProfileArgs {
+            out,
+            interval: profile_interval,
+            source_lines: profile_lines,
+        });
+
         if let Some(module_path) = module_path {
             CliArgs {
                 module_path,
                 link,
                 invoke,
+                trace,
+                profile,
+                coverage,
+                debug,
+                gdb,
+                watch,
+                no_cache,
+                validation,
+                dis,
+                fold,
+                inspect,
+                json,
             }
         } else {
             eprintln!("<MODULE> is required");
@@ -274,49 +595,98 @@ fn hostcall_puts(
 #[derive(Debug)]
 #[allow(dead_code)]
 enum SemblanceError {
-    Read(WasmReadError),
+    Read(PathBuf, WasmReadError),
+    Parse(PathBuf, WasmParseError),
     Link(WasmLinkError),
     Instantiate(WasmInstantiationError),
     Args(ArgumentError),
     Trap(WasmTrap),
+    Io(std::io::Error),
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
 enum ArgumentError {
     ExportNotFound(String),
+    NoMemory,
     InvalidInput(ParseArgError),
 }
 
 type SemblanceResult = Result<DynamicWasmResult, SemblanceError>;
 
+fn read_module(path: &Path, args: &CliArgs) -> Result<WasmModule, SemblanceError> {
+    match default_cache_dir() {
+        Some(cache_dir) if !args.no_cache => {
+            WasmModule::read_cached_with_options(path, &cache_dir, args.validation)
+        }
+        _ => WasmModule::read_with_options(path, args.validation),
+    }
+    .map_err(|e| read_error(path, e))
+}
+
+fn read_error(path: &Path, e: WasmReadError) -> SemblanceError {
+    match e {
+        WasmReadError::Parse(e) => SemblanceError::Parse(path.to_path_buf(), e),
+        e => SemblanceError::Read(path.to_path_buf(), e),
+    }
+}
+
 fn run(args: &CliArgs) -> SemblanceResult {
-    let module = WasmModule::read(&args.module_path).map_err(SemblanceError::Read)?;
+    // The GDB stub serves the binary that code addresses point into, which a
+    // module in the text format only has once it is encoded.
+    let (module, image) = match args.gdb {
+        Some(_) => {
+            let (module, image) =
+                WasmModule::read_image_with_options(&args.module_path, args.validation)
+                    .map_err(|e| read_error(&args.module_path, e))?;
+            (module, Some(image))
+        }
+        None => (read_module(&args.module_path, args)?, None),
+    };
+    if args.dis {
+        let text = module
+            .to_wat(WasmPrintOptions { folded: args.fold })
+            .map_err(|e| SemblanceError::Read(args.module_path.clone(), e.into()))?;
+        print!("{}", text);
+        return Ok(DynamicWasmResult::void());
+    }
+    if args.inspect {
+        match args.json {
+            true => print!("{}", interface_json(&module)),
+            false => print!("{}", interface_text(&module)),
+        }
+        return Ok(DynamicWasmResult::void());
+    }
     if let Some(InvokeArgs {
         ref fn_name,
         ref argv,
     }) = args.invoke
     {
+        let mut linked = vec![];
         let linker = {
             let mut linker = WasmLinker::new();
-            linker.add_host_module(
-                "env".to_string(),
-                &[("puts", &*HOSTCALL_PUTS_TYPE, &hostcall_puts)],
-            );
+            linker.add_host_module("env", &[("puts", &*HOSTCALL_PUTS_TYPE, &hostcall_puts)]);
             for link_arg in &args.link {
-                let module =
-                    WasmModule::read(&link_arg.module_path).map_err(SemblanceError::Read)?;
+                let module = read_module(&link_arg.module_path, args)?;
                 let modname = if let Some(modname) = &link_arg.name {
                     modname.clone()
                 } else {
                     infer_module_name_from_path(&link_arg.module_path)
                         .map_err(SemblanceError::Link)?
                 };
+                linked.push(modname.clone());
                 linker.add_module(modname, Rc::new(module));
             }
             linker
         };
         let (mut store, externvals) = linker.link(&module).map_err(SemblanceError::Link)?;
+        // Only modules given with --link are worth a warning.
+        let report = linker
+            .report(&store, &module)
+            .map_err(SemblanceError::Link)?;
+        for modname in report.unused.iter().filter(|m| linked.contains(m)) {
+            eprintln!("warning: unused module {}", modname);
+        }
         let winst_id = store
             .instantiate(Rc::new(module), &externvals)
             .map_err(SemblanceError::Instantiate)?;
@@ -325,19 +695,213 @@ fn run(args: &CliArgs) -> SemblanceResult {
             .resolve(winst_id)
             .resolve_export_fn_by_name(fn_name)
             .ok_or_else(|| {
+                print_func_exports(&store, winst_id);
                 SemblanceError::Args(ArgumentError::ExportNotFound(fn_name.to_string()))
             })?;
+        if !args.watch.is_empty() {
+            let winst = store.instances.resolve(winst_id);
+            let Some(memaddr) = winst.memaddrs.first().copied() else {
+                eprintln!("--watch needs a module with a memory");
+                return Err(SemblanceError::Args(ArgumentError::NoMemory));
+            };
+            let mem = store.mems.resolve_mut(memaddr);
+            for watch in &args.watch {
+                mem.add_watchpoint(watch.addr..(watch.addr + watch.len), watch.kind);
+            }
+        }
         let ty = store.funcs.resolve(funcaddr).type_.input_type.0.as_ref();
         let invoke_args = parse_args_for_value_type(ty, &argv)
             .map_err(|e| SemblanceError::Args(ArgumentError::InvalidInput(e)))?;
-        let wres = store
-            .invoke(funcaddr, invoke_args, WasmInvokeOptions::default())
-            .map_err(SemblanceError::Trap)?;
+        let wres = if let Some(addr) = &args.gdb {
+            let listener = TcpListener::bind(addr).map_err(SemblanceError::Io)?;
+            eprintln!("waiting for a debugger on {}", addr);
+            let (conn, _) = listener.accept().map_err(SemblanceError::Io)?;
+            let image = image.unwrap_or_default();
+            let name = args.module_path.file_name().unwrap_or_default();
+            let mut stub = WasmGdbStub::new(conn);
+            stub.add_module(winst_id, &name.to_string_lossy(), image);
+            let wres = store.invoke(
+                funcaddr,
+                invoke_args,
+                WasmInvokeOptions::default().with_hooks(&mut stub),
+            );
+            stub.finish(wres.as_ref().map(|_| ()))
+                .map_err(SemblanceError::Io)?;
+            wres
+        } else if args.debug {
+            let stdin = std::io::stdin();
+            let mut debugger = WasmDebugger::new(stdin.lock(), std::io::stdout());
+            store.invoke(
+                funcaddr,
+                invoke_args,
+                WasmInvokeOptions::default().with_hooks(&mut debugger),
+            )
+        } else if let Some(trace) = &args.trace {
+            let out: Box<dyn Write> = match &trace.out {
+                Some(path) => Box::new(File::create(path).map_err(SemblanceError::Io)?),
+                None => Box::new(std::io::stderr()),
+            };
+            let mut tracer = WasmTracer::new(
+                BufWriter::new(out),
+                WasmTraceOptions {
+                    funcs: trace.funcs.clone(),
+                    max_lines: trace.limit,
+                },
+            );
+            store.invoke(
+                funcaddr,
+                invoke_args,
+                WasmInvokeOptions::default().with_hooks(&mut tracer),
+            )
+        } else if let Some(profile) = &args.profile {
+            let mut profiler = WasmProfiler::new(WasmProfileOptions {
+                sample_interval: profile.interval,
+                source_lines: profile.source_lines,
+            });
+            let wres = store.invoke(
+                funcaddr,
+                invoke_args,
+                WasmInvokeOptions::default().with_hooks(&mut profiler),
+            );
+            let out = File::create(&profile.out).map_err(SemblanceError::Io)?;
+            profiler
+                .write_collapsed(&store, BufWriter::new(out))
+                .map_err(SemblanceError::Io)?;
+            wres
+        } else if let Some(coverage_args) = &args.coverage {
+            let mut coverage = WasmCoverage::new();
+            let wres = store.invoke(
+                funcaddr,
+                invoke_args,
+                WasmInvokeOptions::default().with_hooks(&mut coverage),
+            );
+            if let Some(path) = &coverage_args.report {
+                let out = File::create(path).map_err(SemblanceError::Io)?;
+                coverage
+                    .write_report(&store, winst_id, BufWriter::new(out))
+                    .map_err(SemblanceError::Io)?;
+            }
+            if let Some(path) = &coverage_args.lcov {
+                if store.instances.resolve(winst_id).wmod.lines.is_empty() {
+                    eprintln!("warning: module has no DWARF line info, lcov output will be empty");
+                }
+                let out = File::create(path).map_err(SemblanceError::Io)?;
+                coverage
+                    .write_lcov(&store, winst_id, BufWriter::new(out))
+                    .map_err(SemblanceError::Io)?;
+            }
+            wres
+        } else {
+            store.invoke(funcaddr, invoke_args, WasmInvokeOptions::default())
+        }
+        .map_err(|trap| {
+            if let Some(backtrace) = store.trap_backtrace() {
+                eprint!("backtrace:\n{}", backtrace);
+            }
+            SemblanceError::Trap(trap)
+        })?;
         return Ok(wres);
     }
     Ok(DynamicWasmResult::void())
 }
 
+fn interface_text(module: &WasmModule) -> String {
+    let mut out = String::from("imports:\n");
+    for import in module.import_types() {
+        out += &format!("    {}.{}: {}\n", import.module, import.name, import.ty);
+    }
+    out += "exports:\n";
+    for export in module.export_types() {
+        out += &format!("    {}: {}\n", export.name, export.ty);
+    }
+    out
+}
+
+fn interface_json(module: &WasmModule) -> String {
+    fn string(s: &str) -> String {
+        let mut out = String::from('"');
+        for c in s.chars() {
+            match c {
+                '"' => out += "\\\"",
+                '\\' => out += "\\\\",
+                c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+        out
+    }
+    fn types(val_types: &[WasmValueType]) -> String {
+        let types: Vec<_> = val_types.iter().map(|t| string(&t.to_string())).collect();
+        format!("[{}]", types.join(", "))
+    }
+    fn limits(limits: &WasmLimits) -> String {
+        let max = limits.max.map_or("null".to_string(), |max| max.to_string());
+        format!("\"min\": {}, \"max\": {}", limits.min, max)
+    }
+    fn extern_type(ty: WasmExternType) -> String {
+        let fields = match ty {
+            WasmExternType::Func(ty) => format!(
+                "\"params\": {}, \"results\": {}",
+                types(&ty.input_type.0),
+                types(&ty.output_type.0)
+            ),
+            WasmExternType::Table(ty) => format!(
+                "\"element\": {}, {}",
+                string(&WasmValueType::Ref(ty.ref_type).to_string()),
+                limits(&ty.limits)
+            ),
+            WasmExternType::Mem(ty) => limits(&ty.limits),
+            WasmExternType::Global(ty) => format!(
+                "\"type\": {}, \"mutable\": {}",
+                string(&ty.val_type.to_string()),
+                ty.mutability == WasmGlobalMutability::Mutable
+            ),
+        };
+        format!("\"kind\": {}, {}", string(ty.kind()), fields)
+    }
+
+    let imports: Vec<_> = module
+        .import_types()
+        .map(|import| {
+            format!(
+                "    {{\"module\": {}, \"name\": {}, {}}}",
+                string(import.module),
+                string(import.name),
+                extern_type(import.ty)
+            )
+        })
+        .collect();
+    let exports: Vec<_> = module
+        .export_types()
+        .map(|export| {
+            format!(
+                "    {{\"name\": {}, {}}}",
+                string(export.name),
+                extern_type(export.ty)
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"imports\": [\n{}\n  ],\n  \"exports\": [\n{}\n  ]\n}}\n",
+        imports.join(",\n"),
+        exports.join(",\n")
+    )
+}
+
+fn print_func_exports(store: &WasmStore, winst_id: WasmInstanceAddr) {
+    let wmod = &store.instances.resolve(winst_id).wmod;
+    eprintln!("exported functions:");
+    for export in &wmod.exports {
+        if let WasmExportDesc::Func(func_idx) = export.desc {
+            match wmod.func_name(func_idx) {
+                Some(name) => eprintln!("    {} ({})", export.name.0, name),
+                None => eprintln!("    {} (func[{}])", export.name.0, func_idx.0),
+            }
+        }
+    }
+}
+
 fn main() {
     let args = CliArgs::parse_or_exit();
     let wres = run(&args);
@@ -348,6 +912,18 @@ fn main() {
                 println!("{}", v);
             }
         }
+        Err(SemblanceError::Read(path, e)) => {
+            eprintln!("{}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        Err(SemblanceError::Parse(path, e)) => {
+            eprintln!("{}:{}", path.display(), e);
+            std::process::exit(1);
+        }
+        Err(SemblanceError::Link(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
         Err(e) => {
             eprintln!("{:?}", e);
             std::process::exit(1);
@@ -1,12 +1,12 @@
-use std::string::FromUtf8Error;
+use std::{collections::HashMap, error::Error, fmt, hash::Hash, string::FromUtf8Error};
 
 use super::{
     builder::{WasmCode, WasmExprBuilder, WasmModuleBuilder, WasmResultTypeBuilder},
-    *,
+    dwarf, *,
 };
 
 #[derive(Debug)]
-pub enum WasmDecodeError {
+pub enum WasmDecodeErrorKind {
     MagicBytes,
     UnsupportedVersion(u32),
     UnknownSectionId(u8),
@@ -19,61 +19,219 @@ pub enum WasmDecodeError {
     InvalidGlobalMutability(u8),
     InvalidExportDesc(u8),
     InvalidBlockType,
-    InvalidConst,
     InvalidData,
     InvalidElem,
     UnknownOpcode(u8),
     UnknownExtendedOpcode(u32),
     UnsupportedMemIdx(u32),
     UnexpectedByte { expected: u8, actual: u8 },
+    InvalidDebugLine,
+    IntegerTooLong,
+    IntegerTooLarge,
+    SectionOutOfOrder(WasmSectionId),
+    SectionSizeMismatch,
+    BodySizeMismatch,
+    MissingEnd,
+    TooManyLocals,
+    FuncCodeMismatch { funcs: u32, bodies: u32 },
+    DataCountMismatch { count: u32, datas: u32 },
+    DataCountRequired,
     UnexpectedEof,
 }
 
+impl fmt::Display for WasmDecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use WasmDecodeErrorKind::*;
+        match self {
+            MagicBytes => write!(f, "not a wasm binary (bad magic bytes)"),
+            UnsupportedVersion(v) => write!(f, "unsupported binary version {}", v),
+            UnknownSectionId(id) => write!(f, "unknown section id {}", id),
+            NonUtfName(_) => write!(f, "name is not valid UTF-8"),
+            InvalidFuncType(b) => write!(f, "expected function type 0x60, got 0x{:02x}", b),
+            InvalidValueType(b) => write!(f, "invalid value type 0x{:02x}", b),
+            InvalidImportDesc(b) => write!(f, "invalid import kind 0x{:02x}", b),
+            InvalidRefType(b) => write!(f, "invalid reference type 0x{:02x}", b),
+            InvalidLimits(b) => write!(f, "invalid limits flag 0x{:02x}", b),
+            InvalidGlobalMutability(b) => write!(f, "invalid global mutability 0x{:02x}", b),
+            InvalidExportDesc(b) => write!(f, "invalid export kind 0x{:02x}", b),
+            InvalidBlockType => write!(f, "invalid block type"),
+            InvalidData => write!(f, "invalid data segment flags"),
+            InvalidElem => write!(f, "invalid element segment flags"),
+            UnknownOpcode(op) => write!(f, "unknown opcode 0x{:02x}", op),
+            UnknownExtendedOpcode(op) => write!(f, "unknown opcode 0xfc {}", op),
+            UnsupportedMemIdx(idx) => write!(f, "memory index {} is not supported", idx),
+            UnexpectedByte { expected, actual } => {
+                write!(f, "expected byte 0x{:02x}, got 0x{:02x}", expected, actual)
+            }
+            InvalidDebugLine => write!(f, "invalid .debug_line section"),
+            IntegerTooLong => write!(f, "integer representation too long"),
+            IntegerTooLarge => write!(f, "integer too large"),
+            SectionOutOfOrder(sid) => write!(f, "unexpected {} section", sid),
+            SectionSizeMismatch => write!(f, "section size mismatch"),
+            BodySizeMismatch => write!(f, "function body continues after its end"),
+            MissingEnd => write!(f, "function body is missing its end"),
+            TooManyLocals => write!(f, "too many locals"),
+            FuncCodeMismatch { funcs, bodies } => write!(
+                f,
+                "{} functions declared but {} bodies defined",
+                funcs, bodies
+            ),
+            DataCountMismatch { count, datas } => write!(
+                f,
+                "data count is {} but there are {} data segments",
+                count, datas
+            ),
+            DataCountRequired => write!(f, "data count section required"),
+            UnexpectedEof => write!(f, "unexpected end"),
+        }
+    }
+}
+
+/// A decode error and where in the binary it happened.
+#[derive(Debug)]
+pub struct WasmDecodeError {
+    /// The absolute byte offset into the binary.
+    pub offset: usize,
+    /// The section being decoded, if past the module header.
+    pub section: Option<WasmSectionId>,
+    /// The function whose body was being decoded, for errors in the code
+    /// section.
+    pub func_idx: Option<WasmFuncIdx>,
+    pub kind: WasmDecodeErrorKind,
+}
+
+impl WasmDecodeError {
+    /// An error at the start of `pos`. While decoding, `offset` holds the
+    /// address of `pos`; the entry points turn it into an offset with
+    /// [`WasmDecodeError::rebase`].
+    pub(super) fn at(pos: &[u8], kind: WasmDecodeErrorKind) -> Self {
+        WasmDecodeError {
+            offset: pos.as_ptr().addr(),
+            section: None,
+            func_idx: None,
+            kind,
+        }
+    }
+
+    /// An error at an offset that is already absolute.
+    pub(super) fn at_offset(offset: usize, kind: WasmDecodeErrorKind) -> Self {
+        WasmDecodeError {
+            offset,
+            section: None,
+            func_idx: None,
+            kind,
+        }
+    }
+
+    /// Makes the offset relative to `base`, the buffer decoding started at.
+    pub(super) fn rebase(mut self, base: &[u8]) -> Self {
+        self.offset = self.offset.saturating_sub(base.as_ptr().addr());
+        self
+    }
+
+    /// Like [`WasmDecodeError::rebase`], for a `base` found at `base_offset`
+    /// in the binary.
+    pub(super) fn rebase_at(self, base: &[u8], base_offset: usize) -> Self {
+        let mut e = self.rebase(base);
+        e.offset += base_offset;
+        e
+    }
+
+    pub(super) fn in_section(mut self, section: WasmSectionId) -> Self {
+        self.section.get_or_insert(section);
+        self
+    }
+
+    pub(super) fn in_func(mut self, func_idx: WasmFuncIdx) -> Self {
+        self.func_idx.get_or_insert(func_idx);
+        self
+    }
+}
+
+impl fmt::Display for WasmDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset 0x{:x}", self.kind, self.offset)?;
+        if let Some(section) = self.section {
+            write!(f, " in {} section", section)?;
+        }
+        if let Some(func_idx) = self.func_idx {
+            write!(f, ", function {}", func_idx.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for WasmDecodeError {}
+
 pub type WasmDecodeResult<T> = Result<T, WasmDecodeError>;
 
-type Decoded<'b, T> = (T, &'b [u8]);
+pub(super) type Decoded<'b, T> = (T, &'b [u8]);
 
-fn take_bytes<const N: usize>(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, [u8; N]>> {
+/// An unexpected end error at the end of `bytes`.
+pub(super) fn eof(bytes: &[u8]) -> WasmDecodeError {
+    WasmDecodeError::at(&bytes[bytes.len()..], WasmDecodeErrorKind::UnexpectedEof)
+}
+
+pub(super) fn take_bytes<const N: usize>(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, [u8; N]>> {
     if bytes.len() < N {
-        return Err(WasmDecodeError::UnexpectedEof);
+        return Err(eof(bytes));
     }
     let mut buf = [0u8; N];
     buf.copy_from_slice(&bytes[0..N]);
     Ok((buf, &bytes[N..]))
 }
 
-fn take_byte(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u8>> {
+pub(super) fn take_byte(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u8>> {
     let (buf, bytes) = take_bytes::<1>(bytes)?;
     Ok((buf[0], bytes))
 }
 
-fn take_byte_exact<const B: u8>(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, ()>> {
-    let (byte, bytes) = take_byte(bytes)?;
+fn take_byte_exact<const B: u8>(input: &[u8]) -> WasmDecodeResult<Decoded<'_, ()>> {
+    let (byte, bytes) = take_byte(input)?;
     if byte == B {
         Ok(((), bytes))
     } else {
-        Err(WasmDecodeError::UnexpectedByte {
-            expected: B,
-            actual: byte,
-        })
+        Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::UnexpectedByte {
+                expected: B,
+                actual: byte,
+            },
+        ))
     }
 }
 
-fn take_bytes_dyn(bytes: &[u8], n: usize) -> WasmDecodeResult<Decoded<'_, Vec<u8>>> {
+pub(super) fn take_bytes_dyn(bytes: &[u8], n: usize) -> WasmDecodeResult<Decoded<'_, Vec<u8>>> {
     if bytes.len() < n {
-        return Err(WasmDecodeError::UnexpectedEof);
+        return Err(eof(bytes));
     }
     let mut buf = Vec::with_capacity(n);
     buf.extend_from_slice(&bytes[0..n]);
     Ok((buf, &bytes[n..]))
 }
 
-fn decode_leb128(mut bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u32>> {
+pub(super) fn decode_leb128(mut bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u32>> {
     let mut result = 0;
     let mut shift = 0;
     loop {
+        let pos = bytes;
         let (byte, rest) = take_byte(bytes)?;
         bytes = rest;
+        if shift == 28 {
+            // The fifth byte holds the top four bits and ends the number.
+            if byte & 0x80 != 0 {
+                return Err(WasmDecodeError::at(
+                    pos,
+                    WasmDecodeErrorKind::IntegerTooLong,
+                ));
+            }
+            if byte & 0x70 != 0 {
+                return Err(WasmDecodeError::at(
+                    pos,
+                    WasmDecodeErrorKind::IntegerTooLarge,
+                ));
+            }
+        }
         result |= ((byte & !(1 << 7)) as u32) << shift;
         if byte & (1 << 7) == 0 {
             break;
@@ -83,29 +241,74 @@ fn decode_leb128(mut bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u32>> {
     Ok((result, bytes))
 }
 
-fn decode_leb128_signed(mut bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, i64>> {
+/// Decodes a signed LEB128 number of at most `bits` bits, rejecting
+/// encodings that are longer than needed for that width or whose unused bits
+/// in the last byte do not match the sign.
+fn decode_leb128_signed_bits(mut bytes: &[u8], bits: u32) -> WasmDecodeResult<Decoded<'_, i64>> {
     let mut result = 0;
     let mut shift = 0;
-
-    let byte = loop {
+    loop {
+        let pos = bytes;
         let (byte, rest) = take_byte(bytes)?;
         bytes = rest;
+        let remaining = bits - shift;
+        if remaining <= 7 {
+            if byte & (1 << 7) != 0 {
+                return Err(WasmDecodeError::at(
+                    pos,
+                    WasmDecodeErrorKind::IntegerTooLong,
+                ));
+            }
+            let unused = 0x7f & !((1u8 << (remaining - 1)) - 1);
+            if byte & unused != 0 && byte & unused != unused {
+                return Err(WasmDecodeError::at(
+                    pos,
+                    WasmDecodeErrorKind::IntegerTooLarge,
+                ));
+            }
+        }
         result |= ((byte & !(1 << 7)) as i64) << shift;
         shift += 7;
-        if (byte & (1 << 7)) == 0 {
-            break byte;
+        if byte & (1 << 7) == 0 {
+            if (shift < 64) && ((byte & 0x40) != 0) {
+                result |= (u64::MAX as i64) << shift;
+            }
+            return Ok((result, bytes));
         }
-    };
+    }
+}
+
+pub(super) fn decode_leb128_signed(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, i64>> {
+    decode_leb128_signed_bits(bytes, 64)
+}
 
-    if (shift < 64) && ((byte & 0x40) != 0) {
-        result |= (u64::MAX as i64) << shift;
+fn decode_leb128_s32(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, i32>> {
+    let (val, bytes) = decode_leb128_signed_bits(bytes, 32)?;
+    Ok((val as i32, bytes))
+}
+
+/// Decodes a vector length. Every element takes at least a byte, so a
+/// length beyond the remaining input is rejected before anything is
+/// allocated for it.
+fn decode_vec_len(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u32>> {
+    let (len, rest) = decode_leb128(bytes)?;
+    if len as usize > rest.len() {
+        return Err(eof(rest));
     }
+    Ok((len, rest))
+}
 
-    Ok((result, bytes))
+/// Checks that a section or function body was consumed exactly.
+fn expect_end(bytes: &[u8], kind: WasmDecodeErrorKind) -> WasmDecodeResult<()> {
+    if bytes.is_empty() {
+        Ok(())
+    } else {
+        Err(WasmDecodeError::at(bytes, kind))
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
-enum SectionId {
+pub enum WasmSectionId {
     Custom = 0,
     Type = 1,
     Import = 2,
@@ -121,33 +324,83 @@ enum SectionId {
     DataCount = 12,
 }
 
-fn decode_section_id(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, SectionId>> {
-    let (byte, bytes) = take_byte(bytes)?;
+impl WasmSectionId {
+    /// The position of the section in a module. The data count section's id
+    /// is out of sequence because it was added after the others.
+    fn order(self) -> u8 {
+        match self {
+            WasmSectionId::DataCount => WasmSectionId::Element as u8 * 2 + 1,
+            sid => sid as u8 * 2,
+        }
+    }
+
+    /// Whether the section may come after `last`, the last non-custom
+    /// section, which is updated. Custom sections may go anywhere, the others
+    /// at most once and in order.
+    pub(super) fn follows(self, last: &mut Option<WasmSectionId>) -> bool {
+        if self == WasmSectionId::Custom {
+            return true;
+        }
+        if last.is_some_and(|last| self.order() <= last.order()) {
+            return false;
+        }
+        *last = Some(self);
+        true
+    }
+}
+
+impl fmt::Display for WasmSectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WasmSectionId::Custom => "custom",
+            WasmSectionId::Type => "type",
+            WasmSectionId::Import => "import",
+            WasmSectionId::Function => "function",
+            WasmSectionId::Table => "table",
+            WasmSectionId::Memory => "memory",
+            WasmSectionId::Global => "global",
+            WasmSectionId::Export => "export",
+            WasmSectionId::Start => "start",
+            WasmSectionId::Element => "element",
+            WasmSectionId::Code => "code",
+            WasmSectionId::Data => "data",
+            WasmSectionId::DataCount => "data count",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub(super) fn decode_section_id(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmSectionId>> {
+    let (byte, bytes) = take_byte(input)?;
     let sid = match byte {
-        0 => Ok(SectionId::Custom),
-        1 => Ok(SectionId::Type),
-        2 => Ok(SectionId::Import),
-        3 => Ok(SectionId::Function),
-        4 => Ok(SectionId::Table),
-        5 => Ok(SectionId::Memory),
-        6 => Ok(SectionId::Global),
-        7 => Ok(SectionId::Export),
-        8 => Ok(SectionId::Start),
-        9 => Ok(SectionId::Element),
-        10 => Ok(SectionId::Code),
-        11 => Ok(SectionId::Data),
-        12 => Ok(SectionId::DataCount),
-        u => Err(WasmDecodeError::UnknownSectionId(u)),
+        0 => Ok(WasmSectionId::Custom),
+        1 => Ok(WasmSectionId::Type),
+        2 => Ok(WasmSectionId::Import),
+        3 => Ok(WasmSectionId::Function),
+        4 => Ok(WasmSectionId::Table),
+        5 => Ok(WasmSectionId::Memory),
+        6 => Ok(WasmSectionId::Global),
+        7 => Ok(WasmSectionId::Export),
+        8 => Ok(WasmSectionId::Start),
+        9 => Ok(WasmSectionId::Element),
+        10 => Ok(WasmSectionId::Code),
+        11 => Ok(WasmSectionId::Data),
+        12 => Ok(WasmSectionId::DataCount),
+        u => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::UnknownSectionId(u),
+        )),
     }?;
     Ok((sid, bytes))
 }
 
-fn decode_name(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmName>> {
+pub(super) fn decode_name(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmName>> {
     let (len, bytes) = decode_leb128(bytes)?;
-    let (vec, bytes) = take_bytes_dyn(bytes, len as usize)?;
-    let str = String::from_utf8(vec).map_err(WasmDecodeError::NonUtfName)?;
+    let (vec, rest) = take_bytes_dyn(bytes, len as usize)?;
+    let str = String::from_utf8(vec)
+        .map_err(|e| WasmDecodeError::at(bytes, WasmDecodeErrorKind::NonUtfName(e)))?;
     let name = WasmName(str.into_boxed_str());
-    Ok((name, bytes))
+    Ok((name, rest))
 }
 
 fn decode_custom_section(bytes: &[u8]) -> WasmDecodeResult<WasmCustom> {
@@ -158,8 +411,63 @@ fn decode_custom_section(bytes: &[u8]) -> WasmDecodeResult<WasmCustom> {
     })
 }
 
-fn decode_value_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmValueType>> {
-    let (tag, bytes) = take_byte(bytes)?;
+fn decode_name_map<I: Eq + Hash>(
+    bytes: &[u8],
+    idx: fn(u32) -> I,
+) -> WasmDecodeResult<Decoded<'_, HashMap<I, WasmName>>> {
+    let (len, mut bytes) = decode_vec_len(bytes)?;
+    let mut names = HashMap::with_capacity(len as usize);
+    for _ in 0..len {
+        let (i, rest) = decode_leb128(bytes)?;
+        let (name, rest) = decode_name(rest)?;
+        names.insert(idx(i), name);
+        bytes = rest;
+    }
+    Ok((names, bytes))
+}
+
+fn decode_indirect_name_map<I: Eq + Hash>(
+    bytes: &[u8],
+    idx: fn(u32) -> I,
+) -> WasmDecodeResult<Decoded<'_, HashMap<WasmFuncIdx, HashMap<I, WasmName>>>> {
+    let (len, mut bytes) = decode_vec_len(bytes)?;
+    let mut names = HashMap::with_capacity(len as usize);
+    for _ in 0..len {
+        let (func_idx, rest) = decode_func_idx(bytes)?;
+        let (map, rest) = decode_name_map(rest, idx)?;
+        names.insert(func_idx, map);
+        bytes = rest;
+    }
+    Ok((names, bytes))
+}
+
+fn decode_name_section(mut bytes: &[u8]) -> WasmDecodeResult<WasmNameMap> {
+    let mut names = WasmNameMap::default();
+    while !bytes.is_empty() {
+        let (id, rest) = take_byte(bytes)?;
+        let (len, rest) = decode_leb128(rest)?;
+        if rest.len() < len as usize {
+            return Err(eof(rest));
+        }
+        let (subsection, rest) = rest.split_at(len as usize);
+        match id {
+            0 => names.module = Some(decode_name(subsection)?.0),
+            1 => names.funcs = decode_name_map(subsection, WasmFuncIdx)?.0,
+            2 => names.locals = decode_indirect_name_map(subsection, WasmLocalIdx)?.0,
+            5 => names.tables = decode_name_map(subsection, WasmTableIdx)?.0,
+            6 => names.mems = decode_name_map(subsection, WasmMemIdx)?.0,
+            7 => names.globals = decode_name_map(subsection, WasmGlobalIdx)?.0,
+            8 => names.elems = decode_name_map(subsection, WasmElemIdx)?.0,
+            9 => names.datas = decode_name_map(subsection, WasmDataIdx)?.0,
+            _ => {}
+        }
+        bytes = rest;
+    }
+    Ok(names)
+}
+
+pub(super) fn decode_value_type(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmValueType>> {
+    let (tag, bytes) = take_byte(input)?;
     let vtype = match tag {
         0x7F => Ok(WasmValueType::Num(WasmNumType::I32)),
         0x7E => Ok(WasmValueType::Num(WasmNumType::I64)),
@@ -168,14 +476,17 @@ fn decode_value_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmValueType
         0x7B => Ok(WasmValueType::Vec(WasmVecType::V128)),
         0x70 => Ok(WasmValueType::Ref(WasmRefType::FuncRef)),
         0x6F => Ok(WasmValueType::Ref(WasmRefType::ExternRef)),
-        _ => Err(WasmDecodeError::InvalidValueType(tag)),
+        _ => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::InvalidValueType(tag),
+        )),
     }?;
     Ok((vtype, bytes))
 }
 
 fn decode_result_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmResultType>> {
     let mut res = WasmResultTypeBuilder::new();
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     res.reserve(len as usize);
     for _ in 0..len {
         let (vtype, rest) = decode_value_type(bytes)?;
@@ -185,10 +496,13 @@ fn decode_result_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmResultTy
     Ok((res.build(), bytes))
 }
 
-fn decode_func_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmFuncType>> {
-    let (marker, bytes) = take_byte(bytes)?;
+fn decode_func_type(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmFuncType>> {
+    let (marker, bytes) = take_byte(input)?;
     if marker != 0x60 {
-        return Err(WasmDecodeError::InvalidFuncType(marker));
+        return Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::InvalidFuncType(marker),
+        ));
     }
     let (input_type, bytes) = decode_result_type(bytes)?;
     let (output_type, bytes) = decode_result_type(bytes)?;
@@ -202,14 +516,14 @@ fn decode_func_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmFuncType>>
 }
 
 fn decode_type_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_types(len as usize);
     for _ in 0..len {
         let (functype, rest) = decode_func_type(bytes)?;
         wmod.push_type(functype);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
 fn decode_type_idx(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmTypeIdx>> {
@@ -237,12 +551,15 @@ fn decode_mem_idx(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmMemIdx>> {
     Ok((WasmMemIdx(idx), bytes))
 }
 
-fn decode_mem_idx_zero(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, ()>> {
-    let (mem_idx, bytes) = decode_mem_idx(bytes)?;
+fn decode_mem_idx_zero(input: &[u8]) -> WasmDecodeResult<Decoded<'_, ()>> {
+    let (mem_idx, bytes) = decode_mem_idx(input)?;
     if mem_idx.0 == 0 {
         Ok(((), bytes))
     } else {
-        Err(WasmDecodeError::UnsupportedMemIdx(mem_idx.0))
+        Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::UnsupportedMemIdx(mem_idx.0),
+        ))
     }
 }
 
@@ -266,17 +583,20 @@ fn decode_local_idx(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmLocalIdx>>
     Ok((WasmLocalIdx(idx), bytes))
 }
 
-fn decode_ref_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmRefType>> {
-    let (tag, bytes) = take_byte(bytes)?;
+pub(super) fn decode_ref_type(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmRefType>> {
+    let (tag, bytes) = take_byte(input)?;
     match tag {
         0x70 => Ok((WasmRefType::FuncRef, bytes)),
         0x6F => Ok((WasmRefType::ExternRef, bytes)),
-        _ => Err(WasmDecodeError::InvalidRefType(tag)),
+        _ => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::InvalidRefType(tag),
+        )),
     }
 }
 
-fn decode_limits(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmLimits>> {
-    let (flag, bytes) = take_byte(bytes)?;
+fn decode_limits(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmLimits>> {
+    let (flag, bytes) = take_byte(input)?;
     match flag {
         0x00 => {
             let (min, bytes) = decode_leb128(bytes)?;
@@ -293,7 +613,10 @@ fn decode_limits(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmLimits>> {
                 bytes,
             ))
         }
-        _ => Err(WasmDecodeError::InvalidLimits(flag)),
+        _ => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::InvalidLimits(flag),
+        )),
     }
 }
 
@@ -308,12 +631,15 @@ fn decode_mem_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmMemType>> {
     Ok((WasmMemType { limits }, bytes))
 }
 
-fn decode_global_mutability(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmGlobalMutability>> {
-    let (flag, bytes) = take_byte(bytes)?;
+fn decode_global_mutability(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmGlobalMutability>> {
+    let (flag, bytes) = take_byte(input)?;
     match flag {
         0x00 => Ok((WasmGlobalMutability::Immutable, bytes)),
         0x01 => Ok((WasmGlobalMutability::Mutable, bytes)),
-        _ => Err(WasmDecodeError::InvalidGlobalMutability(flag)),
+        _ => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::InvalidGlobalMutability(flag),
+        )),
     }
 }
 
@@ -329,8 +655,8 @@ fn decode_global_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmGlobalTy
     ))
 }
 
-fn decode_import_desc(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmImportDesc>> {
-    let (tag, bytes) = take_byte(bytes)?;
+fn decode_import_desc(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmImportDesc>> {
+    let (tag, bytes) = take_byte(input)?;
     match tag {
         0x00 => {
             let (type_idx, bytes) = decode_type_idx(bytes)?;
@@ -348,7 +674,10 @@ fn decode_import_desc(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmImportDe
             let (global_type, bytes) = decode_global_type(bytes)?;
             Ok((WasmImportDesc::Global(global_type), bytes))
         }
-        _ => Err(WasmDecodeError::InvalidImportDesc(tag)),
+        _ => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::InvalidImportDesc(tag),
+        )),
     }
 }
 
@@ -367,80 +696,144 @@ fn decode_import(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmImport>> {
 }
 
 fn decode_import_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_imports(len as usize);
     for _ in 0..len {
         let (import, rest) = decode_import(bytes)?;
         wmod.push_import(import);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
 fn decode_func_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_funcs(len as usize);
     for _ in 0..len {
         let (type_idx, rest) = decode_type_idx(bytes)?;
         wmod.push_func(type_idx);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
-fn decode_locals(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, Box<[WasmValueType]>>> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
-    let mut locals = Vec::new();
+fn decode_locals(input: &[u8]) -> WasmDecodeResult<Decoded<'_, Box<[WasmValueType]>>> {
+    let (len, mut bytes) = decode_vec_len(input)?;
+    let mut groups = Vec::with_capacity(len as usize);
+    let mut total = 0u64;
     for _ in 0..len {
         let (n, rest) = decode_leb128(bytes)?;
         let (t, rest) = decode_value_type(rest)?;
         bytes = rest;
-        for _ in 0..n {
-            locals.push(t);
-        }
+        groups.push((n, t));
+        total += n as u64;
+    }
+    // Count first so that a huge declaration fails before it is expanded.
+    if total > u32::MAX as u64 {
+        return Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::TooManyLocals,
+        ));
+    }
+    let mut locals = Vec::with_capacity(total as usize);
+    for (n, t) in groups {
+        locals.extend(std::iter::repeat_n(t, n as usize));
     }
     Ok((locals.into_boxed_slice(), bytes))
 }
 
-fn decode_code(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmCode>> {
+/// Decodes a function body. `section` is the code section, or a part of it
+/// starting at `section_offset`, that `bytes` points into.
+pub(super) fn decode_code<'b>(
+    bytes: &'b [u8],
+    section: &[u8],
+    section_offset: usize,
+    has_datacount: bool,
+) -> WasmDecodeResult<Decoded<'b, WasmCode>> {
     let (code_size, bytes) = decode_leb128(bytes)?;
+    if bytes.len() < code_size as usize {
+        return Err(eof(bytes));
+    }
     let (bytes, rest) = bytes.split_at(code_size as usize);
-    let (locals, bytes) = decode_locals(bytes)?;
-    let body = decode_expr(bytes)?;
-    Ok((WasmCode { locals, body }, rest))
+    let (locals, mut bytes) = decode_locals(bytes)?;
+    let mut body = WasmExprBuilder::new();
+    let mut offsets = Vec::new();
+    // The body is done when the `end` matching the function itself is read.
+    let mut depth = 0;
+    loop {
+        if bytes.is_empty() {
+            return Err(WasmDecodeError::at(bytes, WasmDecodeErrorKind::MissingEnd));
+        }
+        offsets.push((section_offset + bytes.as_ptr().addr() - section.as_ptr().addr()) as u32);
+        let (instr, rest) = decode_instr(bytes)?;
+        match instr {
+            WasmInstructionRepr::Block { .. }
+            | WasmInstructionRepr::Loop { .. }
+            | WasmInstructionRepr::If { .. } => depth += 1,
+            WasmInstructionRepr::MemoryInit { .. } | WasmInstructionRepr::DataDrop { .. }
+                if !has_datacount =>
+            {
+                return Err(WasmDecodeError::at(
+                    bytes,
+                    WasmDecodeErrorKind::DataCountRequired,
+                ));
+            }
+            _ => {}
+        }
+        let instr = body.push_instr(instr);
+        bytes = rest;
+        if let WasmInstructionRepr::ExprEnd = instr {
+            if depth == 0 {
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    expect_end(bytes, WasmDecodeErrorKind::BodySizeMismatch)?;
+    Ok((
+        WasmCode {
+            locals,
+            body: body.build(),
+            offsets: offsets.into_boxed_slice(),
+        },
+        rest,
+    ))
 }
 
-fn decode_code_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+fn decode_code_section(section: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
+    let (len, mut bytes) = decode_vec_len(section)?;
     wmod.reserve_code(len as usize);
-    for _ in 0..len {
-        let (code, rest) = decode_code(bytes)?;
+    let num_imported_funcs = wmod.num_imported_funcs();
+    let has_datacount = wmod.declared_datacount().is_some();
+    for i in 0..len {
+        let (code, rest) = decode_code(bytes, section, 0, has_datacount)
+            .map_err(|e| e.in_func(WasmFuncIdx(num_imported_funcs + i)))?;
         wmod.push_code(code);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
 fn decode_table_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_tables(len as usize);
     for _ in 0..len {
         let (table, rest) = decode_table_type(bytes)?;
         wmod.push_table(table);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
 fn decode_memory_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_mems(len as usize);
     for _ in 0..len {
         let (mem, rest) = decode_mem_type(bytes)?;
         wmod.push_mem(mem);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
 fn decode_block_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmBlockType>> {
@@ -449,11 +842,14 @@ fn decode_block_type(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmBlockType
     } else if let Ok((val_type, bytes)) = decode_value_type(bytes) {
         Ok((WasmBlockType::InlineType(Some(val_type)), bytes))
     } else {
-        let (s33, bytes) = decode_leb128_signed(bytes)?;
+        let (s33, rest) = decode_leb128_signed_bits(bytes, 33)?;
         if s33 >= 0 && s33 < (u32::MAX as i64) {
-            Ok((WasmBlockType::TypeRef(WasmTypeIdx(s33 as u32)), bytes))
+            Ok((WasmBlockType::TypeRef(WasmTypeIdx(s33 as u32)), rest))
         } else {
-            Err(WasmDecodeError::InvalidBlockType)
+            Err(WasmDecodeError::at(
+                bytes,
+                WasmDecodeErrorKind::InvalidBlockType,
+            ))
         }
     }
 }
@@ -465,7 +861,7 @@ fn decode_memarg(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmMemArg>> {
 }
 
 fn decode_label_indices(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, Box<[WasmLabelIdx]>>> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     let mut indices = Vec::with_capacity(len as usize);
     for _ in 0..len {
         let (label_idx, rest) = decode_label_idx(bytes)?;
@@ -475,8 +871,8 @@ fn decode_label_indices(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, Box<[WasmL
     Ok((indices.into_boxed_slice(), bytes))
 }
 
-fn decode_extended_instr(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmInstructionRaw>> {
-    let (opcode, bytes) = decode_leb128(bytes)?;
+fn decode_extended_instr(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmInstructionRaw>> {
+    let (opcode, bytes) = decode_leb128(input)?;
     use WasmInstructionRepr::*;
     match opcode {
         0 => Ok((I32TruncSatF32S, bytes)),
@@ -537,12 +933,15 @@ fn decode_extended_instr(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmInstr
             let (table_idx, bytes) = decode_table_idx(bytes)?;
             Ok((TableFill { table_idx }, bytes))
         }
-        _ => Err(WasmDecodeError::UnknownExtendedOpcode(opcode)),
+        _ => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::UnknownExtendedOpcode(opcode),
+        )),
     }
 }
 
-fn decode_instr(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmInstructionRaw>> {
-    let (opcode, bytes) = take_byte(bytes)?;
+pub(super) fn decode_instr(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmInstructionRaw>> {
+    let (opcode, bytes) = take_byte(input)?;
     use WasmInstructionRepr::*;
     match opcode {
         0x00 => Ok((Unreachable, bytes)),
@@ -766,12 +1165,8 @@ fn decode_instr(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmInstructionRaw
             Ok((MemoryGrow, bytes))
         }
         0x41 => {
-            let (v, bytes) = decode_leb128_signed(bytes)?;
-            if v >= (i32::MIN as i64) && v <= (i32::MAX as i64) {
-                Ok((I32Const { val: v as i32 }, bytes))
-            } else {
-                Err(WasmDecodeError::InvalidConst)
-            }
+            let (val, bytes) = decode_leb128_s32(bytes)?;
+            Ok((I32Const { val }, bytes))
         }
         0x42 => {
             let (val, bytes) = decode_leb128_signed(bytes)?;
@@ -916,18 +1311,11 @@ fn decode_instr(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmInstructionRaw
         0xC3 => Ok((I64Extend16S, bytes)),
         0xC4 => Ok((I64Extend32S, bytes)),
         0xFC => decode_extended_instr(bytes),
-        _ => Err(WasmDecodeError::UnknownOpcode(opcode)),
-    }
-}
-
-fn decode_expr(mut bytes: &[u8]) -> WasmDecodeResult<Box<[WasmInstructionRaw]>> {
-    let mut expr = WasmExprBuilder::new();
-    while !bytes.is_empty() {
-        let (instr, rest) = decode_instr(bytes)?;
-        expr.push_instr(instr);
-        bytes = rest;
+        _ => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::UnknownOpcode(opcode),
+        )),
     }
-    Ok(expr.build())
 }
 
 fn decode_const_expr(mut bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, Box<[WasmInstructionRaw]>>> {
@@ -955,18 +1343,18 @@ fn decode_global(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmGlobal<WasmIn
 }
 
 fn decode_global_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_globals(len as usize);
     for _ in 0..len {
         let (global, rest) = decode_global(bytes)?;
         wmod.push_global(global);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
-fn decode_export_desc(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmExportDesc>> {
-    let (tag, bytes) = take_byte(bytes)?;
+fn decode_export_desc(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmExportDesc>> {
+    let (tag, bytes) = take_byte(input)?;
     match tag {
         0x00 => {
             let (func_idx, bytes) = decode_func_idx(bytes)?;
@@ -984,7 +1372,10 @@ fn decode_export_desc(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmExportDe
             let (global_idx, bytes) = decode_global_idx(bytes)?;
             Ok((WasmExportDesc::Global(global_idx), bytes))
         }
-        _ => Err(WasmDecodeError::InvalidExportDesc(tag)),
+        _ => Err(WasmDecodeError::at(
+            input,
+            WasmDecodeErrorKind::InvalidExportDesc(tag),
+        )),
     }
 }
 
@@ -995,20 +1386,20 @@ fn decode_export(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmExport>> {
 }
 
 fn decode_export_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_exports(len as usize);
     for _ in 0..len {
         let (export, rest) = decode_export(bytes)?;
         wmod.push_export(export);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
 fn decode_elem_init_func_refs(
     bytes: &[u8],
 ) -> WasmDecodeResult<Decoded<'_, Box<[Box<WasmExprRaw>]>>> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     let mut exprs = Vec::with_capacity(len as usize);
     for _ in 0..len {
         let (func_idx, rest) = decode_func_idx(bytes)?;
@@ -1022,7 +1413,7 @@ fn decode_elem_init_func_refs(
 }
 
 fn decode_elem_init_exprs(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, Box<[Box<WasmExprRaw>]>>> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     let mut exprs = Vec::with_capacity(len as usize);
     for _ in 0..len {
         let (expr, rest) = decode_const_expr(bytes)?;
@@ -1037,8 +1428,8 @@ fn decode_elem_kind(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmRefType>>
     Ok((WasmRefType::FuncRef, bytes))
 }
 
-fn decode_elem(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmElem<WasmInstructionRaw>>> {
-    let (tag, bytes) = decode_leb128(bytes)?;
+fn decode_elem(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmElem<WasmInstructionRaw>>> {
+    let (tag, bytes) = decode_leb128(input)?;
     match tag {
         0 => {
             let (offset_expr, bytes) = decode_const_expr(bytes)?;
@@ -1152,19 +1543,19 @@ fn decode_elem(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmElem<WasmInstru
                 bytes,
             ))
         }
-        _ => Err(WasmDecodeError::InvalidElem),
+        _ => Err(WasmDecodeError::at(input, WasmDecodeErrorKind::InvalidElem)),
     }
 }
 
 fn decode_element_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_elems(len as usize);
     for _ in 0..len {
         let (elem, rest) = decode_elem(bytes)?;
         wmod.push_elem(elem);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
 fn decode_data_bytes(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, Box<[u8]>>> {
@@ -1173,8 +1564,8 @@ fn decode_data_bytes(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, Box<[u8]>>> {
     Ok((data_bytes.into_boxed_slice(), bytes))
 }
 
-fn decode_data(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmData<WasmInstructionRaw>>> {
-    let (tag, bytes) = decode_leb128(bytes)?;
+fn decode_data(input: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmData<WasmInstructionRaw>>> {
+    let (tag, bytes) = decode_leb128(input)?;
     match tag {
         0 => {
             let (offset_expr, bytes) = decode_const_expr(bytes)?;
@@ -1217,19 +1608,19 @@ fn decode_data(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, WasmData<WasmInstru
                 bytes,
             ))
         }
-        _ => Err(WasmDecodeError::InvalidData),
+        _ => Err(WasmDecodeError::at(input, WasmDecodeErrorKind::InvalidData)),
     }
 }
 
 fn decode_data_section(bytes: &[u8], wmod: &mut WasmModuleBuilder) -> WasmDecodeResult<()> {
-    let (len, mut bytes) = decode_leb128(bytes)?;
+    let (len, mut bytes) = decode_vec_len(bytes)?;
     wmod.reserve_datas(len as usize);
     for _ in 0..len {
         let (data, rest) = decode_data(bytes)?;
         wmod.push_data(data);
         bytes = rest;
     }
-    Ok(())
+    expect_end(bytes, WasmDecodeErrorKind::SectionSizeMismatch)
 }
 
 fn decode_section<'b>(
@@ -1238,63 +1629,80 @@ fn decode_section<'b>(
 ) -> WasmDecodeResult<Decoded<'b, ()>> {
     let (sid, bytes) = decode_section_id(bytes)?;
     let (len, bytes) = decode_leb128(bytes)?;
-    let section = &bytes[0..(len as usize)];
-    let rest = &bytes[(len as usize)..];
+    if bytes.len() < len as usize {
+        return Err(eof(bytes).in_section(sid));
+    }
+    let (section, rest) = bytes.split_at(len as usize);
+    decode_section_contents(sid, section, wmod).map_err(|e| e.in_section(sid))?;
+    Ok(((), rest))
+}
+
+pub(super) fn decode_section_contents(
+    sid: WasmSectionId,
+    section: &[u8],
+    wmod: &mut WasmModuleBuilder,
+) -> WasmDecodeResult<()> {
     match sid {
-        SectionId::Custom => {
+        WasmSectionId::Custom => {
             let custom = decode_custom_section(section)?;
+            // A malformed name section is not an error, the names are just unavailable.
+            if custom.name.0.as_ref() == "name"
+                && let Ok(names) = decode_name_section(&custom.bytes)
+            {
+                wmod.names(names);
+            }
             wmod.push_custom(custom);
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Type => {
+        WasmSectionId::Type => {
             decode_type_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Import => {
+        WasmSectionId::Import => {
             decode_import_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Function => {
+        WasmSectionId::Function => {
             decode_func_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Table => {
+        WasmSectionId::Table => {
             decode_table_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Memory => {
+        WasmSectionId::Memory => {
             decode_memory_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Global => {
+        WasmSectionId::Global => {
             decode_global_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Export => {
+        WasmSectionId::Export => {
             decode_export_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Start => {
-            let (func_idx, _) = decode_func_idx(section)?;
+        WasmSectionId::Start => {
+            let (func_idx, rest) = decode_func_idx(section)?;
             wmod.start(func_idx);
-            Ok(((), rest))
+            expect_end(rest, WasmDecodeErrorKind::SectionSizeMismatch)
         }
-        SectionId::Element => {
+        WasmSectionId::Element => {
             decode_element_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::Data => {
+        WasmSectionId::Data => {
             decode_data_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
-        SectionId::DataCount => {
-            let (datacount, _) = decode_leb128(section)?;
+        WasmSectionId::DataCount => {
+            let (datacount, rest) = decode_leb128(section)?;
             wmod.datacount(datacount);
-            Ok(((), rest))
+            expect_end(rest, WasmDecodeErrorKind::SectionSizeMismatch)
         }
-        SectionId::Code => {
+        WasmSectionId::Code => {
             decode_code_section(section, wmod)?;
-            Ok(((), rest))
+            Ok(())
         }
     }
 }
@@ -1303,34 +1711,159 @@ fn decode_sections<'b>(
     mut bytes: &'b [u8],
     wmod: &mut WasmModuleBuilder,
 ) -> WasmDecodeResult<Decoded<'b, ()>> {
+    let mut last = None;
     while !bytes.is_empty() {
+        let (sid, _) = decode_section_id(bytes)?;
+        if !sid.follows(&mut last) {
+            return Err(WasmDecodeError::at(
+                bytes,
+                WasmDecodeErrorKind::SectionOutOfOrder(sid),
+            ));
+        }
         let (_, rest) = decode_section(bytes, wmod)?;
         bytes = rest;
     }
     Ok(((), bytes))
 }
 
-fn decode_magic_bytes(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, ()>> {
-    let (buf, bytes) = take_bytes::<4>(bytes)?;
+pub(super) fn decode_magic_bytes(input: &[u8]) -> WasmDecodeResult<Decoded<'_, ()>> {
+    let (buf, bytes) = take_bytes::<4>(input)?;
     match buf {
         [0, b'a', b's', b'm'] => Ok(((), bytes)),
-        _ => Err(WasmDecodeError::MagicBytes),
+        _ => Err(WasmDecodeError::at(input, WasmDecodeErrorKind::MagicBytes)),
     }
 }
 
-fn decode_version(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u32>> {
+pub(super) fn decode_version(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u32>> {
     let (buf, bytes) = take_bytes::<4>(bytes)?;
     Ok((u32::from_le_bytes(buf), bytes))
 }
 
 pub fn decode(bytes: &[u8]) -> WasmDecodeResult<WasmModuleRaw> {
+    decode_module(bytes).map_err(|e| e.rebase(bytes))
+}
+
+fn decode_module(bytes: &[u8]) -> WasmDecodeResult<WasmModuleRaw> {
     let mut wmod = WasmModuleBuilder::new();
     let (_, bytes) = decode_magic_bytes(bytes)?;
-    let (version, bytes) = decode_version(bytes)?;
+    let (version, rest) = decode_version(bytes)?;
     if version != 1 {
-        return Err(WasmDecodeError::UnsupportedVersion(version));
+        return Err(WasmDecodeError::at(
+            bytes,
+            WasmDecodeErrorKind::UnsupportedVersion(version),
+        ));
     }
+    let bytes = rest;
     wmod.version(version);
-    decode_sections(bytes, &mut wmod)?;
+    let (_, rest) = decode_sections(bytes, &mut wmod)?;
+    let bodies = wmod.num_bodies();
+    check_counts(&wmod, bodies).map_err(|kind| WasmDecodeError::at(rest, kind))?;
+    decode_debug_info(&mut wmod);
     Ok(wmod.build())
 }
+
+/// Checks the counts that can only be compared once all sections are read.
+pub(super) fn check_counts(
+    wmod: &WasmModuleBuilder,
+    bodies: u32,
+) -> Result<(), WasmDecodeErrorKind> {
+    let funcs = wmod.num_funcs();
+    if funcs != bodies {
+        return Err(WasmDecodeErrorKind::FuncCodeMismatch { funcs, bodies });
+    }
+    if let Some(count) = wmod.declared_datacount()
+        && count != wmod.num_datas()
+    {
+        return Err(WasmDecodeErrorKind::DataCountMismatch {
+            count,
+            datas: wmod.num_datas(),
+        });
+    }
+    Ok(())
+}
+
+pub(super) fn decode_debug_info(wmod: &mut WasmModuleBuilder) {
+    // Like the name section, broken debug info just leaves the table empty.
+    if let Some(debug_line) = wmod.custom(".debug_line") {
+        let debug_line_str = wmod.custom(".debug_line_str");
+        let debug_str = wmod.custom(".debug_str");
+        if let Ok(lines) = dwarf::decode_line_table(debug_line, debug_line_str, debug_str) {
+            wmod.lines(lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_location() {
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+            0x03, 0x02, 0x01, 0x00, // function section
+            0x0a, 0x05, 0x01, 0x03, 0x00, 0xff, 0x0b, // code section
+        ];
+        let err = decode(&bytes).unwrap_err();
+        assert!(matches!(err.kind, WasmDecodeErrorKind::UnknownOpcode(0xff)));
+        assert_eq!(err.offset, 23);
+        assert_eq!(err.section, Some(WasmSectionId::Code));
+        assert_eq!(err.func_idx, Some(WasmFuncIdx(0)));
+        assert_eq!(
+            err.to_string(),
+            "unknown opcode 0xff at offset 0x17 in code section, function 0"
+        );
+
+        let err = decode(&bytes[..6]).unwrap_err();
+        assert!(matches!(err.kind, WasmDecodeErrorKind::UnexpectedEof));
+        assert_eq!(err.offset, 6);
+        assert_eq!(err.section, None);
+    }
+
+    #[test]
+    fn test_name_section() {
+        let bytes = [
+            0x00, 0x04, 0x03, b'm', b'o', b'd', // module name
+            0x01, 0x06, 0x01, 0x01, 0x03, b'a', b'd', b'd', // function names
+            0x02, 0x07, 0x01, 0x01, 0x01, 0x00, 0x02, b'l', b'h', // local names
+            0x04, 0x01, 0x00, // type names, ignored
+        ];
+        let names = decode_name_section(&bytes).unwrap();
+        assert_eq!(names.module_name(), Some("mod"));
+        assert_eq!(names.func_name(WasmFuncIdx(1)), Some("add"));
+        assert_eq!(names.func_name(WasmFuncIdx(0)), None);
+        assert_eq!(
+            names.local_name(WasmFuncIdx(1), WasmLocalIdx(0)),
+            Some("lh")
+        );
+
+        // A function name map claiming two entries but holding one.
+        let malformed = [0x01, 0x06, 0x02, 0x01, 0x03, b'a', b'd', b'd'];
+        assert!(decode_name_section(&malformed).is_err());
+        // A subsection longer than the section.
+        assert!(decode_name_section(&[0x00, 0x09, 0x01, b'm']).is_err());
+    }
+
+    #[test]
+    fn test_leb128_strict() {
+        assert_eq!(
+            decode_leb128(&[0xff, 0xff, 0xff, 0xff, 0x0f]).unwrap().0,
+            u32::MAX
+        );
+        let err = decode_leb128(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]).unwrap_err();
+        assert!(matches!(err.kind, WasmDecodeErrorKind::IntegerTooLong));
+        let err = decode_leb128(&[0xff, 0xff, 0xff, 0xff, 0x1f]).unwrap_err();
+        assert!(matches!(err.kind, WasmDecodeErrorKind::IntegerTooLarge));
+
+        assert_eq!(
+            decode_leb128_s32(&[0x80, 0x80, 0x80, 0x80, 0x78])
+                .unwrap()
+                .0,
+            i32::MIN
+        );
+        let err = decode_leb128_s32(&[0x80, 0x80, 0x80, 0x80, 0x08]).unwrap_err();
+        assert!(matches!(err.kind, WasmDecodeErrorKind::IntegerTooLarge));
+        assert_eq!(decode_leb128_signed(&[0x7f]).unwrap().0, -1);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{valid::validate, *};
+use super::{dwarf::WasmLineTable, valid::validate, *};
 
 #[derive(Default)]
 pub struct WasmModuleBuilder {
@@ -16,11 +16,14 @@ pub struct WasmModuleBuilder {
     imports: Vec<WasmImport>,
     exports: Vec<WasmExport>,
     customs: Vec<WasmCustom>,
+    names: WasmNameMap,
+    lines: WasmLineTable,
 }
 
 pub struct WasmCode {
     pub locals: Box<[WasmValueType]>,
     pub body: Box<WasmExprRaw>,
+    pub offsets: Box<[u32]>,
 }
 
 impl WasmModuleBuilder {
@@ -36,6 +39,22 @@ impl WasmModuleBuilder {
         self.datacount = Some(count);
     }
 
+    pub fn declared_datacount(&self) -> Option<u32> {
+        self.datacount
+    }
+
+    pub fn num_funcs(&self) -> u32 {
+        self.funcs.len() as u32
+    }
+
+    pub fn num_bodies(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    pub fn num_datas(&self) -> u32 {
+        self.datas.len() as u32
+    }
+
     pub fn reserve_types(&mut self, num: usize) {
         self.types.reserve_exact(num);
     }
@@ -112,6 +131,13 @@ impl WasmModuleBuilder {
         self.imports.push(import);
     }
 
+    pub fn num_imported_funcs(&self) -> u32 {
+        self.imports
+            .iter()
+            .filter(|import| matches!(import.desc, WasmImportDesc::Func(_)))
+            .count() as u32
+    }
+
     pub fn reserve_exports(&mut self, num: usize) {
         self.exports.reserve_exact(num);
     }
@@ -124,6 +150,20 @@ impl WasmModuleBuilder {
         self.customs.push(custom);
     }
 
+    pub fn names(&mut self, names: WasmNameMap) {
+        self.names = names;
+    }
+
+    pub fn lines(&mut self, lines: WasmLineTable) {
+        self.lines = lines;
+    }
+
+    pub fn custom(&self, name: &str) -> Option<&WasmCustom> {
+        self.customs
+            .iter()
+            .find(|custom| custom.name.0.as_ref() == name)
+    }
+
     pub fn build(self) -> WasmModuleRaw {
         let funcs = self
             .funcs
@@ -133,6 +173,7 @@ impl WasmModuleBuilder {
                 type_idx,
                 locals: code.locals,
                 body: code.body,
+                offsets: code.offsets,
             })
             .collect::<Vec<_>>();
         WasmModuleRaw {
@@ -148,6 +189,52 @@ impl WasmModuleBuilder {
             imports: self.imports.into_boxed_slice(),
             exports: self.exports.into_boxed_slice(),
             customs: self.customs.into_boxed_slice(),
+            names: self.names,
+            lines: self.lines,
+            lazy_funcs: None,
+        }
+    }
+
+    /// Builds the module declared so far, with bodiless functions. The
+    /// streaming decoder validates bodies against this before it has seen
+    /// the sections that follow the code section.
+    pub fn build_declarations(self) -> WasmModuleRaw {
+        let funcs = self
+            .funcs
+            .iter()
+            .map(|&type_idx| WasmFunc {
+                type_idx,
+                locals: Box::new([]),
+                body: Box::new([]),
+                offsets: Box::new([]),
+            })
+            .collect::<Vec<_>>();
+        WasmModuleRaw {
+            funcs: funcs.into_boxed_slice(),
+            ..self.build()
+        }
+    }
+
+    /// Resumes building a module returned by
+    /// [`WasmModuleBuilder::build_declarations`].
+    pub fn from_declarations(wmod: WasmModuleRaw, datacount: Option<u32>) -> Self {
+        WasmModuleBuilder {
+            version: wmod.version,
+            datacount,
+            types: wmod.types.into_vec(),
+            funcs: wmod.funcs.iter().map(|func| func.type_idx).collect(),
+            code: Vec::new(),
+            tables: wmod.tables.into_vec(),
+            mems: wmod.mems.into_vec(),
+            globals: wmod.globals.into_vec(),
+            elems: wmod.elems.into_vec(),
+            datas: wmod.datas.into_vec(),
+            start: wmod.start,
+            imports: wmod.imports.into_vec(),
+            exports: wmod.exports.into_vec(),
+            customs: wmod.customs.into_vec(),
+            names: wmod.names,
+            lines: wmod.lines,
         }
     }
 }
@@ -0,0 +1,728 @@
+use std::{
+    collections::HashMap,
+    env,
+    hash::Hash,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{
+    bin::{
+        WasmDecodeError, decode_instr, decode_leb128, decode_name, decode_ref_type,
+        decode_value_type, take_byte, take_bytes, take_bytes_dyn,
+    },
+    dwarf::{LineRow, WasmLineTable},
+    enc::{encode_instr, encode_leb128, encode_ref_type, encode_value_type},
+    *,
+};
+
+const MAGIC: &[u8; 8] = b"SMBLCACH";
+const VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum WasmCacheError {
+    Io(io::Error),
+    MagicBytes,
+    UnsupportedVersion(u32),
+    /// The cache was written by another semblance version or for other source bytes.
+    Stale,
+    /// The payload does not match its checksum.
+    Corrupt,
+    Decode(WasmDecodeError),
+    /// The cached module is not valid. Function bodies are validated when
+    /// first needed, so an invalid body is not reported here.
+    Invalid(WasmValidationError),
+}
+
+impl From<io::Error> for WasmCacheError {
+    fn from(value: io::Error) -> Self {
+        WasmCacheError::Io(value)
+    }
+}
+
+impl From<WasmDecodeError> for WasmCacheError {
+    fn from(value: WasmDecodeError) -> Self {
+        WasmCacheError::Decode(value)
+    }
+}
+
+impl From<WasmValidationError> for WasmCacheError {
+    fn from(value: WasmValidationError) -> Self {
+        WasmCacheError::Invalid(value)
+    }
+}
+
+/// The directory the CLIs keep their module caches in: `$SEMBLANCE_CACHE_DIR`,
+/// or a `semblance` directory in the user's cache directory.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("SEMBLANCE_CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        return Some(PathBuf::from(dir).join("semblance"));
+    }
+    let home = env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".cache").join("semblance"))
+}
+
+/// 64-bit FNV-1a, used to key cache files by the module they were built from.
+pub(super) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub(super) fn cache_path(cache_dir: &Path, source: &[u8]) -> PathBuf {
+    cache_dir.join(format!("{:016x}.smblc", hash_bytes(source)))
+}
+
+impl WasmModule {
+    /// Serializes the decoded module. `source` is the binary the module was
+    /// read from. Function bodies whose validation was deferred are
+    /// validated first, and an invalid one is an error.
+    pub fn to_cache_bytes(&self, source: &[u8]) -> WasmValidationResult<Vec<u8>> {
+        let mut payload = Vec::new();
+        encode_module(&mut payload, self, &self.validated_funcs()?);
+
+        let mut out = Vec::with_capacity(payload.len() + 64);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        put_bytes(&mut out, env!("CARGO_PKG_VERSION").as_bytes());
+        out.extend_from_slice(&hash_bytes(source).to_le_bytes());
+        out.extend_from_slice(&(source.len() as u64).to_le_bytes());
+        out.extend_from_slice(&hash_bytes(&payload).to_le_bytes());
+        out.extend_from_slice(&payload);
+        Ok(out)
+    }
+
+    /// Deserializes a module written by [`WasmModule::to_cache_bytes`]
+    /// without decoding `source` again. Nothing in the cache is trusted: the
+    /// module is validated as with [`WasmValidationOptions::lazy`], so each
+    /// function body is validated the first time it is needed.
+    pub fn from_cache_bytes(bytes: &[u8], source: &[u8]) -> Result<Self, WasmCacheError> {
+        Self::decode_cache(bytes, source).map_err(|e| match e {
+            WasmCacheError::Decode(e) => WasmCacheError::Decode(e.rebase(bytes)),
+            e => e,
+        })
+    }
+
+    fn decode_cache(bytes: &[u8], source: &[u8]) -> Result<Self, WasmCacheError> {
+        let (magic, bytes) = take_bytes::<8>(bytes)?;
+        if &magic != MAGIC {
+            return Err(WasmCacheError::MagicBytes);
+        }
+        let (version, bytes) = take_bytes::<4>(bytes)?;
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(WasmCacheError::UnsupportedVersion(version));
+        }
+        let (crate_version, bytes) = decode_name(bytes)?;
+        let (source_hash, bytes) = take_bytes::<8>(bytes)?;
+        let (source_len, bytes) = take_bytes::<8>(bytes)?;
+        if crate_version.0.as_ref() != env!("CARGO_PKG_VERSION")
+            || u64::from_le_bytes(source_hash) != hash_bytes(source)
+            || u64::from_le_bytes(source_len) != source.len() as u64
+        {
+            return Err(WasmCacheError::Stale);
+        }
+        let (checksum, payload) = take_bytes::<8>(bytes)?;
+        if u64::from_le_bytes(checksum) != hash_bytes(payload) {
+            return Err(WasmCacheError::Corrupt);
+        }
+        let mut reader = CacheReader { bytes: payload };
+        let wmod = reader.module()?;
+        if !reader.bytes.is_empty() {
+            return Err(WasmCacheError::Corrupt);
+        }
+        let options = WasmValidationOptions {
+            lazy: true,
+            ..Default::default()
+        };
+        Ok(validate_with_options(wmod, options)?)
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, val: u32) {
+    encode_leb128(out, val);
+}
+
+fn put_len(out: &mut Vec<u8>, len: usize) {
+    encode_leb128(out, len as u32);
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_len(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+fn put_name(out: &mut Vec<u8>, name: &WasmName) {
+    put_bytes(out, name.0.as_bytes());
+}
+
+fn put_value_types(out: &mut Vec<u8>, val_types: &[WasmValueType]) {
+    put_len(out, val_types.len());
+    for val_type in val_types {
+        encode_value_type(out, *val_type);
+    }
+}
+
+fn put_limits(out: &mut Vec<u8>, limits: &WasmLimits) {
+    put_u32(out, limits.min);
+    match limits.max {
+        Some(max) => {
+            out.push(1);
+            put_u32(out, max);
+        }
+        None => out.push(0),
+    }
+}
+
+fn put_global_type(out: &mut Vec<u8>, global_type: &WasmGlobalType) {
+    out.push(match global_type.mutability {
+        WasmGlobalMutability::Immutable => 0,
+        WasmGlobalMutability::Mutable => 1,
+    });
+    encode_value_type(out, global_type.val_type);
+}
+
+fn put_expr(out: &mut Vec<u8>, expr: &WasmExpr) {
+    put_len(out, expr.len());
+    for instr in expr {
+        encode_instr(out, instr);
+    }
+}
+
+fn put_name_map<I: Copy>(out: &mut Vec<u8>, map: &HashMap<I, WasmName>, to_idx: fn(I) -> u32) {
+    let mut entries: Vec<_> = map.iter().map(|(idx, name)| (to_idx(*idx), name)).collect();
+    entries.sort_by_key(|(idx, _)| *idx);
+    put_len(out, entries.len());
+    for (idx, name) in entries {
+        put_u32(out, idx);
+        put_name(out, name);
+    }
+}
+
+fn encode_module(out: &mut Vec<u8>, wmod: &WasmModule, funcs: &[&WasmFunc]) {
+    put_u32(out, wmod.version);
+
+    put_len(out, wmod.types.len());
+    for func_type in &wmod.types {
+        put_value_types(out, &func_type.input_type.0);
+        put_value_types(out, &func_type.output_type.0);
+    }
+
+    put_len(out, funcs.len());
+    for func in funcs {
+        put_u32(out, func.type_idx.0);
+        put_value_types(out, &func.locals);
+        put_expr(out, &func.body);
+        put_len(out, func.offsets.len());
+        for offset in &func.offsets {
+            put_u32(out, *offset);
+        }
+    }
+
+    put_len(out, wmod.tables.len());
+    for table in &wmod.tables {
+        encode_ref_type(out, table.ref_type);
+        put_limits(out, &table.limits);
+    }
+
+    put_len(out, wmod.mems.len());
+    for mem in &wmod.mems {
+        put_limits(out, &mem.limits);
+    }
+
+    put_len(out, wmod.globals.len());
+    for global in &wmod.globals {
+        put_global_type(out, &global.global_type);
+        put_expr(out, &global.init);
+    }
+
+    put_len(out, wmod.elems.len());
+    for elem in &wmod.elems {
+        encode_ref_type(out, elem.ref_type);
+        put_len(out, elem.init.len());
+        for expr in &elem.init {
+            put_expr(out, expr);
+        }
+        match &elem.elem_mode {
+            WasmElemMode::Passive => out.push(0),
+            WasmElemMode::Active {
+                table_idx,
+                offset_expr,
+            } => {
+                out.push(1);
+                put_u32(out, table_idx.0);
+                put_expr(out, offset_expr);
+            }
+            WasmElemMode::Declarative => out.push(2),
+        }
+    }
+
+    put_len(out, wmod.datas.len());
+    for data in &wmod.datas {
+        put_bytes(out, &data.bytes);
+        match &data.mode {
+            WasmDataMode::Passive => out.push(0),
+            WasmDataMode::Active {
+                mem_idx,
+                offset_expr,
+            } => {
+                out.push(1);
+                put_u32(out, mem_idx.0);
+                put_expr(out, offset_expr);
+            }
+        }
+    }
+
+    match wmod.start {
+        Some(func_idx) => {
+            out.push(1);
+            put_u32(out, func_idx.0);
+        }
+        None => out.push(0),
+    }
+
+    put_len(out, wmod.imports.len());
+    for import in &wmod.imports {
+        put_name(out, &import.module_name);
+        put_name(out, &import.item_name);
+        match &import.desc {
+            WasmImportDesc::Func(type_idx) => {
+                out.push(0);
+                put_u32(out, type_idx.0);
+            }
+            WasmImportDesc::Table(table) => {
+                out.push(1);
+                encode_ref_type(out, table.ref_type);
+                put_limits(out, &table.limits);
+            }
+            WasmImportDesc::Mem(mem) => {
+                out.push(2);
+                put_limits(out, &mem.limits);
+            }
+            WasmImportDesc::Global(global_type) => {
+                out.push(3);
+                put_global_type(out, global_type);
+            }
+        }
+    }
+
+    put_len(out, wmod.exports.len());
+    for export in &wmod.exports {
+        put_name(out, &export.name);
+        let (tag, idx) = match export.desc {
+            WasmExportDesc::Func(idx) => (0, idx.0),
+            WasmExportDesc::Table(idx) => (1, idx.0),
+            WasmExportDesc::Mem(idx) => (2, idx.0),
+            WasmExportDesc::Global(idx) => (3, idx.0),
+        };
+        out.push(tag);
+        put_u32(out, idx);
+    }
+
+    put_len(out, wmod.customs.len());
+    for custom in &wmod.customs {
+        put_name(out, &custom.name);
+        put_bytes(out, &custom.bytes);
+    }
+
+    let names = &wmod.names;
+    match &names.module {
+        Some(name) => {
+            out.push(1);
+            put_name(out, name);
+        }
+        None => out.push(0),
+    }
+    put_name_map(out, &names.funcs, |idx| idx.0);
+    let mut locals: Vec<_> = names.locals.iter().collect();
+    locals.sort_by_key(|(func_idx, _)| func_idx.0);
+    put_len(out, locals.len());
+    for (func_idx, local_names) in locals {
+        put_u32(out, func_idx.0);
+        put_name_map(out, local_names, |idx| idx.0);
+    }
+    put_name_map(out, &names.tables, |idx| idx.0);
+    put_name_map(out, &names.mems, |idx| idx.0);
+    put_name_map(out, &names.globals, |idx| idx.0);
+    put_name_map(out, &names.elems, |idx| idx.0);
+    put_name_map(out, &names.datas, |idx| idx.0);
+
+    put_len(out, wmod.lines.files.len());
+    for file in &wmod.lines.files {
+        put_bytes(out, file.as_bytes());
+    }
+    put_len(out, wmod.lines.rows.len());
+    for row in &wmod.lines.rows {
+        put_u32(out, row.address);
+        put_u32(out, row.file);
+        put_u32(out, row.line);
+        put_u32(out, row.column);
+        out.push(row.end_sequence as u8);
+    }
+}
+
+type CacheResult<T> = Result<T, WasmCacheError>;
+
+struct CacheReader<'b> {
+    bytes: &'b [u8],
+}
+
+impl CacheReader<'_> {
+    fn byte(&mut self) -> CacheResult<u8> {
+        let (byte, rest) = take_byte(self.bytes)?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn u32(&mut self) -> CacheResult<u32> {
+        let (val, rest) = decode_leb128(self.bytes)?;
+        self.bytes = rest;
+        Ok(val)
+    }
+
+    /// Reads an element count, rejecting counts that cannot fit in the
+    /// remaining bytes so a bad count cannot cause a huge allocation.
+    fn len(&mut self) -> CacheResult<usize> {
+        let len = self.u32()? as usize;
+        if len > self.bytes.len() {
+            return Err(WasmCacheError::Corrupt);
+        }
+        Ok(len)
+    }
+
+    fn bytes(&mut self) -> CacheResult<Box<[u8]>> {
+        let len = self.len()?;
+        let (bytes, rest) = take_bytes_dyn(self.bytes, len)?;
+        self.bytes = rest;
+        Ok(bytes.into_boxed_slice())
+    }
+
+    fn name(&mut self) -> CacheResult<WasmName> {
+        let (name, rest) = decode_name(self.bytes)?;
+        self.bytes = rest;
+        Ok(name)
+    }
+
+    fn value_type(&mut self) -> CacheResult<WasmValueType> {
+        let (val_type, rest) = decode_value_type(self.bytes)?;
+        self.bytes = rest;
+        Ok(val_type)
+    }
+
+    fn ref_type(&mut self) -> CacheResult<WasmRefType> {
+        let (ref_type, rest) = decode_ref_type(self.bytes)?;
+        self.bytes = rest;
+        Ok(ref_type)
+    }
+
+    fn value_types(&mut self) -> CacheResult<Box<[WasmValueType]>> {
+        let len = self.len()?;
+        (0..len).map(|_| self.value_type()).collect()
+    }
+
+    fn limits(&mut self) -> CacheResult<WasmLimits> {
+        let min = self.u32()?;
+        let max = match self.byte()? {
+            0 => None,
+            _ => Some(self.u32()?),
+        };
+        Ok(WasmLimits { min, max })
+    }
+
+    fn global_type(&mut self) -> CacheResult<WasmGlobalType> {
+        let mutability = match self.byte()? {
+            0 => WasmGlobalMutability::Immutable,
+            _ => WasmGlobalMutability::Mutable,
+        };
+        let val_type = self.value_type()?;
+        Ok(WasmGlobalType {
+            mutability,
+            val_type,
+        })
+    }
+
+    fn instr(&mut self) -> CacheResult<WasmInstructionRaw> {
+        let (instr, rest) = decode_instr(self.bytes)?;
+        self.bytes = rest;
+        Ok(instr)
+    }
+
+    fn expr(&mut self) -> CacheResult<Box<WasmExprRaw>> {
+        let len = self.len()?;
+        (0..len).map(|_| self.instr()).collect()
+    }
+
+    fn name_map<I: Eq + Hash>(
+        &mut self,
+        from_idx: fn(u32) -> I,
+    ) -> CacheResult<HashMap<I, WasmName>> {
+        let len = self.len()?;
+        let mut map = HashMap::with_capacity(len);
+        for _ in 0..len {
+            let idx = from_idx(self.u32()?);
+            map.insert(idx, self.name()?);
+        }
+        Ok(map)
+    }
+
+    fn module(&mut self) -> CacheResult<WasmModuleRaw> {
+        let version = self.u32()?;
+
+        let len = self.len()?;
+        let types = (0..len)
+            .map(|_| {
+                Ok(WasmFuncType {
+                    input_type: WasmResultType(self.value_types()?),
+                    output_type: WasmResultType(self.value_types()?),
+                })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let len = self.len()?;
+        let funcs = (0..len)
+            .map(|_| {
+                let type_idx = WasmTypeIdx(self.u32()?);
+                let locals = self.value_types()?;
+                let body = self.expr()?;
+                let len = self.len()?;
+                let offsets = (0..len).map(|_| self.u32()).collect::<CacheResult<_>>()?;
+                Ok(WasmFunc {
+                    type_idx,
+                    locals,
+                    body,
+                    offsets,
+                })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let len = self.len()?;
+        let tables = (0..len)
+            .map(|_| {
+                let ref_type = self.ref_type()?;
+                let limits = self.limits()?;
+                Ok(WasmTableType { limits, ref_type })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let len = self.len()?;
+        let mems = (0..len)
+            .map(|_| {
+                Ok(WasmMemType {
+                    limits: self.limits()?,
+                })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let len = self.len()?;
+        let globals = (0..len)
+            .map(|_| {
+                Ok(WasmGlobal {
+                    global_type: self.global_type()?,
+                    init: self.expr()?,
+                })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let len = self.len()?;
+        let elems = (0..len)
+            .map(|_| {
+                let ref_type = self.ref_type()?;
+                let len = self.len()?;
+                let init = (0..len).map(|_| self.expr()).collect::<CacheResult<_>>()?;
+                let elem_mode = match self.byte()? {
+                    0 => WasmElemMode::Passive,
+                    1 => WasmElemMode::Active {
+                        table_idx: WasmTableIdx(self.u32()?),
+                        offset_expr: self.expr()?,
+                    },
+                    _ => WasmElemMode::Declarative,
+                };
+                Ok(WasmElem {
+                    ref_type,
+                    init,
+                    elem_mode,
+                })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let len = self.len()?;
+        let datas = (0..len)
+            .map(|_| {
+                let bytes = self.bytes()?;
+                let mode = match self.byte()? {
+                    0 => WasmDataMode::Passive,
+                    _ => WasmDataMode::Active {
+                        mem_idx: WasmMemIdx(self.u32()?),
+                        offset_expr: self.expr()?,
+                    },
+                };
+                Ok(WasmData { bytes, mode })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let start = match self.byte()? {
+            0 => None,
+            _ => Some(WasmFuncIdx(self.u32()?)),
+        };
+
+        let len = self.len()?;
+        let imports = (0..len)
+            .map(|_| {
+                let module_name = self.name()?;
+                let item_name = self.name()?;
+                let desc = match self.byte()? {
+                    0 => WasmImportDesc::Func(WasmTypeIdx(self.u32()?)),
+                    1 => {
+                        let ref_type = self.ref_type()?;
+                        let limits = self.limits()?;
+                        WasmImportDesc::Table(WasmTableType { limits, ref_type })
+                    }
+                    2 => WasmImportDesc::Mem(WasmMemType {
+                        limits: self.limits()?,
+                    }),
+                    _ => WasmImportDesc::Global(self.global_type()?),
+                };
+                Ok(WasmImport {
+                    module_name,
+                    item_name,
+                    desc,
+                })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let len = self.len()?;
+        let exports = (0..len)
+            .map(|_| {
+                let name = self.name()?;
+                let tag = self.byte()?;
+                let idx = self.u32()?;
+                let desc = match tag {
+                    0 => WasmExportDesc::Func(WasmFuncIdx(idx)),
+                    1 => WasmExportDesc::Table(WasmTableIdx(idx)),
+                    2 => WasmExportDesc::Mem(WasmMemIdx(idx)),
+                    _ => WasmExportDesc::Global(WasmGlobalIdx(idx)),
+                };
+                Ok(WasmExport { name, desc })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let len = self.len()?;
+        let customs = (0..len)
+            .map(|_| {
+                Ok(WasmCustom {
+                    name: self.name()?,
+                    bytes: self.bytes()?,
+                })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        let module = match self.byte()? {
+            0 => None,
+            _ => Some(self.name()?),
+        };
+        let funcs_names = self.name_map(WasmFuncIdx)?;
+        let len = self.len()?;
+        let mut locals = HashMap::with_capacity(len);
+        for _ in 0..len {
+            let func_idx = WasmFuncIdx(self.u32()?);
+            locals.insert(func_idx, self.name_map(WasmLocalIdx)?);
+        }
+        let names = WasmNameMap {
+            module,
+            funcs: funcs_names,
+            locals,
+            tables: self.name_map(WasmTableIdx)?,
+            mems: self.name_map(WasmMemIdx)?,
+            globals: self.name_map(WasmGlobalIdx)?,
+            elems: self.name_map(WasmElemIdx)?,
+            datas: self.name_map(WasmDataIdx)?,
+        };
+
+        let len = self.len()?;
+        let files = (0..len)
+            .map(|_| {
+                let name = self.name()?;
+                Ok(Arc::from(name.0))
+            })
+            .collect::<CacheResult<_>>()?;
+        let len = self.len()?;
+        let rows = (0..len)
+            .map(|_| {
+                Ok(LineRow {
+                    address: self.u32()?,
+                    file: self.u32()?,
+                    line: self.u32()?,
+                    column: self.u32()?,
+                    end_sequence: self.byte()? != 0,
+                })
+            })
+            .collect::<CacheResult<_>>()?;
+
+        Ok(WasmModuleRaw {
+            version,
+            types,
+            funcs,
+            tables,
+            mems,
+            globals,
+            elems,
+            datas,
+            start,
+            imports,
+            exports,
+            customs,
+            names,
+            lines: WasmLineTable { files, rows },
+            lazy_funcs: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::fixtures::MODULE, *};
+
+    #[test]
+    fn test_cache_round_trip() {
+        let wmod = WasmModule::from_bytes(MODULE).unwrap();
+        let bytes = wmod.to_cache_bytes(MODULE).unwrap();
+        let cached = WasmModule::from_cache_bytes(&bytes, MODULE).unwrap();
+        assert_eq!(
+            format!("{:?}", wmod.funcs),
+            format!("{:?}", cached.validated_funcs().unwrap())
+        );
+        assert_eq!(wmod.to_bytes().unwrap(), cached.to_bytes().unwrap());
+
+        let mut other = MODULE.to_vec();
+        other[0x18] = b'g';
+        assert!(matches!(
+            WasmModule::from_cache_bytes(&bytes, &other),
+            Err(WasmCacheError::Stale)
+        ));
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        assert!(matches!(
+            WasmModule::from_cache_bytes(&corrupt, MODULE),
+            Err(WasmCacheError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn test_cache_validates_bodies() {
+        let mut wmod = WasmModule::from_bytes(MODULE).unwrap();
+        // A block without its `end` would let a jump run off the body.
+        wmod.funcs[0].body[0] = WasmInstructionRepr::Nop;
+        // The checksum covers the bad body, so only validation catches it.
+        let bytes = wmod.to_cache_bytes(MODULE).unwrap();
+        let cached = WasmModule::from_cache_bytes(&bytes, MODULE).unwrap();
+        assert!(cached.validated_funcs().is_err());
+    }
+}
@@ -0,0 +1,509 @@
+use std::sync::Arc;
+
+use super::{
+    WasmCustom,
+    bin::{
+        Decoded, WasmDecodeError, WasmDecodeErrorKind, WasmDecodeResult, decode_leb128,
+        decode_leb128_signed, eof, take_byte, take_bytes,
+    },
+};
+
+/// The DWARF line table of a module, mapping code section offsets to
+/// source locations.
+#[derive(Debug, Default)]
+pub struct WasmLineTable {
+    pub(super) files: Vec<Arc<str>>,
+    pub(super) rows: Vec<LineRow>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WasmSourceLocation {
+    pub file: Arc<str>,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct LineRow {
+    pub(super) address: u32,
+    pub(super) file: u32,
+    pub(super) line: u32,
+    pub(super) column: u32,
+    pub(super) end_sequence: bool,
+}
+
+impl WasmLineTable {
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Looks up the source location of the instruction at `offset`, a byte
+    /// offset relative to the start of the code section.
+    pub fn lookup(&self, offset: u32) -> Option<WasmSourceLocation> {
+        let idx = self.rows.partition_point(|row| row.address <= offset);
+        let row = self.rows.get(idx.checked_sub(1)?)?;
+        if row.end_sequence || row.line == 0 {
+            return None;
+        }
+        Some(WasmSourceLocation {
+            file: self.files.get(row.file as usize)?.clone(),
+            line: row.line,
+            column: row.column,
+        })
+    }
+}
+
+impl std::fmt::Display for WasmSourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.column == 0 {
+            write!(f, "{}:{}", self.file, self.line)
+        } else {
+            write!(f, "{}:{}:{}", self.file, self.line, self.column)
+        }
+    }
+}
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_SET_COLUMN: u8 = 5;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+
+const DW_LNCT_PATH: u32 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u32 = 2;
+
+const DW_FORM_BLOCK: u32 = 0x09;
+const DW_FORM_DATA1: u32 = 0x0b;
+const DW_FORM_DATA2: u32 = 0x05;
+const DW_FORM_DATA4: u32 = 0x06;
+const DW_FORM_DATA8: u32 = 0x07;
+const DW_FORM_DATA16: u32 = 0x1e;
+const DW_FORM_LINE_STRP: u32 = 0x1f;
+const DW_FORM_STRING: u32 = 0x08;
+const DW_FORM_STRP: u32 = 0x0e;
+const DW_FORM_UDATA: u32 = 0x0f;
+
+/// Addresses at or above this are tombstones the linker left for code it
+/// discarded.
+const TOMBSTONE_ADDRESS: u32 = 0xffff_fff0;
+
+struct StringSections<'s> {
+    debug_line_str: &'s [u8],
+    debug_str: &'s [u8],
+}
+
+struct LineProgramHeader {
+    min_instr_len: u8,
+    line_base: i8,
+    line_range: u8,
+    opcode_base: u8,
+    standard_opcode_lengths: Vec<u8>,
+    files: Vec<Arc<str>>,
+}
+
+fn take_u16(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u16>> {
+    let (buf, bytes) = take_bytes::<2>(bytes)?;
+    Ok((u16::from_le_bytes(buf), bytes))
+}
+
+fn take_u32(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, u32>> {
+    let (buf, bytes) = take_bytes::<4>(bytes)?;
+    Ok((u32::from_le_bytes(buf), bytes))
+}
+
+fn invalid(pos: &[u8]) -> WasmDecodeError {
+    WasmDecodeError::at(pos, WasmDecodeErrorKind::InvalidDebugLine)
+}
+
+fn take_slice(bytes: &[u8], n: usize) -> WasmDecodeResult<Decoded<'_, &[u8]>> {
+    if bytes.len() < n {
+        return Err(eof(bytes));
+    }
+    Ok(bytes.split_at(n))
+}
+
+fn take_cstr(bytes: &[u8]) -> WasmDecodeResult<Decoded<'_, String>> {
+    let len = bytes
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| eof(bytes))?;
+    let s = String::from_utf8_lossy(&bytes[..len]).into_owned();
+    Ok((s, &bytes[len + 1..]))
+}
+
+fn str_at(section: &[u8], offset: u32) -> WasmDecodeResult<String> {
+    let bytes = section
+        .get(offset as usize..)
+        .ok_or_else(|| invalid(section))?;
+    Ok(take_cstr(bytes)?.0)
+}
+
+fn join_path(dir: Option<&str>, file: String) -> Arc<str> {
+    match dir {
+        Some(dir) if !dir.is_empty() && !file.starts_with('/') => {
+            format!("{}/{}", dir.trim_end_matches('/'), file).into()
+        }
+        _ => file.into(),
+    }
+}
+
+/// Decodes one attribute of a DWARF 5 directory or file entry. Strings
+/// are returned as `Some`, everything else is returned as its numeric value.
+fn decode_entry_attr<'b>(
+    bytes: &'b [u8],
+    form: u32,
+    strs: &StringSections,
+) -> WasmDecodeResult<Decoded<'b, (Option<String>, u64)>> {
+    match form {
+        DW_FORM_STRING => {
+            let (s, bytes) = take_cstr(bytes)?;
+            Ok(((Some(s), 0), bytes))
+        }
+        DW_FORM_LINE_STRP => {
+            let (offset, bytes) = take_u32(bytes)?;
+            Ok(((Some(str_at(strs.debug_line_str, offset)?), 0), bytes))
+        }
+        DW_FORM_STRP => {
+            let (offset, bytes) = take_u32(bytes)?;
+            Ok(((Some(str_at(strs.debug_str, offset)?), 0), bytes))
+        }
+        DW_FORM_UDATA => {
+            let (val, bytes) = decode_leb128(bytes)?;
+            Ok(((None, val as u64), bytes))
+        }
+        DW_FORM_DATA1 => {
+            let (val, bytes) = take_byte(bytes)?;
+            Ok(((None, val as u64), bytes))
+        }
+        DW_FORM_DATA2 => {
+            let (val, bytes) = take_u16(bytes)?;
+            Ok(((None, val as u64), bytes))
+        }
+        DW_FORM_DATA4 => {
+            let (val, bytes) = take_u32(bytes)?;
+            Ok(((None, val as u64), bytes))
+        }
+        DW_FORM_DATA8 => {
+            let (buf, bytes) = take_bytes::<8>(bytes)?;
+            Ok(((None, u64::from_le_bytes(buf)), bytes))
+        }
+        DW_FORM_DATA16 => {
+            let (_, bytes) = take_bytes::<16>(bytes)?;
+            Ok(((None, 0), bytes))
+        }
+        DW_FORM_BLOCK => {
+            let (len, bytes) = decode_leb128(bytes)?;
+            let (_, bytes) = take_slice(bytes, len as usize)?;
+            Ok(((None, 0), bytes))
+        }
+        _ => Err(invalid(bytes)),
+    }
+}
+
+/// Decodes a DWARF 5 directory or file name table into (path, directory) pairs.
+fn decode_entry_table<'b>(
+    mut bytes: &'b [u8],
+    strs: &StringSections,
+) -> WasmDecodeResult<Decoded<'b, Vec<(String, u64)>>> {
+    let (format_count, rest) = take_byte(bytes)?;
+    bytes = rest;
+    let mut formats = Vec::with_capacity(format_count as usize);
+    for _ in 0..format_count {
+        let (content_type, rest) = decode_leb128(bytes)?;
+        let (form, rest) = decode_leb128(rest)?;
+        formats.push((content_type, form));
+        bytes = rest;
+    }
+    let (count, rest) = decode_leb128(bytes)?;
+    bytes = rest;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut path = String::new();
+        let mut dir = 0;
+        for (content_type, form) in &formats {
+            let ((s, val), rest) = decode_entry_attr(bytes, *form, strs)?;
+            match *content_type {
+                DW_LNCT_PATH => path = s.unwrap_or_default(),
+                DW_LNCT_DIRECTORY_INDEX => dir = val,
+                _ => {}
+            }
+            bytes = rest;
+        }
+        entries.push((path, dir));
+    }
+    Ok((entries, bytes))
+}
+
+fn decode_header<'b>(
+    bytes: &'b [u8],
+    version: u16,
+    strs: &StringSections,
+) -> WasmDecodeResult<Decoded<'b, LineProgramHeader>> {
+    let bytes = if version >= 5 {
+        // address_size and segment_selector_size
+        take_bytes::<2>(bytes)?.1
+    } else {
+        bytes
+    };
+    let (header_length, bytes) = take_u32(bytes)?;
+    let (header, program) = take_slice(bytes, header_length as usize)?;
+
+    let (min_instr_len, header) = take_byte(header)?;
+    let header = if version >= 4 {
+        // maximum_operations_per_instruction
+        take_byte(header)?.1
+    } else {
+        header
+    };
+    // default_is_stmt
+    let (_, header) = take_byte(header)?;
+    let (line_base, header) = take_byte(header)?;
+    let (line_range, header) = take_byte(header)?;
+    let (opcode_base, header) = take_byte(header)?;
+    if line_range == 0 || opcode_base == 0 {
+        return Err(invalid(header));
+    }
+    let (standard_opcode_lengths, mut header) = take_slice(header, opcode_base as usize - 1)?;
+
+    let mut files = Vec::new();
+    if version >= 5 {
+        let (dirs, rest) = decode_entry_table(header, strs)?;
+        let (entries, _) = decode_entry_table(rest, strs)?;
+        for (path, dir) in entries {
+            let dir = dirs.get(dir as usize).map(|(dir, _)| dir.as_str());
+            files.push(join_path(dir, path));
+        }
+    } else {
+        let mut dirs = Vec::new();
+        loop {
+            let (dir, rest) = take_cstr(header)?;
+            header = rest;
+            if dir.is_empty() {
+                break;
+            }
+            dirs.push(dir);
+        }
+        // File numbers start at 1 before DWARF 5, keep index 0 unused.
+        files.push("".into());
+        loop {
+            let (path, rest) = take_cstr(header)?;
+            if path.is_empty() {
+                break;
+            }
+            let (dir, rest) = decode_leb128(rest)?;
+            let (_mtime, rest) = decode_leb128(rest)?;
+            let (_len, rest) = decode_leb128(rest)?;
+            header = rest;
+            let dir = (dir as usize)
+                .checked_sub(1)
+                .and_then(|dir| dirs.get(dir))
+                .map(String::as_str);
+            files.push(join_path(dir, path));
+        }
+    }
+
+    Ok((
+        LineProgramHeader {
+            min_instr_len,
+            line_base: line_base as i8,
+            line_range,
+            opcode_base,
+            standard_opcode_lengths: standard_opcode_lengths.to_vec(),
+            files,
+        },
+        program,
+    ))
+}
+
+struct LineState {
+    address: u32,
+    file: u32,
+    line: u32,
+    column: u32,
+}
+
+impl LineState {
+    fn new(version: u16) -> Self {
+        LineState {
+            address: 0,
+            file: if version >= 5 { 0 } else { 1 },
+            line: 1,
+            column: 0,
+        }
+    }
+}
+
+/// Runs the line number program of one unit, appending its rows to `rows`.
+/// `file_base` is the index of the unit's first file in the table's files.
+fn run_line_program(
+    mut program: &[u8],
+    version: u16,
+    header: &LineProgramHeader,
+    file_base: u32,
+    rows: &mut Vec<LineRow>,
+) -> WasmDecodeResult<()> {
+    let mut state = LineState::new(version);
+    let mut sequence = Vec::new();
+    let min_instr_len = header.min_instr_len as u32;
+
+    let mut emit = |state: &LineState, end_sequence: bool, sequence: &mut Vec<LineRow>| {
+        sequence.push(LineRow {
+            address: state.address,
+            file: file_base + state.file,
+            line: state.line,
+            column: state.column,
+            end_sequence,
+        });
+        if end_sequence {
+            if sequence[0].address < TOMBSTONE_ADDRESS {
+                rows.append(sequence);
+            }
+            sequence.clear();
+        }
+    };
+
+    while !program.is_empty() {
+        let (opcode, rest) = take_byte(program)?;
+        program = rest;
+        if opcode >= header.opcode_base {
+            let adjusted = opcode - header.opcode_base;
+            let advance = (adjusted / header.line_range) as u32;
+            state.address = state.address.wrapping_add(advance * min_instr_len);
+            let line_delta = header.line_base as i32 + (adjusted % header.line_range) as i32;
+            state.line = state.line.wrapping_add_signed(line_delta);
+            emit(&state, false, &mut sequence);
+            continue;
+        }
+        match opcode {
+            0 => {
+                let (len, rest) = decode_leb128(program)?;
+                let (ext, rest) = take_slice(rest, len as usize)?;
+                program = rest;
+                let (sub_opcode, operands) = take_byte(ext)?;
+                match sub_opcode {
+                    DW_LNE_END_SEQUENCE => {
+                        emit(&state, true, &mut sequence);
+                        state = LineState::new(version);
+                    }
+                    DW_LNE_SET_ADDRESS => {
+                        let (address, _) = take_u32(operands)?;
+                        state.address = address;
+                    }
+                    _ => {}
+                }
+            }
+            DW_LNS_COPY => emit(&state, false, &mut sequence),
+            DW_LNS_ADVANCE_PC => {
+                let (advance, rest) = decode_leb128(program)?;
+                program = rest;
+                state.address = state.address.wrapping_add(advance * min_instr_len);
+            }
+            DW_LNS_ADVANCE_LINE => {
+                let (delta, rest) = decode_leb128_signed(program)?;
+                program = rest;
+                state.line = state.line.wrapping_add_signed(delta as i32);
+            }
+            DW_LNS_SET_FILE => {
+                let (file, rest) = decode_leb128(program)?;
+                program = rest;
+                state.file = file;
+            }
+            DW_LNS_SET_COLUMN => {
+                let (column, rest) = decode_leb128(program)?;
+                program = rest;
+                state.column = column;
+            }
+            DW_LNS_CONST_ADD_PC => {
+                let advance = ((255 - header.opcode_base) / header.line_range) as u32;
+                state.address = state.address.wrapping_add(advance * min_instr_len);
+            }
+            DW_LNS_FIXED_ADVANCE_PC => {
+                let (advance, rest) = take_u16(program)?;
+                program = rest;
+                state.address = state.address.wrapping_add(advance as u32);
+            }
+            _ => {
+                // Skip the operands of opcodes that don't affect the rows we keep.
+                let n = header.standard_opcode_lengths[opcode as usize - 1];
+                for _ in 0..n {
+                    program = decode_leb128(program)?.1;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn decode_line_table(
+    debug_line: &WasmCustom,
+    debug_line_str: Option<&WasmCustom>,
+    debug_str: Option<&WasmCustom>,
+) -> WasmDecodeResult<WasmLineTable> {
+    let strs = StringSections {
+        debug_line_str: debug_line_str.map(|c| c.bytes.as_ref()).unwrap_or(&[]),
+        debug_str: debug_str.map(|c| c.bytes.as_ref()).unwrap_or(&[]),
+    };
+    let mut table = WasmLineTable::default();
+    let mut bytes = debug_line.bytes.as_ref();
+    while !bytes.is_empty() {
+        let (unit_length, rest) = take_u32(bytes)?;
+        if unit_length == 0xffff_ffff {
+            // 64-bit DWARF is never produced for wasm32.
+            return Err(invalid(bytes));
+        }
+        let (unit, rest) = take_slice(rest, unit_length as usize)?;
+        bytes = rest;
+
+        let (version, unit) = take_u16(unit)?;
+        if !(2..=5).contains(&version) {
+            return Err(invalid(unit));
+        }
+        let (header, program) = decode_header(unit, version, &strs)?;
+        let file_base = table.files.len() as u32;
+        table.files.extend(header.files.iter().cloned());
+        run_line_program(program, version, &header, file_base, &mut table.rows)?;
+    }
+    table
+        .rows
+        .sort_by_key(|row| (row.address, !row.end_sequence));
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::WasmName;
+
+    #[test]
+    fn test_decode_v4_line_table() {
+        let mut unit = vec![4, 0];
+        let header: &[u8] = &[
+            1, 1, 1, 0xfb, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1, b's', b'r', b'c', 0, 0,
+            b'a', b'.', b'c', 0, 1, 0, 0, 0,
+        ];
+        unit.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        unit.extend_from_slice(header);
+        unit.extend_from_slice(&[0, 5, DW_LNE_SET_ADDRESS, 0x10, 0, 0, 0]);
+        unit.extend_from_slice(&[DW_LNS_ADVANCE_LINE, 4, DW_LNS_COPY]);
+        unit.extend_from_slice(&[DW_LNS_ADVANCE_PC, 6, DW_LNS_ADVANCE_LINE, 2, DW_LNS_COPY]);
+        unit.extend_from_slice(&[DW_LNS_ADVANCE_PC, 2, 0, 1, DW_LNE_END_SEQUENCE]);
+        let mut bytes = (unit.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(unit);
+        let custom = WasmCustom {
+            name: WasmName(".debug_line".into()),
+            bytes: bytes.into(),
+        };
+
+        let table = decode_line_table(&custom, None, None).unwrap();
+        assert!(table.lookup(0x0f).is_none());
+        let loc = table.lookup(0x12).unwrap();
+        assert_eq!((loc.file.as_ref(), loc.line), ("src/a.c", 5));
+        assert_eq!(table.lookup(0x16).unwrap().line, 7);
+        assert!(table.lookup(0x18).is_none());
+    }
+}
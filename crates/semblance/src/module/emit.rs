@@ -0,0 +1,561 @@
+use super::{
+    builder::{WasmCode, WasmModuleBuilder},
+    *,
+};
+
+/// Builds a validated [`WasmModule`] from Rust code.
+///
+/// Indices are handed out as items are added. Imports take the lowest indices
+/// of their kind, so they must be added before any definition of that kind.
+#[derive(Default)]
+pub struct WasmModuleEmitter {
+    types: Vec<WasmFuncType>,
+    imports: Vec<WasmImport>,
+    num_imported_funcs: u32,
+    num_imported_tables: u32,
+    num_imported_mems: u32,
+    num_imported_globals: u32,
+    funcs: Vec<(WasmTypeIdx, Option<WasmCode>)>,
+    tables: Vec<WasmTableType>,
+    mems: Vec<WasmMemType>,
+    globals: Vec<WasmGlobal<WasmInstructionRaw>>,
+    elems: Vec<WasmElem<WasmInstructionRaw>>,
+    datas: Vec<WasmData<WasmInstructionRaw>>,
+    exports: Vec<WasmExport>,
+    start: Option<WasmFuncIdx>,
+    names: WasmNameMap,
+}
+
+fn const_i32(val: u32) -> Box<WasmExprRaw> {
+    Box::new([
+        WasmInstructionRepr::I32Const { val: val as i32 },
+        WasmInstructionRepr::ExprEnd,
+    ])
+}
+
+impl WasmModuleEmitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the index of the function type, adding it if the module does
+    /// not have it yet.
+    pub fn func_type(
+        &mut self,
+        params: &[WasmValueType],
+        results: &[WasmValueType],
+    ) -> WasmTypeIdx {
+        let func_type = WasmFuncType {
+            input_type: WasmResultType(params.into()),
+            output_type: WasmResultType(results.into()),
+        };
+        match self.types.iter().position(|t| *t == func_type) {
+            Some(idx) => WasmTypeIdx(idx as u32),
+            None => {
+                self.types.push(func_type);
+                WasmTypeIdx(self.types.len() as u32 - 1)
+            }
+        }
+    }
+
+    fn import(&mut self, module_name: &str, item_name: &str, desc: WasmImportDesc) {
+        self.imports.push(WasmImport {
+            module_name: WasmName(module_name.into()),
+            item_name: WasmName(item_name.into()),
+            desc,
+        });
+    }
+
+    pub fn import_func(
+        &mut self,
+        module_name: &str,
+        item_name: &str,
+        type_idx: WasmTypeIdx,
+    ) -> WasmFuncIdx {
+        assert!(
+            self.funcs.is_empty(),
+            "functions must be imported before any are defined"
+        );
+        self.import(module_name, item_name, WasmImportDesc::Func(type_idx));
+        self.num_imported_funcs += 1;
+        WasmFuncIdx(self.num_imported_funcs - 1)
+    }
+
+    pub fn import_table(
+        &mut self,
+        module_name: &str,
+        item_name: &str,
+        table_type: WasmTableType,
+    ) -> WasmTableIdx {
+        assert!(
+            self.tables.is_empty(),
+            "tables must be imported before any are defined"
+        );
+        self.import(module_name, item_name, WasmImportDesc::Table(table_type));
+        self.num_imported_tables += 1;
+        WasmTableIdx(self.num_imported_tables - 1)
+    }
+
+    pub fn import_memory(
+        &mut self,
+        module_name: &str,
+        item_name: &str,
+        limits: WasmLimits,
+    ) -> WasmMemIdx {
+        assert!(
+            self.mems.is_empty(),
+            "memories must be imported before any are defined"
+        );
+        self.import(
+            module_name,
+            item_name,
+            WasmImportDesc::Mem(WasmMemType { limits }),
+        );
+        self.num_imported_mems += 1;
+        WasmMemIdx(self.num_imported_mems - 1)
+    }
+
+    pub fn import_global(
+        &mut self,
+        module_name: &str,
+        item_name: &str,
+        global_type: WasmGlobalType,
+    ) -> WasmGlobalIdx {
+        assert!(
+            self.globals.is_empty(),
+            "globals must be imported before any are defined"
+        );
+        self.import(module_name, item_name, WasmImportDesc::Global(global_type));
+        self.num_imported_globals += 1;
+        WasmGlobalIdx(self.num_imported_globals - 1)
+    }
+
+    /// Declares a function and returns an emitter for its body. The function
+    /// gets its index right away so that it can be called before, or from,
+    /// its own body; pass the emitter to [`WasmModuleEmitter::define`] once
+    /// the body is complete.
+    pub fn func(&mut self, type_idx: WasmTypeIdx) -> WasmFuncEmitter {
+        let func_type = &self.types[type_idx.0 as usize];
+        let idx = WasmFuncIdx(self.num_imported_funcs + self.funcs.len() as u32);
+        self.funcs.push((type_idx, None));
+        WasmFuncEmitter {
+            idx,
+            num_params: func_type.input_type.len() as u32,
+            locals: Vec::new(),
+            body: Vec::new(),
+            blocks: Vec::new(),
+            next_label: 0,
+        }
+    }
+
+    /// Sets the body of a function declared with [`WasmModuleEmitter::func`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the body has blocks that were never ended.
+    pub fn define(&mut self, mut func: WasmFuncEmitter) {
+        assert!(
+            func.blocks.is_empty(),
+            "function {} has unterminated blocks",
+            func.idx.0
+        );
+        func.body.push(WasmInstructionRepr::ExprEnd);
+        let slot = &mut self.funcs[(func.idx.0 - self.num_imported_funcs) as usize];
+        slot.1 = Some(WasmCode {
+            locals: func.locals.into_boxed_slice(),
+            body: func.body.into_boxed_slice(),
+            offsets: Box::new([]),
+        });
+    }
+
+    pub fn table(&mut self, table_type: WasmTableType) -> WasmTableIdx {
+        self.tables.push(table_type);
+        WasmTableIdx(self.num_imported_tables + self.tables.len() as u32 - 1)
+    }
+
+    pub fn memory(&mut self, limits: WasmLimits) -> WasmMemIdx {
+        self.mems.push(WasmMemType { limits });
+        WasmMemIdx(self.num_imported_mems + self.mems.len() as u32 - 1)
+    }
+
+    /// Adds a global initialized by a single constant instruction, like
+    /// `I32Const` or `GlobalGet` of an imported global.
+    pub fn global(
+        &mut self,
+        global_type: WasmGlobalType,
+        init: WasmInstructionRaw,
+    ) -> WasmGlobalIdx {
+        self.globals.push(WasmGlobal {
+            global_type,
+            init: Box::new([init, WasmInstructionRepr::ExprEnd]),
+        });
+        WasmGlobalIdx(self.num_imported_globals + self.globals.len() as u32 - 1)
+    }
+
+    /// Adds an active element segment placing `funcs` in the table at
+    /// `offset`.
+    pub fn elems(
+        &mut self,
+        table_idx: WasmTableIdx,
+        offset: u32,
+        funcs: &[WasmFuncIdx],
+    ) -> WasmElemIdx {
+        let init = funcs
+            .iter()
+            .map(|func_idx| {
+                Box::from([
+                    WasmInstructionRepr::RefFunc {
+                        func_idx: *func_idx,
+                    },
+                    WasmInstructionRepr::ExprEnd,
+                ])
+            })
+            .collect();
+        self.elems.push(WasmElem {
+            ref_type: WasmRefType::FuncRef,
+            init,
+            elem_mode: WasmElemMode::Active {
+                table_idx,
+                offset_expr: const_i32(offset),
+            },
+        });
+        WasmElemIdx(self.elems.len() as u32 - 1)
+    }
+
+    /// Adds an active data segment copying `bytes` to `offset` in memory.
+    pub fn data(&mut self, mem_idx: WasmMemIdx, offset: u32, bytes: &[u8]) -> WasmDataIdx {
+        self.datas.push(WasmData {
+            bytes: bytes.into(),
+            mode: WasmDataMode::Active {
+                mem_idx,
+                offset_expr: const_i32(offset),
+            },
+        });
+        WasmDataIdx(self.datas.len() as u32 - 1)
+    }
+
+    /// Adds a passive data segment for use with `memory.init`.
+    pub fn passive_data(&mut self, bytes: &[u8]) -> WasmDataIdx {
+        self.datas.push(WasmData {
+            bytes: bytes.into(),
+            mode: WasmDataMode::Passive,
+        });
+        WasmDataIdx(self.datas.len() as u32 - 1)
+    }
+
+    pub fn export(&mut self, name: &str, desc: WasmExportDesc) {
+        self.exports.push(WasmExport {
+            name: WasmName(name.into()),
+            desc,
+        });
+    }
+
+    pub fn start(&mut self, func_idx: WasmFuncIdx) {
+        self.start = Some(func_idx);
+    }
+
+    /// Names a function in the name map, for backtraces and the debugger.
+    pub fn name_func(&mut self, func_idx: WasmFuncIdx, name: &str) {
+        self.names.funcs.insert(func_idx, WasmName(name.into()));
+    }
+
+    /// Validates the module.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a function was declared but never defined.
+    pub fn build(self) -> WasmValidationResult<WasmModule> {
+        let mut builder = WasmModuleBuilder::new();
+        builder.version(1);
+        builder.reserve_types(self.types.len());
+        for func_type in self.types {
+            builder.push_type(func_type);
+        }
+        builder.reserve_imports(self.imports.len());
+        for import in self.imports {
+            builder.push_import(import);
+        }
+        builder.reserve_funcs(self.funcs.len());
+        builder.reserve_code(self.funcs.len());
+        for (i, (type_idx, code)) in self.funcs.into_iter().enumerate() {
+            let Some(code) = code else {
+                panic!(
+                    "function {} was declared but never defined",
+                    self.num_imported_funcs as usize + i
+                );
+            };
+            builder.push_func(type_idx);
+            builder.push_code(code);
+        }
+        for table in self.tables {
+            builder.push_table(table);
+        }
+        for mem in self.mems {
+            builder.push_mem(mem);
+        }
+        for global in self.globals {
+            builder.push_global(global);
+        }
+        for elem in self.elems {
+            builder.push_elem(elem);
+        }
+        for data in self.datas {
+            builder.push_data(data);
+        }
+        for export in self.exports {
+            builder.push_export(export);
+        }
+        if let Some(start) = self.start {
+            builder.start(start);
+        }
+        builder.names(self.names);
+        validate(builder.build())
+    }
+}
+
+/// A block opened by [`WasmFuncEmitter`], used as a branch target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmBlockLabel(u32);
+
+struct OpenBlock {
+    label: WasmBlockLabel,
+    is_if: bool,
+    has_else: bool,
+}
+
+/// Emits the body of a function, tracking open blocks so that branches can
+/// name their target instead of computing its depth.
+pub struct WasmFuncEmitter {
+    idx: WasmFuncIdx,
+    num_params: u32,
+    locals: Vec<WasmValueType>,
+    body: Vec<WasmInstructionRaw>,
+    /// The open blocks, innermost last.
+    blocks: Vec<OpenBlock>,
+    next_label: u32,
+}
+
+impl WasmFuncEmitter {
+    pub fn idx(&self) -> WasmFuncIdx {
+        self.idx
+    }
+
+    pub fn param(&self, n: u32) -> WasmLocalIdx {
+        assert!(
+            n < self.num_params,
+            "function {} has no parameter {}",
+            self.idx.0,
+            n
+        );
+        WasmLocalIdx(n)
+    }
+
+    /// Declares a local, initialized to the default of its type.
+    pub fn local(&mut self, val_type: WasmValueType) -> WasmLocalIdx {
+        self.locals.push(val_type);
+        WasmLocalIdx(self.num_params + self.locals.len() as u32 - 1)
+    }
+
+    /// Emits an instruction that does not open, close or branch to a block.
+    ///
+    /// # Panics
+    ///
+    /// Panics on structured control instructions and branches, which must
+    /// go through the methods of the same name so labels stay consistent.
+    pub fn emit(&mut self, instr: WasmInstructionRaw) -> &mut Self {
+        use WasmInstructionRepr::*;
+        assert!(
+            !matches!(
+                instr,
+                Block { .. }
+                    | Loop { .. }
+                    | If { .. }
+                    | Else
+                    | ExprEnd
+                    | Break { .. }
+                    | BreakIf { .. }
+                    | BreakTable { .. }
+            ),
+            "use the block and branch methods to emit {:?}",
+            instr
+        );
+        self.body.push(instr);
+        self
+    }
+
+    fn open(&mut self, instr: WasmInstructionRaw, is_if: bool) -> WasmBlockLabel {
+        let label = WasmBlockLabel(self.next_label);
+        self.next_label += 1;
+        self.body.push(instr);
+        self.blocks.push(OpenBlock {
+            label,
+            is_if,
+            has_else: false,
+        });
+        label
+    }
+
+    pub fn block(&mut self, block_type: WasmBlockType) -> WasmBlockLabel {
+        self.open(
+            WasmInstructionRepr::Block {
+                block_type,
+                imm: (),
+            },
+            false,
+        )
+    }
+
+    pub fn loop_(&mut self, block_type: WasmBlockType) -> WasmBlockLabel {
+        self.open(
+            WasmInstructionRepr::Loop {
+                block_type,
+                imm: (),
+            },
+            false,
+        )
+    }
+
+    /// Opens an `if` block, consuming the condition from the stack.
+    pub fn if_(&mut self, block_type: WasmBlockType) -> WasmBlockLabel {
+        self.open(
+            WasmInstructionRepr::If {
+                block_type,
+                imm: (),
+            },
+            true,
+        )
+    }
+
+    /// Starts the `else` branch of the innermost block, which must be an
+    /// `if` without one.
+    pub fn else_(&mut self) -> &mut Self {
+        let block = self.blocks.last_mut().expect("else outside of a block");
+        assert!(block.is_if && !block.has_else, "else must follow an if");
+        block.has_else = true;
+        self.body.push(WasmInstructionRepr::Else);
+        self
+    }
+
+    /// Ends the innermost block.
+    pub fn end(&mut self) -> &mut Self {
+        self.blocks.pop().expect("end outside of a block");
+        self.body.push(WasmInstructionRepr::ExprEnd);
+        self
+    }
+
+    fn depth(&self, label: WasmBlockLabel) -> WasmLabelIdx {
+        match self
+            .blocks
+            .iter()
+            .rev()
+            .position(|block| block.label == label)
+        {
+            Some(depth) => WasmLabelIdx(depth as u32),
+            None => panic!("branch to a block that is not open"),
+        }
+    }
+
+    pub fn br(&mut self, label: WasmBlockLabel) -> &mut Self {
+        let label_idx = self.depth(label);
+        self.body
+            .push(WasmInstructionRepr::Break { label_idx, imm: () });
+        self
+    }
+
+    pub fn br_if(&mut self, label: WasmBlockLabel) -> &mut Self {
+        let label_idx = self.depth(label);
+        self.body
+            .push(WasmInstructionRepr::BreakIf { label_idx, imm: () });
+        self
+    }
+
+    pub fn br_table(&mut self, labels: &[WasmBlockLabel], default: WasmBlockLabel) -> &mut Self {
+        let labels = labels
+            .iter()
+            .chain([&default])
+            .map(|label| self.depth(*label))
+            .collect();
+        self.body.push(WasmInstructionRepr::BreakTable {
+            imm: UnverifiedBreakTableImmediates { labels },
+        });
+        self
+    }
+
+    pub fn ret(&mut self) -> &mut Self {
+        self.emit(WasmInstructionRepr::Return { imm: () })
+    }
+
+    pub fn call(&mut self, func_idx: WasmFuncIdx) -> &mut Self {
+        self.emit(WasmInstructionRepr::Call { func_idx })
+    }
+
+    pub fn local_get(&mut self, local_idx: WasmLocalIdx) -> &mut Self {
+        self.emit(WasmInstructionRepr::LocalGet { local_idx })
+    }
+
+    pub fn local_set(&mut self, local_idx: WasmLocalIdx) -> &mut Self {
+        self.emit(WasmInstructionRepr::LocalSet { local_idx })
+    }
+
+    pub fn local_tee(&mut self, local_idx: WasmLocalIdx) -> &mut Self {
+        self.emit(WasmInstructionRepr::LocalTee { local_idx })
+    }
+
+    pub fn i32_const(&mut self, val: i32) -> &mut Self {
+        self.emit(WasmInstructionRepr::I32Const { val })
+    }
+
+    pub fn i64_const(&mut self, val: i64) -> &mut Self {
+        self.emit(WasmInstructionRepr::I64Const { val })
+    }
+
+    pub fn f32_const(&mut self, val: f32) -> &mut Self {
+        self.emit(WasmInstructionRepr::F32Const { val })
+    }
+
+    pub fn f64_const(&mut self, val: f64) -> &mut Self {
+        self.emit(WasmInstructionRepr::F64Const { val })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::inst::{WasmInvokeOptions, WasmStore};
+
+    #[test]
+    fn test_emit_loop() {
+        use WasmInstructionRepr::*;
+
+        let mut m = WasmModuleEmitter::new();
+        let ty = m.func_type(&[t!(i32)], &[t!(i32)]);
+        let mut f = m.func(ty);
+        let n = f.param(0);
+        let acc = f.local(t!(i32));
+        let done = f.block(WasmBlockType::InlineType(None));
+        let top = f.loop_(WasmBlockType::InlineType(None));
+        f.local_get(n).emit(I32EqZ).br_if(done);
+        f.local_get(acc).local_get(n).emit(I32Add).local_set(acc);
+        f.local_get(n).i32_const(1).emit(I32Sub).local_set(n);
+        f.br(top).end().end();
+        f.local_get(acc);
+        let sum = f.idx();
+        m.define(f);
+        m.export("sum", WasmExportDesc::Func(sum));
+        let wmod = m.build().unwrap();
+
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(Rc::new(wmod), &[]).unwrap();
+        let funcaddr = store.instances.resolve(winst_id).funcaddrs[0];
+        let res = store
+            .invoke(
+                funcaddr,
+                Box::new([10.into()]),
+                WasmInvokeOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(unsafe { res.res.0[0].num.i32 }, 55);
+    }
+}
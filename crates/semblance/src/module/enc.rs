@@ -0,0 +1,821 @@
+use super::*;
+
+pub(super) fn encode_leb128(out: &mut Vec<u8>, mut val: u32) {
+    loop {
+        let byte = (val & 0x7F) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub(super) fn encode_leb128_signed(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7F) as u8;
+        val >>= 7;
+        let done = (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub(super) fn encode_value_type(out: &mut Vec<u8>, val_type: WasmValueType) {
+    out.push(match val_type {
+        WasmValueType::Num(WasmNumType::I32) => 0x7F,
+        WasmValueType::Num(WasmNumType::I64) => 0x7E,
+        WasmValueType::Num(WasmNumType::F32) => 0x7D,
+        WasmValueType::Num(WasmNumType::F64) => 0x7C,
+        WasmValueType::Vec(WasmVecType::V128) => 0x7B,
+        WasmValueType::Ref(WasmRefType::FuncRef) => 0x70,
+        WasmValueType::Ref(WasmRefType::ExternRef) => 0x6F,
+    });
+}
+
+pub(super) fn encode_ref_type(out: &mut Vec<u8>, ref_type: WasmRefType) {
+    encode_value_type(out, WasmValueType::Ref(ref_type));
+}
+
+fn encode_block_type(out: &mut Vec<u8>, block_type: &WasmBlockType) {
+    match block_type {
+        WasmBlockType::InlineType(None) => out.push(0x40),
+        WasmBlockType::InlineType(Some(val_type)) => encode_value_type(out, *val_type),
+        WasmBlockType::TypeRef(WasmTypeIdx(idx)) => encode_leb128_signed(out, *idx as i64),
+    }
+}
+
+fn encode_memarg(out: &mut Vec<u8>, opcode: u8, memarg: &WasmMemArg) {
+    out.push(opcode);
+    encode_leb128(out, memarg.align);
+    encode_leb128(out, memarg.offset);
+}
+
+fn encode_extended(out: &mut Vec<u8>, opcode: u32) {
+    out.push(0xFC);
+    encode_leb128(out, opcode);
+}
+
+/// Encodes `instr` in the binary format. Immediates computed during
+/// validation are not part of the binary format and are left out.
+pub(super) fn encode_instr<I: Immediates>(out: &mut Vec<u8>, instr: &WasmInstructionRepr<I>) {
+    use WasmInstructionRepr::*;
+    match instr {
+        Block { block_type, .. } => {
+            out.push(0x02);
+            encode_block_type(out, block_type);
+        }
+        Loop { block_type, .. } => {
+            out.push(0x03);
+            encode_block_type(out, block_type);
+        }
+        If { block_type, .. } => {
+            out.push(0x04);
+            encode_block_type(out, block_type);
+        }
+        Break { label_idx, .. } => {
+            out.push(0x0C);
+            encode_leb128(out, label_idx.0);
+        }
+        BreakIf { label_idx, .. } => {
+            out.push(0x0D);
+            encode_leb128(out, label_idx.0);
+        }
+        BreakTable { imm } => {
+            out.push(0x0E);
+            let labels: Vec<_> = I::break_table_labels(imm).collect();
+            let (default_label, labels) = labels.split_last().expect("br_table without labels");
+            encode_leb128(out, labels.len() as u32);
+            for label in labels {
+                encode_leb128(out, label.0);
+            }
+            encode_leb128(out, default_label.0);
+        }
+        Return { .. } => out.push(0x0F),
+        Call { func_idx } => {
+            out.push(0x10);
+            encode_leb128(out, func_idx.0);
+        }
+        CallIndirect {
+            table_idx,
+            type_idx,
+        } => {
+            out.push(0x11);
+            encode_leb128(out, type_idx.0);
+            encode_leb128(out, table_idx.0);
+        }
+        RefNull { ref_type } => {
+            out.push(0xD0);
+            encode_ref_type(out, *ref_type);
+        }
+        RefFunc { func_idx } => {
+            out.push(0xD2);
+            encode_leb128(out, func_idx.0);
+        }
+        Select { value_types } if value_types.is_empty() => out.push(0x1B),
+        Select { value_types } => {
+            out.push(0x1C);
+            encode_leb128(out, value_types.len() as u32);
+            for val_type in value_types {
+                encode_value_type(out, *val_type);
+            }
+        }
+        LocalGet { local_idx } => {
+            out.push(0x20);
+            encode_leb128(out, local_idx.0);
+        }
+        LocalSet { local_idx } => {
+            out.push(0x21);
+            encode_leb128(out, local_idx.0);
+        }
+        LocalTee { local_idx } => {
+            out.push(0x22);
+            encode_leb128(out, local_idx.0);
+        }
+        GlobalGet { global_idx } => {
+            out.push(0x23);
+            encode_leb128(out, global_idx.0);
+        }
+        GlobalSet { global_idx } => {
+            out.push(0x24);
+            encode_leb128(out, global_idx.0);
+        }
+        TableGet { table_idx } => {
+            out.push(0x25);
+            encode_leb128(out, table_idx.0);
+        }
+        TableSet { table_idx } => {
+            out.push(0x26);
+            encode_leb128(out, table_idx.0);
+        }
+        I32Load { memarg } => encode_memarg(out, 0x28, memarg),
+        I64Load { memarg } => encode_memarg(out, 0x29, memarg),
+        F32Load { memarg } => encode_memarg(out, 0x2A, memarg),
+        F64Load { memarg } => encode_memarg(out, 0x2B, memarg),
+        I32Load8S { memarg } => encode_memarg(out, 0x2C, memarg),
+        I32Load8U { memarg } => encode_memarg(out, 0x2D, memarg),
+        I32Load16S { memarg } => encode_memarg(out, 0x2E, memarg),
+        I32Load16U { memarg } => encode_memarg(out, 0x2F, memarg),
+        I64Load8S { memarg } => encode_memarg(out, 0x30, memarg),
+        I64Load8U { memarg } => encode_memarg(out, 0x31, memarg),
+        I64Load16S { memarg } => encode_memarg(out, 0x32, memarg),
+        I64Load16U { memarg } => encode_memarg(out, 0x33, memarg),
+        I64Load32S { memarg } => encode_memarg(out, 0x34, memarg),
+        I64Load32U { memarg } => encode_memarg(out, 0x35, memarg),
+        I32Store { memarg } => encode_memarg(out, 0x36, memarg),
+        I64Store { memarg } => encode_memarg(out, 0x37, memarg),
+        F32Store { memarg } => encode_memarg(out, 0x38, memarg),
+        F64Store { memarg } => encode_memarg(out, 0x39, memarg),
+        I32Store8 { memarg } => encode_memarg(out, 0x3A, memarg),
+        I32Store16 { memarg } => encode_memarg(out, 0x3B, memarg),
+        I64Store8 { memarg } => encode_memarg(out, 0x3C, memarg),
+        I64Store16 { memarg } => encode_memarg(out, 0x3D, memarg),
+        I64Store32 { memarg } => encode_memarg(out, 0x3E, memarg),
+        MemorySize => out.extend_from_slice(&[0x3F, 0x00]),
+        MemoryGrow => out.extend_from_slice(&[0x40, 0x00]),
+        I32Const { val } => {
+            out.push(0x41);
+            encode_leb128_signed(out, *val as i64);
+        }
+        I64Const { val } => {
+            out.push(0x42);
+            encode_leb128_signed(out, *val);
+        }
+        F32Const { val } => {
+            out.push(0x43);
+            out.extend_from_slice(&val.to_le_bytes());
+        }
+        F64Const { val } => {
+            out.push(0x44);
+            out.extend_from_slice(&val.to_le_bytes());
+        }
+        I32TruncSatF32S => encode_extended(out, 0),
+        I32TruncSatF32U => encode_extended(out, 1),
+        I32TruncSatF64S => encode_extended(out, 2),
+        I32TruncSatF64U => encode_extended(out, 3),
+        I64TruncSatF32S => encode_extended(out, 4),
+        I64TruncSatF32U => encode_extended(out, 5),
+        I64TruncSatF64S => encode_extended(out, 6),
+        I64TruncSatF64U => encode_extended(out, 7),
+        MemoryInit { data_idx } => {
+            encode_extended(out, 8);
+            encode_leb128(out, data_idx.0);
+            out.push(0x00);
+        }
+        DataDrop { data_idx } => {
+            encode_extended(out, 9);
+            encode_leb128(out, data_idx.0);
+        }
+        MemoryCopy => {
+            encode_extended(out, 10);
+            out.extend_from_slice(&[0x00, 0x00]);
+        }
+        MemoryFill => {
+            encode_extended(out, 11);
+            out.push(0x00);
+        }
+        TableInit {
+            table_idx,
+            elem_idx,
+        } => {
+            encode_extended(out, 12);
+            encode_leb128(out, elem_idx.0);
+            encode_leb128(out, table_idx.0);
+        }
+        ElemDrop { elem_idx } => {
+            encode_extended(out, 13);
+            encode_leb128(out, elem_idx.0);
+        }
+        TableCopy { dst, src } => {
+            encode_extended(out, 14);
+            encode_leb128(out, dst.0);
+            encode_leb128(out, src.0);
+        }
+        TableGrow { table_idx } => {
+            encode_extended(out, 15);
+            encode_leb128(out, table_idx.0);
+        }
+        TableSize { table_idx } => {
+            encode_extended(out, 16);
+            encode_leb128(out, table_idx.0);
+        }
+        TableFill { table_idx } => {
+            encode_extended(out, 17);
+            encode_leb128(out, table_idx.0);
+        }
+        Unreachable => out.push(0x00),
+        Nop => out.push(0x01),
+        Else => out.push(0x05),
+        ExprEnd => out.push(0x0B),
+        RefIsNull => out.push(0xD1),
+        Drop => out.push(0x1A),
+        I32EqZ => out.push(0x45),
+        I32Eq => out.push(0x46),
+        I32Neq => out.push(0x47),
+        I32LtS => out.push(0x48),
+        I32LtU => out.push(0x49),
+        I32GtS => out.push(0x4A),
+        I32GtU => out.push(0x4B),
+        I32LeS => out.push(0x4C),
+        I32LeU => out.push(0x4D),
+        I32GeS => out.push(0x4E),
+        I32GeU => out.push(0x4F),
+        I64EqZ => out.push(0x50),
+        I64Eq => out.push(0x51),
+        I64Neq => out.push(0x52),
+        I64LtS => out.push(0x53),
+        I64LtU => out.push(0x54),
+        I64GtS => out.push(0x55),
+        I64GtU => out.push(0x56),
+        I64LeS => out.push(0x57),
+        I64LeU => out.push(0x58),
+        I64GeS => out.push(0x59),
+        I64GeU => out.push(0x5A),
+        F32Eq => out.push(0x5B),
+        F32Neq => out.push(0x5C),
+        F32Lt => out.push(0x5D),
+        F32Gt => out.push(0x5E),
+        F32Le => out.push(0x5F),
+        F32Ge => out.push(0x60),
+        F64Eq => out.push(0x61),
+        F64Neq => out.push(0x62),
+        F64Lt => out.push(0x63),
+        F64Gt => out.push(0x64),
+        F64Le => out.push(0x65),
+        F64Ge => out.push(0x66),
+        I32Clz => out.push(0x67),
+        I32Ctz => out.push(0x68),
+        I32Popcnt => out.push(0x69),
+        I32Add => out.push(0x6A),
+        I32Sub => out.push(0x6B),
+        I32Mul => out.push(0x6C),
+        I32DivS => out.push(0x6D),
+        I32DivU => out.push(0x6E),
+        I32RemS => out.push(0x6F),
+        I32RemU => out.push(0x70),
+        I32And => out.push(0x71),
+        I32Or => out.push(0x72),
+        I32Xor => out.push(0x73),
+        I32Shl => out.push(0x74),
+        I32ShrS => out.push(0x75),
+        I32ShrU => out.push(0x76),
+        I32Rotl => out.push(0x77),
+        I32Rotr => out.push(0x78),
+        I64Clz => out.push(0x79),
+        I64Ctz => out.push(0x7A),
+        I64Popcnt => out.push(0x7B),
+        I64Add => out.push(0x7C),
+        I64Sub => out.push(0x7D),
+        I64Mul => out.push(0x7E),
+        I64DivS => out.push(0x7F),
+        I64DivU => out.push(0x80),
+        I64RemS => out.push(0x81),
+        I64RemU => out.push(0x82),
+        I64And => out.push(0x83),
+        I64Or => out.push(0x84),
+        I64Xor => out.push(0x85),
+        I64Shl => out.push(0x86),
+        I64ShrS => out.push(0x87),
+        I64ShrU => out.push(0x88),
+        I64Rotl => out.push(0x89),
+        I64Rotr => out.push(0x8A),
+        F32Abs => out.push(0x8B),
+        F32Neg => out.push(0x8C),
+        F32Ceil => out.push(0x8D),
+        F32Floor => out.push(0x8E),
+        F32Trunc => out.push(0x8F),
+        F32Nearest => out.push(0x90),
+        F32Sqrt => out.push(0x91),
+        F32Add => out.push(0x92),
+        F32Sub => out.push(0x93),
+        F32Mul => out.push(0x94),
+        F32Div => out.push(0x95),
+        F32Min => out.push(0x96),
+        F32Max => out.push(0x97),
+        F32CopySign => out.push(0x98),
+        F64Abs => out.push(0x99),
+        F64Neg => out.push(0x9A),
+        F64Ceil => out.push(0x9B),
+        F64Floor => out.push(0x9C),
+        F64Trunc => out.push(0x9D),
+        F64Nearest => out.push(0x9E),
+        F64Sqrt => out.push(0x9F),
+        F64Add => out.push(0xA0),
+        F64Sub => out.push(0xA1),
+        F64Mul => out.push(0xA2),
+        F64Div => out.push(0xA3),
+        F64Min => out.push(0xA4),
+        F64Max => out.push(0xA5),
+        F64CopySign => out.push(0xA6),
+        I32WrapI64 => out.push(0xA7),
+        I32TruncF32S => out.push(0xA8),
+        I32TruncF32U => out.push(0xA9),
+        I32TruncF64S => out.push(0xAA),
+        I32TruncF64U => out.push(0xAB),
+        I64ExtendI32S => out.push(0xAC),
+        I64ExtendI32U => out.push(0xAD),
+        I64TruncF32S => out.push(0xAE),
+        I64TruncF32U => out.push(0xAF),
+        I64TruncF64S => out.push(0xB0),
+        I64TruncF64U => out.push(0xB1),
+        F32ConvertI32S => out.push(0xB2),
+        F32ConvertI32U => out.push(0xB3),
+        F32ConvertI64S => out.push(0xB4),
+        F32ConvertI64U => out.push(0xB5),
+        F32DemoteF64 => out.push(0xB6),
+        F64ConvertI32S => out.push(0xB7),
+        F64ConvertI32U => out.push(0xB8),
+        F64ConvertI64S => out.push(0xB9),
+        F64ConvertI64U => out.push(0xBA),
+        F64PromoteF32 => out.push(0xBB),
+        I32ReinterpretF32 => out.push(0xBC),
+        I64ReinterpretF64 => out.push(0xBD),
+        F32ReinterpretI32 => out.push(0xBE),
+        F64ReinterpretI64 => out.push(0xBF),
+        I32Extend8S => out.push(0xC0),
+        I32Extend16S => out.push(0xC1),
+        I64Extend8S => out.push(0xC2),
+        I64Extend16S => out.push(0xC3),
+        I64Extend32S => out.push(0xC4),
+    }
+}
+
+/// Appends `section` to `out` and clears it for the next section.
+fn encode_section(out: &mut Vec<u8>, id: u8, section: &mut Vec<u8>) {
+    out.push(id);
+    encode_leb128(out, section.len() as u32);
+    out.extend_from_slice(section);
+    section.clear();
+}
+
+fn encode_vec<T>(out: &mut Vec<u8>, items: &[T], mut encode_item: impl FnMut(&mut Vec<u8>, &T)) {
+    encode_leb128(out, items.len() as u32);
+    for item in items {
+        encode_item(out, item);
+    }
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &WasmName) {
+    encode_leb128(out, name.0.len() as u32);
+    out.extend_from_slice(name.0.as_bytes());
+}
+
+fn encode_result_type(out: &mut Vec<u8>, result_type: &WasmResultType) {
+    encode_vec(out, &result_type.0, |out, val_type| {
+        encode_value_type(out, *val_type)
+    });
+}
+
+fn encode_limits(out: &mut Vec<u8>, limits: &WasmLimits) {
+    match limits.max {
+        None => {
+            out.push(0x00);
+            encode_leb128(out, limits.min);
+        }
+        Some(max) => {
+            out.push(0x01);
+            encode_leb128(out, limits.min);
+            encode_leb128(out, max);
+        }
+    }
+}
+
+fn encode_table_type(out: &mut Vec<u8>, table_type: &WasmTableType) {
+    encode_ref_type(out, table_type.ref_type);
+    encode_limits(out, &table_type.limits);
+}
+
+fn encode_global_type(out: &mut Vec<u8>, global_type: &WasmGlobalType) {
+    encode_value_type(out, global_type.val_type);
+    out.push(match global_type.mutability {
+        WasmGlobalMutability::Immutable => 0x00,
+        WasmGlobalMutability::Mutable => 0x01,
+    });
+}
+
+fn encode_expr<I: Immediates>(out: &mut Vec<u8>, expr: &[WasmInstructionRepr<I>]) {
+    for instr in expr {
+        encode_instr(out, instr);
+    }
+}
+
+fn encode_locals(out: &mut Vec<u8>, locals: &[WasmValueType]) {
+    let mut runs: Vec<(u32, WasmValueType)> = Vec::new();
+    for local in locals {
+        match runs.last_mut() {
+            Some((n, val_type)) if val_type == local => *n += 1,
+            _ => runs.push((1, *local)),
+        }
+    }
+    encode_vec(out, &runs, |out, (n, val_type)| {
+        encode_leb128(out, *n);
+        encode_value_type(out, *val_type);
+    });
+}
+
+/// The function indices of an element segment, if every initializer is a
+/// plain `ref.func` and the segment can use the compact encoding.
+fn elem_func_indices<I: Immediates>(elem: &WasmElem<WasmInstructionRepr<I>>) -> Option<Vec<u32>> {
+    if elem.ref_type != WasmRefType::FuncRef {
+        return None;
+    }
+    elem.init
+        .iter()
+        .map(|expr| match expr.as_ref() {
+            [
+                WasmInstructionRepr::RefFunc { func_idx },
+                WasmInstructionRepr::ExprEnd,
+            ] => Some(func_idx.0),
+            _ => None,
+        })
+        .collect()
+}
+
+fn encode_elem<I: Immediates>(out: &mut Vec<u8>, elem: &WasmElem<WasmInstructionRepr<I>>) {
+    let func_indices = elem_func_indices(elem);
+    let mode_flags = match &elem.elem_mode {
+        WasmElemMode::Active { table_idx, .. }
+            if table_idx.0 == 0 && elem.ref_type == WasmRefType::FuncRef =>
+        {
+            0
+        }
+        WasmElemMode::Passive => 1,
+        WasmElemMode::Active { .. } => 2,
+        WasmElemMode::Declarative => 3,
+    };
+    let flags = if func_indices.is_some() {
+        mode_flags
+    } else {
+        mode_flags | 4
+    };
+    encode_leb128(out, flags);
+    if let WasmElemMode::Active {
+        table_idx,
+        offset_expr,
+    } = &elem.elem_mode
+    {
+        if flags & 2 != 0 {
+            encode_leb128(out, table_idx.0);
+        }
+        encode_expr(out, offset_expr);
+    }
+    if flags & 3 != 0 {
+        match func_indices {
+            Some(_) => out.push(0x00),
+            None => encode_ref_type(out, elem.ref_type),
+        }
+    }
+    match func_indices {
+        Some(indices) => encode_vec(out, &indices, |out, idx| encode_leb128(out, *idx)),
+        None => encode_vec(out, &elem.init, |out, expr| encode_expr(out, expr)),
+    }
+}
+
+fn encode_data<I: Immediates>(out: &mut Vec<u8>, data: &WasmData<WasmInstructionRepr<I>>) {
+    match &data.mode {
+        WasmDataMode::Active {
+            mem_idx,
+            offset_expr,
+        } => {
+            if mem_idx.0 == 0 {
+                out.push(0x00);
+            } else {
+                out.push(0x02);
+                encode_leb128(out, mem_idx.0);
+            }
+            encode_expr(out, offset_expr);
+        }
+        WasmDataMode::Passive => out.push(0x01),
+    }
+    encode_leb128(out, data.bytes.len() as u32);
+    out.extend_from_slice(&data.bytes);
+}
+
+fn uses_data_count<I: Immediates>(funcs: &[&WasmFunc<WasmInstructionRepr<I>>]) -> bool {
+    funcs.iter().flat_map(|func| func.body.iter()).any(|instr| {
+        matches!(
+            instr,
+            WasmInstructionRepr::MemoryInit { .. } | WasmInstructionRepr::DataDrop { .. }
+        )
+    })
+}
+
+/// Encodes a module, with the bodies of its defined functions in `funcs`, in
+/// the binary format. Sections that would be empty are left out and custom
+/// sections are written after all other sections.
+pub(super) fn encode<I: Immediates>(
+    wmod: &WasmModuleRepr<WasmInstructionRepr<I>>,
+    funcs: &[&WasmFunc<WasmInstructionRepr<I>>],
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(b"\0asm");
+    out.extend_from_slice(&wmod.version.to_le_bytes());
+
+    let mut section = Vec::new();
+
+    if !wmod.types.is_empty() {
+        encode_vec(&mut section, &wmod.types, |out, func_type| {
+            out.push(0x60);
+            encode_result_type(out, &func_type.input_type);
+            encode_result_type(out, &func_type.output_type);
+        });
+        encode_section(&mut out, 1, &mut section);
+    }
+    if !wmod.imports.is_empty() {
+        encode_vec(&mut section, &wmod.imports, |out, import| {
+            encode_name(out, &import.module_name);
+            encode_name(out, &import.item_name);
+            match &import.desc {
+                WasmImportDesc::Func(type_idx) => {
+                    out.push(0x00);
+                    encode_leb128(out, type_idx.0);
+                }
+                WasmImportDesc::Table(table_type) => {
+                    out.push(0x01);
+                    encode_table_type(out, table_type);
+                }
+                WasmImportDesc::Mem(mem_type) => {
+                    out.push(0x02);
+                    encode_limits(out, &mem_type.limits);
+                }
+                WasmImportDesc::Global(global_type) => {
+                    out.push(0x03);
+                    encode_global_type(out, global_type);
+                }
+            }
+        });
+        encode_section(&mut out, 2, &mut section);
+    }
+    if !wmod.funcs.is_empty() {
+        encode_vec(&mut section, &wmod.funcs, |out, func| {
+            encode_leb128(out, func.type_idx.0)
+        });
+        encode_section(&mut out, 3, &mut section);
+    }
+    if !wmod.tables.is_empty() {
+        encode_vec(&mut section, &wmod.tables, encode_table_type);
+        encode_section(&mut out, 4, &mut section);
+    }
+    if !wmod.mems.is_empty() {
+        encode_vec(&mut section, &wmod.mems, |out, mem_type| {
+            encode_limits(out, &mem_type.limits)
+        });
+        encode_section(&mut out, 5, &mut section);
+    }
+    if !wmod.globals.is_empty() {
+        encode_vec(&mut section, &wmod.globals, |out, global| {
+            encode_global_type(out, &global.global_type);
+            encode_expr(out, &global.init);
+        });
+        encode_section(&mut out, 6, &mut section);
+    }
+    if !wmod.exports.is_empty() {
+        encode_vec(&mut section, &wmod.exports, |out, export| {
+            encode_name(out, &export.name);
+            let (tag, idx) = match export.desc {
+                WasmExportDesc::Func(idx) => (0x00, idx.0),
+                WasmExportDesc::Table(idx) => (0x01, idx.0),
+                WasmExportDesc::Mem(idx) => (0x02, idx.0),
+                WasmExportDesc::Global(idx) => (0x03, idx.0),
+            };
+            out.push(tag);
+            encode_leb128(out, idx);
+        });
+        encode_section(&mut out, 7, &mut section);
+    }
+    if let Some(start) = wmod.start {
+        encode_leb128(&mut section, start.0);
+        encode_section(&mut out, 8, &mut section);
+    }
+    if !wmod.elems.is_empty() {
+        encode_vec(&mut section, &wmod.elems, encode_elem);
+        encode_section(&mut out, 9, &mut section);
+    }
+    if uses_data_count(funcs) {
+        encode_leb128(&mut section, wmod.datas.len() as u32);
+        encode_section(&mut out, 12, &mut section);
+    }
+    if !funcs.is_empty() {
+        let mut code = Vec::new();
+        encode_vec(&mut section, funcs, |out, func| {
+            code.clear();
+            encode_locals(&mut code, &func.locals);
+            encode_expr(&mut code, &func.body);
+            encode_leb128(out, code.len() as u32);
+            out.extend_from_slice(&code);
+        });
+        encode_section(&mut out, 10, &mut section);
+    }
+    if !wmod.datas.is_empty() {
+        encode_vec(&mut section, &wmod.datas, encode_data);
+        encode_section(&mut out, 11, &mut section);
+    }
+    for custom in &wmod.customs {
+        encode_name(&mut section, &custom.name);
+        section.extend_from_slice(&custom.bytes);
+        encode_section(&mut out, 0, &mut section);
+    }
+    // Names from a decoded module are written back with its custom sections.
+    if !wmod.customs.iter().any(|custom| &*custom.name.0 == "name") {
+        encode_name_section(&mut section, &wmod.names);
+        if !section.is_empty() {
+            let mut contents = Vec::new();
+            encode_name(&mut contents, &WasmName("name".into()));
+            contents.append(&mut section);
+            encode_section(&mut out, 0, &mut contents);
+        }
+    }
+    out
+}
+
+fn encode_name_map<'n>(out: &mut Vec<u8>, names: impl Iterator<Item = (u32, &'n WasmName)>) {
+    let mut names = names.collect::<Vec<_>>();
+    names.sort_by_key(|(i, _)| *i);
+    encode_vec(out, &names, |out, (i, name)| {
+        encode_leb128(out, *i);
+        encode_name(out, name);
+    });
+}
+
+fn encode_name_subsection<'n>(
+    out: &mut Vec<u8>,
+    id: u8,
+    names: impl ExactSizeIterator<Item = (u32, &'n WasmName)>,
+) {
+    if names.len() > 0 {
+        let mut subsection = Vec::new();
+        encode_name_map(&mut subsection, names);
+        encode_section(out, id, &mut subsection);
+    }
+}
+
+fn encode_name_section(out: &mut Vec<u8>, names: &WasmNameMap) {
+    if let Some(name) = &names.module {
+        let mut subsection = Vec::new();
+        encode_name(&mut subsection, name);
+        encode_section(out, 0, &mut subsection);
+    }
+    encode_name_subsection(out, 1, names.funcs.iter().map(|(i, n)| (i.0, n)));
+    if !names.locals.is_empty() {
+        let mut locals = names.locals.iter().collect::<Vec<_>>();
+        locals.sort_by_key(|(func_idx, _)| func_idx.0);
+        let mut subsection = Vec::new();
+        encode_vec(&mut subsection, &locals, |out, (func_idx, names)| {
+            encode_leb128(out, func_idx.0);
+            encode_name_map(out, names.iter().map(|(i, n)| (i.0, n)));
+        });
+        encode_section(out, 2, &mut subsection);
+    }
+    encode_name_subsection(out, 5, names.tables.iter().map(|(i, n)| (i.0, n)));
+    encode_name_subsection(out, 6, names.mems.iter().map(|(i, n)| (i.0, n)));
+    encode_name_subsection(out, 7, names.globals.iter().map(|(i, n)| (i.0, n)));
+    encode_name_subsection(out, 8, names.elems.iter().map(|(i, n)| (i.0, n)));
+    encode_name_subsection(out, 9, names.datas.iter().map(|(i, n)| (i.0, n)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"(module $rt
+          (type $binop (func (param i32 i32) (result i32)))
+          (import "env" "log" (func $log (param i32)))
+          (import "env" "tbl" (table 4 funcref))
+          (import "env" "g" (global $imported f64))
+          (table $ext 1 externref)
+          (table $funcs 4 8 funcref)
+          (memory $mem 1 2)
+          (global $counter (mut i32) (i32.const -7))
+          (global $big i64 (i64.const -9223372036854775808))
+          (global $f (mut f32) (f32.const nan:0x200000))
+          (export "add" (func $add))
+          (export "mem" (memory $mem))
+          (export "counter" (global $counter))
+          (export "funcs" (table $funcs))
+          (start $init)
+          (elem (i32.const 0) $add $sub)
+          (elem $passive func $add)
+          (elem declare func $sub)
+          (elem (table $funcs) (i32.const 2) funcref (ref.func $add) (ref.null func))
+          (elem $exprs funcref (ref.null func))
+          (data (i32.const 16) "hello")
+          (data $bytes "\00\01\02")
+          (func $add (type $binop) (i32.add (local.get 0) (local.get 1)))
+          (func $sub (type $binop) (i32.sub (local.get 0) (local.get 1)))
+          (func $init
+            (local i32 i32 i64 f64)
+            (memory.init $bytes (i32.const 32) (i32.const 0) (i32.const 3))
+            (data.drop $bytes)
+            (table.init $funcs $passive (i32.const 3) (i32.const 0) (i32.const 1))
+            (elem.drop $passive)
+            (global.set $counter
+              (call_indirect (type $binop) (i32.const 1) (i32.const 2) (i32.const 0)))
+            (block $out
+              (loop $again
+                (br_table $out $again $out (local.get 0))))
+            (if (local.get 1) (then (call $log (i32.const 1))) (else (nop)))
+            (drop (select (result i64) (i64.const 1) (i64.const 2) (local.get 0)))
+            (drop (select (f64.const 1.5) (local.get 3) (i32.const 0)))
+            (drop (i32.trunc_sat_f64_s (global.get $imported)))
+            (memory.fill (i32.const 0) (i32.const 0) (i32.const 4))
+            (memory.copy (i32.const 4) (i32.const 0) (i32.const 4))
+            (drop (memory.grow (memory.size)))
+            (i64.store32 offset=8 align=2 (i32.const 0) (i64.load16_u (i32.const 2)))
+            (drop (table.grow $funcs (ref.null func) (i32.const 1)))
+            (table.fill $funcs (i32.const 0) (ref.null func) (i32.const 0))
+            (table.copy $funcs $funcs (i32.const 0) (i32.const 1) (i32.const 1))
+            (drop (table.size $funcs))
+            (table.set $ext (i32.const 0) (ref.null extern))
+            (drop (ref.is_null (ref.func $add)))
+            (return)))"#;
+
+    /// Checks that decoding and encoding `bytes` gives `bytes` back, for both
+    /// the raw and the validated module.
+    fn assert_round_trips(bytes: &[u8]) {
+        let raw = WasmModuleRaw::from_bytes(bytes).unwrap();
+        assert_eq!(raw.to_bytes(), bytes);
+        let valid = WasmModule::from_bytes(bytes).unwrap();
+        assert_eq!(valid.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let bytes = WasmModuleRaw::from_wat(TEXT).unwrap().to_bytes();
+        assert_round_trips(&bytes);
+        let wmod = WasmModule::from_bytes(&bytes).unwrap();
+        assert_eq!(wmod.imports.len(), 3);
+        assert_eq!(wmod.tables.len(), 2);
+        assert_eq!(wmod.mems[0].limits.max, Some(2));
+        assert_eq!(wmod.globals.len(), 3);
+        assert_eq!(wmod.elems.len(), 5);
+        assert_eq!(wmod.datas.len(), 2);
+        assert_eq!(wmod.start, Some(WasmFuncIdx(3)));
+        assert_eq!(wmod.names.module_name(), Some("rt"));
+        assert_eq!(wmod.func_name(WasmFuncIdx(3)), Some("init"));
+        assert_eq!(wmod.names.data_name(WasmDataIdx(1)), Some("bytes"));
+    }
+
+    #[test]
+    fn test_encode_examples() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../wasm");
+        let mut examples = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let bytes = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("wasm") => std::fs::read(&path).unwrap(),
+                Some("wat") => {
+                    let text = std::fs::read_to_string(&path).unwrap();
+                    WasmModuleRaw::from_wat(&text).unwrap().to_bytes()
+                }
+                _ => continue,
+            };
+            assert_round_trips(&bytes);
+            examples += 1;
+        }
+        assert!(examples > 0);
+    }
+}
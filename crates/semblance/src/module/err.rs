@@ -1,14 +1,37 @@
-use std::io;
+use std::{error::Error, fmt, io};
 
-use crate::module::{WasmDecodeError, WasmValidationError};
+use crate::module::{WasmDecodeError, WasmParseError, WasmValidationError};
 
 #[derive(Debug)]
 pub enum WasmReadError {
     Io(io::Error),
     Decode(WasmDecodeError),
+    Parse(WasmParseError),
     Validation(WasmValidationError),
 }
 
+impl fmt::Display for WasmReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasmReadError::Io(e) => write!(f, "{}", e),
+            WasmReadError::Decode(e) => write!(f, "malformed module: {}", e),
+            WasmReadError::Parse(e) => write!(f, "{}", e),
+            WasmReadError::Validation(e) => write!(f, "invalid module: {}", e),
+        }
+    }
+}
+
+impl Error for WasmReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WasmReadError::Io(e) => Some(e),
+            WasmReadError::Decode(e) => Some(e),
+            WasmReadError::Parse(e) => Some(e),
+            WasmReadError::Validation(e) => Some(e),
+        }
+    }
+}
+
 impl From<io::Error> for WasmReadError {
     fn from(value: io::Error) -> Self {
         WasmReadError::Io(value)
@@ -21,6 +44,12 @@ impl From<WasmDecodeError> for WasmReadError {
     }
 }
 
+impl From<WasmParseError> for WasmReadError {
+    fn from(value: WasmParseError) -> Self {
+        WasmReadError::Parse(value)
+    }
+}
+
 impl From<WasmValidationError> for WasmReadError {
     fn from(value: WasmValidationError) -> Self {
         WasmReadError::Validation(value)
@@ -33,6 +62,17 @@ pub enum WasmFromBytesError {
     Validation(WasmValidationError),
 }
 
+impl fmt::Display for WasmFromBytesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasmFromBytesError::Decode(e) => write!(f, "malformed module: {}", e),
+            WasmFromBytesError::Validation(e) => write!(f, "invalid module: {}", e),
+        }
+    }
+}
+
+impl Error for WasmFromBytesError {}
+
 impl From<WasmDecodeError> for WasmFromBytesError {
     fn from(value: WasmDecodeError) -> Self {
         WasmFromBytesError::Decode(value)
@@ -0,0 +1,20 @@
+//! A small module shared by the tests of the decoder, encoder, printer and
+//! cache, covering blocks, `br_table` and `if`/`else`.
+
+pub(super) const TEXT: &str = r#"
+    (module
+      (func $f (export "f") (param i32) (result i32)
+        (block (result i32)
+          (br_table 0 1 0 (i32.const 7) (local.get 0)))
+        (if (result i32) (local.get 0) (then (i32.const 1)) (else (i32.const 2)))
+        i32.add))
+"#;
+
+/// `TEXT` as encoded by wat2wasm, with the function's name.
+pub(super) const MODULE: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+    0x03, 0x02, 0x01, 0x00, 0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x00, 0x0a, 0x1b, 0x01, 0x19, 0x00,
+    0x02, 0x7f, 0x41, 0x07, 0x20, 0x00, 0x0e, 0x02, 0x00, 0x01, 0x00, 0x0b, 0x20, 0x00, 0x04, 0x7f,
+    0x41, 0x01, 0x05, 0x41, 0x02, 0x0b, 0x6a, 0x0b, 0x00, 0x0b, 0x04, 0x6e, 0x61, 0x6d, 0x65, 0x01,
+    0x04, 0x01, 0x00, 0x01, 0x66,
+];
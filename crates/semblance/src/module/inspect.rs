@@ -0,0 +1,177 @@
+use std::fmt;
+
+use super::{
+    print::{global_type_text, limits_text, types_text},
+    text::heap_type_name,
+    *,
+};
+
+/// The type of an import or export.
+#[derive(Debug, Clone, Copy)]
+pub enum WasmExternType<'m> {
+    Func(&'m WasmFuncType),
+    Table(&'m WasmTableType),
+    Mem(&'m WasmMemType),
+    Global(&'m WasmGlobalType),
+}
+
+impl WasmExternType<'_> {
+    /// The kind of item, as the text format names it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WasmExternType::Func(_) => "func",
+            WasmExternType::Table(_) => "table",
+            WasmExternType::Mem(_) => "memory",
+            WasmExternType::Global(_) => "global",
+        }
+    }
+}
+
+/// Formats the type as in the text format, like `(func (param i32))`.
+impl fmt::Display for WasmExternType<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WasmExternType::Func(ty) => write!(
+                f,
+                "(func{}{})",
+                types_text("param", &ty.input_type.0),
+                types_text("result", &ty.output_type.0)
+            ),
+            WasmExternType::Table(ty) => write!(
+                f,
+                "(table {} {}ref)",
+                limits_text(&ty.limits),
+                heap_type_name(ty.ref_type)
+            ),
+            WasmExternType::Mem(ty) => write!(f, "(memory {})", limits_text(&ty.limits)),
+            WasmExternType::Global(ty) => write!(f, "(global {})", global_type_text(ty)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WasmImportType<'m> {
+    pub module: &'m str,
+    pub name: &'m str,
+    pub ty: WasmExternType<'m>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WasmExportType<'m> {
+    pub name: &'m str,
+    pub ty: WasmExternType<'m>,
+}
+
+impl WasmModule {
+    /// The module's imports in order, with their types.
+    pub fn import_types(&self) -> impl Iterator<Item = WasmImportType<'_>> {
+        self.imports.iter().map(|import| WasmImportType {
+            module: &import.module_name.0,
+            name: &import.item_name.0,
+            ty: match &import.desc {
+                WasmImportDesc::Func(type_idx) => {
+                    WasmExternType::Func(&self.types[type_idx.0 as usize])
+                }
+                WasmImportDesc::Table(ty) => WasmExternType::Table(ty),
+                WasmImportDesc::Mem(ty) => WasmExternType::Mem(ty),
+                WasmImportDesc::Global(ty) => WasmExternType::Global(ty),
+            },
+        })
+    }
+
+    /// The module's exports in order, with the types of the items they
+    /// export, whether imported or defined.
+    pub fn export_types(&self) -> impl Iterator<Item = WasmExportType<'_>> {
+        self.exports.iter().map(|export| WasmExportType {
+            name: &export.name.0,
+            ty: self.item_type(&export.desc),
+        })
+    }
+
+    pub fn export_type(&self, name: &str) -> Option<WasmExportType<'_>> {
+        self.export_types().find(|export| export.name == name)
+    }
+
+    fn item_type(&self, desc: &WasmExportDesc) -> WasmExternType<'_> {
+        use WasmExternType::*;
+        match *desc {
+            WasmExportDesc::Func(WasmFuncIdx(idx)) => self.nth_item(
+                idx,
+                |ty| matches!(ty, Func(_)),
+                |i| Func(&self.types[self.funcs[i].type_idx.0 as usize]),
+            ),
+            WasmExportDesc::Table(WasmTableIdx(idx)) => {
+                self.nth_item(idx, |ty| matches!(ty, Table(_)), |i| Table(&self.tables[i]))
+            }
+            WasmExportDesc::Mem(WasmMemIdx(idx)) => {
+                self.nth_item(idx, |ty| matches!(ty, Mem(_)), |i| Mem(&self.mems[i]))
+            }
+            WasmExportDesc::Global(WasmGlobalIdx(idx)) => self.nth_item(
+                idx,
+                |ty| matches!(ty, Global(_)),
+                |i| Global(&self.globals[i].global_type),
+            ),
+        }
+    }
+
+    /// The type of item `idx` in an index space, which numbers the imports
+    /// of a kind first and then the definitions, passed to `defined`.
+    fn nth_item<'m>(
+        &'m self,
+        idx: u32,
+        is_kind: fn(&WasmExternType) -> bool,
+        defined: impl FnOnce(usize) -> WasmExternType<'m>,
+    ) -> WasmExternType<'m> {
+        let mut num_imported = 0;
+        for ty in self.import_types().map(|import| import.ty) {
+            if is_kind(&ty) {
+                if num_imported == idx {
+                    return ty;
+                }
+                num_imported += 1;
+            }
+        }
+        defined((idx - num_imported) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_export_types() {
+        let text = r#"(module
+            (import "env" "log" (func $log (param i32)))
+            (import "env" "g" (global i64))
+            (memory (export "memory") 1 2)
+            (global (export "counter") (mut i32) (i32.const 0))
+            (func (export "run") (param i32 i32) (result i32) local.get 0)
+            (export "log" (func $log))
+            (export "g" (global 0)))"#;
+        let wmod = validate(WasmModuleRaw::from_wat(text).unwrap()).unwrap();
+        let imports = wmod
+            .import_types()
+            .map(|import| format!("{}.{}: {}", import.module, import.name, import.ty))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            imports,
+            ["env.log: (func (param i32))", "env.g: (global i64)"]
+        );
+        let exports = wmod
+            .export_types()
+            .map(|export| format!("{}: {}", export.name, export.ty))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            exports,
+            [
+                "memory: (memory 1 2)",
+                "counter: (global (mut i32))",
+                "run: (func (param i32 i32) (result i32))",
+                "log: (func (param i32))",
+                "g: (global i64)",
+            ]
+        );
+        assert!(wmod.export_type("missing").is_none());
+    }
+}
@@ -1,33 +1,188 @@
 mod bin;
 mod builder;
+mod cache;
+mod dwarf;
+mod enc;
 #[macro_use]
 mod repr;
+mod emit;
 mod err;
+#[cfg(test)]
+mod fixtures;
+mod inspect;
+mod print;
+mod stream;
+mod text;
 mod valid;
+mod wat;
 
-use std::{fs::File, io::Read, path::Path};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
 
-pub use bin::{WasmDecodeError, WasmDecodeResult};
+pub use bin::{WasmDecodeError, WasmDecodeErrorKind, WasmDecodeResult, WasmSectionId};
+pub use cache::{WasmCacheError, default_cache_dir};
+pub use dwarf::{WasmLineTable, WasmSourceLocation};
+pub use emit::{WasmBlockLabel, WasmFuncEmitter, WasmModuleEmitter};
 pub use err::{WasmFromBytesError, WasmReadError};
+pub use inspect::{WasmExportType, WasmExternType, WasmImportType};
+pub use print::WasmPrintOptions;
 pub use repr::*;
-pub use valid::{WasmValidationError, WasmValidationResult, validate};
+pub use valid::{
+    MaybeUntyped, WasmValidationError, WasmValidationErrorKind, WasmValidationOptions,
+    WasmValidationResult, validate, validate_with_options,
+};
+pub use wat::{WasmParseError, WasmParseErrorKind, WasmParseResult};
 
 impl repr::WasmModule {
+    /// Reads a module in the binary format, or in the text format when the
+    /// file name ends in `.wat`. Binary modules are streamed from the file.
     pub fn read(path: &Path) -> Result<Self, WasmReadError> {
-        let bytes = {
-            let mut f = File::open(path)?;
-            let meta = f.metadata()?;
-            let mut buf = Vec::with_capacity(meta.len() as usize);
-            f.read_to_end(&mut buf)?;
-            buf
-        };
-        let wmod = WasmModule::from_bytes(&bytes)?;
+        Self::read_with_options(path, WasmValidationOptions::default())
+    }
+
+    pub fn read_with_options(
+        path: &Path,
+        options: WasmValidationOptions,
+    ) -> Result<Self, WasmReadError> {
+        if is_wat(path) {
+            let bytes = read_file(path)?;
+            return load(path, &bytes, options);
+        }
+        Self::from_reader_with_options(File::open(path)?, options)
+    }
+
+    /// Decodes and validates a module in the binary format as it is read,
+    /// without holding the whole binary in memory. The reader is buffered
+    /// internally.
+    pub fn from_reader(reader: impl Read) -> Result<Self, WasmReadError> {
+        Self::from_reader_with_options(reader, WasmValidationOptions::default())
+    }
+
+    pub fn from_reader_with_options(
+        reader: impl Read,
+        options: WasmValidationOptions,
+    ) -> Result<Self, WasmReadError> {
+        stream::read_module(reader, options)
+    }
+
+    /// Like [`WasmModule::read`], but loads the decoded module from
+    /// `cache_dir` when it has been read before, and caches it otherwise.
+    /// A module loaded from the cache has its function bodies validated
+    /// lazily. Failing to read or write the cache is not an error.
+    pub fn read_cached(path: &Path, cache_dir: &Path) -> Result<Self, WasmReadError> {
+        Self::read_cached_with_options(path, cache_dir, WasmValidationOptions::default())
+    }
+
+    /// Like [`WasmModule::read_cached`]. A lazily validated module is not
+    /// cached, as caching it would validate every function body.
+    pub fn read_cached_with_options(
+        path: &Path,
+        cache_dir: &Path,
+        options: WasmValidationOptions,
+    ) -> Result<Self, WasmReadError> {
+        let bytes = read_file(path)?;
+        let cache_path = cache::cache_path(cache_dir, &bytes);
+        if let Ok(cached) = fs::read(&cache_path)
+            && let Ok(wmod) = WasmModule::from_cache_bytes(&cached, &bytes)
+        {
+            return Ok(wmod);
+        }
+        let wmod = load(path, &bytes, options)?;
+        if options.lazy {
+            return Ok(wmod);
+        }
+        // Write to a temporary file first so a concurrent reader never sees
+        // a partial cache.
+        let tmp_path = cache_path.with_extension(format!("tmp{}", std::process::id()));
+        let _ = fs::create_dir_all(cache_dir)
+            .and_then(|_| {
+                let cached = wmod.to_cache_bytes(&bytes).map_err(std::io::Error::other)?;
+                fs::write(&tmp_path, cached)
+            })
+            .and_then(|_| fs::rename(&tmp_path, &cache_path));
+        let _ = fs::remove_file(&tmp_path);
         Ok(wmod)
     }
 
+    /// Reads a module along with the binary that the code offsets of its
+    /// functions refer to. A module in the text format is encoded first and
+    /// decoded from the result.
+    pub fn read_image(path: &Path) -> Result<(Self, Vec<u8>), WasmReadError> {
+        Self::read_image_with_options(path, WasmValidationOptions::default())
+    }
+
+    pub fn read_image_with_options(
+        path: &Path,
+        options: WasmValidationOptions,
+    ) -> Result<(Self, Vec<u8>), WasmReadError> {
+        let mut bytes = read_file(path)?;
+        if is_wat(path) {
+            bytes = wat::parse_bytes(&bytes)?.to_bytes();
+        }
+        let wmod = WasmModule::from_bytes_with_options(&bytes, options)?;
+        Ok((wmod, bytes))
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, WasmFromBytesError> {
-        let wmod = bin::decode(&bytes)?;
-        let valid = validate(wmod)?;
+        Self::from_bytes_with_options(bytes, WasmValidationOptions::default())
+    }
+
+    pub fn from_bytes_with_options(
+        bytes: &[u8],
+        options: WasmValidationOptions,
+    ) -> Result<Self, WasmFromBytesError> {
+        let wmod = bin::decode(bytes)?;
+        let valid = validate_with_options(wmod, options)?;
         Ok(valid)
     }
+
+    /// Encodes the module in the binary format. Function bodies whose
+    /// validation was deferred are validated first, and an invalid one is
+    /// an error.
+    pub fn to_bytes(&self) -> WasmValidationResult<Vec<u8>> {
+        Ok(enc::encode(self, &self.validated_funcs()?))
+    }
+}
+
+impl repr::WasmModuleRaw {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WasmDecodeError> {
+        bin::decode(bytes)
+    }
+
+    /// Parses a module in the WebAssembly text format.
+    pub fn from_wat(text: &str) -> Result<Self, WasmParseError> {
+        wat::parse(text)
+    }
+
+    /// Encodes the module in the binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        enc::encode(self, &self.funcs.iter().collect::<Vec<_>>())
+    }
+}
+
+fn load(
+    path: &Path,
+    bytes: &[u8],
+    options: WasmValidationOptions,
+) -> Result<WasmModule, WasmReadError> {
+    if is_wat(path) {
+        let wmod = wat::parse_bytes(bytes)?;
+        return Ok(validate_with_options(wmod, options)?);
+    }
+    Ok(WasmModule::from_bytes_with_options(bytes, options)?)
+}
+
+fn is_wat(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "wat")
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, std::io::Error> {
+    let mut f = File::open(path)?;
+    let meta = f.metadata()?;
+    let mut buf = Vec::with_capacity(meta.len() as usize);
+    f.read_to_end(&mut buf)?;
+    Ok(buf)
 }
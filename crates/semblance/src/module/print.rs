@@ -0,0 +1,851 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{text::*, *};
+
+/// Options for [`WasmModule::to_wat`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WasmPrintOptions {
+    /// Nest operands inside the instructions that consume them instead of
+    /// printing one instruction per line.
+    pub folded: bool,
+}
+
+impl WasmModule {
+    /// Renders the module in the WebAssembly text format, using the names
+    /// from the name section where they are valid identifiers. Function
+    /// bodies whose validation was deferred are validated first, and an
+    /// invalid one is an error.
+    pub fn to_wat(&self, options: WasmPrintOptions) -> WasmValidationResult<String> {
+        Ok(Printer::new(self, self.validated_funcs()?, options).print())
+    }
+}
+
+impl WasmModuleRaw {
+    /// Renders the module in the WebAssembly text format.
+    pub fn to_wat(&self, options: WasmPrintOptions) -> String {
+        Printer::new(self, self.funcs.iter().collect(), options).print()
+    }
+}
+
+/// Maps indices to identifiers, dropping names that are empty or that an
+/// earlier index already uses.
+fn ids<T: Copy>(names: &HashMap<T, WasmName>, to_idx: fn(T) -> u32) -> HashMap<u32, String> {
+    let mut entries: Vec<_> = names
+        .iter()
+        .map(|(idx, name)| (to_idx(*idx), name))
+        .collect();
+    entries.sort_by_key(|(idx, _)| *idx);
+    let mut used = HashSet::new();
+    let mut ids = HashMap::new();
+    for (idx, name) in entries {
+        let id: String = name
+            .0
+            .chars()
+            .map(|c| if is_id_char(c) { c } else { '_' })
+            .collect();
+        if !id.is_empty() && used.insert(id.clone()) {
+            ids.insert(idx, id);
+        }
+    }
+    ids
+}
+
+fn reference(ids: &HashMap<u32, String>, idx: u32) -> String {
+    match ids.get(&idx) {
+        Some(id) => format!("${}", id),
+        None => idx.to_string(),
+    }
+}
+
+/// The opening of a definition, like `(func $name (;3;)`.
+fn definition(kind: &str, ids: &HashMap<u32, String>, idx: u32) -> String {
+    match ids.get(&idx) {
+        Some(id) => format!("({} ${} (;{};)", kind, id, idx),
+        None => format!("({} (;{};)", kind, idx),
+    }
+}
+
+/// Writes a group like ` (param i32 i64)`, or nothing for no types.
+pub(super) fn types_text(kind: &str, val_types: &[WasmValueType]) -> String {
+    if val_types.is_empty() {
+        return String::new();
+    }
+    let types: Vec<_> = val_types.iter().map(|t| t.to_string()).collect();
+    format!(" ({} {})", kind, types.join(" "))
+}
+
+pub(super) fn limits_text(limits: &WasmLimits) -> String {
+    match limits.max {
+        Some(max) => format!("{} {}", limits.min, max),
+        None => limits.min.to_string(),
+    }
+}
+
+pub(super) fn global_type_text(global_type: &WasmGlobalType) -> String {
+    match global_type.mutability {
+        WasmGlobalMutability::Mutable => format!("(mut {})", global_type.val_type),
+        WasmGlobalMutability::Immutable => global_type.val_type.to_string(),
+    }
+}
+
+fn f32_text(val: f32) -> String {
+    if val.is_nan() {
+        let bits = val.to_bits();
+        let sign = if bits >> 31 == 1 { "-" } else { "" };
+        match bits & 0x7FFFFF {
+            0x400000 => format!("{}nan", sign),
+            payload => format!("{}nan:0x{:x}", sign, payload),
+        }
+    } else if val.is_infinite() {
+        if val > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        val.to_string()
+    }
+}
+
+fn f64_text(val: f64) -> String {
+    if val.is_nan() {
+        let bits = val.to_bits();
+        let sign = if bits >> 63 == 1 { "-" } else { "" };
+        match bits & 0xFFFFFFFFFFFFF {
+            0x8000000000000 => format!("{}nan", sign),
+            payload => format!("{}nan:0x{:x}", sign, payload),
+        }
+    } else if val.is_infinite() {
+        if val > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        val.to_string()
+    }
+}
+
+/// A folded instruction with the instructions producing its operands.
+struct Node {
+    text: String,
+    results: usize,
+    children: Vec<Node>,
+    body: Option<NodeBody>,
+}
+
+enum NodeBody {
+    Block(Vec<Node>),
+    If(Vec<Node>, Option<Vec<Node>>),
+}
+
+struct Printer<'m, I: Immediates> {
+    wmod: &'m WasmModuleRepr<WasmInstructionRepr<I>>,
+    /// The bodies of the defined functions.
+    bodies: Vec<&'m WasmFunc<WasmInstructionRepr<I>>>,
+    options: WasmPrintOptions,
+    out: String,
+    func_types: Vec<WasmTypeIdx>,
+    funcs: HashMap<u32, String>,
+    tables: HashMap<u32, String>,
+    mems: HashMap<u32, String>,
+    globals: HashMap<u32, String>,
+    elems: HashMap<u32, String>,
+    datas: HashMap<u32, String>,
+    locals: HashMap<u32, String>,
+    /// The branch arity of each enclosing block, innermost last.
+    labels: Vec<usize>,
+    func_results: usize,
+}
+
+impl<'m, I: Immediates> Printer<'m, I> {
+    fn new(
+        wmod: &'m WasmModuleRepr<WasmInstructionRepr<I>>,
+        bodies: Vec<&'m WasmFunc<WasmInstructionRepr<I>>>,
+        options: WasmPrintOptions,
+    ) -> Self {
+        let func_types = wmod
+            .imports
+            .iter()
+            .filter_map(|import| match import.desc {
+                WasmImportDesc::Func(type_idx) => Some(type_idx),
+                _ => None,
+            })
+            .chain(wmod.funcs.iter().map(|func| func.type_idx))
+            .collect();
+        let names = &wmod.names;
+        Printer {
+            wmod,
+            bodies,
+            options,
+            out: String::new(),
+            func_types,
+            funcs: ids(&names.funcs, |idx| idx.0),
+            tables: ids(&names.tables, |idx| idx.0),
+            mems: ids(&names.mems, |idx| idx.0),
+            globals: ids(&names.globals, |idx| idx.0),
+            elems: ids(&names.elems, |idx| idx.0),
+            datas: ids(&names.datas, |idx| idx.0),
+            locals: HashMap::new(),
+            labels: Vec::new(),
+            func_results: 0,
+        }
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        self.out.push('\n');
+        for _ in 0..depth {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+    }
+
+    fn func_type(&self, type_idx: WasmTypeIdx) -> Option<&'m WasmFuncType> {
+        self.wmod.types.get(type_idx.0 as usize)
+    }
+
+    fn func_arity(&self, type_idx: Option<WasmTypeIdx>) -> (usize, usize) {
+        type_idx
+            .and_then(|type_idx| self.func_type(type_idx))
+            .map_or((0, 0), |func_type| {
+                (func_type.input_type.len(), func_type.output_type.len())
+            })
+    }
+
+    fn block_arity(&self, block_type: &WasmBlockType) -> (usize, usize) {
+        match block_type {
+            WasmBlockType::InlineType(None) => (0, 0),
+            WasmBlockType::InlineType(Some(_)) => (0, 1),
+            WasmBlockType::TypeRef(type_idx) => self.func_arity(Some(*type_idx)),
+        }
+    }
+
+    fn label_arity(&self, label_idx: WasmLabelIdx) -> usize {
+        let depth = label_idx.0 as usize;
+        if depth < self.labels.len() {
+            self.labels[self.labels.len() - 1 - depth]
+        } else {
+            self.func_results
+        }
+    }
+
+    fn label_ref(&self, label_idx: WasmLabelIdx) -> String {
+        match self.labels.len().checked_sub(label_idx.0 as usize) {
+            Some(target) => format!("{} (;@{};)", label_idx.0, target),
+            None => label_idx.0.to_string(),
+        }
+    }
+
+    /// The keyword and annotations opening a structured instruction, and
+    /// the arity of branches to it.
+    fn block_head(&self, instr: &WasmInstructionRepr<I>) -> Option<(String, usize)> {
+        use WasmInstructionRepr::*;
+        let (keyword, block_type) = match instr {
+            Block { block_type, .. } => ("block", block_type),
+            Loop { block_type, .. } => ("loop", block_type),
+            If { block_type, .. } => ("if", block_type),
+            _ => return None,
+        };
+        let type_text = match block_type {
+            WasmBlockType::InlineType(None) => String::new(),
+            WasmBlockType::InlineType(Some(val_type)) => format!(" (result {})", val_type),
+            WasmBlockType::TypeRef(type_idx) => format!(" (type {})", type_idx.0),
+        };
+        let (params, results) = self.block_arity(block_type);
+        let arity = if keyword == "loop" { params } else { results };
+        let text = format!("{}{} (;@{};)", keyword, type_text, self.labels.len() + 1);
+        Some((text, arity))
+    }
+
+    fn arity(&self, instr: &WasmInstructionRepr<I>) -> (usize, usize) {
+        use WasmInstructionRepr::*;
+        if let Some(plain) = plain_instr(instr) {
+            return (plain.pops, plain.pushes);
+        }
+        if let Some((name, _, _)) = mem_instr(instr) {
+            return if name.contains("store") {
+                (2, 0)
+            } else {
+                (1, 1)
+            };
+        }
+        match instr {
+            Block { block_type, .. } | Loop { block_type, .. } => self.block_arity(block_type),
+            If { block_type, .. } => {
+                let (params, results) = self.block_arity(block_type);
+                (params + 1, results)
+            }
+            Break { label_idx, .. } => (self.label_arity(*label_idx), 0),
+            BreakIf { label_idx, .. } => {
+                let arity = self.label_arity(*label_idx);
+                (arity + 1, arity)
+            }
+            BreakTable { imm } => {
+                let arity = I::break_table_labels(imm)
+                    .last()
+                    .map_or(0, |label_idx| self.label_arity(label_idx));
+                (arity + 1, 0)
+            }
+            Return { .. } => (self.func_results, 0),
+            Call { func_idx } => self.func_arity(self.func_types.get(func_idx.0 as usize).copied()),
+            CallIndirect { type_idx, .. } => {
+                let (params, results) = self.func_arity(Some(*type_idx));
+                (params + 1, results)
+            }
+            RefNull { .. } | RefFunc { .. } => (0, 1),
+            Select { .. } => (3, 1),
+            LocalGet { .. } | GlobalGet { .. } => (0, 1),
+            LocalSet { .. } | GlobalSet { .. } => (1, 0),
+            LocalTee { .. } => (1, 1),
+            TableGet { .. } => (1, 1),
+            TableSet { .. } => (2, 0),
+            TableSize { .. } => (0, 1),
+            TableGrow { .. } => (2, 1),
+            TableFill { .. } | TableCopy { .. } | TableInit { .. } | MemoryInit { .. } => (3, 0),
+            I32Const { .. } | I64Const { .. } | F32Const { .. } | F64Const { .. } => (0, 1),
+            _ => (0, 0),
+        }
+    }
+
+    /// The text of an instruction that does not open or close a block.
+    fn instr_text(&self, instr: &WasmInstructionRepr<I>) -> String {
+        use WasmInstructionRepr::*;
+        if let Some(plain) = plain_instr(instr) {
+            return plain.name.to_string();
+        }
+        if let Some((name, natural_align, memarg)) = mem_instr(instr) {
+            let mut text = name.to_string();
+            if memarg.offset != 0 {
+                text.push_str(&format!(" offset={}", memarg.offset));
+            }
+            if memarg.align != natural_align {
+                text.push_str(&format!(" align={}", 1u64 << memarg.align.min(63)));
+            }
+            return text;
+        }
+        match instr {
+            Break { label_idx, .. } => format!("br {}", self.label_ref(*label_idx)),
+            BreakIf { label_idx, .. } => format!("br_if {}", self.label_ref(*label_idx)),
+            BreakTable { imm } => {
+                let mut text = "br_table".to_string();
+                for label_idx in I::break_table_labels(imm) {
+                    text.push(' ');
+                    text.push_str(&self.label_ref(label_idx));
+                }
+                text
+            }
+            Return { .. } => "return".to_string(),
+            Call { func_idx } => format!("call {}", reference(&self.funcs, func_idx.0)),
+            CallIndirect {
+                table_idx,
+                type_idx,
+            } => {
+                if table_idx.0 == 0 {
+                    format!("call_indirect (type {})", type_idx.0)
+                } else {
+                    format!(
+                        "call_indirect {} (type {})",
+                        reference(&self.tables, table_idx.0),
+                        type_idx.0
+                    )
+                }
+            }
+            RefNull { ref_type } => format!("ref.null {}", heap_type_name(*ref_type)),
+            RefFunc { func_idx } => format!("ref.func {}", reference(&self.funcs, func_idx.0)),
+            Select { value_types } if value_types.is_empty() => "select".to_string(),
+            Select { value_types } => {
+                let types: Vec<_> = value_types.iter().map(|t| t.to_string()).collect();
+                format!("select (result {})", types.join(" "))
+            }
+            LocalGet { local_idx } => format!("local.get {}", reference(&self.locals, local_idx.0)),
+            LocalSet { local_idx } => format!("local.set {}", reference(&self.locals, local_idx.0)),
+            LocalTee { local_idx } => format!("local.tee {}", reference(&self.locals, local_idx.0)),
+            GlobalGet { global_idx } => {
+                format!("global.get {}", reference(&self.globals, global_idx.0))
+            }
+            GlobalSet { global_idx } => {
+                format!("global.set {}", reference(&self.globals, global_idx.0))
+            }
+            TableGet { table_idx } => format!("table.get {}", reference(&self.tables, table_idx.0)),
+            TableSet { table_idx } => format!("table.set {}", reference(&self.tables, table_idx.0)),
+            TableSize { table_idx } => {
+                format!("table.size {}", reference(&self.tables, table_idx.0))
+            }
+            TableGrow { table_idx } => {
+                format!("table.grow {}", reference(&self.tables, table_idx.0))
+            }
+            TableFill { table_idx } => {
+                format!("table.fill {}", reference(&self.tables, table_idx.0))
+            }
+            TableCopy { dst, src } => format!(
+                "table.copy {} {}",
+                reference(&self.tables, dst.0),
+                reference(&self.tables, src.0)
+            ),
+            TableInit {
+                table_idx,
+                elem_idx,
+            } => format!(
+                "table.init {} {}",
+                reference(&self.tables, table_idx.0),
+                reference(&self.elems, elem_idx.0)
+            ),
+            ElemDrop { elem_idx } => format!("elem.drop {}", reference(&self.elems, elem_idx.0)),
+            MemoryInit { data_idx } => {
+                format!("memory.init {}", reference(&self.datas, data_idx.0))
+            }
+            DataDrop { data_idx } => format!("data.drop {}", reference(&self.datas, data_idx.0)),
+            I32Const { val } => format!("i32.const {}", val),
+            I64Const { val } => format!("i64.const {}", val),
+            F32Const { val } => format!("f32.const {}", f32_text(*val)),
+            F64Const { val } => format!("f64.const {}", f64_text(*val)),
+            Block { .. } | Loop { .. } | If { .. } => self
+                .block_head(instr)
+                .map(|(text, _)| text)
+                .unwrap_or_default(),
+            Else => "else".to_string(),
+            ExprEnd => "end".to_string(),
+            _ => unreachable!("plain instructions are handled above"),
+        }
+    }
+
+    /// Renders a constant expression on one line, like `(i32.const 8)`.
+    fn const_expr_text(&self, expr: &[WasmInstructionRepr<I>]) -> String {
+        let parts: Vec<_> = expr
+            .iter()
+            .filter(|instr| !matches!(instr, WasmInstructionRepr::ExprEnd))
+            .map(|instr| format!("({})", self.instr_text(instr)))
+            .collect();
+        parts.join(" ")
+    }
+
+    fn print_flat(&mut self, body: &[WasmInstructionRepr<I>]) {
+        use WasmInstructionRepr::*;
+        let base = 2;
+        for instr in body {
+            let depth = base + self.labels.len();
+            match instr {
+                Block { .. } | Loop { .. } | If { .. } => {
+                    let (text, arity) = self.block_head(instr).unwrap();
+                    self.line(depth, &text);
+                    self.labels.push(arity);
+                }
+                Else => self.line(depth.saturating_sub(1), "else"),
+                ExprEnd => {
+                    self.labels.pop();
+                    self.line(base + self.labels.len(), "end");
+                }
+                _ => {
+                    let text = self.instr_text(instr);
+                    self.line(depth, &text);
+                }
+            }
+        }
+    }
+
+    /// Folds instructions up to the next `else` or `end`, nesting each
+    /// instruction's operands inside it where their producers are known.
+    fn fold_seq(&mut self, body: &[WasmInstructionRepr<I>], pos: &mut usize) -> Vec<Node> {
+        use WasmInstructionRepr::*;
+        let mut nodes: Vec<Node> = Vec::new();
+        while let Some(instr) = body.get(*pos) {
+            if matches!(instr, Else | ExprEnd) {
+                break;
+            }
+            *pos += 1;
+            let (pops, results) = self.arity(instr);
+            let mut node = match self.block_head(instr) {
+                Some((text, arity)) => {
+                    self.labels.push(arity);
+                    let then = self.fold_seq(body, pos);
+                    let els = if let Some(Else) = body.get(*pos) {
+                        *pos += 1;
+                        Some(self.fold_seq(body, pos))
+                    } else {
+                        None
+                    };
+                    *pos += 1;
+                    self.labels.pop();
+                    let body = match instr {
+                        If { .. } => NodeBody::If(then, els),
+                        _ => NodeBody::Block(then),
+                    };
+                    Node {
+                        text,
+                        results,
+                        children: Vec::new(),
+                        body: Some(body),
+                    }
+                }
+                None => Node {
+                    text: self.instr_text(instr),
+                    results,
+                    children: Vec::new(),
+                    body: None,
+                },
+            };
+            let mut needed = pops;
+            let mut taken = 0;
+            for operand in nodes.iter().rev() {
+                if operand.results == 0 || operand.results > needed {
+                    break;
+                }
+                needed -= operand.results;
+                taken += 1;
+            }
+            node.children = nodes.split_off(nodes.len() - taken);
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    fn print_node(&mut self, depth: usize, node: Node) {
+        self.line(depth, &format!("({}", node.text));
+        for child in node.children {
+            self.print_node(depth + 1, child);
+        }
+        match node.body {
+            None => {}
+            Some(NodeBody::Block(body)) => {
+                for child in body {
+                    self.print_node(depth + 1, child);
+                }
+            }
+            Some(NodeBody::If(then, els)) => {
+                self.line(depth + 1, "(then");
+                for child in then {
+                    self.print_node(depth + 2, child);
+                }
+                self.out.push(')');
+                if let Some(els) = els {
+                    self.line(depth + 1, "(else");
+                    for child in els {
+                        self.print_node(depth + 2, child);
+                    }
+                    self.out.push(')');
+                }
+            }
+        }
+        self.out.push(')');
+    }
+
+    /// Writes `(kind t...)` groups for params or locals, one per local when
+    /// any of them has a name.
+    fn locals_text(&self, kind: &str, first_idx: u32, val_types: &[WasmValueType]) -> String {
+        let named = (0..val_types.len() as u32).any(|i| self.locals.contains_key(&(first_idx + i)));
+        if named {
+            let mut text = String::new();
+            for (i, val_type) in val_types.iter().enumerate() {
+                match self.locals.get(&(first_idx + i as u32)) {
+                    Some(id) => text.push_str(&format!(" ({} ${} {})", kind, id, val_type)),
+                    None => text.push_str(&format!(" ({} {})", kind, val_type)),
+                }
+            }
+            text
+        } else {
+            types_text(kind, val_types)
+        }
+    }
+
+    fn print_func(&mut self, func_idx: u32, func: &WasmFunc<WasmInstructionRepr<I>>) {
+        self.locals = self
+            .wmod
+            .names
+            .locals
+            .get(&WasmFuncIdx(func_idx))
+            .map(|names| ids(names, |idx| idx.0))
+            .unwrap_or_default();
+        let func_type = self.func_type(func.type_idx);
+        let params = func_type.map_or(&[][..], |t| &t.input_type.0);
+        let results = func_type.map_or(&[][..], |t| &t.output_type.0);
+        let header = format!(
+            "{} (type {}){}{}",
+            definition("func", &self.funcs, func_idx),
+            func.type_idx.0,
+            self.locals_text("param", 0, params),
+            types_text("result", results),
+        );
+        self.line(1, &header);
+        let locals = self.locals_text("local", params.len() as u32, &func.locals);
+        if !locals.is_empty() {
+            self.line(2, locals.trim_start());
+        }
+
+        self.func_results = results.len();
+        self.labels.clear();
+        let body = match func.body.split_last() {
+            Some((WasmInstructionRepr::ExprEnd, body)) => body,
+            _ => &func.body,
+        };
+        if self.options.folded {
+            let mut pos = 0;
+            let mut nodes = Vec::new();
+            while pos < body.len() {
+                nodes.extend(self.fold_seq(body, &mut pos));
+                // A stray `else` or `end` cannot be folded; print it as is.
+                if let Some(instr) = body.get(pos) {
+                    nodes.push(Node {
+                        text: self.instr_text(instr),
+                        results: 0,
+                        children: Vec::new(),
+                        body: None,
+                    });
+                    pos += 1;
+                }
+            }
+            for node in nodes {
+                self.print_node(2, node);
+            }
+        } else {
+            self.print_flat(body);
+        }
+        self.out.push(')');
+    }
+
+    fn print(mut self) -> String {
+        let wmod = self.wmod;
+        self.out.push_str("(module");
+        if let Some(name) = &wmod.names.module {
+            let module_ids = ids(&HashMap::from([(0, WasmName(name.0.clone()))]), |idx| idx);
+            if let Some(id) = module_ids.get(&0) {
+                self.out.push_str(&format!(" ${}", id));
+            }
+        }
+
+        for (i, func_type) in wmod.types.iter().enumerate() {
+            let text = format!(
+                "(type (;{};) (func{}{}))",
+                i,
+                types_text("param", &func_type.input_type.0),
+                types_text("result", &func_type.output_type.0),
+            );
+            self.line(1, &text);
+        }
+
+        let (mut num_funcs, mut num_tables, mut num_mems, mut num_globals) = (0, 0, 0, 0);
+        for import in &wmod.imports {
+            let mut text = "(import ".to_string();
+            write_string(&mut text, import.module_name.0.as_bytes());
+            text.push(' ');
+            write_string(&mut text, import.item_name.0.as_bytes());
+            let desc = match &import.desc {
+                WasmImportDesc::Func(type_idx) => {
+                    num_funcs += 1;
+                    format!(
+                        "{} (type {}))",
+                        definition("func", &self.funcs, num_funcs - 1),
+                        type_idx.0
+                    )
+                }
+                WasmImportDesc::Table(table_type) => {
+                    num_tables += 1;
+                    format!(
+                        "{} {} {})",
+                        definition("table", &self.tables, num_tables - 1),
+                        limits_text(&table_type.limits),
+                        WasmValueType::Ref(table_type.ref_type)
+                    )
+                }
+                WasmImportDesc::Mem(mem_type) => {
+                    num_mems += 1;
+                    format!(
+                        "{} {})",
+                        definition("memory", &self.mems, num_mems - 1),
+                        limits_text(&mem_type.limits)
+                    )
+                }
+                WasmImportDesc::Global(global_type) => {
+                    num_globals += 1;
+                    format!(
+                        "{} {})",
+                        definition("global", &self.globals, num_globals - 1),
+                        global_type_text(global_type)
+                    )
+                }
+            };
+            text.push(' ');
+            text.push_str(&desc);
+            text.push(')');
+            self.line(1, &text);
+        }
+
+        for (i, func) in std::mem::take(&mut self.bodies).into_iter().enumerate() {
+            self.print_func(num_funcs + i as u32, func);
+        }
+
+        for (i, table_type) in wmod.tables.iter().enumerate() {
+            let text = format!(
+                "{} {} {})",
+                definition("table", &self.tables, num_tables + i as u32),
+                limits_text(&table_type.limits),
+                WasmValueType::Ref(table_type.ref_type)
+            );
+            self.line(1, &text);
+        }
+
+        for (i, mem_type) in wmod.mems.iter().enumerate() {
+            let text = format!(
+                "{} {})",
+                definition("memory", &self.mems, num_mems + i as u32),
+                limits_text(&mem_type.limits)
+            );
+            self.line(1, &text);
+        }
+
+        for (i, global) in wmod.globals.iter().enumerate() {
+            let text = format!(
+                "{} {} {})",
+                definition("global", &self.globals, num_globals + i as u32),
+                global_type_text(&global.global_type),
+                self.const_expr_text(&global.init)
+            );
+            self.line(1, &text);
+        }
+
+        for export in &wmod.exports {
+            let mut text = "(export ".to_string();
+            write_string(&mut text, export.name.0.as_bytes());
+            let desc = match export.desc {
+                WasmExportDesc::Func(idx) => format!("(func {})", reference(&self.funcs, idx.0)),
+                WasmExportDesc::Table(idx) => {
+                    format!("(table {})", reference(&self.tables, idx.0))
+                }
+                WasmExportDesc::Mem(idx) => format!("(memory {})", reference(&self.mems, idx.0)),
+                WasmExportDesc::Global(idx) => {
+                    format!("(global {})", reference(&self.globals, idx.0))
+                }
+            };
+            text.push_str(&format!(" {})", desc));
+            self.line(1, &text);
+        }
+
+        if let Some(start) = wmod.start {
+            let text = format!("(start {})", reference(&self.funcs, start.0));
+            self.line(1, &text);
+        }
+
+        for (i, elem) in wmod.elems.iter().enumerate() {
+            let mut text = definition("elem", &self.elems, i as u32);
+            match &elem.elem_mode {
+                WasmElemMode::Active {
+                    table_idx,
+                    offset_expr,
+                } => {
+                    if table_idx.0 != 0 {
+                        text.push_str(&format!(
+                            " (table {})",
+                            reference(&self.tables, table_idx.0)
+                        ));
+                    }
+                    text.push(' ');
+                    text.push_str(&self.const_expr_text(offset_expr));
+                }
+                WasmElemMode::Passive => {}
+                WasmElemMode::Declarative => text.push_str(" declare"),
+            }
+            let func_refs: Option<Vec<_>> = elem
+                .init
+                .iter()
+                .map(|expr| match expr.as_ref() {
+                    [
+                        WasmInstructionRepr::RefFunc { func_idx },
+                        WasmInstructionRepr::ExprEnd,
+                    ] => Some(reference(&self.funcs, func_idx.0)),
+                    _ => None,
+                })
+                .collect();
+            match func_refs {
+                Some(func_refs) if elem.ref_type == WasmRefType::FuncRef => {
+                    text.push_str(" func");
+                    for func_ref in func_refs {
+                        text.push(' ');
+                        text.push_str(&func_ref);
+                    }
+                }
+                _ => {
+                    text.push_str(&format!(" {}", WasmValueType::Ref(elem.ref_type)));
+                    for expr in &elem.init {
+                        text.push(' ');
+                        text.push_str(&self.const_expr_text(expr));
+                    }
+                }
+            }
+            text.push(')');
+            self.line(1, &text);
+        }
+
+        for (i, data) in wmod.datas.iter().enumerate() {
+            let mut text = definition("data", &self.datas, i as u32);
+            if let WasmDataMode::Active {
+                mem_idx,
+                offset_expr,
+            } = &data.mode
+            {
+                if mem_idx.0 != 0 {
+                    text.push_str(&format!(" (memory {})", reference(&self.mems, mem_idx.0)));
+                }
+                text.push(' ');
+                text.push_str(&self.const_expr_text(offset_expr));
+            }
+            text.push(' ');
+            write_string(&mut text, &data.bytes);
+            text.push(')');
+            self.line(1, &text);
+        }
+
+        for custom in &wmod.customs {
+            let mut text = "(; custom section ".to_string();
+            write_string(&mut text, custom.name.0.as_bytes());
+            text.push_str(&format!(", {} bytes ;)", custom.bytes.len()));
+            self.line(1, &text);
+        }
+
+        self.out.push_str(")\n");
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::fixtures::MODULE, *};
+
+    #[test]
+    fn test_print_flat_and_folded() {
+        let wmod = WasmModule::from_bytes(MODULE).unwrap();
+        let flat = wmod.to_wat(WasmPrintOptions::default()).unwrap();
+        assert_eq!(
+            flat,
+            r#"(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func $f (;0;) (type 0) (param i32) (result i32)
+    block (result i32) (;@1;)
+      i32.const 7
+      local.get 0
+      br_table 0 (;@1;) 1 (;@0;) 0 (;@1;)
+    end
+    local.get 0
+    if (result i32) (;@1;)
+      i32.const 1
+    else
+      i32.const 2
+    end
+    i32.add)
+  (export "f" (func $f))
+  (; custom section "name", 6 bytes ;))
+"#
+        );
+        let folded = wmod.to_wat(WasmPrintOptions { folded: true }).unwrap();
+        assert_eq!(
+            folded,
+            r#"(module
+  (type (;0;) (func (param i32) (result i32)))
+  (func $f (;0;) (type 0) (param i32) (result i32)
+    (i32.add
+      (block (result i32) (;@1;)
+        (br_table 0 (;@1;) 1 (;@0;) 0 (;@1;)
+          (i32.const 7)
+          (local.get 0)))
+      (if (result i32) (;@1;)
+        (local.get 0)
+        (then
+          (i32.const 1))
+        (else
+          (i32.const 2)))))
+  (export "f" (func $f))
+  (; custom section "name", 6 bytes ;))
+"#
+        );
+    }
+}
@@ -1,4 +1,6 @@
-use std::mem;
+use std::{collections::HashMap, mem};
+
+use super::{dwarf::WasmLineTable, valid::LazyFuncs};
 
 pub type WasmModule = WasmModuleRepr<WasmInstruction>;
 pub type WasmModuleRaw = WasmModuleRepr<WasmInstructionRaw>;
@@ -17,9 +19,17 @@ pub struct WasmModuleRepr<TWasmInstruction> {
     pub imports: Box<[WasmImport]>,
     pub exports: Box<[WasmExport]>,
     pub customs: Box<[WasmCustom]>,
+    pub names: WasmNameMap,
+    pub lines: WasmLineTable,
+    /// The bodies still to validate, when validated lazily.
+    pub(crate) lazy_funcs: Option<Box<LazyFuncs>>,
 }
 
 impl<TWasmInstruction> WasmModuleRepr<TWasmInstruction> {
+    pub fn func_name(&self, func_idx: WasmFuncIdx) -> Option<&str> {
+        self.names.func_name(func_idx)
+    }
+
     pub fn empty() -> Self {
         WasmModuleRepr {
             version: 0,
@@ -34,6 +44,9 @@ impl<TWasmInstruction> WasmModuleRepr<TWasmInstruction> {
             imports: Box::new([]),
             exports: Box::new([]),
             customs: Box::new([]),
+            names: WasmNameMap::default(),
+            lines: WasmLineTable::default(),
+            lazy_funcs: None,
         }
     }
 }
@@ -42,19 +55,19 @@ impl<TWasmInstruction> WasmModuleRepr<TWasmInstruction> {
 pub struct WasmTypeIdx(pub u32);
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct WasmFuncIdx(pub u32);
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct WasmTableIdx(pub u32);
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct WasmMemIdx(pub u32);
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct WasmGlobalIdx(pub u32);
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct WasmElemIdx(pub u32);
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct WasmDataIdx(pub u32);
 #[derive(Debug, Copy, Clone)]
 pub struct WasmLabelIdx(pub u32);
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct WasmLocalIdx(pub u32);
 
 impl WasmMemIdx {
@@ -110,6 +123,21 @@ impl WasmValueType {
     }
 }
 
+impl std::fmt::Display for WasmValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WasmValueType::Num(WasmNumType::I32) => "i32",
+            WasmValueType::Num(WasmNumType::I64) => "i64",
+            WasmValueType::Num(WasmNumType::F32) => "f32",
+            WasmValueType::Num(WasmNumType::F64) => "f64",
+            WasmValueType::Vec(WasmVecType::V128) => "v128",
+            WasmValueType::Ref(WasmRefType::FuncRef) => "funcref",
+            WasmValueType::Ref(WasmRefType::ExternRef) => "externref",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 macro_rules! t {
     (i32) => {
         crate::module::WasmValueType::Num(crate::module::WasmNumType::I32)
@@ -173,6 +201,9 @@ pub trait Immediates: std::fmt::Debug {
     type IfImmediates: std::fmt::Debug;
     type BreakImmediates: std::fmt::Debug;
     type BreakTableImmediates: std::fmt::Debug;
+
+    /// The label indices of a `br_table`, with the default label last.
+    fn break_table_labels(imm: &Self::BreakTableImmediates) -> impl Iterator<Item = WasmLabelIdx>;
 }
 
 #[derive(Debug)]
@@ -189,6 +220,10 @@ impl Immediates for UnverifiedImmediates {
     type IfImmediates = ();
     type BreakImmediates = ();
     type BreakTableImmediates = UnverifiedBreakTableImmediates;
+
+    fn break_table_labels(imm: &Self::BreakTableImmediates) -> impl Iterator<Item = WasmLabelIdx> {
+        imm.labels.iter().copied()
+    }
 }
 
 #[derive(Debug)]
@@ -229,6 +264,10 @@ impl Immediates for VerifiedImmediates {
     type IfImmediates = VerifiedIfImmediates;
     type BreakImmediates = VerifiedBreakImmediates;
     type BreakTableImmediates = VerifiedBreakTableImmediates;
+
+    fn break_table_labels(imm: &Self::BreakTableImmediates) -> impl Iterator<Item = WasmLabelIdx> {
+        imm.heap_args.labels.iter().map(|entry| entry.labelidx)
+    }
 }
 
 pub type WasmInstruction = WasmInstructionRepr<VerifiedImmediates>;
@@ -578,21 +617,24 @@ pub struct WasmFunc<TWasmInstruction = WasmInstruction> {
     pub type_idx: WasmTypeIdx,
     pub locals: Box<[WasmValueType]>,
     pub body: Box<[TWasmInstruction]>,
+    /// Byte offset of each instruction in `body`, relative to the start of
+    /// the code section. This is the address space DWARF uses for wasm.
+    pub offsets: Box<[u32]>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WasmLimits {
     pub min: u32,
     pub max: Option<u32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WasmTableType {
     pub limits: WasmLimits,
     pub ref_type: WasmRefType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WasmMemType {
     pub limits: WasmLimits,
 }
@@ -647,7 +689,7 @@ pub struct WasmData<TWasmInstruction = WasmInstruction> {
     pub mode: WasmDataMode<TWasmInstruction>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum WasmImportDesc {
     Func(WasmTypeIdx),
     Table(WasmTableType),
@@ -655,10 +697,10 @@ pub enum WasmImportDesc {
     Global(WasmGlobalType),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WasmName(pub Box<str>);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WasmImport {
     pub module_name: WasmName,
     pub item_name: WasmName,
@@ -684,6 +726,57 @@ pub struct WasmCustom {
     pub bytes: Box<[u8]>,
 }
 
+/// Names recorded in the "name" custom section, including the subsections
+/// added by the extended name section proposal.
+#[derive(Debug, Default)]
+pub struct WasmNameMap {
+    pub module: Option<WasmName>,
+    pub funcs: HashMap<WasmFuncIdx, WasmName>,
+    pub locals: HashMap<WasmFuncIdx, HashMap<WasmLocalIdx, WasmName>>,
+    pub tables: HashMap<WasmTableIdx, WasmName>,
+    pub mems: HashMap<WasmMemIdx, WasmName>,
+    pub globals: HashMap<WasmGlobalIdx, WasmName>,
+    pub elems: HashMap<WasmElemIdx, WasmName>,
+    pub datas: HashMap<WasmDataIdx, WasmName>,
+}
+
+impl WasmNameMap {
+    pub fn module_name(&self) -> Option<&str> {
+        self.module.as_ref().map(|name| name.0.as_ref())
+    }
+
+    pub fn func_name(&self, func_idx: WasmFuncIdx) -> Option<&str> {
+        self.funcs.get(&func_idx).map(|name| name.0.as_ref())
+    }
+
+    pub fn local_name(&self, func_idx: WasmFuncIdx, local_idx: WasmLocalIdx) -> Option<&str> {
+        self.locals
+            .get(&func_idx)?
+            .get(&local_idx)
+            .map(|name| name.0.as_ref())
+    }
+
+    pub fn table_name(&self, table_idx: WasmTableIdx) -> Option<&str> {
+        self.tables.get(&table_idx).map(|name| name.0.as_ref())
+    }
+
+    pub fn mem_name(&self, mem_idx: WasmMemIdx) -> Option<&str> {
+        self.mems.get(&mem_idx).map(|name| name.0.as_ref())
+    }
+
+    pub fn global_name(&self, global_idx: WasmGlobalIdx) -> Option<&str> {
+        self.globals.get(&global_idx).map(|name| name.0.as_ref())
+    }
+
+    pub fn elem_name(&self, elem_idx: WasmElemIdx) -> Option<&str> {
+        self.elems.get(&elem_idx).map(|name| name.0.as_ref())
+    }
+
+    pub fn data_name(&self, data_idx: WasmDataIdx) -> Option<&str> {
+        self.datas.get(&data_idx).map(|name| name.0.as_ref())
+    }
+}
+
 impl std::fmt::Debug for WasmCustom {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(
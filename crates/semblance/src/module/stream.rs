@@ -0,0 +1,265 @@
+use std::io::{self, BufReader, Read};
+
+use super::{
+    bin,
+    builder::WasmModuleBuilder,
+    valid::{self, FuncValidator},
+    *,
+};
+
+/// A reader that knows how far into the binary it is, so that errors can
+/// point at the same offsets as when decoding from a slice.
+struct Stream<R> {
+    reader: R,
+    pos: usize,
+}
+
+impl<R: Read> Stream<R> {
+    /// Reads a byte, or `None` at the end of the input.
+    fn try_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut buf = [0];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {
+                    self.pos += 1;
+                    return Ok(Some(buf[0]));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, WasmReadError> {
+        self.try_byte()?.ok_or_else(|| self.eof())
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<Vec<u8>, WasmReadError> {
+        // Not reserving `n` up front, it comes straight from the input.
+        let mut buf = Vec::new();
+        (&mut self.reader).take(n as u64).read_to_end(&mut buf)?;
+        self.pos += buf.len();
+        if buf.len() < n {
+            return Err(self.eof());
+        }
+        Ok(buf)
+    }
+
+    /// Reads an unsigned LEB128 integer, returning its encoding too.
+    fn leb128(&mut self) -> Result<(u32, Vec<u8>), WasmReadError> {
+        let start = self.pos;
+        let mut buf = Vec::with_capacity(5);
+        loop {
+            let byte = self.byte()?;
+            buf.push(byte);
+            if byte & 0x80 == 0 || buf.len() == 5 {
+                break;
+            }
+        }
+        let (n, _) = bin::decode_leb128(&buf).map_err(|e| e.rebase_at(&buf, start))?;
+        Ok((n, buf))
+    }
+
+    fn eof(&self) -> WasmReadError {
+        WasmDecodeError::at_offset(self.pos, WasmDecodeErrorKind::UnexpectedEof).into()
+    }
+}
+
+/// Decodes and validates a module section by section. Only the section being
+/// decoded is held in memory, except for the code section, which is decoded
+/// and validated one function body at a time. Validating on several threads
+/// or lazily needs all the bodies, so then they are kept and validated once
+/// the whole module is read.
+pub(super) fn read_module(
+    reader: impl Read,
+    options: WasmValidationOptions,
+) -> Result<WasmModule, WasmReadError> {
+    // Most reads are of a byte or a few.
+    let reader = BufReader::new(reader);
+    let mut stream = Stream { reader, pos: 0 };
+    let deferred = options.lazy || options.threads > 1;
+    let header = stream.bytes(8)?;
+    let header_err = |e: WasmDecodeError| e.rebase(&header);
+    let (_, rest) = bin::decode_magic_bytes(&header).map_err(header_err)?;
+    let (version, _) = bin::decode_version(rest).map_err(header_err)?;
+    if version != 1 {
+        return Err(WasmDecodeError::at_offset(
+            4,
+            WasmDecodeErrorKind::UnsupportedVersion(version),
+        )
+        .into());
+    }
+
+    let mut wmod = WasmModuleBuilder::new();
+    wmod.version(version);
+    let mut funcs = Vec::new();
+    let mut raw_funcs = Vec::new();
+    let mut last = None;
+    while let Some(id) = stream.try_byte()? {
+        let id_offset = stream.pos - 1;
+        let id = [id];
+        let (sid, _) = bin::decode_section_id(&id).map_err(|e| e.rebase_at(&id, id_offset))?;
+        if !sid.follows(&mut last) {
+            return Err(WasmDecodeError::at_offset(
+                id_offset,
+                WasmDecodeErrorKind::SectionOutOfOrder(sid),
+            )
+            .into());
+        }
+        let (len, _) = stream.leb128().map_err(|e| in_section(e, sid))?;
+        if sid == WasmSectionId::Code {
+            let datacount = wmod.declared_datacount();
+            let decls = wmod.build_declarations();
+            let len = len as usize;
+            if deferred {
+                read_code_section(&mut stream, len, &decls, datacount, |_, func| {
+                    raw_funcs.push(func);
+                    Ok(())
+                })
+            } else {
+                let validator = FuncValidator::new(&decls)?.with_datacount(datacount.unwrap_or(0));
+                read_code_section(&mut stream, len, &decls, datacount, |func_idx, func| {
+                    funcs.push(validator.validate(func_idx, func)?);
+                    Ok(())
+                })
+            }
+            .map_err(|e| in_section(e, sid))?;
+            wmod = WasmModuleBuilder::from_declarations(decls, datacount);
+        } else {
+            let section_offset = stream.pos;
+            let section = stream.bytes(len as usize).map_err(|e| in_section(e, sid))?;
+            bin::decode_section_contents(sid, &section, &mut wmod)
+                .map_err(|e| e.rebase_at(&section, section_offset).in_section(sid))?;
+        }
+    }
+
+    bin::check_counts(&wmod, (funcs.len() + raw_funcs.len()) as u32)
+        .map_err(|kind| WasmDecodeError::at_offset(stream.pos, kind))?;
+    bin::decode_debug_info(&mut wmod);
+    if deferred {
+        let mut wmod = wmod.build_declarations();
+        wmod.funcs = raw_funcs.into_boxed_slice();
+        return Ok(valid::validate_with_options(wmod, options)?);
+    }
+    Ok(valid::validate_with_funcs(
+        wmod.build_declarations(),
+        funcs,
+    )?)
+}
+
+/// Decodes the function bodies of the code section, handing each to
+/// `on_body` as soon as it is read.
+fn read_code_section<R: Read>(
+    stream: &mut Stream<R>,
+    len: usize,
+    decls: &WasmModuleRaw,
+    datacount: Option<u32>,
+    mut on_body: impl FnMut(WasmFuncIdx, WasmFunc<WasmInstructionRaw>) -> Result<(), WasmReadError>,
+) -> Result<(), WasmReadError> {
+    let section_offset = stream.pos;
+    let end = section_offset + len;
+    let num_imported_funcs = decls
+        .imports
+        .iter()
+        .filter(|import| matches!(import.desc, WasmImportDesc::Func(_)))
+        .count();
+    let (count, _) = stream.leb128()?;
+    for i in 0..count as usize {
+        let func_idx = WasmFuncIdx((num_imported_funcs + i) as u32);
+        let body_offset = stream.pos;
+        let (size, mut buf) = stream.leb128()?;
+        if stream.pos + size as usize > end {
+            let e = WasmDecodeError::at_offset(end, WasmDecodeErrorKind::UnexpectedEof);
+            return Err(e.in_func(func_idx).into());
+        }
+        buf.extend(stream.bytes(size as usize)?);
+        let Some(decl) = decls.funcs.get(i) else {
+            return Err(WasmDecodeError::at_offset(
+                body_offset,
+                WasmDecodeErrorKind::FuncCodeMismatch {
+                    funcs: decls.funcs.len() as u32,
+                    bodies: count,
+                },
+            )
+            .into());
+        };
+        let (code, _) = bin::decode_code(
+            &buf,
+            &buf,
+            body_offset - section_offset,
+            datacount.is_some(),
+        )
+        .map_err(|e| e.rebase_at(&buf, body_offset).in_func(func_idx))?;
+        let func = WasmFunc {
+            type_idx: decl.type_idx,
+            locals: code.locals,
+            body: code.body,
+            offsets: code.offsets,
+        };
+        on_body(func_idx, func)?;
+    }
+    if stream.pos != end {
+        return Err(WasmDecodeError::at_offset(
+            stream.pos.min(end),
+            WasmDecodeErrorKind::SectionSizeMismatch,
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn in_section(e: WasmReadError, sid: WasmSectionId) -> WasmReadError {
+    match e {
+        WasmReadError::Decode(e) => WasmReadError::Decode(e.in_section(sid)),
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_module() {
+        let text = r#"(module
+            (memory 1)
+            (func $add (export "add") (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.add)
+            (func (export "double") (param i32) (result i32)
+                local.get 0
+                local.get 0
+                call $add)
+            (data (i32.const 0) "hi"))"#;
+        let mut raw = WasmModuleRaw::from_wat(text).unwrap();
+        // Without names the binary ends in the data section.
+        raw.names = WasmNameMap::default();
+        let bytes = raw.to_bytes();
+        let streamed = WasmModule::from_reader(&bytes[..]).unwrap();
+        let decoded = WasmModule::from_bytes(&bytes).unwrap();
+        assert_eq!(streamed.to_bytes().unwrap(), decoded.to_bytes().unwrap());
+        assert_eq!(streamed.funcs[1].offsets, decoded.funcs[1].offsets);
+
+        let options = WasmValidationOptions {
+            threads: 2,
+            lazy: false,
+        };
+        let threaded = WasmModule::from_reader_with_options(&bytes[..], options).unwrap();
+        assert_eq!(threaded.to_bytes().unwrap(), decoded.to_bytes().unwrap());
+        let options = WasmValidationOptions {
+            threads: 1,
+            lazy: true,
+        };
+        let lazy = WasmModule::from_reader_with_options(&bytes[..], options).unwrap();
+        assert_eq!(lazy.to_bytes().unwrap(), decoded.to_bytes().unwrap());
+
+        let truncated = &bytes[..bytes.len() - 3];
+        let Err(WasmReadError::Decode(e)) = WasmModule::from_reader(truncated) else {
+            panic!("truncated module was read");
+        };
+        assert_eq!(e.offset, truncated.len());
+        assert_eq!(e.section, Some(WasmSectionId::Data));
+    }
+}
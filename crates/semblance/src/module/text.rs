@@ -0,0 +1,256 @@
+use super::*;
+
+/// The text format of an instruction without immediates.
+pub(super) struct PlainInstr {
+    pub name: &'static str,
+    pub pops: usize,
+    pub pushes: usize,
+}
+
+macro_rules! plain_instrs {
+    ($($variant:ident => $name:literal, $pops:literal, $pushes:literal;)*) => {
+        pub(super) fn plain_instr<I: Immediates>(
+            instr: &WasmInstructionRepr<I>,
+        ) -> Option<PlainInstr> {
+            match instr {
+                $(WasmInstructionRepr::$variant => Some(PlainInstr {
+                    name: $name,
+                    pops: $pops,
+                    pushes: $pushes,
+                }),)*
+                _ => None,
+            }
+        }
+
+        pub(super) fn plain_instr_by_name<I: Immediates>(
+            name: &str,
+        ) -> Option<WasmInstructionRepr<I>> {
+            match name {
+                $($name => Some(WasmInstructionRepr::$variant),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+macro_rules! mem_instrs {
+    ($($variant:ident => $name:literal, $align:literal;)*) => {
+        /// The mnemonic and natural alignment of a memory access.
+        pub(super) fn mem_instr<I: Immediates>(
+            instr: &WasmInstructionRepr<I>,
+        ) -> Option<(&'static str, u32, &WasmMemArg)> {
+            match instr {
+                $(WasmInstructionRepr::$variant { memarg } => Some(($name, $align, memarg)),)*
+                _ => None,
+            }
+        }
+
+        pub(super) fn mem_instr_by_name<I: Immediates>(
+            name: &str,
+        ) -> Option<(u32, fn(WasmMemArg) -> WasmInstructionRepr<I>)> {
+            match name {
+                $($name => Some(($align, |memarg| WasmInstructionRepr::$variant { memarg })),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+mem_instrs! {
+    I32Load => "i32.load", 2;
+    I64Load => "i64.load", 3;
+    F32Load => "f32.load", 2;
+    F64Load => "f64.load", 3;
+    I32Load8S => "i32.load8_s", 0;
+    I32Load8U => "i32.load8_u", 0;
+    I32Load16S => "i32.load16_s", 1;
+    I32Load16U => "i32.load16_u", 1;
+    I64Load8S => "i64.load8_s", 0;
+    I64Load8U => "i64.load8_u", 0;
+    I64Load16S => "i64.load16_s", 1;
+    I64Load16U => "i64.load16_u", 1;
+    I64Load32S => "i64.load32_s", 2;
+    I64Load32U => "i64.load32_u", 2;
+    I32Store => "i32.store", 2;
+    I64Store => "i64.store", 3;
+    F32Store => "f32.store", 2;
+    F64Store => "f64.store", 3;
+    I32Store8 => "i32.store8", 0;
+    I32Store16 => "i32.store16", 1;
+    I64Store8 => "i64.store8", 0;
+    I64Store16 => "i64.store16", 1;
+    I64Store32 => "i64.store32", 2;
+}
+
+plain_instrs! {
+    Unreachable => "unreachable", 0, 0;
+    Nop => "nop", 0, 0;
+    Drop => "drop", 1, 0;
+    I32EqZ => "i32.eqz", 1, 1;
+    I32Eq => "i32.eq", 2, 1;
+    I32Neq => "i32.ne", 2, 1;
+    I32LtS => "i32.lt_s", 2, 1;
+    I32LtU => "i32.lt_u", 2, 1;
+    I32GtS => "i32.gt_s", 2, 1;
+    I32GtU => "i32.gt_u", 2, 1;
+    I32LeS => "i32.le_s", 2, 1;
+    I32LeU => "i32.le_u", 2, 1;
+    I32GeS => "i32.ge_s", 2, 1;
+    I32GeU => "i32.ge_u", 2, 1;
+    I64EqZ => "i64.eqz", 1, 1;
+    I64Eq => "i64.eq", 2, 1;
+    I64Neq => "i64.ne", 2, 1;
+    I64LtS => "i64.lt_s", 2, 1;
+    I64LtU => "i64.lt_u", 2, 1;
+    I64GtS => "i64.gt_s", 2, 1;
+    I64GtU => "i64.gt_u", 2, 1;
+    I64LeS => "i64.le_s", 2, 1;
+    I64LeU => "i64.le_u", 2, 1;
+    I64GeS => "i64.ge_s", 2, 1;
+    I64GeU => "i64.ge_u", 2, 1;
+    F32Eq => "f32.eq", 2, 1;
+    F32Neq => "f32.ne", 2, 1;
+    F32Lt => "f32.lt", 2, 1;
+    F32Gt => "f32.gt", 2, 1;
+    F32Le => "f32.le", 2, 1;
+    F32Ge => "f32.ge", 2, 1;
+    F64Eq => "f64.eq", 2, 1;
+    F64Neq => "f64.ne", 2, 1;
+    F64Lt => "f64.lt", 2, 1;
+    F64Gt => "f64.gt", 2, 1;
+    F64Le => "f64.le", 2, 1;
+    F64Ge => "f64.ge", 2, 1;
+    I32Clz => "i32.clz", 1, 1;
+    I32Ctz => "i32.ctz", 1, 1;
+    I32Popcnt => "i32.popcnt", 1, 1;
+    I32Add => "i32.add", 2, 1;
+    I32Sub => "i32.sub", 2, 1;
+    I32Mul => "i32.mul", 2, 1;
+    I32DivS => "i32.div_s", 2, 1;
+    I32DivU => "i32.div_u", 2, 1;
+    I32RemS => "i32.rem_s", 2, 1;
+    I32RemU => "i32.rem_u", 2, 1;
+    I32And => "i32.and", 2, 1;
+    I32Or => "i32.or", 2, 1;
+    I32Xor => "i32.xor", 2, 1;
+    I32Shl => "i32.shl", 2, 1;
+    I32ShrS => "i32.shr_s", 2, 1;
+    I32ShrU => "i32.shr_u", 2, 1;
+    I32Rotl => "i32.rotl", 2, 1;
+    I32Rotr => "i32.rotr", 2, 1;
+    I64Clz => "i64.clz", 1, 1;
+    I64Ctz => "i64.ctz", 1, 1;
+    I64Popcnt => "i64.popcnt", 1, 1;
+    I64Add => "i64.add", 2, 1;
+    I64Sub => "i64.sub", 2, 1;
+    I64Mul => "i64.mul", 2, 1;
+    I64DivS => "i64.div_s", 2, 1;
+    I64DivU => "i64.div_u", 2, 1;
+    I64RemS => "i64.rem_s", 2, 1;
+    I64RemU => "i64.rem_u", 2, 1;
+    I64And => "i64.and", 2, 1;
+    I64Or => "i64.or", 2, 1;
+    I64Xor => "i64.xor", 2, 1;
+    I64Shl => "i64.shl", 2, 1;
+    I64ShrS => "i64.shr_s", 2, 1;
+    I64ShrU => "i64.shr_u", 2, 1;
+    I64Rotl => "i64.rotl", 2, 1;
+    I64Rotr => "i64.rotr", 2, 1;
+    F32Abs => "f32.abs", 1, 1;
+    F32Neg => "f32.neg", 1, 1;
+    F32Ceil => "f32.ceil", 1, 1;
+    F32Floor => "f32.floor", 1, 1;
+    F32Trunc => "f32.trunc", 1, 1;
+    F32Nearest => "f32.nearest", 1, 1;
+    F32Sqrt => "f32.sqrt", 1, 1;
+    F32Add => "f32.add", 2, 1;
+    F32Sub => "f32.sub", 2, 1;
+    F32Mul => "f32.mul", 2, 1;
+    F32Div => "f32.div", 2, 1;
+    F32Min => "f32.min", 2, 1;
+    F32Max => "f32.max", 2, 1;
+    F32CopySign => "f32.copysign", 2, 1;
+    F64Abs => "f64.abs", 1, 1;
+    F64Neg => "f64.neg", 1, 1;
+    F64Ceil => "f64.ceil", 1, 1;
+    F64Floor => "f64.floor", 1, 1;
+    F64Trunc => "f64.trunc", 1, 1;
+    F64Nearest => "f64.nearest", 1, 1;
+    F64Sqrt => "f64.sqrt", 1, 1;
+    F64Add => "f64.add", 2, 1;
+    F64Sub => "f64.sub", 2, 1;
+    F64Mul => "f64.mul", 2, 1;
+    F64Div => "f64.div", 2, 1;
+    F64Min => "f64.min", 2, 1;
+    F64Max => "f64.max", 2, 1;
+    F64CopySign => "f64.copysign", 2, 1;
+    I32WrapI64 => "i32.wrap_i64", 1, 1;
+    I32TruncF32S => "i32.trunc_f32_s", 1, 1;
+    I32TruncF32U => "i32.trunc_f32_u", 1, 1;
+    I32TruncF64S => "i32.trunc_f64_s", 1, 1;
+    I32TruncF64U => "i32.trunc_f64_u", 1, 1;
+    I64ExtendI32S => "i64.extend_i32_s", 1, 1;
+    I64ExtendI32U => "i64.extend_i32_u", 1, 1;
+    I64TruncF32S => "i64.trunc_f32_s", 1, 1;
+    I64TruncF32U => "i64.trunc_f32_u", 1, 1;
+    I64TruncF64S => "i64.trunc_f64_s", 1, 1;
+    I64TruncF64U => "i64.trunc_f64_u", 1, 1;
+    F32ConvertI32S => "f32.convert_i32_s", 1, 1;
+    F32ConvertI32U => "f32.convert_i32_u", 1, 1;
+    F32ConvertI64S => "f32.convert_i64_s", 1, 1;
+    F32ConvertI64U => "f32.convert_i64_u", 1, 1;
+    F32DemoteF64 => "f32.demote_f64", 1, 1;
+    F64ConvertI32S => "f64.convert_i32_s", 1, 1;
+    F64ConvertI32U => "f64.convert_i32_u", 1, 1;
+    F64ConvertI64S => "f64.convert_i64_s", 1, 1;
+    F64ConvertI64U => "f64.convert_i64_u", 1, 1;
+    F64PromoteF32 => "f64.promote_f32", 1, 1;
+    I32ReinterpretF32 => "i32.reinterpret_f32", 1, 1;
+    I64ReinterpretF64 => "i64.reinterpret_f64", 1, 1;
+    F32ReinterpretI32 => "f32.reinterpret_i32", 1, 1;
+    F64ReinterpretI64 => "f64.reinterpret_i64", 1, 1;
+    I32Extend8S => "i32.extend8_s", 1, 1;
+    I32Extend16S => "i32.extend16_s", 1, 1;
+    I64Extend8S => "i64.extend8_s", 1, 1;
+    I64Extend16S => "i64.extend16_s", 1, 1;
+    I64Extend32S => "i64.extend32_s", 1, 1;
+    RefIsNull => "ref.is_null", 1, 1;
+    I32TruncSatF32S => "i32.trunc_sat_f32_s", 1, 1;
+    I32TruncSatF32U => "i32.trunc_sat_f32_u", 1, 1;
+    I32TruncSatF64S => "i32.trunc_sat_f64_s", 1, 1;
+    I32TruncSatF64U => "i32.trunc_sat_f64_u", 1, 1;
+    I64TruncSatF32S => "i64.trunc_sat_f32_s", 1, 1;
+    I64TruncSatF32U => "i64.trunc_sat_f32_u", 1, 1;
+    I64TruncSatF64S => "i64.trunc_sat_f64_s", 1, 1;
+    I64TruncSatF64U => "i64.trunc_sat_f64_u", 1, 1;
+    MemorySize => "memory.size", 0, 1;
+    MemoryGrow => "memory.grow", 1, 1;
+    MemoryCopy => "memory.copy", 3, 0;
+    MemoryFill => "memory.fill", 3, 0;
+}
+
+pub(super) fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(c)
+}
+
+pub(super) fn heap_type_name(ref_type: WasmRefType) -> &'static str {
+    match ref_type {
+        WasmRefType::FuncRef => "func",
+        WasmRefType::ExternRef => "extern",
+    }
+}
+
+/// Writes `bytes` as a string literal, escaping anything that is not
+/// printable ASCII.
+pub(super) fn write_string(out: &mut String, bytes: &[u8]) {
+    out.push('"');
+    for &byte in bytes {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..0x7F => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+    out.push('"');
+}
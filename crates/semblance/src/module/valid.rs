@@ -1,9 +1,20 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    fmt,
+    rc::Rc,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
 
 use super::*;
 
-#[derive(Debug)]
-pub enum WasmValidationError {
+#[derive(Debug, Clone)]
+pub enum WasmValidationErrorKind {
     InvalidTypeIdx(u32),
     InvalidGlobalIdx(u32),
     InvalidTableIdx(u32),
@@ -53,6 +64,110 @@ pub enum WasmValidationError {
     StackUnderflow,
 }
 
+impl fmt::Display for WasmValidationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use WasmValidationErrorKind::*;
+        match self {
+            InvalidTypeIdx(idx) => write!(f, "unknown type {}", idx),
+            InvalidGlobalIdx(idx) => write!(f, "unknown global {}", idx),
+            InvalidTableIdx(idx) => write!(f, "unknown table {}", idx),
+            InvalidMemIdx(idx) => write!(f, "unknown memory {}", idx),
+            InvalidFuncIdx(idx) => write!(f, "unknown function {}", idx),
+            InvalidElemIdx(idx) => write!(f, "unknown element segment {}", idx),
+            InvalidDataIdx(idx) => write!(f, "unknown data segment {}", idx),
+            InvalidLocalIdx(idx) => write!(f, "unknown local {}", idx),
+            InvalidLabelIdx(idx) => write!(f, "unknown label {}", idx),
+            InvalidLimits { range } => write!(f, "limits must be at most {}", range),
+            InvalidStartFunc => write!(f, "start function must have type [] -> []"),
+            TooManyMemories => write!(f, "multiple memories"),
+            NoMemory => write!(f, "no memory"),
+            InvalidAlignment => write!(f, "alignment must not be larger than natural"),
+            ExprNotConst => write!(f, "constant expression required"),
+            DuplicateExportName(name) => write!(f, "duplicate export name {:?}", name),
+            MismatchedType { expected, actual } => {
+                write!(f, "type mismatch: expected {}, found {}", expected, actual)
+            }
+            MismatchedTableCopy { src, dst } => write!(
+                f,
+                "cannot copy from a {} table to a {} table",
+                WasmValueType::Ref(*src),
+                WasmValueType::Ref(*dst)
+            ),
+            MismatchedTableInit { table, elem, .. } => write!(
+                f,
+                "cannot initialize a {} table with {} elements",
+                WasmValueType::Ref(*table),
+                WasmValueType::Ref(*elem)
+            ),
+            MutationOfImmutableGlobal => write!(f, "global is immutable"),
+            TooManySelectTypes => write!(f, "select takes at most one type"),
+            InvalidReturn => write!(f, "return outside of a function"),
+            InvalidCallIndirect => write!(f, "call_indirect needs a funcref table"),
+            UnopenedBlock => write!(f, "end without a matching block"),
+            InvalidElse => write!(f, "else without a matching if"),
+            MissingRequiredElseBlock => write!(f, "if with a result type needs an else"),
+            UnexpectedStackDepth { expected, actual } => write!(
+                f,
+                "expected {} values on the stack at the end of the block, found {}",
+                expected, actual
+            ),
+            InvalidBlockArity { expected, actual } => write!(
+                f,
+                "br_table targets must have the same arity, expected {}, found {}",
+                expected, actual
+            ),
+            StackUnderflow => write!(f, "operand stack underflow"),
+        }
+    }
+}
+
+/// A validation error and, for errors in a function body, where it happened.
+#[derive(Debug, Clone)]
+pub struct WasmValidationError {
+    pub kind: WasmValidationErrorKind,
+    pub func_idx: Option<WasmFuncIdx>,
+    /// The function's name from the name section.
+    pub func_name: Option<WasmName>,
+    /// The index of the failing instruction within the function body.
+    pub instr_idx: Option<WasmInstructionIdx>,
+    /// The operand stack before the failing instruction, like `[i32 f64]`.
+    pub stack: Option<String>,
+}
+
+impl From<WasmValidationErrorKind> for WasmValidationError {
+    fn from(kind: WasmValidationErrorKind) -> Self {
+        WasmValidationError {
+            kind,
+            func_idx: None,
+            func_name: None,
+            instr_idx: None,
+            stack: None,
+        }
+    }
+}
+
+impl fmt::Display for WasmValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(func_idx) = self.func_idx {
+            write!(f, "in function {}", func_idx.0)?;
+            if let Some(name) = &self.func_name {
+                write!(f, " ({})", name.0)?;
+            }
+            if let Some(instr_idx) = self.instr_idx {
+                write!(f, " at instruction {}", instr_idx.0)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.kind)?;
+        if let Some(stack) = &self.stack {
+            write!(f, " (stack: {})", stack)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for WasmValidationError {}
+
 pub type WasmValidationResult<T> = Result<T, WasmValidationError>;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -61,11 +176,388 @@ pub enum MaybeUntyped {
     KnownType(WasmValueType),
 }
 
+impl fmt::Display for MaybeUntyped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaybeUntyped::UnknownType => write!(f, "unknown"),
+            MaybeUntyped::KnownType(t) => write!(f, "{}", t),
+        }
+    }
+}
+
 use context::*;
 
+#[derive(Debug, Clone, Copy)]
+pub struct WasmValidationOptions {
+    /// The number of threads validating function bodies. However the work
+    /// is split, the error reported is that of the lowest failing function.
+    pub threads: usize,
+    /// Only validate the module structure up front, and each function body
+    /// the first time it is needed. Calling an invalid function traps. The
+    /// entries of [`WasmModule::funcs`] then only hold the function types;
+    /// the bodies are kept by the module until validated.
+    pub lazy: bool,
+}
+
+impl Default for WasmValidationOptions {
+    fn default() -> Self {
+        WasmValidationOptions {
+            threads: 1,
+            lazy: false,
+        }
+    }
+}
+
 pub fn validate(wmod: WasmModuleRaw) -> WasmValidationResult<WasmModule> {
+    validate_with_options(wmod, WasmValidationOptions::default())
+}
+
+pub fn validate_with_options(
+    mut wmod: WasmModuleRaw,
+    options: WasmValidationOptions,
+) -> WasmValidationResult<WasmModule> {
+    // Move the bodies out so that they can be handed to the validator while
+    // the context borrows the rest of the module.
+    let bodies = wmod
+        .funcs
+        .iter_mut()
+        .map(|func| WasmFunc {
+            type_idx: func.type_idx,
+            locals: std::mem::take(&mut func.locals),
+            body: std::mem::take(&mut func.body),
+            offsets: std::mem::take(&mut func.offsets),
+        })
+        .collect();
+    if options.lazy {
+        let lazy_funcs = {
+            let validator = FuncValidator::new(&wmod)?;
+            validate_module_items(&wmod, &validator.wmod_ctx)?;
+            LazyFuncs::new(&wmod, &validator.wmod_ctx, bodies)
+        };
+        let funcs = wmod
+            .funcs
+            .iter()
+            .map(|func| WasmFunc {
+                type_idx: func.type_idx,
+                locals: Box::new([]),
+                body: Box::new([]),
+                offsets: Box::new([]),
+            })
+            .collect();
+        let mut valid = reencode_module(wmod, funcs);
+        valid.lazy_funcs = Some(Box::new(lazy_funcs));
+        return Ok(valid);
+    }
+    let funcs = {
+        let validator = FuncValidator::new(&wmod)?;
+        let funcs = validator.validate_all(bodies, options.threads)?;
+        validate_module_items(&wmod, &validator.wmod_ctx)?;
+        funcs
+    };
+    Ok(reencode_module(wmod, funcs.into_boxed_slice()))
+}
+
+/// The function bodies of a module validated with
+/// [`WasmValidationOptions::lazy`], each validated when first needed.
+pub(crate) struct LazyFuncs {
+    // Borrows from `decls`, so it is declared, and dropped, first.
+    validator: FuncValidator<'static>,
+    decls: Box<WasmModuleRaw>,
+    /// The bodies not validated yet. A body is taken out when validated.
+    pending: Box<[Mutex<Option<WasmFunc<WasmInstructionRaw>>>]>,
+    /// Whether each function has been validated, and how that went.
+    validated: Box<[OnceLock<WasmValidationResult<WasmFunc>>]>,
+}
+
+impl LazyFuncs {
+    fn new(
+        wmod: &WasmModuleRaw,
+        wmod_ctx: &ModuleContext,
+        bodies: Vec<WasmFunc<WasmInstructionRaw>>,
+    ) -> Self {
+        // Only the declarations the context refers to are kept. What the
+        // context takes from the rest of the module is copied over.
+        let decls = Box::new(WasmModuleRaw {
+            version: wmod.version,
+            types: wmod.types.clone(),
+            funcs: wmod
+                .funcs
+                .iter()
+                .map(|func| WasmFunc {
+                    type_idx: func.type_idx,
+                    locals: Box::new([]),
+                    body: Box::new([]),
+                    offsets: Box::new([]),
+                })
+                .collect(),
+            tables: wmod.tables.clone(),
+            mems: wmod.mems.clone(),
+            globals: wmod
+                .globals
+                .iter()
+                .map(|global| WasmGlobal {
+                    global_type: global.global_type,
+                    init: Box::new([]),
+                })
+                .collect(),
+            imports: wmod.imports.clone(),
+            ..WasmModuleRaw::empty()
+        });
+        // SAFETY: `decls` is never changed and outlives the validator, and
+        // moving the box does not move what it points to.
+        let decls_ref = unsafe { &*(decls.as_ref() as *const WasmModuleRaw) };
+        let mut validator = FuncValidator {
+            wmod: decls_ref,
+            wmod_ctx: ModuleContext::from_module(decls_ref)
+                .expect("declarations were already validated"),
+        };
+        validator.wmod_ctx.include_internal_globals(decls_ref);
+        validator.wmod_ctx.elements = wmod_ctx.elements.clone();
+        validator.wmod_ctx.datas = wmod_ctx.datas;
+        validator.wmod_ctx.refs = wmod_ctx.refs.clone();
+        LazyFuncs {
+            validator,
+            decls,
+            validated: bodies.iter().map(|_| OnceLock::new()).collect(),
+            pending: bodies
+                .into_iter()
+                .map(|func| Mutex::new(Some(func)))
+                .collect(),
+        }
+    }
+
+    /// The `i`th defined function, validated on the first call.
+    fn get(&self, i: usize) -> &WasmValidationResult<WasmFunc> {
+        self.validated[i].get_or_init(|| {
+            let func = self.pending[i]
+                .lock()
+                .unwrap()
+                .take()
+                .expect("pending body is only taken once");
+            let first_idx = self.validator.wmod_ctx.funcs.len() - self.decls.funcs.len();
+            let func_idx = WasmFuncIdx((first_idx + i) as u32);
+            self.validator.validate(func_idx, func)
+        })
+    }
+
+    /// The index of a function returned by [`LazyFuncs::get`].
+    fn index_of(&self, func: *const WasmFunc) -> Option<usize> {
+        let cells = self.validated.as_ptr_range();
+        if !(cells.start.cast()..cells.end.cast()).contains(&func) {
+            return None;
+        }
+        let cell_size = size_of::<OnceLock<WasmValidationResult<WasmFunc>>>();
+        Some((func.addr() - cells.start.addr()) / cell_size)
+    }
+}
+
+impl fmt::Debug for LazyFuncs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyFuncs")
+            .field("funcs", &self.validated.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl WasmModule {
+    /// The body of the `i`th defined function, validated now if it was
+    /// deferred. `None` if validating it fails.
+    pub(crate) fn validated_func(&self, i: usize) -> Option<&WasmFunc> {
+        match &self.lazy_funcs {
+            Some(lazy_funcs) => lazy_funcs.get(i).as_ref().ok(),
+            None => Some(&self.funcs[i]),
+        }
+    }
+
+    /// The bodies of all defined functions, validating the deferred ones
+    /// now. Fails with the error of the first invalid function.
+    pub(crate) fn validated_funcs(&self) -> WasmValidationResult<Vec<&WasmFunc>> {
+        let Some(lazy_funcs) = &self.lazy_funcs else {
+            return Ok(self.funcs.iter().collect());
+        };
+        (0..self.funcs.len())
+            .map(|i| lazy_funcs.get(i).as_ref().map_err(Clone::clone))
+            .collect()
+    }
+
+    /// The index of `func` if it is one of the entries of
+    /// [`WasmModule::funcs`] standing in for a deferred body.
+    pub(crate) fn deferred_func_index(&self, func: *const WasmFunc) -> Option<usize> {
+        self.lazy_funcs.as_ref()?;
+        self.funcs
+            .as_ptr_range()
+            .contains(&func)
+            .then(|| self.defined_func_index(func))
+    }
+
+    /// The types of at most `depth` operands on top of the stack before
+    /// each instruction of `func`, topmost last. The interpreter's value
+    /// stack is untyped, so they are found by validating the body again.
+    pub(crate) fn operand_types(
+        &self,
+        func: &WasmFunc,
+        depth: usize,
+    ) -> Option<Box<[Box<[MaybeUntyped]>]>> {
+        let mut wmod_ctx = ModuleContext::from_module(self).ok()?;
+        wmod_ctx.include_internal_globals(self);
+        let mut expr_ctx = ExprContext::for_func(&wmod_ctx, func).ok()?;
+        let mut types = Vec::with_capacity(func.body.len());
+        for (i, op) in func.body.iter().enumerate() {
+            types.push(expr_ctx.stack().top(depth));
+            validate_instr(op, &wmod_ctx, &mut expr_ctx, WasmInstructionIdx(i as u32)).ok()?;
+        }
+        Some(types.into_boxed_slice())
+    }
+
+    /// The position in [`WasmModule::funcs`] of `func`, which is one of the
+    /// module's functions or returned by [`WasmModule::validated_func`].
+    pub(crate) fn defined_func_index(&self, func: *const WasmFunc) -> usize {
+        if let Some(i) = self
+            .lazy_funcs
+            .as_ref()
+            .and_then(|lazy| lazy.index_of(func))
+        {
+            return i;
+        }
+        unsafe { func.offset_from(self.funcs.as_ptr()) as usize }
+    }
+}
+
+/// Validates a module whose function bodies were already validated one by
+/// one with a [`FuncValidator`]. The functions of `wmod` only need their
+/// types; their bodies are replaced with `funcs`.
+pub(super) fn validate_with_funcs(
+    wmod: WasmModuleRaw,
+    funcs: Vec<WasmFunc>,
+) -> WasmValidationResult<WasmModule> {
+    {
+        let wmod_ctx = module_context(&wmod)?;
+        validate_module_items(&wmod, &wmod_ctx)?;
+    }
+    Ok(reencode_module(wmod, funcs.into_boxed_slice()))
+}
+
+/// Validates function bodies against a module that declares the functions
+/// without them.
+pub(super) struct FuncValidator<'wmod> {
+    wmod: &'wmod WasmModuleRaw,
+    wmod_ctx: ModuleContext<'wmod>,
+}
+
+impl<'wmod> FuncValidator<'wmod> {
+    pub fn new(wmod: &'wmod WasmModuleRaw) -> WasmValidationResult<Self> {
+        let wmod_ctx = module_context(wmod)?;
+        Ok(FuncValidator { wmod, wmod_ctx })
+    }
+
+    /// For a module whose data section has not been seen yet, takes the
+    /// number of data segments from the data count section.
+    pub fn with_datacount(mut self, datacount: u32) -> Self {
+        self.wmod_ctx.datas = datacount as usize;
+        self
+    }
+
+    pub fn validate(
+        &self,
+        func_idx: WasmFuncIdx,
+        func: WasmFunc<WasmInstructionRaw>,
+    ) -> WasmValidationResult<WasmFunc> {
+        let side_tables = validate_func(&func, &self.wmod_ctx)
+            .map_err(|e| in_func(e, func_idx, &self.wmod.names))?;
+        Ok(reencode_func_with_side_tables(func, side_tables))
+    }
+
+    /// Validates the bodies of all functions defined by the module.
+    fn validate_all(
+        &self,
+        funcs: Vec<WasmFunc<WasmInstructionRaw>>,
+        threads: usize,
+    ) -> WasmValidationResult<Vec<WasmFunc>> {
+        let num_funcs = funcs.len();
+        let first_idx = self.wmod_ctx.funcs.len() - num_funcs;
+        let func_idx = |i: usize| WasmFuncIdx((first_idx + i) as u32);
+        if threads <= 1 {
+            return funcs
+                .into_iter()
+                .enumerate()
+                .map(|(i, func)| self.validate(func_idx(i), func))
+                .collect();
+        }
+
+        // Batches are handed out in order and a failure only stops the ones
+        // after it, so every function before the lowest failing one is still
+        // validated.
+        const BATCH_LEN: usize = 64;
+        let mut batches = Vec::new();
+        let mut funcs = funcs.into_iter();
+        loop {
+            let batch = funcs.by_ref().take(BATCH_LEN).collect::<Vec<_>>();
+            if batch.is_empty() {
+                break;
+            }
+            batches.push(batch);
+        }
+        let mut results = (0..batches.len()).map(|_| None).collect::<Vec<_>>();
+        let num_workers = threads.min(batches.len());
+        let queue = Mutex::new(batches.into_iter().enumerate());
+        let first_failed = AtomicUsize::new(usize::MAX);
+        thread::scope(|scope| {
+            let workers = (0..num_workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        while let Some((b, batch)) = queue.lock().unwrap().next() {
+                            if b > first_failed.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            let res = batch
+                                .into_iter()
+                                .enumerate()
+                                .map(|(i, func)| self.validate(func_idx(b * BATCH_LEN + i), func))
+                                .collect::<WasmValidationResult<Vec<_>>>();
+                            if res.is_err() {
+                                first_failed.fetch_min(b, Ordering::Relaxed);
+                            }
+                            done.push((b, res));
+                        }
+                        done
+                    })
+                })
+                .collect::<Vec<_>>();
+            for worker in workers {
+                for (b, res) in worker.join().unwrap() {
+                    results[b] = Some(res);
+                }
+            }
+        });
+
+        // Only batches after the first failure can be missing, and those are
+        // never reached as the failure is returned first.
+        let mut validated = Vec::with_capacity(num_funcs);
+        for (b, res) in results.into_iter().enumerate() {
+            let res = res.unwrap_or_else(|| panic!("batch {} was not validated", b));
+            validated.extend(res?);
+        }
+        assert_eq!(validated.len(), num_funcs);
+        Ok(validated)
+    }
+}
+
+fn in_func(
+    mut e: WasmValidationError,
+    func_idx: WasmFuncIdx,
+    names: &WasmNameMap,
+) -> WasmValidationError {
+    e.func_idx = Some(func_idx);
+    e.func_name = names.funcs.get(&func_idx).cloned();
+    e
+}
+
+/// Builds the context function bodies are validated in (C in the spec),
+/// validating the definitions it is made of along the way.
+fn module_context(wmod: &WasmModuleRaw) -> WasmValidationResult<ModuleContext<'_>> {
     // C'
-    let mut wmod_ctx = ModuleContext::from_module(&wmod)?;
+    let mut wmod_ctx = ModuleContext::from_module(wmod)?;
     for table in &wmod.tables {
         validate_table(table)?;
     }
@@ -83,29 +575,29 @@ pub fn validate(wmod: WasmModuleRaw) -> WasmValidationResult<WasmModule> {
     }
 
     // C
-    wmod_ctx.include_internal_globals(&wmod);
-    let mut side_tables = Vec::with_capacity(wmod.funcs.len());
-    for func in &wmod.funcs {
-        let side_table = validate_func(func, &wmod_ctx)?;
-        side_tables.push(side_table);
-    }
+    wmod_ctx.include_internal_globals(wmod);
+    Ok(wmod_ctx)
+}
+
+fn validate_module_items(
+    wmod: &WasmModuleRaw,
+    wmod_ctx: &ModuleContext,
+) -> WasmValidationResult<()> {
     if let Some(start) = wmod.start {
-        validate_start_func(start, &wmod_ctx)?;
+        validate_start_func(start, wmod_ctx)?;
     }
     for import in &wmod.imports {
-        validate_import(import, &wmod_ctx)?;
+        validate_import(import, wmod_ctx)?;
     }
     for export in &wmod.exports {
-        validate_export(export, &wmod_ctx)?;
+        validate_export(export, wmod_ctx)?;
     }
 
     if wmod_ctx.memories.len() > 1 {
-        return Err(WasmValidationError::TooManyMemories);
+        return Err(WasmValidationErrorKind::TooManyMemories.into());
     }
 
-    validate_export_names(&wmod)?;
-
-    Ok(reencode_module_with_side_tables(wmod, side_tables))
+    validate_export_names(wmod)
 }
 
 type ControlFlowMap = HashMap<WasmInstructionIdx, WasmInstructionIdx>;
@@ -130,14 +622,11 @@ impl ValidationSideTables {
     }
 }
 
-fn reencode_module_with_side_tables(
-    wmod: WasmModuleRaw,
-    side_tables: Vec<ValidationSideTables>,
-) -> WasmModule {
+fn reencode_module(wmod: WasmModuleRaw, funcs: Box<[WasmFunc]>) -> WasmModule {
     WasmModule {
         version: wmod.version,
         types: wmod.types,
-        funcs: reencode_funcs_with_side_tables(wmod.funcs, side_tables),
+        funcs,
         tables: wmod.tables,
         mems: wmod.mems,
         globals: wmod
@@ -169,6 +658,9 @@ fn reencode_module_with_side_tables(
         imports: wmod.imports,
         exports: wmod.exports,
         customs: wmod.customs,
+        names: wmod.names,
+        lines: wmod.lines,
+        lazy_funcs: None,
     }
 }
 
@@ -224,17 +716,6 @@ fn reencode_const_instr(instr: WasmInstructionRaw) -> WasmInstruction {
     }
 }
 
-fn reencode_funcs_with_side_tables(
-    funcs: Box<[WasmFunc<WasmInstructionRaw>]>,
-    side_tables: Vec<ValidationSideTables>,
-) -> Box<[WasmFunc]> {
-    funcs
-        .into_iter()
-        .zip(side_tables)
-        .map(|(func, side_table)| reencode_func_with_side_tables(func, side_table))
-        .collect()
-}
-
 fn reencode_func_with_side_tables(
     func: WasmFunc<WasmInstructionRaw>,
     side_tables: ValidationSideTables,
@@ -243,6 +724,7 @@ fn reencode_func_with_side_tables(
         type_idx: func.type_idx,
         locals: func.locals,
         body: reencode_expr_with_side_tables(func.body, side_tables),
+        offsets: func.offsets,
     }
 }
 
@@ -328,6 +810,21 @@ fn reencode_instr_with_side_tables(
                 .remove(&ic)
                 .expect("missing break immediates"),
         },
+        _ => reencode_plain_instr(instr),
+    }
+}
+
+/// Re-encodes an instruction whose immediates do not depend on validation.
+fn reencode_plain_instr(instr: WasmInstructionRaw) -> WasmInstruction {
+    use WasmInstructionRepr::*;
+    match instr {
+        Block { .. }
+        | Loop { .. }
+        | If { .. }
+        | Break { .. }
+        | BreakIf { .. }
+        | BreakTable { .. }
+        | Return { .. } => panic!("instruction needs verified immediates"),
         Unreachable => Unreachable,
         Nop => Nop,
         Else => Else,
@@ -548,9 +1045,9 @@ fn validate_export_names(wmod: &WasmModuleRaw) -> WasmValidationResult<()> {
     let mut names = std::collections::HashSet::new();
     for export in &wmod.exports {
         if names.contains(&export.name.0) {
-            return Err(WasmValidationError::DuplicateExportName(
-                export.name.0.to_string(),
-            ));
+            return Err(
+                WasmValidationErrorKind::DuplicateExportName(export.name.0.to_string()).into(),
+            );
         } else {
             names.insert(&export.name.0);
         }
@@ -564,28 +1061,28 @@ fn validate_export(export: &WasmExport, wmod_ctx: &ModuleContext) -> WasmValidat
             wmod_ctx
                 .funcs
                 .get(func_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidFuncIdx(func_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidFuncIdx(func_idx.0))?;
             Ok(())
         }
         WasmExportDesc::Table(table_idx) => {
             wmod_ctx
                 .tables
                 .get(table_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
             Ok(())
         }
         WasmExportDesc::Mem(mem_idx) => {
             wmod_ctx
                 .memories
                 .get(mem_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidMemIdx(mem_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidMemIdx(mem_idx.0))?;
             Ok(())
         }
         WasmExportDesc::Global(global_idx) => {
             wmod_ctx
                 .globals
                 .get(global_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidGlobalIdx(global_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidGlobalIdx(global_idx.0))?;
             Ok(())
         }
     }
@@ -597,7 +1094,7 @@ fn validate_import(import: &WasmImport, wmod_ctx: &ModuleContext) -> WasmValidat
             let _type = wmod_ctx
                 .types
                 .get(type_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTypeIdx(type_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTypeIdx(type_idx.0))?;
             Ok(())
         }
         WasmImportDesc::Table(ref table_type) => {
@@ -616,12 +1113,12 @@ fn validate_start_func(start: WasmFuncIdx, wmod_ctx: &ModuleContext) -> WasmVali
     let func = wmod_ctx
         .funcs
         .get(start.0 as usize)
-        .ok_or(WasmValidationError::InvalidFuncIdx(start.0))?;
+        .ok_or(WasmValidationErrorKind::InvalidFuncIdx(start.0))?;
     if func.input_type.0.len() > 0 {
-        return Err(WasmValidationError::InvalidStartFunc);
+        return Err(WasmValidationErrorKind::InvalidStartFunc.into());
     }
     if func.output_type.0.len() > 0 {
-        return Err(WasmValidationError::InvalidStartFunc);
+        return Err(WasmValidationErrorKind::InvalidStartFunc.into());
     }
     Ok(())
 }
@@ -631,7 +1128,30 @@ fn validate_func(
     wmod_ctx: &ModuleContext,
 ) -> WasmValidationResult<ValidationSideTables> {
     let expr_ctx = ExprContext::for_func(&wmod_ctx, func)?;
-    validate_instr_sequence(&func.body, &wmod_ctx, expr_ctx)
+    validate_instr_sequence(&func.body, &wmod_ctx, expr_ctx).map_err(|mut e| {
+        if let Some(instr_idx) = e.instr_idx {
+            e.stack = Some(stack_before(func, wmod_ctx, instr_idx));
+        }
+        e
+    })
+}
+
+/// Renders the operand stack as it was before the instruction at
+/// `instr_idx`, by validating the body again up to it. The failing
+/// instruction may have popped some operands before it was rejected.
+fn stack_before(
+    func: &WasmFunc<WasmInstructionRaw>,
+    wmod_ctx: &ModuleContext,
+    instr_idx: WasmInstructionIdx,
+) -> String {
+    let Ok(mut expr_ctx) = ExprContext::for_func(wmod_ctx, func) else {
+        return String::new();
+    };
+    for (i, op) in func.body[..instr_idx.0 as usize].iter().enumerate() {
+        // Everything before the failing instruction validated the first time.
+        let _ = validate_instr(op, wmod_ctx, &mut expr_ctx, WasmInstructionIdx(i as u32));
+    }
+    expr_ctx.stack().to_string()
 }
 
 fn validate_data(
@@ -655,7 +1175,7 @@ fn validate_active_data(
     let _mem = wmod_ctx
         .memories
         .get(mem_idx.0 as usize)
-        .ok_or(WasmValidationError::InvalidMemIdx(mem_idx.0))?;
+        .ok_or(WasmValidationErrorKind::InvalidMemIdx(mem_idx.0))?;
     validate_instr_sequence(
         offset_expr,
         wmod_ctx,
@@ -695,13 +1215,14 @@ fn validate_active_elem(
     let table = wmod_ctx
         .tables
         .get(table_idx.0 as usize)
-        .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+        .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
     if table.ref_type != ref_type {
-        return Err(WasmValidationError::MismatchedTableInit {
+        return Err(WasmValidationErrorKind::MismatchedTableInit {
             active: true,
             table: table.ref_type,
             elem: ref_type,
-        });
+        }
+        .into());
     }
     validate_instr_sequence(
         offset_expr,
@@ -745,7 +1266,7 @@ fn validate_limits_within_range(limits: &WasmLimits, range: u32) -> WasmValidati
             None => return Ok(()),
         };
     }
-    Err(WasmValidationError::InvalidLimits { range })
+    Err(WasmValidationErrorKind::InvalidLimits { range }.into())
 }
 
 fn bit_width(t: WasmValueType) -> u32 {
@@ -767,7 +1288,7 @@ fn validate_alignment(
     bits: Option<u32>,
 ) -> WasmValidationResult<()> {
     if 2u32.pow(memarg.align) > (bits.unwrap_or_else(|| bit_width(t)) / 8) {
-        return Err(WasmValidationError::InvalidAlignment);
+        return Err(WasmValidationErrorKind::InvalidAlignment.into());
     }
     Ok(())
 }
@@ -782,7 +1303,7 @@ fn validate_load_instr(
     let _mem = wmod_ctx
         .memories
         .get(0)
-        .ok_or(WasmValidationError::NoMemory)?;
+        .ok_or(WasmValidationErrorKind::NoMemory)?;
     validate_alignment(memarg, t, bits)?;
     let stack = expr_ctx.stack();
     stack.pop(t!(i32))?;
@@ -800,7 +1321,7 @@ fn validate_store_instr(
     let _mem = wmod_ctx
         .memories
         .get(0)
-        .ok_or(WasmValidationError::NoMemory)?;
+        .ok_or(WasmValidationErrorKind::NoMemory)?;
     validate_alignment(memarg, t, bits)?;
     let stack = expr_ctx.stack();
     stack.pop(t)?;
@@ -817,7 +1338,7 @@ fn validate_block_type(
             let t = wmod_ctx
                 .types
                 .get(type_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTypeIdx(type_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTypeIdx(type_idx.0))?;
             Ok(t.clone())
         }
         WasmBlockType::InlineType(Some(t)) => Ok(WasmFuncType {
@@ -831,8 +1352,8 @@ fn validate_block_type(
     }
 }
 
-fn validate_instr(
-    op: &WasmInstructionRaw,
+fn validate_instr<I: Immediates>(
+    op: &WasmInstructionRepr<I>,
     wmod_ctx: &ModuleContext,
     expr_ctx: &mut ExprContext,
     idx: WasmInstructionIdx,
@@ -1010,9 +1531,9 @@ fn validate_instr(
             let _func = wmod_ctx
                 .funcs
                 .get(func_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidFuncIdx(func_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidFuncIdx(func_idx.0))?;
             if !wmod_ctx.refs.contains(func_idx) {
-                return Err(WasmValidationError::InvalidFuncIdx(func_idx.0));
+                return Err(WasmValidationErrorKind::InvalidFuncIdx(func_idx.0).into());
             }
             expr_ctx.stack().push(t!(funcref));
         }
@@ -1037,7 +1558,7 @@ fn validate_instr(
                 stack.push(t);
             }
             _ => {
-                return Err(WasmValidationError::TooManySelectTypes);
+                return Err(WasmValidationErrorKind::TooManySelectTypes.into());
             }
         },
         // -- variable instructions -- //
@@ -1045,21 +1566,21 @@ fn validate_instr(
             let local_type = expr_ctx
                 .locals
                 .get(local_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidLocalIdx(local_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidLocalIdx(local_idx.0))?;
             expr_ctx.stack().push(*local_type);
         }
         LocalSet { local_idx } => {
             let local_type = expr_ctx
                 .locals
                 .get(local_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidLocalIdx(local_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidLocalIdx(local_idx.0))?;
             expr_ctx.stack().pop(*local_type)?;
         }
         LocalTee { local_idx } => {
             let local_type = *expr_ctx
                 .locals
                 .get(local_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidLocalIdx(local_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidLocalIdx(local_idx.0))?;
             let stack = expr_ctx.stack();
             stack.pop(local_type)?;
             stack.push(local_type);
@@ -1068,16 +1589,16 @@ fn validate_instr(
             let global_type = wmod_ctx
                 .globals
                 .get(global_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidGlobalIdx(global_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidGlobalIdx(global_idx.0))?;
             expr_ctx.stack().push(global_type.val_type);
         }
         GlobalSet { global_idx } => {
             let global_type = wmod_ctx
                 .globals
                 .get(global_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidGlobalIdx(global_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidGlobalIdx(global_idx.0))?;
             if global_type.mutability == WasmGlobalMutability::Immutable {
-                return Err(WasmValidationError::MutationOfImmutableGlobal);
+                return Err(WasmValidationErrorKind::MutationOfImmutableGlobal.into());
             }
             expr_ctx.stack().pop(global_type.val_type)?;
         }
@@ -1086,7 +1607,7 @@ fn validate_instr(
             let table = wmod_ctx
                 .tables
                 .get(table_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
             stack.push(WasmValueType::Ref(table.ref_type));
@@ -1095,7 +1616,7 @@ fn validate_instr(
             let table = wmod_ctx
                 .tables
                 .get(table_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
             let t = WasmValueType::Ref(table.ref_type);
             let stack = expr_ctx.stack();
             stack.pop(t)?;
@@ -1105,14 +1626,14 @@ fn validate_instr(
             let _table = wmod_ctx
                 .tables
                 .get(table_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
             expr_ctx.stack().push(t!(i32));
         }
         TableGrow { table_idx } => {
             let table = wmod_ctx
                 .tables
                 .get(table_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
             stack.pop(WasmValueType::Ref(table.ref_type))?;
@@ -1122,7 +1643,7 @@ fn validate_instr(
             let table = wmod_ctx
                 .tables
                 .get(table_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
             stack.pop(WasmValueType::Ref(table.ref_type))?;
@@ -1132,16 +1653,17 @@ fn validate_instr(
             let src_table = wmod_ctx
                 .tables
                 .get(src.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(src.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(src.0))?;
             let dst_table = wmod_ctx
                 .tables
                 .get(dst.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(dst.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(dst.0))?;
             if src_table.ref_type != dst_table.ref_type {
-                return Err(WasmValidationError::MismatchedTableCopy {
+                return Err(WasmValidationErrorKind::MismatchedTableCopy {
                     src: src_table.ref_type,
                     dst: dst_table.ref_type,
-                });
+                }
+                .into());
             }
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
@@ -1155,17 +1677,18 @@ fn validate_instr(
             let table = wmod_ctx
                 .tables
                 .get(table_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
             let elem = wmod_ctx
                 .elements
                 .get(elem_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidElemIdx(elem_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidElemIdx(elem_idx.0))?;
             if table.ref_type != *elem {
-                return Err(WasmValidationError::MismatchedTableInit {
+                return Err(WasmValidationErrorKind::MismatchedTableInit {
                     active: false,
                     table: table.ref_type,
                     elem: *elem,
-                });
+                }
+                .into());
             }
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
@@ -1176,7 +1699,7 @@ fn validate_instr(
             let _elem = wmod_ctx
                 .elements
                 .get(elem_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidElemIdx(elem_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidElemIdx(elem_idx.0))?;
         }
         // -- memory instructions -- //
         I32Load { memarg } => {
@@ -1252,14 +1775,14 @@ fn validate_instr(
             let _mem = wmod_ctx
                 .memories
                 .get(0)
-                .ok_or(WasmValidationError::NoMemory)?;
+                .ok_or(WasmValidationErrorKind::NoMemory)?;
             expr_ctx.stack().push(t!(i32));
         }
         MemoryGrow => {
             let _mem = wmod_ctx
                 .memories
                 .get(0)
-                .ok_or(WasmValidationError::NoMemory)?;
+                .ok_or(WasmValidationErrorKind::NoMemory)?;
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
             stack.push(t!(i32));
@@ -1268,7 +1791,7 @@ fn validate_instr(
             let _mem = wmod_ctx
                 .memories
                 .get(0)
-                .ok_or(WasmValidationError::NoMemory)?;
+                .ok_or(WasmValidationErrorKind::NoMemory)?;
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
             stack.pop(t!(i32))?;
@@ -1278,7 +1801,7 @@ fn validate_instr(
             let _mem = wmod_ctx
                 .memories
                 .get(0)
-                .ok_or(WasmValidationError::NoMemory)?;
+                .ok_or(WasmValidationErrorKind::NoMemory)?;
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
             stack.pop(t!(i32))?;
@@ -1288,9 +1811,9 @@ fn validate_instr(
             let _mem = wmod_ctx
                 .memories
                 .get(0)
-                .ok_or(WasmValidationError::NoMemory)?;
+                .ok_or(WasmValidationErrorKind::NoMemory)?;
             if data_idx.0 as usize >= wmod_ctx.datas {
-                return Err(WasmValidationError::InvalidDataIdx(data_idx.0));
+                return Err(WasmValidationErrorKind::InvalidDataIdx(data_idx.0).into());
             }
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
@@ -1299,7 +1822,7 @@ fn validate_instr(
         }
         DataDrop { data_idx } => {
             if data_idx.0 as usize >= wmod_ctx.datas {
-                return Err(WasmValidationError::InvalidDataIdx(data_idx.0));
+                return Err(WasmValidationErrorKind::InvalidDataIdx(data_idx.0).into());
             }
         }
         // -- control instructions -- //
@@ -1345,7 +1868,7 @@ fn validate_instr(
             let label_entry = expr_ctx
                 .labels
                 .peek(*label_idx)
-                .ok_or(WasmValidationError::InvalidLabelIdx(label_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidLabelIdx(label_idx.0))?;
             let ty = label_entry.label_types();
             let arity = ty.len().try_into().expect("arity immediate too large");
             expr_ctx.stack().pop_result_type(ty)?;
@@ -1362,7 +1885,7 @@ fn validate_instr(
             let label_entry = expr_ctx
                 .labels
                 .peek(*label_idx)
-                .ok_or(WasmValidationError::InvalidLabelIdx(label_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidLabelIdx(label_idx.0))?;
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
             let label_types = label_entry.label_types();
@@ -1381,13 +1904,13 @@ fn validate_instr(
                 .insert(idx, VerifiedBreakImmediates { arity, drop });
         }
         BreakTable { imm } => {
-            let all_labels = &imm.labels;
+            let all_labels = I::break_table_labels(imm).collect::<Vec<_>>();
             let default_label = all_labels.last().unwrap();
             let labels = &all_labels[0..all_labels.len() - 1];
             let default_label_entry = expr_ctx
                 .labels
                 .peek(*default_label)
-                .ok_or(WasmValidationError::InvalidLabelIdx(default_label.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidLabelIdx(default_label.0))?;
             let ty = default_label_entry.label_types();
             let arity = ty.len();
             let mut verified_labels = Vec::with_capacity(all_labels.len());
@@ -1398,13 +1921,14 @@ fn validate_instr(
                     let label_entry = expr_ctx
                         .labels
                         .peek(*label_idx)
-                        .ok_or(WasmValidationError::InvalidLabelIdx(label_idx.0))?;
+                        .ok_or(WasmValidationErrorKind::InvalidLabelIdx(label_idx.0))?;
                     let types = label_entry.label_types();
                     if types.len() != arity {
-                        return Err(WasmValidationError::InvalidBlockArity {
+                        return Err(WasmValidationErrorKind::InvalidBlockArity {
                             expected: arity,
                             actual: types.len(),
-                        });
+                        }
+                        .into());
                     }
                     let popped = stack.pop_result_type_dyn(types)?;
                     let drop = stack.depth() - label_entry.min_stack_depth;
@@ -1432,7 +1956,7 @@ fn validate_instr(
             );
         }
         Return { imm: _ } => match expr_ctx.ret {
-            None => return Err(WasmValidationError::InvalidReturn),
+            None => return Err(WasmValidationErrorKind::InvalidReturn.into()),
             Some(ref result_type) => {
                 expr_ctx.stack().pop_result_type(result_type)?;
                 let arity = result_type.len();
@@ -1450,7 +1974,7 @@ fn validate_instr(
             let func_type = wmod_ctx
                 .funcs
                 .get(func_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidFuncIdx(func_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidFuncIdx(func_idx.0))?;
             let stack = expr_ctx.stack();
             stack.pop_result_type(&func_type.input_type)?;
             stack.push_result_type(&func_type.output_type);
@@ -1462,14 +1986,14 @@ fn validate_instr(
             let table = wmod_ctx
                 .tables
                 .get(table_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTableIdx(table_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTableIdx(table_idx.0))?;
             if table.ref_type != WasmRefType::FuncRef {
-                return Err(WasmValidationError::InvalidCallIndirect);
+                return Err(WasmValidationErrorKind::InvalidCallIndirect.into());
             }
             let func_type = wmod_ctx
                 .types
                 .get(type_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTypeIdx(type_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTypeIdx(type_idx.0))?;
             let stack = expr_ctx.stack();
             stack.pop(t!(i32))?;
             stack.pop_result_type(&func_type.input_type)?;
@@ -1478,7 +2002,7 @@ fn validate_instr(
         Else => {
             let label_entry = expr_ctx.pop_label()?;
             if label_entry.opcode != LabelOpcode::If {
-                return Err(WasmValidationError::InvalidElse);
+                return Err(WasmValidationErrorKind::InvalidElse.into());
             }
             expr_ctx.push_label(LabelEntry {
                 ty: label_entry.ty,
@@ -1517,7 +2041,11 @@ fn validate_instr_sequence(
     mut expr_ctx: ExprContext,
 ) -> WasmValidationResult<ValidationSideTables> {
     for (i, op) in instrs.iter().enumerate() {
-        validate_instr(op, wmod_ctx, &mut expr_ctx, WasmInstructionIdx(i as u32))?;
+        let instr_idx = WasmInstructionIdx(i as u32);
+        validate_instr(op, wmod_ctx, &mut expr_ctx, instr_idx).map_err(|mut e| {
+            e.instr_idx = Some(instr_idx);
+            e
+        })?;
     }
     Ok(expr_ctx.consume_side_tables())
 }
@@ -1529,11 +2057,11 @@ fn validate_global_is_const(
     let global_type = wmod_ctx
         .globals
         .get(global_idx.0 as usize)
-        .ok_or(WasmValidationError::InvalidGlobalIdx(global_idx.0))?;
+        .ok_or(WasmValidationErrorKind::InvalidGlobalIdx(global_idx.0))?;
     if global_type.mutability == WasmGlobalMutability::Immutable {
         Ok(())
     } else {
-        Err(WasmValidationError::ExprNotConst)
+        Err(WasmValidationErrorKind::ExprNotConst.into())
     }
 }
 
@@ -1552,7 +2080,7 @@ fn validate_expr_is_const(
             RefFunc { func_idx: _ } => Ok(()),
             GlobalGet { global_idx } => validate_global_is_const(*global_idx, wmod_ctx),
             ExprEnd => Ok(()),
-            _ => Err(WasmValidationError::ExprNotConst),
+            _ => Err(WasmValidationErrorKind::ExprNotConst.into()),
         }?;
     }
     Ok(())
@@ -1662,14 +2190,14 @@ mod context {
     }
 
     impl ExprContext {
-        pub fn for_func<'wmod>(
+        pub fn for_func<'wmod, I: Immediates>(
             wmod_ctx: &ModuleContext<'wmod>,
-            wfunc: &'wmod WasmFunc<WasmInstructionRaw>,
+            wfunc: &'wmod WasmFunc<WasmInstructionRepr<I>>,
         ) -> WasmValidationResult<Self> {
             let func_type = wmod_ctx
                 .types
                 .get(wfunc.type_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTypeIdx(wfunc.type_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTypeIdx(wfunc.type_idx.0))?;
             let mut locals = vec![];
             locals.extend(func_type.input_type.0.iter());
             locals.extend(wfunc.locals.iter());
@@ -1711,13 +2239,14 @@ mod context {
             let entry = self
                 .labels
                 .peek_top()
-                .ok_or(WasmValidationError::UnopenedBlock)?;
+                .ok_or(WasmValidationErrorKind::UnopenedBlock)?;
             self.stack().pop_result_type(&entry.ty.output_type)?;
             if self.stack.depth() != entry.min_stack_depth {
-                return Err(WasmValidationError::UnexpectedStackDepth {
+                return Err(WasmValidationErrorKind::UnexpectedStackDepth {
                     expected: entry.min_stack_depth,
                     actual: self.stack.depth(),
-                });
+                }
+                .into());
             }
             Ok(self.labels.pop().unwrap())
         }
@@ -1740,7 +2269,9 @@ mod context {
     }
 
     impl<'wmod> ModuleContext<'wmod> {
-        pub fn from_module(wmod: &'wmod WasmModuleRaw) -> WasmValidationResult<Self> {
+        pub fn from_module<I: Immediates>(
+            wmod: &'wmod WasmModuleRepr<WasmInstructionRepr<I>>,
+        ) -> WasmValidationResult<Self> {
             Ok(ModuleContext {
                 types: context_types(wmod),
                 funcs: context_funcs(wmod)?,
@@ -1753,24 +2284,31 @@ mod context {
             })
         }
 
-        pub fn include_internal_globals(&mut self, wmod: &'wmod WasmModuleRaw) {
+        pub fn include_internal_globals<I: Immediates>(
+            &mut self,
+            wmod: &'wmod WasmModuleRepr<WasmInstructionRepr<I>>,
+        ) {
             self.globals
                 .extend(wmod.globals.iter().map(|g| &g.global_type))
         }
     }
 
-    fn context_types(wmod: &WasmModuleRaw) -> &[WasmFuncType] {
+    fn context_types<I: Immediates>(
+        wmod: &WasmModuleRepr<WasmInstructionRepr<I>>,
+    ) -> &[WasmFuncType] {
         wmod.types.as_ref()
     }
 
-    fn context_funcs(wmod: &WasmModuleRaw) -> WasmValidationResult<Vec<&WasmFuncType>> {
+    fn context_funcs<I: Immediates>(
+        wmod: &WasmModuleRepr<WasmInstructionRepr<I>>,
+    ) -> WasmValidationResult<Vec<&WasmFuncType>> {
         let mut funcs = Vec::new();
         for import in &wmod.imports {
             if let WasmImportDesc::Func(ref typeidx) = import.desc {
                 let ty = wmod
                     .types
                     .get(typeidx.0 as usize)
-                    .ok_or(WasmValidationError::InvalidTypeIdx(typeidx.0))?;
+                    .ok_or(WasmValidationErrorKind::InvalidTypeIdx(typeidx.0))?;
                 funcs.push(ty);
             }
         }
@@ -1778,13 +2316,15 @@ mod context {
             let ty = wmod
                 .types
                 .get(func.type_idx.0 as usize)
-                .ok_or(WasmValidationError::InvalidTypeIdx(func.type_idx.0))?;
+                .ok_or(WasmValidationErrorKind::InvalidTypeIdx(func.type_idx.0))?;
             funcs.push(ty);
         }
         Ok(funcs)
     }
 
-    fn context_tables(wmod: &WasmModuleRaw) -> Vec<&WasmTableType> {
+    fn context_tables<I: Immediates>(
+        wmod: &WasmModuleRepr<WasmInstructionRepr<I>>,
+    ) -> Vec<&WasmTableType> {
         let mut tables = Vec::new();
         tables.extend(wmod.imports.iter().filter_map(|i| match i.desc {
             WasmImportDesc::Table(ref t) => Some(t),
@@ -1794,7 +2334,9 @@ mod context {
         tables
     }
 
-    fn context_mems(wmod: &WasmModuleRaw) -> Vec<&WasmMemType> {
+    fn context_mems<I: Immediates>(
+        wmod: &WasmModuleRepr<WasmInstructionRepr<I>>,
+    ) -> Vec<&WasmMemType> {
         let mut mems = Vec::new();
         mems.extend(wmod.imports.iter().filter_map(|i| match i.desc {
             WasmImportDesc::Mem(ref m) => Some(m),
@@ -1804,7 +2346,9 @@ mod context {
         mems
     }
 
-    fn context_globals(wmod: &WasmModuleRaw) -> Vec<&WasmGlobalType> {
+    fn context_globals<I: Immediates>(
+        wmod: &WasmModuleRepr<WasmInstructionRepr<I>>,
+    ) -> Vec<&WasmGlobalType> {
         let mut globals = Vec::new();
         globals.extend(wmod.imports.iter().filter_map(|i| match i.desc {
             WasmImportDesc::Global(ref g) => Some(g),
@@ -1813,15 +2357,19 @@ mod context {
         globals
     }
 
-    fn context_elems(wmod: &WasmModuleRaw) -> Vec<WasmRefType> {
+    fn context_elems<I: Immediates>(
+        wmod: &WasmModuleRepr<WasmInstructionRepr<I>>,
+    ) -> Vec<WasmRefType> {
         wmod.elems.iter().map(|e| e.ref_type).collect()
     }
 
-    fn context_datas(wmod: &WasmModuleRaw) -> usize {
+    fn context_datas<I: Immediates>(wmod: &WasmModuleRepr<WasmInstructionRepr<I>>) -> usize {
         wmod.datas.len()
     }
 
-    fn context_refs(wmod: &WasmModuleRaw) -> HashSet<WasmFuncIdx> {
+    fn context_refs<I: Immediates>(
+        wmod: &WasmModuleRepr<WasmInstructionRepr<I>>,
+    ) -> HashSet<WasmFuncIdx> {
         let mut refs = HashSet::new();
         for data in &wmod.datas {
             if let WasmDataMode::Active {
@@ -1853,7 +2401,10 @@ mod context {
         refs
     }
 
-    fn add_const_expr_refs(expr: &WasmExprRaw, refs: &mut HashSet<WasmFuncIdx>) {
+    fn add_const_expr_refs<I: Immediates>(
+        expr: &[WasmInstructionRepr<I>],
+        refs: &mut HashSet<WasmFuncIdx>,
+    ) {
         for op in expr {
             if let WasmInstructionRepr::RefFunc { func_idx } = op {
                 refs.insert(*func_idx);
@@ -1910,7 +2461,7 @@ impl TypeStack {
             return Ok(MaybeUntyped::UnknownType);
         }
         if self.depth() == self.min_depth {
-            return Err(WasmValidationError::StackUnderflow);
+            return Err(WasmValidationErrorKind::StackUnderflow.into());
         }
         Ok(self.stack.borrow_mut().pop().unwrap())
     }
@@ -1926,11 +2477,12 @@ impl TypeStack {
             t @ MaybeUntyped::KnownType(WasmValueType::Num(_)) => Ok(t),
             t @ MaybeUntyped::KnownType(WasmValueType::Vec(_)) => Ok(t),
             t @ MaybeUntyped::UnknownType => Ok(t),
-            actual => Err(WasmValidationError::MismatchedType {
+            actual => Err(WasmValidationErrorKind::MismatchedType {
                 actual,
                 // TODO: should be num|vec
                 expected: WasmValueType::Num(WasmNumType::I32),
-            }),
+            }
+            .into()),
         }
     }
 
@@ -1939,7 +2491,7 @@ impl TypeStack {
         match popped {
             MaybeUntyped::KnownType(t) if t == expected => Ok(popped),
             MaybeUntyped::UnknownType => Ok(popped),
-            actual => Err(WasmValidationError::MismatchedType { expected, actual }),
+            actual => Err(WasmValidationErrorKind::MismatchedType { expected, actual }.into()),
         }
     }
 
@@ -1951,9 +2503,9 @@ impl TypeStack {
         };
         let popped = self.pop(ty);
         // recover from type mismatch if expecting an unknown type
-        if let Err(WasmValidationError::MismatchedType {
-            expected: _,
-            actual,
+        if let Err(WasmValidationError {
+            kind: WasmValidationErrorKind::MismatchedType { actual, .. },
+            ..
         }) = popped
             && expected == MaybeUntyped::UnknownType
         {
@@ -1968,10 +2520,11 @@ impl TypeStack {
         match popped {
             MaybeUntyped::KnownType(WasmValueType::Ref(_)) => Ok(()),
             MaybeUntyped::UnknownType => Ok(()),
-            actual => Err(WasmValidationError::MismatchedType {
+            actual => Err(WasmValidationErrorKind::MismatchedType {
                 expected: WasmValueType::Ref(WasmRefType::FuncRef), // TODO represent this type better (any ref type)
                 actual,
-            }),
+            }
+            .into()),
         }
     }
 
@@ -1997,4 +2550,159 @@ impl TypeStack {
     pub fn depth(&self) -> usize {
         self.stack.borrow().len()
     }
+
+    pub fn top(&self, n: usize) -> Box<[MaybeUntyped]> {
+        let stack = self.stack.borrow();
+        stack[stack.len().saturating_sub(n)..].into()
+    }
+}
+
+impl fmt::Display for TypeStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, t) in self.stack.borrow().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", t)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_context() {
+        let wmod = WasmModuleRaw::from_wat(
+            "(module (func $f (param i32) (result i32) local.get 0 i64.const 1 i32.add))",
+        )
+        .unwrap();
+        let err = validate(wmod).err().unwrap();
+        assert!(matches!(
+            err.kind,
+            WasmValidationErrorKind::MismatchedType {
+                expected: t!(i32),
+                actual: MaybeUntyped::KnownType(t!(i64)),
+            }
+        ));
+        assert_eq!(err.func_idx, Some(WasmFuncIdx(0)));
+        assert_eq!(err.instr_idx, Some(WasmInstructionIdx(2)));
+        assert_eq!(
+            err.to_string(),
+            "in function 0 (f) at instruction 2: type mismatch: expected i32, found i64 \
+             (stack: [i32 i64])"
+        );
+    }
+
+    #[test]
+    fn test_parallel_validation() {
+        let module = |bad: &[usize]| {
+            let funcs = (0..300)
+                .map(|i| {
+                    if bad.contains(&i) {
+                        "(func (result i32) i64.const 0)"
+                    } else {
+                        "(func (result i32) i32.const 1 i32.const 2 i32.add)"
+                    }
+                })
+                .collect::<String>();
+            WasmModuleRaw::from_wat(&format!("(module {})", funcs)).unwrap()
+        };
+        let parallel = WasmValidationOptions {
+            threads: 4,
+            ..Default::default()
+        };
+
+        let valid = validate_with_options(module(&[]), parallel).unwrap();
+        assert_eq!(
+            valid.to_bytes().unwrap(),
+            validate(module(&[])).unwrap().to_bytes().unwrap()
+        );
+
+        for _ in 0..10 {
+            let err = validate_with_options(module(&[250, 100, 101]), parallel)
+                .err()
+                .unwrap();
+            assert_eq!(err.func_idx, Some(WasmFuncIdx(100)));
+        }
+    }
+
+    #[test]
+    fn test_lazy_validation() {
+        use crate::inst::{WasmInvokeOptions, WasmStore};
+
+        let text = "(module
+            (func $double (param i32) (result i32) local.get 0 call $add_self)
+            (func $add_self (param i32) (result i32) local.get 0 local.get 0 i32.add)
+            (func $bad (result i32) i64.const 0))";
+        assert!(validate(WasmModuleRaw::from_wat(text).unwrap()).is_err());
+        let options = WasmValidationOptions {
+            lazy: true,
+            ..Default::default()
+        };
+        let wmod = validate_with_options(WasmModuleRaw::from_wat(text).unwrap(), options).unwrap();
+
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(std::rc::Rc::new(wmod), &[]).unwrap();
+        let funcaddrs = store.instances.resolve(winst_id).funcaddrs.clone();
+        let res = store
+            .invoke(
+                funcaddrs[0],
+                Box::new([21.into()]),
+                WasmInvokeOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(unsafe { res.res.0[0].num.i32 }, 42);
+        assert_eq!(store.resolve_func_name(funcaddrs[1]), Some("add_self"));
+
+        let trap = store
+            .invoke(funcaddrs[2], Box::new([]), WasmInvokeOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(trap.0, "invalid function");
+        let wmod = &store.instances.resolve(winst_id).wmod;
+        let err = wmod.to_bytes().err().unwrap();
+        assert_eq!(err.func_idx, Some(WasmFuncIdx(2)));
+    }
+
+    #[test]
+    fn test_lazy_module_output() {
+        use crate::{
+            inst::{WasmInvokeOptions, WasmStore},
+            module::WasmPrintOptions,
+        };
+
+        let text = "(module
+            (func $double (export \"double\") (param i32) (result i32)
+                local.get 0 call $add_self)
+            (func $add_self (param i32) (result i32) local.get 0 local.get 0 i32.add))";
+        let source = WasmModuleRaw::from_wat(text).unwrap().to_bytes();
+        let eager = WasmModule::from_bytes(&source).unwrap();
+        let options = WasmValidationOptions {
+            lazy: true,
+            ..Default::default()
+        };
+        // Nothing has been validated when each of these runs.
+        let lazy = || WasmModule::from_bytes_with_options(&source, options).unwrap();
+        assert_eq!(lazy().to_bytes().unwrap(), source);
+        let print = WasmPrintOptions::default();
+        assert_eq!(lazy().to_wat(print).unwrap(), eager.to_wat(print).unwrap());
+
+        let cached = lazy().to_cache_bytes(&source).unwrap();
+        let wmod = WasmModule::from_cache_bytes(&cached, &source).unwrap();
+        let mut store = WasmStore::new();
+        let winst_id = store.instantiate(std::rc::Rc::new(wmod), &[]).unwrap();
+        let double = store
+            .instances
+            .resolve(winst_id)
+            .resolve_export_fn_by_name("double")
+            .unwrap();
+        let res = store
+            .invoke(double, Box::new([21.into()]), WasmInvokeOptions::default())
+            .unwrap();
+        assert_eq!(unsafe { res.res.0[0].num.i32 }, 42);
+    }
 }
@@ -0,0 +1,1819 @@
+use std::collections::HashMap;
+
+use super::{
+    builder::{WasmCode, WasmModuleBuilder},
+    text::*,
+    *,
+};
+
+#[derive(Debug)]
+pub struct WasmParseError {
+    pub line: u32,
+    pub column: u32,
+    pub kind: WasmParseErrorKind,
+}
+
+#[derive(Debug)]
+pub enum WasmParseErrorKind {
+    InvalidUtf8,
+    UnexpectedChar(char),
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidEscape,
+    UnclosedParen,
+    UnexpectedCloseParen,
+    Expected(&'static str),
+    UnexpectedToken(Box<str>),
+    UnknownInstruction(Box<str>),
+    UnknownId(Box<str>),
+    DuplicateId(Box<str>),
+    InvalidNumber(Box<str>),
+    ImportAfterDefinition,
+    MismatchedLabel(Box<str>),
+    MismatchedTypeUse,
+}
+
+impl std::fmt::Display for WasmParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            WasmParseErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            WasmParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            WasmParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            WasmParseErrorKind::UnterminatedComment => write!(f, "unterminated block comment"),
+            WasmParseErrorKind::InvalidEscape => write!(f, "invalid escape sequence"),
+            WasmParseErrorKind::UnclosedParen => write!(f, "unclosed parenthesis"),
+            WasmParseErrorKind::UnexpectedCloseParen => write!(f, "unexpected `)`"),
+            WasmParseErrorKind::Expected(what) => write!(f, "expected {}", what),
+            WasmParseErrorKind::UnexpectedToken(token) => write!(f, "unexpected `{}`", token),
+            WasmParseErrorKind::UnknownInstruction(name) => {
+                write!(f, "unknown instruction `{}`", name)
+            }
+            WasmParseErrorKind::UnknownId(id) => write!(f, "unknown identifier `{}`", id),
+            WasmParseErrorKind::DuplicateId(id) => write!(f, "duplicate identifier `{}`", id),
+            WasmParseErrorKind::InvalidNumber(text) => write!(f, "invalid number `{}`", text),
+            WasmParseErrorKind::ImportAfterDefinition => {
+                write!(f, "imports must come before definitions of the same kind")
+            }
+            WasmParseErrorKind::MismatchedLabel(label) => {
+                write!(f, "mismatched label `{}`", label)
+            }
+            WasmParseErrorKind::MismatchedTypeUse => {
+                write!(f, "inline function type does not match the referenced type")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WasmParseError {}
+
+pub type WasmParseResult<T> = Result<T, WasmParseError>;
+
+#[derive(Debug, Clone, Copy)]
+struct Pos {
+    line: u32,
+    column: u32,
+}
+
+fn err<T>(pos: Pos, kind: WasmParseErrorKind) -> WasmParseResult<T> {
+    Err(WasmParseError {
+        line: pos.line,
+        column: pos.column,
+        kind,
+    })
+}
+
+enum Sexp {
+    List {
+        items: Vec<Sexp>,
+        pos: Pos,
+        end: Pos,
+    },
+    /// Keywords, numbers, identifiers and anything else that is not a
+    /// string or a list.
+    Atom {
+        text: String,
+        pos: Pos,
+    },
+    Str {
+        bytes: Vec<u8>,
+        pos: Pos,
+    },
+}
+
+impl Sexp {
+    fn pos(&self) -> Pos {
+        match self {
+            Sexp::List { pos, .. } | Sexp::Atom { pos, .. } | Sexp::Str { pos, .. } => *pos,
+        }
+    }
+
+    /// The text of an atom, or nothing for strings and lists.
+    fn head_text(&self) -> &str {
+        match self {
+            Sexp::Atom { text, .. } => text,
+            _ => "",
+        }
+    }
+
+    fn head(&self) -> Option<&str> {
+        match self {
+            Sexp::List { items, .. } => match items.first() {
+                Some(Sexp::Atom { text, .. }) => Some(text),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn is_index(&self) -> bool {
+        matches!(self, Sexp::Atom { text, .. } if text.starts_with('$') || text.starts_with(|c: char| c.is_ascii_digit()))
+    }
+
+    fn describe(&self) -> Box<str> {
+        match self {
+            Sexp::List { .. } => match self.head() {
+                Some(head) => format!("({}", head).into(),
+                None => "(".into(),
+            },
+            Sexp::Atom { text, .. } => text.as_str().into(),
+            Sexp::Str { .. } => "string".into(),
+        }
+    }
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    idx: usize,
+    line: u32,
+    column: u32,
+}
+
+impl Lexer {
+    fn new(text: &str) -> Self {
+        Lexer {
+            chars: text.chars().collect(),
+            idx: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn pos(&self) -> Pos {
+        Pos {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.idx + ahead).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek_at(0)?;
+        self.idx += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_trivia(&mut self) -> WasmParseResult<()> {
+        loop {
+            match (self.peek_at(0), self.peek_at(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some(';'), Some(';')) => {
+                    while self.peek_at(0).is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                (Some('('), Some(';')) => {
+                    let pos = self.pos();
+                    self.bump();
+                    self.bump();
+                    let mut depth = 1;
+                    while depth > 0 {
+                        match (self.bump(), self.peek_at(0)) {
+                            (Some('('), Some(';')) => {
+                                self.bump();
+                                depth += 1;
+                            }
+                            (Some(';'), Some(')')) => {
+                                self.bump();
+                                depth -= 1;
+                            }
+                            (Some(_), _) => {}
+                            (None, _) => return err(pos, WasmParseErrorKind::UnterminatedComment),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Reads the items of a list up to its closing parenthesis, or up to
+    /// the end of the input when `open` is `None`. Annotations other than
+    /// `@custom` are dropped.
+    fn read_items(&mut self, open: Option<Pos>) -> WasmParseResult<(Vec<Sexp>, Pos)> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_trivia()?;
+            let pos = self.pos();
+            match self.peek_at(0) {
+                None => match open {
+                    Some(open) => return err(open, WasmParseErrorKind::UnclosedParen),
+                    None => return Ok((items, pos)),
+                },
+                Some(')') => match open {
+                    Some(_) => {
+                        self.bump();
+                        return Ok((items, pos));
+                    }
+                    None => return err(pos, WasmParseErrorKind::UnexpectedCloseParen),
+                },
+                Some('(') => {
+                    self.bump();
+                    let (list, end) = self.read_items(Some(pos))?;
+                    let list = Sexp::List {
+                        items: list,
+                        pos,
+                        end,
+                    };
+                    match list.head() {
+                        Some(head) if head.starts_with('@') && head != "@custom" => {}
+                        _ => items.push(list),
+                    }
+                }
+                Some('"') => {
+                    self.bump();
+                    let bytes = self.read_string(pos)?;
+                    items.push(Sexp::Str { bytes, pos });
+                }
+                Some(c) if is_id_char(c) => {
+                    let mut text = String::new();
+                    while let Some(c) = self.peek_at(0).filter(|c| is_id_char(*c)) {
+                        text.push(c);
+                        self.bump();
+                    }
+                    items.push(Sexp::Atom { text, pos });
+                }
+                Some(c) => return err(pos, WasmParseErrorKind::UnexpectedChar(c)),
+            }
+        }
+    }
+
+    fn read_string(&mut self, open: Pos) -> WasmParseResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        loop {
+            let pos = self.pos();
+            match self.bump() {
+                None | Some('\n') => return err(open, WasmParseErrorKind::UnterminatedString),
+                Some('"') => return Ok(bytes),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('t') => '\t',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\'') => '\'',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            if self.bump() != Some('{') {
+                                return err(pos, WasmParseErrorKind::InvalidEscape);
+                            }
+                            let mut hex = String::new();
+                            while let Some(c) = self.bump().filter(|c| *c != '}') {
+                                hex.push(c);
+                            }
+                            match parse_uint(&hex, 16)
+                                .and_then(|n| u32::try_from(n).ok())
+                                .and_then(char::from_u32)
+                            {
+                                Some(c) => c,
+                                None => return err(pos, WasmParseErrorKind::InvalidEscape),
+                            }
+                        }
+                        Some(hi) => {
+                            let lo = self.bump();
+                            match (hi.to_digit(16), lo.and_then(|lo| lo.to_digit(16))) {
+                                (Some(hi), Some(lo)) => {
+                                    bytes.push((hi * 16 + lo) as u8);
+                                    continue;
+                                }
+                                _ => return err(pos, WasmParseErrorKind::InvalidEscape),
+                            }
+                        }
+                        None => return err(open, WasmParseErrorKind::UnterminatedString),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(c) => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Parses digits in `radix`, allowing single underscores between digits.
+fn parse_uint(text: &str, radix: u32) -> Option<u64> {
+    if text.is_empty() || text.starts_with('_') || text.ends_with('_') || text.contains("__") {
+        return None;
+    }
+    let mut n: u64 = 0;
+    for c in text.chars().filter(|c| *c != '_') {
+        n = n
+            .checked_mul(radix as u64)?
+            .checked_add(c.to_digit(radix)? as u64)?;
+    }
+    Some(n)
+}
+
+fn parse_nat(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => parse_uint(hex, 16),
+        None => parse_uint(text, 10),
+    }
+}
+
+fn split_sign(text: &str) -> (bool, &str) {
+    match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    }
+}
+
+fn parse_i32(text: &str) -> Option<i32> {
+    let (negative, digits) = split_sign(text);
+    let n = parse_nat(digits)?;
+    if negative {
+        (n <= 1 << 31).then(|| (n as i64).wrapping_neg() as i32)
+    } else {
+        u32::try_from(n).ok().map(|n| n as i32)
+    }
+}
+
+fn parse_i64(text: &str) -> Option<i64> {
+    let (negative, digits) = split_sign(text);
+    let n = parse_nat(digits)?;
+    if negative {
+        (n <= 1 << 63).then(|| (n as i64).wrapping_neg())
+    } else {
+        Some(n as i64)
+    }
+}
+
+/// Rounds `mantissa * 2^exp` to the nearest float with `mant_bits` explicit
+/// mantissa bits and `exp_bits` exponent bits, ties to even. `sticky` means
+/// nonzero bits were dropped below the mantissa. Returns `None` on overflow.
+fn round_float(
+    mantissa: u64,
+    sticky: bool,
+    exp: i64,
+    mant_bits: u32,
+    exp_bits: u32,
+) -> Option<u64> {
+    if mantissa == 0 {
+        return Some(0);
+    }
+    let bias = (1i64 << (exp_bits - 1)) - 1;
+    let top = 63 - mantissa.leading_zeros() as i64;
+    let mut e = top + exp;
+    let mut shift = top - mant_bits as i64;
+    if e < 1 - bias {
+        shift += 1 - bias - e;
+        e = 1 - bias;
+    }
+    let mantissa = mantissa as u128;
+    let mut q = if shift <= 0 {
+        mantissa << -shift
+    } else if shift > 100 {
+        0
+    } else {
+        let q = mantissa >> shift;
+        let rem = mantissa & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        if rem > half || (rem == half && (sticky || q & 1 == 1)) {
+            q + 1
+        } else {
+            q
+        }
+    };
+    if q >> (mant_bits + 1) != 0 {
+        q >>= 1;
+        e += 1;
+    }
+    if e > bias {
+        return None;
+    }
+    let field = if q >> mant_bits != 0 {
+        (e + bias) as u64
+    } else {
+        0
+    };
+    Some((field << mant_bits) | (q as u64 & ((1 << mant_bits) - 1)))
+}
+
+fn parse_hex_float(text: &str, mant_bits: u32, exp_bits: u32) -> Option<u64> {
+    let (mantissa_text, exp_text) = match text.find(['p', 'P']) {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    };
+    let (int_text, frac_text) = match mantissa_text.split_once('.') {
+        Some((int_text, frac_text)) => (int_text, frac_text),
+        None => (mantissa_text, ""),
+    };
+    parse_uint(int_text, 16)?;
+    if !frac_text.is_empty() {
+        parse_uint(frac_text, 16)?;
+    }
+    let mut mantissa: u64 = 0;
+    let mut sticky = false;
+    let mut exp: i64 = 0;
+    for (c, is_frac) in int_text
+        .chars()
+        .map(|c| (c, false))
+        .chain(frac_text.chars().map(|c| (c, true)))
+    {
+        let Some(digit) = c.to_digit(16) else {
+            continue;
+        };
+        if mantissa < 1 << 59 {
+            mantissa = mantissa * 16 + digit as u64;
+            if is_frac {
+                exp -= 4;
+            }
+        } else {
+            sticky |= digit != 0;
+            if !is_frac {
+                exp += 4;
+            }
+        }
+    }
+    if let Some(exp_text) = exp_text {
+        let (negative, digits) = split_sign(exp_text);
+        let n = parse_uint(digits, 10).unwrap_or(u64::MAX).min(100_000) as i64;
+        exp += if negative { -n } else { n };
+    }
+    round_float(mantissa, sticky, exp, mant_bits, exp_bits)
+}
+
+/// Parses a float literal into the bits of a float with the given layout.
+fn parse_float(text: &str, mant_bits: u32, exp_bits: u32) -> Option<u64> {
+    let (negative, rest) = split_sign(text);
+    let sign = (negative as u64) << (mant_bits + exp_bits);
+    let inf = ((1u64 << exp_bits) - 1) << mant_bits;
+    let bits = if rest == "inf" {
+        inf
+    } else if rest == "nan" {
+        inf | 1 << (mant_bits - 1)
+    } else if let Some(payload) = rest.strip_prefix("nan:0x") {
+        let payload = parse_uint(payload, 16)?;
+        if payload == 0 || payload >> mant_bits != 0 {
+            return None;
+        }
+        inf | payload
+    } else if let Some(hex) = rest.strip_prefix("0x") {
+        parse_hex_float(hex, mant_bits, exp_bits)?
+    } else {
+        if !rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let mut prev = ' ';
+        for c in rest.chars() {
+            let ok = match c {
+                '0'..='9' => true,
+                '_' => prev.is_ascii_digit(),
+                '.' | 'e' | 'E' => prev.is_ascii_digit(),
+                '+' | '-' => prev == 'e' || prev == 'E',
+                _ => false,
+            };
+            if !ok || (prev == '_' && !c.is_ascii_digit()) {
+                return None;
+            }
+            prev = c;
+        }
+        let digits: String = rest.chars().filter(|c| *c != '_').collect();
+        if mant_bits == 23 {
+            let val = digits.parse::<f32>().ok().filter(|val| val.is_finite())?;
+            val.to_bits() as u64
+        } else {
+            let val = digits.parse::<f64>().ok().filter(|val| val.is_finite())?;
+            val.to_bits()
+        }
+    };
+    Some(sign | bits)
+}
+
+struct Cursor<'s> {
+    items: &'s [Sexp],
+    idx: usize,
+    end: Pos,
+}
+
+impl<'s> Cursor<'s> {
+    fn new(items: &'s [Sexp], end: Pos) -> Self {
+        Cursor { items, idx: 0, end }
+    }
+
+    fn peek(&self) -> Option<&'s Sexp> {
+        self.items.get(self.idx)
+    }
+
+    fn next(&mut self) -> Option<&'s Sexp> {
+        let item = self.peek()?;
+        self.idx += 1;
+        Some(item)
+    }
+
+    fn pos(&self) -> Pos {
+        self.peek().map_or(self.end, Sexp::pos)
+    }
+
+    fn expected<T>(&self, what: &'static str) -> WasmParseResult<T> {
+        match self.peek() {
+            Some(item) => err(item.pos(), WasmParseErrorKind::Expected(what)),
+            None => err(self.end, WasmParseErrorKind::Expected(what)),
+        }
+    }
+
+    fn finish(&self) -> WasmParseResult<()> {
+        match self.peek() {
+            Some(item) => err(
+                item.pos(),
+                WasmParseErrorKind::UnexpectedToken(item.describe()),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn peek_atom(&self) -> Option<&'s str> {
+        match self.peek() {
+            Some(Sexp::Atom { text, .. }) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn atom(&mut self, what: &'static str) -> WasmParseResult<&'s str> {
+        match self.peek_atom() {
+            Some(text) => {
+                self.idx += 1;
+                Ok(text)
+            }
+            None => self.expected(what),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_atom() == Some(keyword);
+        if found {
+            self.idx += 1;
+        }
+        found
+    }
+
+    /// Consumes an identifier, returning it without the `$`.
+    fn id(&mut self) -> Option<&'s str> {
+        let id = self.peek_atom()?.strip_prefix('$')?;
+        self.idx += 1;
+        Some(id)
+    }
+
+    fn peek_list(&self, head: &str) -> bool {
+        self.peek().and_then(Sexp::head) == Some(head)
+    }
+
+    /// Consumes a list starting with `head`, returning a cursor over the
+    /// rest of its items.
+    fn list(&mut self, head: &str) -> Option<Cursor<'s>> {
+        match self.peek() {
+            Some(Sexp::List { items, end, .. })
+                if items.first().is_some_and(
+                    |first| matches!(first, Sexp::Atom { text, .. } if text == head),
+                ) =>
+            {
+                self.idx += 1;
+                Some(Cursor::new(&items[1..], *end))
+            }
+            _ => None,
+        }
+    }
+
+    fn string(&mut self) -> WasmParseResult<&'s [u8]> {
+        match self.peek() {
+            Some(Sexp::Str { bytes, .. }) => {
+                self.idx += 1;
+                Ok(bytes)
+            }
+            _ => self.expected("a string"),
+        }
+    }
+
+    fn name(&mut self) -> WasmParseResult<WasmName> {
+        let pos = self.pos();
+        let bytes = self.string()?;
+        match std::str::from_utf8(bytes) {
+            Ok(name) => Ok(WasmName(name.into())),
+            Err(_) => err(pos, WasmParseErrorKind::InvalidUtf8),
+        }
+    }
+
+    fn u32(&mut self) -> WasmParseResult<u32> {
+        let pos = self.pos();
+        let text = self.atom("a number")?;
+        match parse_nat(text).and_then(|n| u32::try_from(n).ok()) {
+            Some(n) => Ok(n),
+            None => err(pos, WasmParseErrorKind::InvalidNumber(text.into())),
+        }
+    }
+}
+
+/// An index space with the identifiers bound in it.
+#[derive(Default)]
+struct Space<'s> {
+    ids: HashMap<&'s str, u32>,
+    len: u32,
+    defined: bool,
+}
+
+impl<'s> Space<'s> {
+    fn declare(&mut self, id: Option<&'s str>, pos: Pos, import: bool) -> WasmParseResult<u32> {
+        if import && self.defined {
+            return err(pos, WasmParseErrorKind::ImportAfterDefinition);
+        }
+        self.defined |= !import;
+        let idx = self.len;
+        self.len += 1;
+        if let Some(id) = id
+            && self.ids.insert(id, idx).is_some()
+        {
+            return err(pos, WasmParseErrorKind::DuplicateId(id.into()));
+        }
+        Ok(idx)
+    }
+
+    fn resolve(&self, cur: &mut Cursor<'s>, what: &'static str) -> WasmParseResult<u32> {
+        let pos = cur.pos();
+        if let Some(id) = cur.id() {
+            return match self.ids.get(id) {
+                Some(idx) => Ok(*idx),
+                None => err(pos, WasmParseErrorKind::UnknownId(id.into())),
+            };
+        }
+        match cur.peek() {
+            Some(item) if item.is_index() => cur.u32(),
+            _ => cur.expected(what),
+        }
+    }
+
+    fn resolve_opt(&self, cur: &mut Cursor<'s>) -> WasmParseResult<Option<u32>> {
+        match cur.peek() {
+            Some(item) if item.is_index() => self.resolve(cur, "an index").map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn names<T: Eq + std::hash::Hash>(&self, to_idx: fn(u32) -> T) -> HashMap<T, WasmName> {
+        self.ids
+            .iter()
+            .map(|(id, idx)| (to_idx(*idx), WasmName((*id).into())))
+            .collect()
+    }
+}
+
+struct Parser<'s> {
+    builder: WasmModuleBuilder,
+    types: Vec<WasmFuncType>,
+    type_ids: Space<'s>,
+    funcs: Space<'s>,
+    tables: Space<'s>,
+    mems: Space<'s>,
+    globals: Space<'s>,
+    elems: Space<'s>,
+    datas: Space<'s>,
+    local_names: HashMap<WasmFuncIdx, HashMap<WasmLocalIdx, WasmName>>,
+    /// The locals of the function being parsed.
+    locals: Space<'s>,
+    /// The labels of the enclosing blocks, innermost last.
+    labels: Vec<Option<&'s str>>,
+}
+
+fn value_type(cur: &mut Cursor) -> WasmParseResult<WasmValueType> {
+    let val_type = match cur.peek_atom() {
+        Some("i32") => t!(i32),
+        Some("i64") => t!(i64),
+        Some("f32") => t!(f32),
+        Some("f64") => t!(f64),
+        Some("v128") => t!(v128),
+        Some("funcref") => t!(funcref),
+        Some("externref") => t!(externref),
+        _ => return cur.expected("a value type"),
+    };
+    cur.next();
+    Ok(val_type)
+}
+
+fn ref_type(cur: &mut Cursor) -> WasmParseResult<WasmRefType> {
+    match value_type(cur) {
+        Ok(WasmValueType::Ref(ref_type)) => Ok(ref_type),
+        _ => err(cur.pos(), WasmParseErrorKind::Expected("a reference type")),
+    }
+}
+
+fn limits(cur: &mut Cursor) -> WasmParseResult<WasmLimits> {
+    let min = cur.u32()?;
+    let max = match cur.peek() {
+        Some(item) if item.is_index() => Some(cur.u32()?),
+        _ => None,
+    };
+    Ok(WasmLimits { min, max })
+}
+
+fn global_type(cur: &mut Cursor) -> WasmParseResult<WasmGlobalType> {
+    if let Some(mut inner) = cur.list("mut") {
+        let val_type = value_type(&mut inner)?;
+        inner.finish()?;
+        return Ok(WasmGlobalType {
+            mutability: WasmGlobalMutability::Mutable,
+            val_type,
+        });
+    }
+    Ok(WasmGlobalType {
+        mutability: WasmGlobalMutability::Immutable,
+        val_type: value_type(cur)?,
+    })
+}
+
+/// Parses `(param ...)*` and `(result ...)*`, returning the parameter
+/// identifiers alongside the type.
+fn func_type<'s>(cur: &mut Cursor<'s>) -> WasmParseResult<(WasmFuncType, Vec<Option<&'s str>>)> {
+    let mut params = Vec::new();
+    let mut ids = Vec::new();
+    while let Some(mut list) = cur.list("param") {
+        if let Some(id) = list.id() {
+            params.push(value_type(&mut list)?);
+            ids.push(Some(id));
+        } else {
+            while list.peek().is_some() {
+                params.push(value_type(&mut list)?);
+                ids.push(None);
+            }
+        }
+        list.finish()?;
+    }
+    let mut results = Vec::new();
+    while let Some(mut list) = cur.list("result") {
+        while list.peek().is_some() {
+            results.push(value_type(&mut list)?);
+        }
+    }
+    let func_type = WasmFuncType {
+        input_type: WasmResultType(params.into_boxed_slice()),
+        output_type: WasmResultType(results.into_boxed_slice()),
+    };
+    Ok((func_type, ids))
+}
+
+/// Whether a function, table, memory or global field is an inline import.
+fn is_inline_import(items: &[Sexp], end: Pos) -> bool {
+    let mut cur = Cursor::new(items, end);
+    cur.id();
+    while cur.list("export").is_some() {}
+    cur.peek_list("import")
+}
+
+impl<'s> Parser<'s> {
+    fn new() -> Self {
+        Parser {
+            builder: WasmModuleBuilder::new(),
+            types: Vec::new(),
+            type_ids: Space::default(),
+            funcs: Space::default(),
+            tables: Space::default(),
+            mems: Space::default(),
+            globals: Space::default(),
+            elems: Space::default(),
+            datas: Space::default(),
+            local_names: HashMap::new(),
+            locals: Space::default(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Binds the identifiers of a field and records explicit types, so that
+    /// fields can refer to later ones.
+    fn declare_field(&mut self, field: &'s Sexp) -> WasmParseResult<()> {
+        let Sexp::List { items, pos, end } = field else {
+            return err(field.pos(), WasmParseErrorKind::Expected("a module field"));
+        };
+        let mut cur = Cursor::new(items, *end);
+        let pos = *pos;
+        match cur.atom("a module field")? {
+            "type" => {
+                let id = cur.id();
+                self.type_ids.declare(id, pos, false)?;
+                let Some(mut inner) = cur.list("func") else {
+                    return cur.expected("a function type");
+                };
+                let (func_type, _) = func_type(&mut inner)?;
+                inner.finish()?;
+                cur.finish()?;
+                self.types.push(func_type);
+            }
+            "import" => {
+                cur.name()?;
+                cur.name()?;
+                let Some(Sexp::List { items, pos, end }) = cur.next() else {
+                    return cur.expected("an import description");
+                };
+                let mut desc = Cursor::new(items, *end);
+                let space = match desc.atom("an import description")? {
+                    "func" => &mut self.funcs,
+                    "table" => &mut self.tables,
+                    "memory" => &mut self.mems,
+                    "global" => &mut self.globals,
+                    other => {
+                        return err(*pos, WasmParseErrorKind::UnexpectedToken(other.into()));
+                    }
+                };
+                space.declare(desc.id(), *pos, true)?;
+            }
+            kind @ ("func" | "table" | "memory" | "global") => {
+                let import = is_inline_import(&items[1..], *end);
+                let id = cur.id();
+                match kind {
+                    "func" => self.funcs.declare(id, pos, import)?,
+                    "table" => {
+                        if !import && has_inline_segment(&cur, "elem") {
+                            self.elems.declare(None, pos, false)?;
+                        }
+                        self.tables.declare(id, pos, import)?
+                    }
+                    "memory" => {
+                        if !import && has_inline_segment(&cur, "data") {
+                            self.datas.declare(None, pos, false)?;
+                        }
+                        self.mems.declare(id, pos, import)?
+                    }
+                    _ => self.globals.declare(id, pos, import)?,
+                };
+            }
+            "elem" => {
+                self.elems.declare(cur.id(), pos, false)?;
+            }
+            "data" => {
+                self.datas.declare(cur.id(), pos, false)?;
+            }
+            "export" | "start" | "@custom" => {}
+            other => return err(pos, WasmParseErrorKind::UnexpectedToken(other.into())),
+        }
+        Ok(())
+    }
+
+    fn type_idx(&mut self, func_type: WasmFuncType) -> WasmTypeIdx {
+        match self.types.iter().position(|t| *t == func_type) {
+            Some(idx) => WasmTypeIdx(idx as u32),
+            None => {
+                self.types.push(func_type);
+                WasmTypeIdx(self.types.len() as u32 - 1)
+            }
+        }
+    }
+
+    /// Parses `(type x)? (param ...)* (result ...)*`, adding the inline type
+    /// to the module when no index is given.
+    fn type_use(
+        &mut self,
+        cur: &mut Cursor<'s>,
+    ) -> WasmParseResult<(WasmTypeIdx, Vec<Option<&'s str>>)> {
+        let pos = cur.pos();
+        let explicit = match cur.list("type") {
+            Some(mut inner) => {
+                let idx = self.type_ids.resolve(&mut inner, "a type index")?;
+                inner.finish()?;
+                Some(idx)
+            }
+            None => None,
+        };
+        let inline_pos = cur.pos();
+        let (inline, ids) = func_type(cur)?;
+        match explicit {
+            Some(idx) => {
+                let Some(declared) = self.types.get(idx as usize) else {
+                    return err(pos, WasmParseErrorKind::UnknownId(idx.to_string().into()));
+                };
+                let is_empty = inline.input_type.len() == 0 && inline.output_type.len() == 0;
+                if !is_empty && *declared != inline {
+                    return err(inline_pos, WasmParseErrorKind::MismatchedTypeUse);
+                }
+                let ids = if is_empty {
+                    vec![None; declared.input_type.len()]
+                } else {
+                    ids
+                };
+                Ok((WasmTypeIdx(idx), ids))
+            }
+            None => Ok((self.type_idx(inline), ids)),
+        }
+    }
+
+    fn block_type(&mut self, cur: &mut Cursor<'s>) -> WasmParseResult<WasmBlockType> {
+        if cur.peek_list("type") {
+            return Ok(WasmBlockType::TypeRef(self.type_use(cur)?.0));
+        }
+        let (func_type, _) = func_type(cur)?;
+        match (&*func_type.input_type.0, &*func_type.output_type.0) {
+            ([], []) => Ok(WasmBlockType::InlineType(None)),
+            ([], [val_type]) => Ok(WasmBlockType::InlineType(Some(*val_type))),
+            _ => Ok(WasmBlockType::TypeRef(self.type_idx(func_type))),
+        }
+    }
+
+    fn label(&self, cur: &mut Cursor<'s>) -> WasmParseResult<WasmLabelIdx> {
+        let pos = cur.pos();
+        if let Some(id) = cur.id() {
+            return match self
+                .labels
+                .iter()
+                .rev()
+                .position(|label| *label == Some(id))
+            {
+                Some(depth) => Ok(WasmLabelIdx(depth as u32)),
+                None => err(pos, WasmParseErrorKind::UnknownId(id.into())),
+            };
+        }
+        Ok(WasmLabelIdx(cur.u32()?))
+    }
+
+    fn end_label(&self, cur: &mut Cursor<'s>, label: Option<&'s str>) -> WasmParseResult<()> {
+        let pos = cur.pos();
+        match cur.id() {
+            Some(id) if Some(id) != label => {
+                err(pos, WasmParseErrorKind::MismatchedLabel(id.into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn memarg(&self, cur: &mut Cursor<'s>, natural_align: u32) -> WasmParseResult<WasmMemArg> {
+        let mut memarg = WasmMemArg {
+            offset: 0,
+            align: natural_align,
+        };
+        let pos = cur.pos();
+        if let Some(offset) = cur
+            .peek_atom()
+            .and_then(|text| text.strip_prefix("offset="))
+        {
+            cur.next();
+            memarg.offset = match parse_nat(offset).and_then(|n| u32::try_from(n).ok()) {
+                Some(offset) => offset,
+                None => return err(pos, WasmParseErrorKind::InvalidNumber(offset.into())),
+            };
+        }
+        let pos = cur.pos();
+        if let Some(align) = cur.peek_atom().and_then(|text| text.strip_prefix("align=")) {
+            cur.next();
+            memarg.align = match parse_nat(align).filter(|n| n.is_power_of_two()) {
+                Some(align) => align.trailing_zeros(),
+                None => return err(pos, WasmParseErrorKind::InvalidNumber(align.into())),
+            };
+        }
+        Ok(memarg)
+    }
+
+    fn number<T>(
+        &self,
+        cur: &mut Cursor<'s>,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> WasmParseResult<T> {
+        let pos = cur.pos();
+        let text = cur.atom("a number")?;
+        match parse(text) {
+            Some(val) => Ok(val),
+            None => err(pos, WasmParseErrorKind::InvalidNumber(text.into())),
+        }
+    }
+
+    /// Parses the immediates of an instruction that is not a block.
+    fn instr(
+        &mut self,
+        name: &'s str,
+        pos: Pos,
+        cur: &mut Cursor<'s>,
+    ) -> WasmParseResult<WasmInstructionRaw> {
+        use WasmInstructionRepr::*;
+        if let Some(instr) = plain_instr_by_name(name) {
+            return Ok(instr);
+        }
+        if let Some((natural_align, make)) = mem_instr_by_name(name) {
+            return Ok(make(self.memarg(cur, natural_align)?));
+        }
+        let instr = match name {
+            "br" => Break {
+                label_idx: self.label(cur)?,
+                imm: (),
+            },
+            "br_if" => BreakIf {
+                label_idx: self.label(cur)?,
+                imm: (),
+            },
+            "br_table" => {
+                let mut labels = vec![self.label(cur)?];
+                while cur.peek().is_some_and(Sexp::is_index) {
+                    labels.push(self.label(cur)?);
+                }
+                BreakTable {
+                    imm: UnverifiedBreakTableImmediates {
+                        labels: labels.into_boxed_slice(),
+                    },
+                }
+            }
+            "return" => Return { imm: () },
+            "call" => Call {
+                func_idx: WasmFuncIdx(self.funcs.resolve(cur, "a function index")?),
+            },
+            "call_indirect" => {
+                let table_idx = self.tables.resolve_opt(cur)?.unwrap_or(0);
+                CallIndirect {
+                    table_idx: WasmTableIdx(table_idx),
+                    type_idx: self.type_use(cur)?.0,
+                }
+            }
+            "ref.null" => {
+                let ref_type = match cur.atom("a heap type")? {
+                    "func" => WasmRefType::FuncRef,
+                    "extern" => WasmRefType::ExternRef,
+                    other => return err(pos, WasmParseErrorKind::UnexpectedToken(other.into())),
+                };
+                RefNull { ref_type }
+            }
+            "ref.func" => RefFunc {
+                func_idx: WasmFuncIdx(self.funcs.resolve(cur, "a function index")?),
+            },
+            "select" => {
+                let mut value_types = Vec::new();
+                while let Some(mut list) = cur.list("result") {
+                    while list.peek().is_some() {
+                        value_types.push(value_type(&mut list)?);
+                    }
+                }
+                Select {
+                    value_types: value_types.into_boxed_slice(),
+                }
+            }
+            "local.get" | "local.set" | "local.tee" => {
+                let local_idx = WasmLocalIdx(self.locals.resolve(cur, "a local index")?);
+                match name {
+                    "local.get" => LocalGet { local_idx },
+                    "local.set" => LocalSet { local_idx },
+                    _ => LocalTee { local_idx },
+                }
+            }
+            "global.get" | "global.set" => {
+                let global_idx = WasmGlobalIdx(self.globals.resolve(cur, "a global index")?);
+                match name {
+                    "global.get" => GlobalGet { global_idx },
+                    _ => GlobalSet { global_idx },
+                }
+            }
+            "table.get" | "table.set" | "table.size" | "table.grow" | "table.fill" => {
+                let table_idx = WasmTableIdx(self.tables.resolve_opt(cur)?.unwrap_or(0));
+                match name {
+                    "table.get" => TableGet { table_idx },
+                    "table.set" => TableSet { table_idx },
+                    "table.size" => TableSize { table_idx },
+                    "table.grow" => TableGrow { table_idx },
+                    _ => TableFill { table_idx },
+                }
+            }
+            "table.copy" => {
+                let (dst, src) = match self.tables.resolve_opt(cur)? {
+                    Some(dst) => (dst, self.tables.resolve(cur, "a table index")?),
+                    None => (0, 0),
+                };
+                TableCopy {
+                    dst: WasmTableIdx(dst),
+                    src: WasmTableIdx(src),
+                }
+            }
+            "table.init" => {
+                let two_indices = cur.items[cur.idx..]
+                    .iter()
+                    .take(2)
+                    .filter(|item| item.is_index())
+                    .count()
+                    == 2;
+                let table_idx = if two_indices {
+                    self.tables.resolve(cur, "a table index")?
+                } else {
+                    0
+                };
+                TableInit {
+                    table_idx: WasmTableIdx(table_idx),
+                    elem_idx: WasmElemIdx(self.elems.resolve(cur, "an element segment index")?),
+                }
+            }
+            "elem.drop" => ElemDrop {
+                elem_idx: WasmElemIdx(self.elems.resolve(cur, "an element segment index")?),
+            },
+            "memory.init" => MemoryInit {
+                data_idx: WasmDataIdx(self.datas.resolve(cur, "a data segment index")?),
+            },
+            "data.drop" => DataDrop {
+                data_idx: WasmDataIdx(self.datas.resolve(cur, "a data segment index")?),
+            },
+            "i32.const" => I32Const {
+                val: self.number(cur, parse_i32)?,
+            },
+            "i64.const" => I64Const {
+                val: self.number(cur, parse_i64)?,
+            },
+            "f32.const" => F32Const {
+                val: f32::from_bits(self.number(cur, |text| parse_float(text, 23, 8))? as u32),
+            },
+            "f64.const" => F64Const {
+                val: f64::from_bits(self.number(cur, |text| parse_float(text, 52, 11))?),
+            },
+            _ => return err(pos, WasmParseErrorKind::UnknownInstruction(name.into())),
+        };
+        Ok(instr)
+    }
+
+    /// Parses flat and folded instructions up to the end of the cursor or
+    /// an `else` or `end` keyword.
+    fn instrs(
+        &mut self,
+        cur: &mut Cursor<'s>,
+        out: &mut Vec<WasmInstructionRaw>,
+    ) -> WasmParseResult<()> {
+        while let Some(item) = cur.peek() {
+            match item {
+                Sexp::List { .. } => {
+                    cur.next();
+                    self.folded_instr(item, out)?;
+                }
+                Sexp::Atom { text, pos } => {
+                    if text == "else" || text == "end" {
+                        return Ok(());
+                    }
+                    cur.next();
+                    self.flat_instr(text, *pos, cur, out)?;
+                }
+                Sexp::Str { .. } => return cur.expected("an instruction"),
+            }
+        }
+        Ok(())
+    }
+
+    fn block_start(
+        &mut self,
+        name: &str,
+        cur: &mut Cursor<'s>,
+    ) -> WasmParseResult<(Option<&'s str>, WasmInstructionRaw)> {
+        let label = cur.id();
+        let block_type = self.block_type(cur)?;
+        let instr = match name {
+            "block" => WasmInstructionRepr::Block {
+                block_type,
+                imm: (),
+            },
+            "loop" => WasmInstructionRepr::Loop {
+                block_type,
+                imm: (),
+            },
+            _ => WasmInstructionRepr::If {
+                block_type,
+                imm: (),
+            },
+        };
+        Ok((label, instr))
+    }
+
+    fn flat_instr(
+        &mut self,
+        name: &'s str,
+        pos: Pos,
+        cur: &mut Cursor<'s>,
+        out: &mut Vec<WasmInstructionRaw>,
+    ) -> WasmParseResult<()> {
+        if !matches!(name, "block" | "loop" | "if") {
+            let instr = self.instr(name, pos, cur)?;
+            out.push(instr);
+            return Ok(());
+        }
+        let (label, instr) = self.block_start(name, cur)?;
+        out.push(instr);
+        self.labels.push(label);
+        self.instrs(cur, out)?;
+        if name == "if" && cur.keyword("else") {
+            self.end_label(cur, label)?;
+            out.push(WasmInstructionRepr::Else);
+            self.instrs(cur, out)?;
+        }
+        if !cur.keyword("end") {
+            return cur.expected("`end`");
+        }
+        self.end_label(cur, label)?;
+        self.labels.pop();
+        out.push(WasmInstructionRepr::ExprEnd);
+        Ok(())
+    }
+
+    fn folded_instr(
+        &mut self,
+        item: &'s Sexp,
+        out: &mut Vec<WasmInstructionRaw>,
+    ) -> WasmParseResult<()> {
+        let Sexp::List { items, pos, end } = item else {
+            return err(
+                item.pos(),
+                WasmParseErrorKind::Expected("a folded instruction"),
+            );
+        };
+        let mut cur = Cursor::new(items, *end);
+        let name = cur.atom("an instruction")?;
+        match name {
+            "block" | "loop" => {
+                let (label, instr) = self.block_start(name, &mut cur)?;
+                out.push(instr);
+                self.labels.push(label);
+                self.instrs(&mut cur, out)?;
+                cur.finish()?;
+                self.labels.pop();
+                out.push(WasmInstructionRepr::ExprEnd);
+            }
+            "if" => {
+                let (label, instr) = self.block_start(name, &mut cur)?;
+                while let Some(operand) = cur.peek().filter(|item| {
+                    matches!(item, Sexp::List { .. })
+                        && !matches!(item.head(), Some("then" | "else"))
+                }) {
+                    cur.next();
+                    self.folded_instr(operand, out)?;
+                }
+                out.push(instr);
+                self.labels.push(label);
+                let Some(mut then) = cur.list("then") else {
+                    return cur.expected("`(then`");
+                };
+                self.instrs(&mut then, out)?;
+                then.finish()?;
+                if let Some(mut els) = cur.list("else") {
+                    out.push(WasmInstructionRepr::Else);
+                    self.instrs(&mut els, out)?;
+                    els.finish()?;
+                }
+                cur.finish()?;
+                self.labels.pop();
+                out.push(WasmInstructionRepr::ExprEnd);
+            }
+            _ => {
+                let instr = self.instr(name, *pos, &mut cur)?;
+                while let Some(operand) = cur.next() {
+                    self.folded_instr(operand, out)?;
+                }
+                out.push(instr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses instructions up to the end of the cursor as an expression.
+    fn expr(&mut self, cur: &mut Cursor<'s>) -> WasmParseResult<Box<WasmExprRaw>> {
+        let mut out = Vec::new();
+        self.instrs(cur, &mut out)?;
+        cur.finish()?;
+        out.push(WasmInstructionRepr::ExprEnd);
+        Ok(out.into_boxed_slice())
+    }
+
+    /// Parses `(offset instr*)` or a single folded instruction.
+    fn offset(&mut self, cur: &mut Cursor<'s>) -> WasmParseResult<Box<WasmExprRaw>> {
+        if let Some(mut inner) = cur.list("offset") {
+            return self.expr(&mut inner);
+        }
+        match cur.next() {
+            Some(item @ Sexp::List { .. }) => {
+                let mut out = Vec::new();
+                self.folded_instr(item, &mut out)?;
+                out.push(WasmInstructionRepr::ExprEnd);
+                Ok(out.into_boxed_slice())
+            }
+            _ => err(
+                cur.pos(),
+                WasmParseErrorKind::Expected("an offset expression"),
+            ),
+        }
+    }
+
+    fn func_refs(&mut self, cur: &mut Cursor<'s>) -> WasmParseResult<Box<[Box<WasmExprRaw>]>> {
+        let mut init = Vec::new();
+        while cur.peek().is_some_and(Sexp::is_index) {
+            let func_idx = WasmFuncIdx(self.funcs.resolve(cur, "a function index")?);
+            init.push(Box::from([
+                WasmInstructionRepr::RefFunc { func_idx },
+                WasmInstructionRepr::ExprEnd,
+            ]));
+        }
+        Ok(init.into_boxed_slice())
+    }
+
+    /// Parses `func x*`, `reftype item*` or, for legacy active segments, a
+    /// bare list of function indices.
+    fn elem_list(
+        &mut self,
+        cur: &mut Cursor<'s>,
+    ) -> WasmParseResult<(WasmRefType, Box<[Box<WasmExprRaw>]>)> {
+        if cur.keyword("func") {
+            return Ok((WasmRefType::FuncRef, self.func_refs(cur)?));
+        }
+        if matches!(cur.peek_atom(), Some("funcref" | "externref")) {
+            let ref_type = ref_type(cur)?;
+            let mut init = Vec::new();
+            while let Some(item) = cur.peek() {
+                if let Some(mut inner) = cur.list("item") {
+                    init.push(self.expr(&mut inner)?);
+                } else {
+                    cur.next();
+                    let mut out = Vec::new();
+                    self.folded_instr(item, &mut out)?;
+                    out.push(WasmInstructionRepr::ExprEnd);
+                    init.push(out.into_boxed_slice());
+                }
+            }
+            return Ok((ref_type, init.into_boxed_slice()));
+        }
+        Ok((WasmRefType::FuncRef, self.func_refs(cur)?))
+    }
+
+    fn inline_exports(
+        &mut self,
+        cur: &mut Cursor<'s>,
+        desc: impl Fn() -> WasmExportDesc,
+    ) -> WasmParseResult<()> {
+        while let Some(mut inner) = cur.list("export") {
+            let name = inner.name()?;
+            inner.finish()?;
+            self.builder.push_export(WasmExport { name, desc: desc() });
+        }
+        Ok(())
+    }
+
+    fn inline_import(
+        &mut self,
+        cur: &mut Cursor<'s>,
+    ) -> WasmParseResult<Option<(WasmName, WasmName)>> {
+        match cur.list("import") {
+            Some(mut inner) => {
+                let module_name = inner.name()?;
+                let item_name = inner.name()?;
+                inner.finish()?;
+                Ok(Some((module_name, item_name)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn import_desc(&mut self, kind: &str, cur: &mut Cursor<'s>) -> WasmParseResult<WasmImportDesc> {
+        let desc = match kind {
+            "func" => WasmImportDesc::Func(self.type_use(cur)?.0),
+            "table" => {
+                let limits = limits(cur)?;
+                WasmImportDesc::Table(WasmTableType {
+                    limits,
+                    ref_type: ref_type(cur)?,
+                })
+            }
+            "memory" => WasmImportDesc::Mem(WasmMemType {
+                limits: limits(cur)?,
+            }),
+            _ => WasmImportDesc::Global(global_type(cur)?),
+        };
+        cur.finish()?;
+        Ok(desc)
+    }
+
+    fn func(&mut self, func_idx: u32, cur: &mut Cursor<'s>) -> WasmParseResult<()> {
+        self.inline_exports(cur, || WasmExportDesc::Func(WasmFuncIdx(func_idx)))?;
+        if let Some((module_name, item_name)) = self.inline_import(cur)? {
+            let desc = self.import_desc("func", cur)?;
+            self.builder.push_import(WasmImport {
+                module_name,
+                item_name,
+                desc,
+            });
+            return Ok(());
+        }
+        let (type_idx, param_ids) = self.type_use(cur)?;
+        self.locals = Space::default();
+        let pos = cur.pos();
+        for id in param_ids {
+            self.locals.declare(id, pos, false)?;
+        }
+        let mut locals = Vec::new();
+        while let Some(mut inner) = cur.list("local") {
+            let pos = inner.pos();
+            if let Some(id) = inner.id() {
+                locals.push(value_type(&mut inner)?);
+                self.locals.declare(Some(id), pos, false)?;
+            } else {
+                while inner.peek().is_some() {
+                    locals.push(value_type(&mut inner)?);
+                    self.locals.declare(None, pos, false)?;
+                }
+            }
+            inner.finish()?;
+        }
+        self.labels.clear();
+        let body = self.expr(cur)?;
+        if !self.locals.ids.is_empty() {
+            let names = self.locals.names(WasmLocalIdx);
+            self.local_names.insert(WasmFuncIdx(func_idx), names);
+        }
+        self.builder.push_func(type_idx);
+        self.builder.push_code(WasmCode {
+            locals: locals.into_boxed_slice(),
+            body,
+            offsets: Box::new([]),
+        });
+        Ok(())
+    }
+
+    fn field(&mut self, field: &'s Sexp, counts: &mut [u32; 6]) -> WasmParseResult<()> {
+        let Sexp::List { items, pos, end } = field else {
+            unreachable!("fields are checked while declaring them");
+        };
+        let mut cur = Cursor::new(&items[1..], *end);
+        let [funcs, tables, mems, globals, elems, datas] = counts;
+        match items[0].head_text() {
+            "type" => {}
+            "import" => {
+                let module_name = cur.name()?;
+                let item_name = cur.name()?;
+                let Some(Sexp::List { items, end, .. }) = cur.next() else {
+                    unreachable!("imports are checked while declaring them");
+                };
+                let mut inner = Cursor::new(&items[1..], *end);
+                inner.id();
+                let kind = items[0].head_text();
+                let counter = match kind {
+                    "func" => funcs,
+                    "table" => tables,
+                    "memory" => mems,
+                    _ => globals,
+                };
+                *counter += 1;
+                let desc = self.import_desc(kind, &mut inner)?;
+                cur.finish()?;
+                self.builder.push_import(WasmImport {
+                    module_name,
+                    item_name,
+                    desc,
+                });
+            }
+            "func" => {
+                cur.id();
+                *funcs += 1;
+                self.func(*funcs - 1, &mut cur)?;
+            }
+            "table" => {
+                cur.id();
+                *tables += 1;
+                let table_idx = *tables - 1;
+                self.inline_exports(&mut cur, || WasmExportDesc::Table(WasmTableIdx(table_idx)))?;
+                if let Some((module_name, item_name)) = self.inline_import(&mut cur)? {
+                    let desc = self.import_desc("table", &mut cur)?;
+                    self.builder.push_import(WasmImport {
+                        module_name,
+                        item_name,
+                        desc,
+                    });
+                } else if has_inline_segment(&cur, "elem") {
+                    let ref_type = ref_type(&mut cur)?;
+                    let mut inner = cur.list("elem").unwrap();
+                    let (_, init) = self.elem_list(&mut inner)?;
+                    inner.finish()?;
+                    cur.finish()?;
+                    let len = init.len() as u32;
+                    self.builder.push_table(WasmTableType {
+                        limits: WasmLimits {
+                            min: len,
+                            max: Some(len),
+                        },
+                        ref_type,
+                    });
+                    *elems += 1;
+                    self.builder.push_elem(WasmElem {
+                        ref_type,
+                        init,
+                        elem_mode: WasmElemMode::Active {
+                            table_idx: WasmTableIdx(table_idx),
+                            offset_expr: zero_offset(),
+                        },
+                    });
+                } else {
+                    let limits = limits(&mut cur)?;
+                    let ref_type = ref_type(&mut cur)?;
+                    cur.finish()?;
+                    self.builder.push_table(WasmTableType { limits, ref_type });
+                }
+            }
+            "memory" => {
+                cur.id();
+                *mems += 1;
+                let mem_idx = *mems - 1;
+                self.inline_exports(&mut cur, || WasmExportDesc::Mem(WasmMemIdx(mem_idx)))?;
+                if let Some((module_name, item_name)) = self.inline_import(&mut cur)? {
+                    let desc = self.import_desc("memory", &mut cur)?;
+                    self.builder.push_import(WasmImport {
+                        module_name,
+                        item_name,
+                        desc,
+                    });
+                } else if let Some(mut inner) = cur.list("data") {
+                    let mut bytes = Vec::new();
+                    while inner.peek().is_some() {
+                        bytes.extend_from_slice(inner.string()?);
+                    }
+                    cur.finish()?;
+                    let pages = bytes.len().div_ceil(1 << 16) as u32;
+                    self.builder.push_mem(WasmMemType {
+                        limits: WasmLimits {
+                            min: pages,
+                            max: Some(pages),
+                        },
+                    });
+                    *datas += 1;
+                    self.builder.push_data(WasmData {
+                        bytes: bytes.into_boxed_slice(),
+                        mode: WasmDataMode::Active {
+                            mem_idx: WasmMemIdx(mem_idx),
+                            offset_expr: zero_offset(),
+                        },
+                    });
+                } else {
+                    let limits = limits(&mut cur)?;
+                    cur.finish()?;
+                    self.builder.push_mem(WasmMemType { limits });
+                }
+            }
+            "global" => {
+                cur.id();
+                *globals += 1;
+                let global_idx = *globals - 1;
+                self.inline_exports(&mut cur, || {
+                    WasmExportDesc::Global(WasmGlobalIdx(global_idx))
+                })?;
+                if let Some((module_name, item_name)) = self.inline_import(&mut cur)? {
+                    let desc = self.import_desc("global", &mut cur)?;
+                    self.builder.push_import(WasmImport {
+                        module_name,
+                        item_name,
+                        desc,
+                    });
+                } else {
+                    let global_type = global_type(&mut cur)?;
+                    let init = self.expr(&mut cur)?;
+                    self.builder.push_global(WasmGlobal { global_type, init });
+                }
+            }
+            "export" => {
+                let name = cur.name()?;
+                let Some(Sexp::List { items, end, .. }) = cur.peek() else {
+                    return cur.expected("an export description");
+                };
+                cur.next();
+                let mut inner = Cursor::new(&items[1..], *end);
+                let desc = match items.first().map(Sexp::head_text) {
+                    Some("func") => WasmExportDesc::Func(WasmFuncIdx(
+                        self.funcs.resolve(&mut inner, "a function index")?,
+                    )),
+                    Some("table") => WasmExportDesc::Table(WasmTableIdx(
+                        self.tables.resolve(&mut inner, "a table index")?,
+                    )),
+                    Some("memory") => WasmExportDesc::Mem(WasmMemIdx(
+                        self.mems.resolve(&mut inner, "a memory index")?,
+                    )),
+                    Some("global") => WasmExportDesc::Global(WasmGlobalIdx(
+                        self.globals.resolve(&mut inner, "a global index")?,
+                    )),
+                    _ => {
+                        return err(
+                            items.first().map_or(*pos, Sexp::pos),
+                            WasmParseErrorKind::Expected("an export description"),
+                        );
+                    }
+                };
+                inner.finish()?;
+                cur.finish()?;
+                self.builder.push_export(WasmExport { name, desc });
+            }
+            "start" => {
+                let func_idx = self.funcs.resolve(&mut cur, "a function index")?;
+                cur.finish()?;
+                self.builder.start(WasmFuncIdx(func_idx));
+            }
+            "elem" => {
+                cur.id();
+                *elems += 1;
+                let elem_mode = if cur.keyword("declare") {
+                    WasmElemMode::Declarative
+                } else if let Some(mut inner) = cur.list("table") {
+                    let table_idx = self.tables.resolve(&mut inner, "a table index")?;
+                    inner.finish()?;
+                    WasmElemMode::Active {
+                        table_idx: WasmTableIdx(table_idx),
+                        offset_expr: self.offset(&mut cur)?,
+                    }
+                } else if let Some(Sexp::List { .. }) = cur.peek() {
+                    WasmElemMode::Active {
+                        table_idx: WasmTableIdx(0),
+                        offset_expr: self.offset(&mut cur)?,
+                    }
+                } else {
+                    WasmElemMode::Passive
+                };
+                let (ref_type, init) = self.elem_list(&mut cur)?;
+                cur.finish()?;
+                self.builder.push_elem(WasmElem {
+                    ref_type,
+                    init,
+                    elem_mode,
+                });
+            }
+            "data" => {
+                cur.id();
+                *datas += 1;
+                let mode = if let Some(mut inner) = cur.list("memory") {
+                    let mem_idx = self.mems.resolve(&mut inner, "a memory index")?;
+                    inner.finish()?;
+                    WasmDataMode::Active {
+                        mem_idx: WasmMemIdx(mem_idx),
+                        offset_expr: self.offset(&mut cur)?,
+                    }
+                } else if let Some(Sexp::List { .. }) = cur.peek() {
+                    WasmDataMode::Active {
+                        mem_idx: WasmMemIdx(0),
+                        offset_expr: self.offset(&mut cur)?,
+                    }
+                } else {
+                    WasmDataMode::Passive
+                };
+                let mut bytes = Vec::new();
+                while cur.peek().is_some() {
+                    bytes.extend_from_slice(cur.string()?);
+                }
+                self.builder.push_data(WasmData {
+                    bytes: bytes.into_boxed_slice(),
+                    mode,
+                });
+            }
+            "@custom" => {
+                let name = cur.name()?;
+                // The placement is ignored; custom sections are always
+                // encoded at the end of the module.
+                if cur.list("before").is_none() {
+                    cur.list("after");
+                }
+                let mut bytes = Vec::new();
+                while cur.peek().is_some() {
+                    bytes.extend_from_slice(cur.string()?);
+                }
+                self.builder.push_custom(WasmCustom {
+                    name,
+                    bytes: bytes.into_boxed_slice(),
+                });
+            }
+            _ => unreachable!("fields are checked while declaring them"),
+        }
+        Ok(())
+    }
+
+    fn build(mut self, module_id: Option<&str>) -> WasmModuleRaw {
+        self.builder.version(1);
+        self.builder.reserve_types(self.types.len());
+        for func_type in std::mem::take(&mut self.types) {
+            self.builder.push_type(func_type);
+        }
+        self.builder.names(WasmNameMap {
+            module: module_id.map(|id| WasmName(id.into())),
+            funcs: self.funcs.names(WasmFuncIdx),
+            locals: self.local_names,
+            tables: self.tables.names(WasmTableIdx),
+            mems: self.mems.names(WasmMemIdx),
+            globals: self.globals.names(WasmGlobalIdx),
+            elems: self.elems.names(WasmElemIdx),
+            datas: self.datas.names(WasmDataIdx),
+        });
+        self.builder.build()
+    }
+}
+
+/// Whether a table or memory field abbreviates a segment, like
+/// `(table funcref (elem $f))` or `(memory (data "..."))`.
+fn has_inline_segment(cur: &Cursor, head: &str) -> bool {
+    cur.items[cur.idx..]
+        .iter()
+        .any(|item| item.head() == Some(head))
+}
+
+fn zero_offset() -> Box<WasmExprRaw> {
+    Box::new([
+        WasmInstructionRepr::I32Const { val: 0 },
+        WasmInstructionRepr::ExprEnd,
+    ])
+}
+
+/// Parses a module in the WebAssembly text format. The `(module ...)`
+/// wrapper may be omitted.
+pub(super) fn parse(text: &str) -> WasmParseResult<WasmModuleRaw> {
+    let (sexps, _) = Lexer::new(text).read_items(None)?;
+    let (module_id, fields) = match sexps.as_slice() {
+        [Sexp::List { items, end, .. }, rest @ ..]
+            if items.first().map(Sexp::head_text) == Some("module") =>
+        {
+            if let Some(extra) = rest.first() {
+                return err(
+                    extra.pos(),
+                    WasmParseErrorKind::UnexpectedToken(extra.describe()),
+                );
+            }
+            let mut cur = Cursor::new(&items[1..], *end);
+            let id = cur.id();
+            (id, &cur.items[cur.idx..])
+        }
+        _ => (None, sexps.as_slice()),
+    };
+
+    let mut parser = Parser::new();
+    for field in fields {
+        parser.declare_field(field)?;
+    }
+    let mut counts = [0; 6];
+    for field in fields {
+        parser.field(field, &mut counts)?;
+    }
+    Ok(parser.build(module_id))
+}
+
+/// Like [`parse`], for text that has not been checked to be UTF-8.
+pub(super) fn parse_bytes(bytes: &[u8]) -> WasmParseResult<WasmModuleRaw> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => parse(text),
+        Err(e) => {
+            let valid = &bytes[..e.valid_up_to()];
+            let line = valid.iter().filter(|b| **b == b'\n').count() as u32 + 1;
+            let line_start = valid.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+            let column = String::from_utf8_lossy(&valid[line_start..])
+                .chars()
+                .count() as u32
+                + 1;
+            err(Pos { line, column }, WasmParseErrorKind::InvalidUtf8)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::fixtures::{MODULE, TEXT},
+        *,
+    };
+
+    #[test]
+    fn test_parse_matches_binary() {
+        let wmod = WasmModuleRaw::from_wat(TEXT).unwrap();
+        assert_eq!(wmod.to_bytes(), MODULE);
+        assert_eq!(wmod.names.func_name(WasmFuncIdx(0)), Some("f"));
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let e = WasmModuleRaw::from_wat("(module\n  (func\n    i32.frob))").unwrap_err();
+        assert_eq!((e.line, e.column), (3, 5));
+        assert!(matches!(e.kind, WasmParseErrorKind::UnknownInstruction(_)));
+    }
+
+    #[test]
+    fn test_parse_float() {
+        let f32_bits = |text| parse_float(text, 23, 8);
+        assert_eq!(f32_bits("0x1p-149"), Some(1));
+        assert_eq!(f32_bits("0x1.fffffep127"), Some(0x7F7FFFFF));
+        assert_eq!(f32_bits("-0x1.8p1"), Some((-3.0f32).to_bits() as u64));
+        assert_eq!(f32_bits("0x1p128"), None);
+        assert_eq!(f32_bits("1e40"), None);
+        assert_eq!(f32_bits("-nan:0x200000"), Some(0xFFA00000));
+        assert_eq!(parse_float("1_000.5", 52, 11), Some(1000.5f64.to_bits()));
+    }
+}
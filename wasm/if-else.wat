@@ -30,7 +30,7 @@
     i32.const 2
     i32.rem_s
     i32.eqz
-    if
+    if (result i32)
       i32.const 1024
     else
       i32.const 1030